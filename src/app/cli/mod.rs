@@ -1,9 +1,14 @@
 use clap::Parser;
 use tokio::sync::mpsc;
 
+use tracing::warn;
+
 use crate::app::SDFViewerApp;
+use crate::app::expr::ExprEditor;
 use crate::sdf::demo::SDFDemo;
+use crate::sdf::expr::ExprSDF;
 use crate::sdf::wasm::load;
+use crate::sdf::wasm::wasi::CliWasiEnv;
 
 pub(crate) mod settings;
 
@@ -27,10 +32,39 @@ impl CliApp {
         // Configure the initial SDF provider (may be changed later)
         let (sender_of_updates, receiver_of_updates) = mpsc::channel(16);
         app.set_root_sdf_loading_manager(receiver_of_updates);
+        app.expr_editor = None;
         match self.sdf_provider.clone() {
             CliSDFProvider::Demo(s) => app.set_root_sdf(Box::new(s), Some(self.max_voxels_side), Some(self.loading_passes)),
             CliSDFProvider::Url(watch) => {
-                load::load_sdf_from_path_or_url(sender_of_updates, watch.url);
+                load::load_sdf_from_path_or_url(sender_of_updates, watch.url, watch.wasi);
+            }
+            #[cfg(all(feature = "dylib", not(target_arch = "wasm32")))]
+            CliSDFProvider::NativeLib(lib) => {
+                load::load_sdf_from_native_lib(sender_of_updates, lib.path);
+            }
+            CliSDFProvider::Remote(remote) => {
+                load::load_sdf_from_remote(sender_of_updates, remote.url);
+            }
+            #[cfg(feature = "voxel_grids")]
+            CliSDFProvider::Voxels(voxels) => { // Detected from the contents, like any other input
+                load::load_sdf_from_path_or_url(sender_of_updates, voxels.path, CliWasiEnv::default());
+            }
+            #[cfg(feature = "mesh_inputs")]
+            CliSDFProvider::Mesh(mesh) => { // Detected from the contents or extension, like any other input
+                load::load_sdf_from_path_or_url(sender_of_updates, mesh.path, CliWasiEnv::default());
+            }
+            CliSDFProvider::Expr(expr) => {
+                // Display the example while the expression is not valid, so that it can be fixed in the editor
+                let (sdf, error) = match ExprSDF::new(&expr.source) {
+                    Ok(sdf) => (sdf, None),
+                    Err(err) => {
+                        warn!("Invalid expression, displaying the example instead: {}", err);
+                        (ExprSDF::new(ExprSDF::EXAMPLE).expect("the example is valid"), Some(err))
+                    }
+                };
+                let sdf = sdf.with_id(0);
+                app.expr_editor = Some(ExprEditor::new(sdf.clone(), expr.source, error));
+                app.set_root_sdf(Box::new(sdf), Some(self.max_voxels_side), Some(self.loading_passes));
             }
         }
         // TODO: Many more settings! (should be easy to add and automatically update the CLI and UI)
@@ -41,6 +75,20 @@ impl CliApp {
 pub enum CliSDFProvider {
     /// Display a WebAssembly file downloaded from the given URL.
     Url(CliAppWatchUrl),
+    /// Display a native shared library (.so, .dylib or .dll) that implements the same API as the WebAssembly files.
+    /// It runs at full speed, but without any sandboxing: only load trusted libraries!
+    #[cfg(all(feature = "dylib", not(target_arch = "wasm32")))]
+    NativeLib(CliAppNativeLib),
+    /// Display a SDF that is evaluated by another process or machine, like `server --remote-sdf`.
+    Remote(CliAppRemote),
+    /// Display a dense voxel grid, like a CT scan or a precomputed distance grid, as a SDF.
+    #[cfg(feature = "voxel_grids")]
+    Voxels(CliAppVoxels),
+    /// Display the signed distance to a triangle mesh (OBJ, STL or PLY file), with its vertex colors.
+    #[cfg(feature = "mesh_inputs")]
+    Mesh(CliAppMesh),
+    /// Display a SDF defined by a math expression, which can be edited in the app.
+    Expr(CliAppExpr),
     /// An embedded demo SDF provider for testing and feature-showcasing purposes
     Demo(SDFDemo),
 }
@@ -52,10 +100,16 @@ impl Default for CliSDFProvider {
 }
 
 impl CliSDFProvider {
-    /// Get the URL to download the SDF from, if available.
+    /// Get the URL (or path) to load the SDF from, if available.
     pub fn url(&self) -> Option<&str> {
         match self {
             CliSDFProvider::Url(watch) => Some(&watch.url),
+            #[cfg(all(feature = "dylib", not(target_arch = "wasm32")))]
+            CliSDFProvider::NativeLib(lib) => Some(&lib.path),
+            #[cfg(feature = "voxel_grids")]
+            CliSDFProvider::Voxels(voxels) => Some(&voxels.path),
+            #[cfg(feature = "mesh_inputs")]
+            CliSDFProvider::Mesh(mesh) => Some(&mesh.path),
             _ => None,
         }
     }
@@ -65,11 +119,71 @@ impl CliSDFProvider {
 pub struct CliAppWatchUrl {
     /// The url where a WebAssembly file representing a SDF is hosted.
     ///
-    /// Supported schemes are http(s):// and file://.
+    /// It may also be WebAssembly text (.wat), compressed with gzip or zstd, or a zip bundle with a
+    /// module, its assets and an optional sdf-manifest.txt. Voxel grids and triangle meshes (see the voxels
+    /// and mesh providers) are also detected.
+    ///
+    /// Supported schemes are http(s)://, file:// and data: (like data:application/wasm;base64,...).
     /// If no scheme is given, it is assumed to be a local file in native and a relative URL in web.
+    /// On native, "-" reads the SDF from stdin once, at startup (so it is not watched for changes).
     ///
     /// The app expects the server to listen for file changes if ?wait=true is appended to the URL, but
     /// detects servers that don't support this and displays a warning, disabling this feature.
     #[clap(parse(try_from_str))]
     pub url: String,
+    #[clap(flatten)]
+    pub wasi: CliWasiEnv,
+}
+
+#[cfg(all(feature = "dylib", not(target_arch = "wasm32")))]
+#[derive(Parser, Debug, Clone, PartialEq, Eq)]
+pub struct CliAppNativeLib {
+    /// The path to a native shared library representing a SDF, like a `cdylib` crate that uses `export_sdf!`.
+    ///
+    /// It must be built for the same platform as this app. It is loaded once, so restart the app to
+    /// load a rebuilt library.
+    #[clap(value_hint = clap::ValueHint::FilePath)]
+    pub path: String,
+}
+
+#[derive(Parser, Debug, Clone, PartialEq, Eq)]
+pub struct CliAppRemote {
+    /// The base URL of a server of the remote SDF protocol, like `http://127.0.0.1:8080/sdf`.
+    ///
+    /// Every query to the SDF is a request to the server, so it works best with batched sampling on
+    /// a fast network.
+    #[clap(parse(try_from_str))]
+    pub url: String,
+}
+
+#[cfg(feature = "voxel_grids")]
+#[derive(Parser, Debug, Clone, PartialEq, Eq)]
+pub struct CliAppVoxels {
+    /// The path or URL of a NRRD (.nrrd, .nhdr) or MetaImage (.mha, .mhd) volume.
+    ///
+    /// The surface is where the interpolated values cross the iso offset, which is a parameter of the
+    /// SDF (see the global --voxels-* options for the initial values). Headers with detached data
+    /// (.nhdr, .mhd) are only supported as local files.
+    #[clap(value_hint = clap::ValueHint::FilePath)]
+    pub path: String,
+}
+
+#[cfg(feature = "mesh_inputs")]
+#[derive(Parser, Debug, Clone, PartialEq, Eq)]
+pub struct CliAppMesh {
+    /// The path or URL of a triangle mesh: a Wavefront OBJ (.obj), STL (.stl, binary or ASCII) or PLY (.ply) file.
+    ///
+    /// The mesh should be closed and oriented for the default sign method (pseudo-normals). Otherwise,
+    /// switch the sign parameter of the SDF to the winding number, which is slower but more robust.
+    #[clap(value_hint = clap::ValueHint::FilePath)]
+    pub path: String,
+}
+
+#[derive(Parser, Debug, Clone, PartialEq, Eq)]
+pub struct CliAppExpr {
+    /// The expression of the distance to the surface in terms of the point `p`, like
+    /// `max(box(p, vec3(1)), -sphere(p, 1.2))`, optionally preceded by named constants (like `r = 1.2`)
+    /// that can be modified as parameters. See the documentation of `sdf::expr` for the full syntax.
+    #[clap(default_value = ExprSDF::EXAMPLE, hide_default_value = true)]
+    pub source: String,
 }
@@ -0,0 +1,128 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use eframe::egui;
+use eframe::egui::panel::TopBottomSide;
+use eframe::egui::{Context, Label, RichText, ScrollArea, TextEdit, Ui};
+
+use crate::sdf::wasm::console::{self, ConsoleLine, ConsoleStream};
+
+/// The console panel, displaying the output of the SDF modules next to our own logs
+/// (see [`crate::sdf::wasm::console`]), with some filters.
+pub struct ConsolePanel {
+    /// Whether the panel is displayed.
+    pub open: bool,
+    /// Only lines containing this text (case-insensitive) are displayed.
+    filter: String,
+    /// Whether to display the output of the modules.
+    show_output: bool,
+    /// Whether to display our own logs.
+    show_logs: bool,
+    /// The modules (or log targets) whose lines are not displayed.
+    hidden_sources: BTreeSet<String>,
+    /// A copy of the lines of the console, updated when they change.
+    lines: Vec<ConsoleLine>,
+    generation: Option<u64>,
+}
+
+impl Default for ConsolePanel {
+    fn default() -> Self {
+        Self {
+            open: false,
+            filter: String::new(),
+            show_output: true,
+            show_logs: true,
+            hidden_sources: BTreeSet::new(),
+            lines: vec![],
+            generation: None,
+        }
+    }
+}
+
+impl ConsolePanel {
+    pub fn show(&mut self, ctx: &Context) {
+        if !self.open {
+            return;
+        }
+        let generation = console::generation();
+        if self.generation != Some(generation) {
+            self.lines = console::lines();
+            self.generation = Some(generation);
+        }
+        ctx.request_repaint_after(Duration::from_millis(250)); // Keep polling for new lines
+        egui::TopBottomPanel::new(TopBottomSide::Bottom, egui::Id::new("console"))
+            .resizable(true)
+            .default_height(200.0)
+            .show(ctx, |ui| {
+                self.ui_toolbar(ui);
+                ui.separator();
+                let filter = self.filter.to_lowercase();
+                let visible = self.lines.iter().filter(|line| {
+                    let stream_visible = match line.stream {
+                        ConsoleStream::Stdout | ConsoleStream::Stderr => self.show_output,
+                        ConsoleStream::Log(_) => self.show_logs,
+                    };
+                    stream_visible && !self.hidden_sources.contains(&line.source) &&
+                        (filter.is_empty() || line.text.to_lowercase().contains(&filter))
+                }).collect::<Vec<_>>();
+                let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                ScrollArea::both()
+                    .auto_shrink([false, false])
+                    .stick_to_bottom(true)
+                    .show_rows(ui, row_height, visible.len(), |ui, rows| {
+                        for line in &visible[rows] {
+                            ui.add(Label::new(Self::line_text(ui, line)).extend());
+                        }
+                    });
+            });
+    }
+
+    fn ui_toolbar(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Console");
+            ui.add(TextEdit::singleline(&mut self.filter).hint_text("🔍 Filter").desired_width(160.0));
+            ui.toggle_value(&mut self.show_output, "SDF output")
+                .on_hover_text("Show what the SDF modules print to their stdout and stderr");
+            ui.toggle_value(&mut self.show_logs, "Viewer logs")
+                .on_hover_text("Show the logs of the viewer");
+            ui.menu_button("Sources", |ui| {
+                let sources = self.lines.iter().map(|line| &line.source).collect::<BTreeSet<_>>();
+                if sources.is_empty() {
+                    ui.weak("Nothing printed yet");
+                }
+                for source in sources {
+                    let mut visible = !self.hidden_sources.contains(source);
+                    if ui.checkbox(&mut visible, source.as_str()).changed() {
+                        if visible {
+                            self.hidden_sources.remove(source);
+                        } else {
+                            self.hidden_sources.insert(source.clone());
+                        }
+                    }
+                }
+            });
+            if ui.button("🗑 Clear").clicked() {
+                console::clear();
+            }
+        });
+    }
+
+    /// Formats the line with its prefix, highlighting errors and warnings.
+    fn line_text(ui: &Ui, line: &ConsoleLine) -> RichText {
+        let visuals = ui.visuals();
+        let (prefix, color) = match line.stream {
+            ConsoleStream::Stdout => (format!("[{}]", line.source), visuals.text_color()),
+            ConsoleStream::Stderr => (format!("[{}]", line.source), visuals.error_fg_color),
+            ConsoleStream::Log(level) => {
+                let color = match level {
+                    tracing::Level::ERROR => visuals.error_fg_color,
+                    tracing::Level::WARN => visuals.warn_fg_color,
+                    tracing::Level::INFO => visuals.text_color(),
+                    _ => visuals.weak_text_color(),
+                };
+                (format!("{} {}", level, line.source), color)
+            }
+        };
+        RichText::new(format!("{} {}", prefix, line.text)).monospace().color(color)
+    }
+}
@@ -0,0 +1,41 @@
+use eframe::egui::{TextEdit, Ui};
+
+use crate::sdf::expr::{ExprError, ExprSDF};
+
+/// The editor of the source of an expression SDF (see [`crate::sdf::expr`]), which re-evaluates it
+/// on every change and displays the errors next to the source.
+pub struct ExprEditor {
+    /// The displayed SDF, which shares its state with this clone.
+    sdf: ExprSDF,
+    /// The source being edited, which may not be valid.
+    text: String,
+    /// Why the edited source is not valid, if it is not (the SDF keeps the last valid version).
+    error: Option<ExprError>,
+}
+
+impl ExprEditor {
+    pub fn new(sdf: ExprSDF, text: String, error: Option<ExprError>) -> Self {
+        Self { sdf, text, error }
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        if self.error.is_none() {
+            // Keep up with the parameters modified from the hierarchy, which rewrite the source
+            let source = self.sdf.source();
+            if source != self.text {
+                self.text = source;
+            }
+        }
+        let response = ui.add(TextEdit::multiline(&mut self.text)
+            .code_editor()
+            .desired_rows(6)
+            .desired_width(f32::INFINITY));
+        if response.changed() {
+            self.error = self.sdf.set_source(&self.text).err();
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, format!("⚠ {error}"))
+                .on_hover_text("The last valid version of the expression is still displayed");
+        }
+    }
+}
@@ -12,16 +12,21 @@ use tokio::sync::Mutex;
 use tracing::{error, info, warn};
 
 use cli::settings::SettingsWindow;
+use console::ConsolePanel;
+use expr::ExprEditor;
 use scene::SDFViewerAppScene;
 
 use crate::app::cli::CliApp;
 use crate::app::frameinput::FrameInput;
 use crate::cli::env_get;
 use crate::sdf::demo::cube::SDFDemoCube;
-use crate::sdf::wasm::load::spawn_async;
+use crate::sdf::error::SDFError;
+use crate::sdf::wasm::load::{spawn_async, SDFLoadResult};
 use crate::sdf::SDFSurface;
 
 pub mod cli;
+mod console;
+mod expr;
 mod frameinput;
 pub mod scene;
 
@@ -49,12 +54,25 @@ pub struct SDFViewerApp {
     /// the window.
     #[cfg(feature = "meshers")]
     pub mesher_result: Arc<Option<Mutex<Option<String>>>>,
+    /// The progress of the running mesher, displayed in the bottom panel with a button to cancel it.
+    #[cfg(feature = "meshers")]
+    pub mesher_progress: Option<crate::sdf::meshers::progress::MeshingProgress>,
     // ===== LOADING =====
     /// The currently loading SDF surface, that will replace the current [`sdf`] when ready.
     /// It will be polled on update.
-    pub sdf_loading: Option<Receiver<Box<(dyn SDFSurface + Send + Sync)>>>,
+    /// If it fails, the current [`sdf`] is kept and the error is displayed.
+    pub sdf_loading: Option<Receiver<SDFLoadResult>>,
     /// The SDF loading manager: receives values to replace sdf_loading whenever an SDF update is detected.
-    pub sdf_loading_mgr: Option<Receiver<Receiver<Box<(dyn SDFSurface + Send + Sync)>>>>,
+    pub sdf_loading_mgr: Option<Receiver<Receiver<SDFLoadResult>>>,
+    /// The console with the output of the SDF and our logs.
+    pub console: ConsolePanel,
+    /// The editor of the expression, if the SDF is defined by one (see [`cli::CliSDFProvider::Expr`]).
+    pub expr_editor: Option<ExprEditor>,
+    // ===== ERRORS =====
+    /// The errors of loading or querying the SDF, displayed until the user dismisses them.
+    pub sdf_errors: Vec<SDFError>,
+    /// The fault of the root SDF that the user already closed, so that it is not displayed again.
+    pub dismissed_sdf_fault: Option<SDFError>,
 }
 
 impl SDFViewerApp {
@@ -73,6 +91,10 @@ impl SDFViewerApp {
             sdf: Rc::new(Box::<SDFDemoCube>::default()),
             sdf_loading: None,
             sdf_loading_mgr: None,
+            console: ConsolePanel::default(),
+            expr_editor: None,
+            sdf_errors: vec![],
+            dismissed_sdf_fault: None,
             selected_params_sdf: None,
             app_settings: SettingsWindow::Configured { settings: cli_args },
             #[cfg(feature = "server")]
@@ -81,6 +103,8 @@ impl SDFViewerApp {
             mesher_settings: SettingsWindow::Configured { settings: crate::sdf::meshers::CliMesher::default() },
             #[cfg(feature = "meshers")]
             mesher_result: Arc::new(None),
+            #[cfg(feature = "meshers")]
+            mesher_progress: None,
         };
 
         // In order to configure the 3D scene after initialization, we need to create a new scene now.
@@ -103,6 +127,7 @@ impl SDFViewerApp {
         loading_passes: Option<usize>,  // None means keep the current value
     ) {
         self.sdf = Rc::new(sdf); // Reference counted ownership as we need to share it with the scene renderer.
+        self.dismissed_sdf_fault = None;
         Self::scene_mut(|scene| {
             scene.set_sdf(Rc::clone(&self.sdf), max_voxels_side, loading_passes)
         });
@@ -110,13 +135,13 @@ impl SDFViewerApp {
 
     /// Updates the root SDF using a promise that will be polled on update.
     /// When the promise is ready, [`set_root_sdf`](#method.set_root_sdf) will be called internally automatically.
-    pub fn set_root_sdf_loading(&mut self, promise: Receiver<Box<(dyn SDFSurface + Send + Sync)>>) {
+    pub fn set_root_sdf_loading(&mut self, promise: Receiver<SDFLoadResult>) {
         self.sdf_loading = Some(promise);
     }
 
     /// Automatically queues updates for the root SDF using a promise that returns a promise of the updated SDF.
     /// When the promise is ready, [`set_root_sdf`](#method.set_root_sdf) will be called internally automatically.
-    pub fn set_root_sdf_loading_manager(&mut self, promise: Receiver<Receiver<Box<(dyn SDFSurface + Send + Sync)>>>) {
+    pub fn set_root_sdf_loading_manager(&mut self, promise: Receiver<Receiver<SDFLoadResult>>) {
         self.sdf_loading_mgr = Some(promise);
     }
 
@@ -143,7 +168,7 @@ impl SDFViewerApp {
         // Poll the SDF loading promise if it is set
         self.sdf_loading = if let Some(mut receiver) = self.sdf_loading.take() {
             match receiver.try_recv() {
-                Ok(new_root_sdf) => {
+                Ok(Ok(new_root_sdf)) => {
                     let cur_settings = self.app_settings.current();
                     self.set_root_sdf(
                         new_root_sdf,
@@ -152,6 +177,11 @@ impl SDFViewerApp {
                     );
                     None // Disconnect after the first update (more updates should generate another receiver to display progress)
                 }
+                Ok(Err(err)) => {
+                    error!("Failed to load the SDF, keeping the previous one: {}", err);
+                    self.push_sdf_error(err);
+                    None
+                }
                 Err(TryRecvError::Empty) => {
                     ctx.request_repaint(); // Request a repaint to keep polling the promise.
                     // TODO: Less frequent repaints?
@@ -165,6 +195,15 @@ impl SDFViewerApp {
         } else { None };
     }
 
+    /// Records an error to be displayed, forgetting the oldest ones if there are too many.
+    fn push_sdf_error(&mut self, err: SDFError) {
+        const MAX_DISPLAYED_ERRORS: usize = 16;
+        if self.sdf_errors.len() >= MAX_DISPLAYED_ERRORS {
+            self.sdf_errors.remove(0);
+        }
+        self.sdf_errors.push(err);
+    }
+
     pub fn scene_mut<R>(
         f: impl FnOnce(&mut SDFViewerAppScene) -> R,
     ) -> Option<R> {
@@ -259,6 +298,8 @@ impl SDFViewerApp {
                         ui.add_enabled_ui(false, |ui| ui.menu_button("🌐 Server (not enabled)", |_| {}));
                         #[cfg(not(feature = "meshers"))]
                         ui.add_enabled_ui(false, |ui| ui.menu_button("💾 Mesher (not enabled)", |_| {}));
+                        ui.toggle_value(&mut self.console.open, "🖵 Console")
+                            .on_hover_text("Show the output of the SDF and the logs of the viewer");
                         // Add an spacer to right-align some options
                         ui.allocate_space(Vec2::new(ui.available_width() - 26.0, 1.0));
                         egui::widgets::global_theme_preference_switch(ui);
@@ -315,6 +356,13 @@ impl SDFViewerApp {
                                 });
                         });
                 }
+                // The editor of the expression that defines the SDF, if any
+                if let Some(expr_editor) = self.expr_editor.as_mut() {
+                    egui::CollapsingHeader::new("✏ Expression")
+                        .default_open(true)
+                        .show(ui, |ui| expr_editor.ui(ui));
+                    ui.separator();
+                }
                 // The main SDF hierarchy with action buttons
                 ui.horizontal_wrapped(|ui| {
                     ui.heading("Hierarchy");
@@ -323,6 +371,10 @@ impl SDFViewerApp {
                             "Currently downloading/compiling a new version of the SDF code");
                     }
                 });
+                if let Some(metadata) = self.sdf.metadata() {
+                    ui.weak(format!("📦 {} v{}", metadata.crate_name, metadata.crate_version))
+                        .on_hover_text("The crate that exported the loaded SDF");
+                }
                 ScrollArea::both()
                     .auto_shrink([false, false])
                     .show(ui, |ui| {
@@ -333,15 +385,27 @@ impl SDFViewerApp {
     }
 
     fn ui_bottom_panel(&mut self, ctx: &Context) {
+        #[cfg(feature = "meshers")]
+        let mut cancel_mesher = false;
         // Bottom panel, containing the progress bar if applicable.
         egui::TopBottomPanel::new(TopBottomSide::Bottom, egui::Id::new("bottom"))
             .frame(Frame::default().inner_margin(0.0))
             .min_height(0.0) // Hide when unused
             .show(ctx, |ui| {
                 if let Some((progress, text)) = self.progress.as_ref() {
-                    ui.add(ProgressBar::new(*progress).text(text.clone()).animate(true));
+                    ui.horizontal(|ui| {
+                        #[cfg(feature = "meshers")]
+                        if self.mesher_progress.is_some() && ui.button("❌ Cancel").on_hover_text("Stop exporting the model").clicked() {
+                            cancel_mesher = true;
+                        }
+                        ui.add(ProgressBar::new(*progress).text(text.clone()).animate(true));
+                    });
                 }
             });
+        #[cfg(feature = "meshers")]
+        if cancel_mesher {
+            self.cancel_mesher();
+        }
     }
 
     fn ui_central_panel(&mut self, ctx: &Context) {
@@ -359,8 +423,12 @@ impl SDFViewerApp {
     #[cfg(feature = "meshers")]
     fn run_mesher(&mut self, mut mesher: crate::sdf::meshers::CliMesher) {
         // Overwrite the input to our input
-        if let Some(inp) = self.app_settings.previous().sdf_provider.url() {
+        let sdf_provider = &self.app_settings.previous().sdf_provider;
+        if let Some(inp) = sdf_provider.url() {
             mesher.input = inp.to_string();
+            if let cli::CliSDFProvider::Url(watch) = sdf_provider { // Same environment as the displayed SDF
+                mesher.wasi = watch.wasi.clone();
+            }
         } else {
             error!("Can't find URL for SDF to render (don't use non-wasm demo source)");
             return;
@@ -368,19 +436,25 @@ impl SDFViewerApp {
         // For notifying that we are running...
         self.mesher_result = Arc::new(Some(Mutex::new(None)));
         let mesher_result_ref = Arc::clone(&self.mesher_result);
+        let progress = crate::sdf::meshers::progress::MeshingProgress::default();
+        self.mesher_progress = Some(progress.clone());
         // Run in a new thread/async block to avoid blocking the UI while exporting
         spawn_async(async move {
             let mut in_memory_model = vec![];
+            let format = mesher.format();
             let output_file_clone = mesher.output_file.to_str().unwrap_or("").to_string();
             if output_file_clone.is_empty() || output_file_clone.eq("-") {
-                if let Err(err) = mesher.run_custom_out(&mut in_memory_model).await {
+                if let Err(err) = mesher.run_custom_out(&mut in_memory_model, &progress).await {
                     let msg = format!("Failed to export model: {err}");
                     error!("{}", msg);
                     in_memory_model = msg.into_bytes();
+                } else if format.is_binary() {
+                    in_memory_model = format!("Exported a binary {:?} model of {} bytes, which can't be displayed: \
+                        set an output file to save it.", format, in_memory_model.len()).into_bytes();
                 }
             } else {
                 #[cfg(not(target_arch = "wasm32"))]
-                if let Err(err) = mesher.run_cli().await {
+                if let Err(err) = mesher.run_file_out(&progress).await {
                     let msg = format!("Failed to export model: {err}");
                     error!("{}", msg);
                     in_memory_model = msg.into_bytes();
@@ -388,20 +462,28 @@ impl SDFViewerApp {
                     in_memory_model = "Done!".to_string().into_bytes();
                 }
                 #[cfg(target_arch = "wasm32")]
-                if let Err(err) = mesher.run_custom_out(&mut in_memory_model).await {
-                    let msg = format!("Failed to export model: {}", err);
-                    error!("{}", msg);
-                    in_memory_model = msg.into_bytes();
-                } else {
-                    // Saving to a file on wasm32 is a special case, which buffers the data and then forces a download
-                    if let Err(err) = js_sys::eval(js_download_file_code(
-                        &output_file_clone, in_memory_model.as_bytes()).as_str()) {
-                        error!("Failed to export model using JS code: {:?}", err);
+                {
+                    let mtl_file = mesher.output_file.with_extension("mtl").to_string_lossy().to_string();
+                    let mut in_memory_mtl = vec![];
+                    let mtl = (format == crate::sdf::meshers::export::MeshFormat::Obj).then_some(&mut in_memory_mtl);
+                    if let Err(err) = mesher.run_custom_out_with_materials(&mut in_memory_model, mtl, &progress).await {
+                        let msg = format!("Failed to export model: {}", err);
+                        error!("{}", msg);
+                        in_memory_model = msg.into_bytes();
+                    } else {
+                        // Saving to a file on wasm32 is a special case, which buffers the data and then forces a download
+                        let downloads = std::iter::once((&output_file_clone, &in_memory_model))
+                            .chain((!in_memory_mtl.is_empty()).then_some((&mtl_file, &in_memory_mtl)));
+                        for (name, contents) in downloads {
+                            if let Err(err) = js_sys::eval(js_download_file_code(name, contents.as_bytes()).as_str()) {
+                                error!("Failed to export model using JS code: {:?}", err);
+                            }
+                        }
+                        in_memory_model = "Done!".to_string().into_bytes();
                     }
-                    in_memory_model = "Done!".to_string().into_bytes();
                 }
             }
-            // PLY is valid ASCII text! Future model formats may not keep this property (use file outputs for them)
+            // Only text formats are shown, binary formats are replaced by a message above
             let in_memory_model_text = String::from_utf8_lossy(in_memory_model.as_bytes());
             // Notify the user that the export is finished, and show a dialog with the model if requested.
             let mut guard = Option::as_ref(&mesher_result_ref).unwrap().lock().await;
@@ -409,13 +491,113 @@ impl SDFViewerApp {
         }, false);
     }
 
+    /// Stops the running mesher and forgets about it (its result will be ignored).
+    #[cfg(feature = "meshers")]
+    fn cancel_mesher(&mut self) {
+        if let Some(progress) = self.mesher_progress.take() {
+            info!("Cancelling the export of the model");
+            progress.cancel();
+        }
+        self.mesher_result = Arc::new(None);
+        self.progress = None;
+    }
+
+    fn ui_custom_glsl_error_window(&mut self, ctx: &Context) {
+        let error = Self::scene_mut(|scene| scene.custom_glsl_error.clone()).flatten();
+        if let Some(error) = error {
+            let mut open = true;
+            egui::Window::new("Custom GLSL error")
+                .open(&mut open)
+                .resizable(true)
+                .scroll([true, true])
+                .show(ctx, |ui| {
+                    ui.label("The custom GLSL code of the SDF failed to compile, using the default shader:");
+                    ui.code(error);
+                });
+            if !open { // Just closed the window, forget the error
+                Self::scene_mut(|scene| scene.custom_glsl_error = None);
+            }
+        }
+    }
+
+    fn ui_sdf_fault_window(&mut self, ctx: &Context) {
+        let fault = self.sdf.fault().filter(|fault| Some(fault) != self.dismissed_sdf_fault.as_ref());
+        if let Some(fault) = fault {
+            let mut open = true;
+            egui::Window::new("SDF stopped working")
+                .open(&mut open)
+                .resizable(true)
+                .show(ctx, |ui| {
+                    ui.label("The SDF was stopped and will no longer be updated. Fix it and load it again:");
+                    Self::ui_sdf_error(ui, &fault);
+                });
+            if !open { // Just closed the window, don't show this fault again
+                self.dismissed_sdf_fault = Some(fault);
+            }
+        }
+    }
+
+    fn ui_sdf_errors_window(&mut self, ctx: &Context) {
+        for err in self.sdf.take_errors() {
+            self.push_sdf_error(err);
+        }
+        if self.sdf_errors.is_empty() {
+            return;
+        }
+        let mut open = true;
+        let mut dismissed = None;
+        egui::Window::new(format!("SDF errors ({})", self.sdf_errors.len()))
+            .open(&mut open)
+            .resizable(true)
+            .scroll([false, true])
+            .show(ctx, |ui| {
+                for (i, err) in self.sdf_errors.iter().enumerate() {
+                    ui.push_id(i, |ui| ui.horizontal(|ui| {
+                        if ui.small_button("✖").on_hover_text("Dismiss this error").clicked() {
+                            dismissed = Some(i);
+                        }
+                        ui.vertical(|ui| Self::ui_sdf_error(ui, err));
+                    }));
+                    ui.separator();
+                }
+            });
+        if !open { // Just closed the window, dismiss all errors
+            self.sdf_errors.clear();
+        } else if let Some(i) = dismissed {
+            self.sdf_errors.remove(i);
+        }
+    }
+
+    /// Displays the error with all of its context.
+    fn ui_sdf_error(ui: &mut Ui, err: &SDFError) {
+        ui.code(&err.message);
+        if let Some(export) = &err.export {
+            match err.sdf_id {
+                Some(sdf_id) => ui.weak(format!("In `{export}` of the SDF with ID {sdf_id}")),
+                None => ui.weak(format!("In `{export}`")),
+            };
+        }
+        if let Some(url) = &err.url {
+            ui.weak(format!("While loading {url}"));
+        }
+        if !err.backtrace.is_empty() {
+            ui.collapsing(format!("Backtrace ({} frames)", err.backtrace.len()), |ui| {
+                ui.code(err.backtrace.join("\n"));
+            });
+        }
+    }
+
     pub fn ui_exported_model_window(&mut self, ctx: &Context) {
         // Optimization to ignore mutex normally!
         let forget = if let Some(res) = self.mesher_result.as_ref() {
             // Progress reporting assumes no other concurrent processes are running (will override them)
-            self.progress = Some((0.0, "Exporting model...".to_string()));
+            self.progress = Some(match &self.mesher_progress {
+                Some(progress) => (progress.fraction().unwrap_or(0.0), format!("Exporting model: {}", progress.status())),
+                None => (0.0, "Exporting model...".to_string()),
+            });
             if let Ok(mut guard) = res.try_lock() { // Try_lock should be safe here (called every frame)
                 if let Some(model) = guard.as_mut() {
+                    self.mesher_progress = None;
                     self.progress = Some((1.0, "Exported model!".to_string()));
                     let mut open = true;
                     egui::Window::new("Exported model")
@@ -449,8 +631,12 @@ impl eframe::App for SDFViewerApp {
         self.ui_menu_bar(ctx);
         self.ui_settings_windows(ctx);
         self.ui_exported_model_window(ctx);
+        self.ui_custom_glsl_error_window(ctx);
+        self.ui_sdf_fault_window(ctx);
+        self.ui_sdf_errors_window(ctx);
         self.ui_left_panel(ctx);
         self.ui_bottom_panel(ctx);
+        self.console.show(ctx);
         self.ui_central_panel(ctx);
         // ctx.request_repaint(); // Uncomment to always render at maximum framerate instead of lazy renders
     }
@@ -8,7 +8,7 @@ use eframe::egui::Response;
 use eframe::glow;
 use instant::Instant;
 use three_d::*;
-use tracing::info;
+use tracing::{info, warn};
 
 use camera::CameraController;
 
@@ -42,6 +42,11 @@ pub struct SDFViewerAppScene {
     pub sdf_viewer: SDFViewer,
     /// When we last updated the GPU texture of the SDF
     pub sdf_viewer_last_commit: Option<Instant>,
+    /// The errors produced while compiling the custom GLSL code of the SDF, to be displayed to the user.
+    pub custom_glsl_error: Option<String>,
+    /// The custom GLSL code of the SDF that was last applied (even if it failed to compile), to only
+    /// recompile the shader when the SDF reports new code.
+    pub custom_glsl: Option<String>,
     // === CAMERA ===
     /// The 3D perspective camera
     pub camera: CameraController,
@@ -124,15 +129,19 @@ impl SDFViewerAppScene {
         // let tmp_object = Gm::new(tmp_mesh, tmp_material);
         // objects.push(Box::new(tmp_object));
 
-        Self {
+        let mut slf = Self {
             ctx,
             camera: CameraController::new(camera),
             sdf,
             sdf_viewer,
             sdf_viewer_last_commit: None,
+            custom_glsl_error: None,
+            custom_glsl: None,
             lights,
             objects,
-        }
+        };
+        slf.apply_custom_glsl();
+        slf
     }
 
     /// Updates the SDF to render (and clears all required caches).
@@ -153,6 +162,26 @@ impl SDFViewerAppScene {
         let loading_passes_val = loading_passes.unwrap_or(self.sdf_viewer.loading_mgr.passes);
         self.sdf_viewer =
             SDFViewer::from_bb(&self.ctx, &bb, max_voxels_side_val, loading_passes_val);
+        self.custom_glsl = None; // The new viewer starts with the default shader
+        self.apply_custom_glsl();
+    }
+
+    /// Configures the custom GLSL code of the current SDF, falling back to the default shader on errors.
+    /// It is queried again whenever the SDF reports a change, but only recompiled if the code changed.
+    fn apply_custom_glsl(&mut self) {
+        let custom_glsl = self.sdf.glsl();
+        if self.custom_glsl.as_ref() == Some(&custom_glsl) {
+            return;
+        }
+        self.custom_glsl = Some(custom_glsl.clone());
+        let lights_vec = self.lights.iter().map(|e| &**e).collect::<Vec<_>>();
+        self.custom_glsl_error = match self.sdf_viewer.set_custom_glsl(custom_glsl, &lights_vec) {
+            Ok(()) => None,
+            Err(err) => {
+                warn!("Failed to compile the custom GLSL code of the SDF, using the default shader: {}", err);
+                Some(err)
+            }
+        };
     }
 
     pub fn render(
@@ -166,6 +195,9 @@ impl SDFViewerAppScene {
         // Load more of the SDF to the GPU in real time (if needed)
         let load_start_cpu = Instant::now();
         let cpu_updates = self.sdf_viewer.update(&self.sdf, Duration::from_millis(30));
+        if self.sdf_viewer.take_sdf_changed() {
+            self.apply_custom_glsl();
+        }
         if cpu_updates > 0 {
             // Update the GPU texture sparingly (to mitigate stuttering on high-detail rendering loads)
             if self
@@ -69,14 +69,26 @@ vec3 sdfSampleTex1MetallicRoughnessOcclusion(vec4 raw) {
     return raw.rgb;
 }
 
+// The custom GLSL code provided by the SDF (if any) is inserted here, see SDFSurface::glsl.
+// {{SDF_CUSTOM_GLSL}}
+
+// Evaluate the distance to the surface at the given position (from the texture or the custom code).
+float sdfDist(vec3 p) {
+#ifdef SDF_CUSTOM_DISTANCE
+    return sdfCustomDistance(p);
+#else
+    return sdfSampleTex0Dist(sdfSampleRawInterp(0, p));
+#endif
+}
+
 /// Approximate the SDF's normal at the given position. From https://iquilezles.org/articles/normalsSDF/.
 vec3 sdfNormal(vec3 p) {
     float h = 1./length(sdfTexSize / sdfLODDistBetweenSamples);
     const vec2 k = vec2(1, -1);
-    return normalize(k.xyy*sdfSampleTex0Dist(sdfSampleRawInterp(0, p + k.xyy*h)) +
-    k.yyx*sdfSampleTex0Dist(sdfSampleRawInterp(0, p + k.yyx*h)) +
-    k.yxy*sdfSampleTex0Dist(sdfSampleRawInterp(0, p + k.yxy*h)) +
-    k.xxx*sdfSampleTex0Dist(sdfSampleRawInterp(0, p + k.xxx*h)));
+    return normalize(k.xyy*sdfDist(p + k.xyy*h) +
+    k.yyx*sdfDist(p + k.yyx*h) +
+    k.yxy*sdfDist(p + k.yxy*h) +
+    k.xxx*sdfDist(p + k.xxx*h));
 }
 
 // How far away (underestimate) from the bounding box the position is.
@@ -110,7 +122,11 @@ vec4[2] sdfRaycast(vec3 rayPos, vec3 rayDir, int maxSteps) {
 
         // The SDF is evaluated at the current position in the ray.
         vec4 sampleTex0Raw = sdfSampleRawInterp(0, rayPos);
+#ifdef SDF_CUSTOM_DISTANCE
+        float sampleDist = sdfCustomDistance(rayPos);
+#else
         float sampleDist = sdfSampleTex0Dist(sampleTex0Raw);
+#endif
 
         // Stop condition: actually hit the surface.
         // NOTE: floating point precision mitigation: use a small epsilon to avoid hitting the surface exactly.
@@ -160,7 +176,11 @@ void main() {
     vec3 sampleProps = sdfSampleTex1MetallicRoughnessOcclusion(sampleTex1Raw);
 
     // Compute the color using the lighting model.
+#ifdef SDF_CUSTOM_SHADING
+    outColor.rgb = sdfCustomShading(cameraPosition, hitPos, normal, sampleColor, sampleProps.x, sampleProps.y, sampleProps.z);
+#else
     outColor.rgb = calculate_lighting(cameraPosition, sampleColor, hitPos, normal, sampleProps.x, sampleProps.y, sampleProps.z);
+#endif
     //outColor.rgb = sampleColor;
 
     // Apply tone mapping, color mapping and transparency.
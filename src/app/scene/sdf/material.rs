@@ -1,3 +1,6 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
 use crate::cli::env_get;
 use cgmath::{vec3, Vector3};
 use three_d::core::Program;
@@ -17,6 +20,9 @@ pub struct SDFViewerMaterial {
     pub lod_dist_between_samples: f32,
     /// Base surface color (tint). Assumed to be in linear color space.
     pub color: Srgba,
+    /// The custom GLSL code provided by the SDF (see `SDFSurface::glsl`). Empty to use the default shader.
+    /// It must be validated with [`SDFViewerMaterial::validate_custom_glsl`] before being set.
+    pub custom_glsl: String,
 }
 
 impl SDFViewerMaterial {
@@ -27,24 +33,50 @@ impl SDFViewerMaterial {
             voxels_bounds,
             lod_dist_between_samples: 1f32,
             color: Srgba::WHITE,
+            custom_glsl: String::new(),
         }
     }
+
+    /// Compiles the fragment shader that would be generated with the given custom GLSL code, returning
+    /// the compilation errors (if any). This avoids crashing while rendering if the code is invalid.
+    /// The program is built by three-d itself, so that the same header (desktop or WebGL2) is used.
+    pub fn validate_custom_glsl(ctx: &three_d::Context, custom_glsl: &str, lights: &[&dyn Light]) -> Result<(), String> {
+        Program::from_source(ctx, VALIDATION_VERTEX_SHADER, &fragment_shader_source(custom_glsl, lights))
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// A minimal vertex shader that provides the inputs of `material.frag`, only used for validation.
+const VALIDATION_VERTEX_SHADER: &str = "in vec3 position;\nout vec3 pos;\n\
+    void main() { pos = position; gl_Position = vec4(position, 1.0); }\n";
+
+/// Builds the fragment shader source, including the given custom GLSL code.
+fn fragment_shader_source(custom_glsl: &str, lights: &[&dyn Light]) -> String {
+    let mut output = lights_shader_source(lights);
+    output.push_str(ToneMapping::fragment_shader_source());
+    output.push_str(ColorMapping::fragment_shader_source());
+    if let Some(gamma) = env_get("gamma") {
+        output.push_str(&format!("#define GAMMA_CORRECTION {}\n", gamma));
+    }
+    output.push_str(&include_str!("material.frag").replace("// {{SDF_CUSTOM_GLSL}}", custom_glsl));
+    output
 }
 
 impl Material for SDFViewerMaterial {
     fn fragment_shader_source(&self, lights: &[&dyn Light]) -> String {
-        let mut output = lights_shader_source(lights);
-        output.push_str(ToneMapping::fragment_shader_source());
-        output.push_str(ColorMapping::fragment_shader_source());
-        if let Some(gamma) = env_get("gamma") {
-            output.push_str(&format!("#define GAMMA_CORRECTION {}\n", gamma));
-        }
-        output.push_str(include_str!("material.frag"));
-        output
+        fragment_shader_source(&self.custom_glsl, lights)
     }
 
     fn id(&self) -> EffectMaterialId {
-        EffectMaterialId(0)
+        if self.custom_glsl.is_empty() {
+            EffectMaterialId(0)
+        } else {
+            // Each custom shader must have a different ID, as compiled programs are cached by ID.
+            let mut hasher = DefaultHasher::new();
+            self.custom_glsl.hash(&mut hasher);
+            EffectMaterialId(1 + (hasher.finish() % 0x7FFE) as u16)
+        }
     }
 
     fn use_uniforms(
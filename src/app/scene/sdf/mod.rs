@@ -5,7 +5,7 @@ use std::rc::Rc;
 use cgmath::ElementWise;
 use cgmath::num_traits::Pow;
 use eframe::glow::HasContext;
-use three_d::{context, CpuMesh, CpuTexture3D, Gm, Mesh, Srgba, Texture3D, Vector3};
+use three_d::{context, CpuMesh, CpuTexture3D, Gm, Light, Mesh, Srgba, Texture3D, Vector3};
 use three_d::{Interpolation, Positions, TextureData, Wrapping};
 
 use material::SDFViewerMaterial;
@@ -33,6 +33,9 @@ pub struct SDFViewer {
     pub changed_box: Option<[Vector3<f32>; 2]>,
     /// If this is true, another `loading_mgr` pass should be queued after this one
     pub changed_box_while_loading: bool,
+    /// Whether the SDF reported a change since the last [`SDFViewer::take_sdf_changed`] call.
+    /// Changes (like new parameter values) may also change its custom GLSL code.
+    pub sdf_changed: bool,
     /// The three-d cloned context
     pub ctx: three_d::Context,
 }
@@ -41,6 +44,10 @@ pub struct SDFViewer {
 /// artifacts, but will slow down rendering if too small. It is also useful for progressive loading.
 const AIR_DIST: f32 = 1e-1 + 0.001234;
 
+/// The maximum number of voxels to sample in a single [`SDFSurface::sample_batch`] call while loading.
+/// Larger batches reduce the per-call overhead, but make it harder to keep an interactive framerate.
+const SAMPLE_BATCH_SIZE: usize = 256;
+
 impl SDFViewer {
     /// Creates a new SDF viewer for the given bounding box (tries to keep aspect ratio).
     pub fn from_bb(ctx: &three_d::Context, bb: &[Vector3<f32>; 2], max_voxels_side: usize, loading_passes: usize) -> Self {
@@ -96,6 +103,7 @@ impl SDFViewer {
             bounding_box: *bb,
             changed_box: None,
             changed_box_while_loading: false,
+            sdf_changed: false,
             ctx: ctx.clone(),
         }
     }
@@ -136,6 +144,7 @@ impl SDFViewer {
             });
             self.changed_box_while_loading = self.loading_mgr.len() > 0 || self.changed_box_while_loading;
             just_changed_box = true;
+            self.sdf_changed = true;
         }
 
         // If we still have changes to process and the loading manager is not busy, perform another
@@ -170,9 +179,22 @@ impl SDFViewer {
         let start_time = instant::Instant::now();
 
         // Start sampling the SDF on the CPU to prepare the data for the GPU, as long as there is time.
-        while first || start_time.elapsed() < max_delta_time {
+        let mut batch_flat_indices = Vec::with_capacity(SAMPLE_BATCH_SIZE);
+        let mut batch_positions = Vec::with_capacity(SAMPLE_BATCH_SIZE);
+        let mut finished = false;
+        while !finished && (first || start_time.elapsed() < max_delta_time) {
             first = false; // TODO: Cross-platform parallel iteration?
-            if let Some(index) = self.loading_mgr.next() {
+            // Collect a batch of voxels that need to be updated.
+            batch_flat_indices.clear();
+            batch_positions.clear();
+            while batch_positions.len() < SAMPLE_BATCH_SIZE {
+                let index = match self.loading_mgr.next() {
+                    Some(index) => index,
+                    None => {
+                        finished = true; // No more work to do!
+                        break;
+                    }
+                };
                 // Compute the flat index: 3D texture data is in the row-major order.
                 let flat_index = (index.z * self.tex0.height as usize + index.y) * self.tex0.width as usize + index.x;
                 // Compute the position in the SDF surface.
@@ -189,33 +211,53 @@ impl SDFViewer {
                             pos.z >= changed_box[0].z && pos.z <= changed_box[1].z;
                 }
                 if update_required { // Only update if not already computed
-                    // Actually sample the SDF.
-                    let mut sample = sdf.sample(pos, false);
-                    // Apply a function to store the distance (-inf, inf) in the texture [0, 1].
-                    // KEEP IN SYNC WITH GPU CODE!
-                    tex0_data_ref[flat_index][0] = (1e-1 + sample.distance).clamp(0.0, 1.0);
-                    if sample.color.x == 0. && sample.color.y == 0. && sample.color.z == 0. {
-                        // Avoid invisible objects if left as default with dark environment
-                        sample.color = Vector3::new(0.5, 0.5, 0.5);
-                    }
-                    let color_raw = Srgba::from(sample.color).to_linear_srgb();
-                    tex0_data_ref[flat_index][1] = color_raw.x;
-                    tex0_data_ref[flat_index][2] = color_raw.y;
-                    tex0_data_ref[flat_index][3] = color_raw.z;
-                    tex1_data_ref[flat_index][0] = sample.metallic;
-                    tex1_data_ref[flat_index][1] = sample.roughness;
-                    // NOTE: Default occlusion is 1, to use the ambient light by default
-                    tex1_data_ref[flat_index][2] = if sample.occlusion <= 0.0 { 1.0 } else { sample.occlusion };
-                    // info!("Updated voxel color {:?}", data[flat_index][1]);
-                    // TODO: Provide more voxel data to the shader, like a material kind index for using custom GLSL code.
+                    batch_flat_indices.push(flat_index);
+                    batch_positions.push(pos);
+                }
+            }
+            if batch_positions.is_empty() {
+                continue;
+            }
+            // Actually sample the SDF (all the batch at once, to reduce the overhead of each call).
+            let samples = sdf.sample_batch(&batch_positions, false);
+            for (&flat_index, mut sample) in batch_flat_indices.iter().zip(samples) {
+                // Apply a function to store the distance (-inf, inf) in the texture [0, 1].
+                // KEEP IN SYNC WITH GPU CODE!
+                tex0_data_ref[flat_index][0] = (1e-1 + sample.distance).clamp(0.0, 1.0);
+                if sample.color.x == 0. && sample.color.y == 0. && sample.color.z == 0. {
+                    // Avoid invisible objects if left as default with dark environment
+                    sample.color = Vector3::new(0.5, 0.5, 0.5);
                 }
-            } else {
-                break; // No more work to do!
+                let color_raw = Srgba::from(sample.color).to_linear_srgb();
+                tex0_data_ref[flat_index][1] = color_raw.x;
+                tex0_data_ref[flat_index][2] = color_raw.y;
+                tex0_data_ref[flat_index][3] = color_raw.z;
+                tex1_data_ref[flat_index][0] = sample.metallic;
+                tex1_data_ref[flat_index][1] = sample.roughness;
+                // NOTE: Default occlusion is 1, to use the ambient light by default
+                tex1_data_ref[flat_index][2] = if sample.occlusion <= 0.0 { 1.0 } else { sample.occlusion };
+                // info!("Updated voxel color {:?}", data[flat_index][1]);
+                // TODO: Provide more voxel data to the shader, like a material kind index for using custom GLSL code.
             }
         }
         self.loading_mgr.total_iterations() - start_iter
     }
 
+    /// Returns whether the SDF reported a change since the last call (see [`SDFViewer::sdf_changed`]).
+    pub fn take_sdf_changed(&mut self) -> bool {
+        std::mem::take(&mut self.sdf_changed)
+    }
+
+    /// Sets the custom GLSL code provided by the SDF (see [`SDFSurface::glsl`]), only if it compiles.
+    /// On errors, the default shader is kept and the compilation errors are returned.
+    pub fn set_custom_glsl(&mut self, custom_glsl: String, lights: &[&dyn Light]) -> Result<(), String> {
+        if !custom_glsl.is_empty() {
+            SDFViewerMaterial::validate_custom_glsl(&self.ctx, &custom_glsl, lights)?;
+        }
+        self.volume.borrow_mut().material.custom_glsl = custom_glsl;
+        Ok(())
+    }
+
     /// Commits all previous `update`s to the GPU, updating the GPU-side texture data.
     pub fn commit(&mut self) {
         let mut vol_mut = self.volume.borrow_mut();
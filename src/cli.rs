@@ -11,6 +11,21 @@ use tracing::error;
 pub struct Cli {
     #[clap(subcommand)]
     pub command: Commands,
+    #[cfg(all(feature = "module_cache", not(target_arch = "wasm32")))]
+    #[clap(flatten)]
+    pub module_cache: crate::sdf::wasm::cache::CliModuleCache,
+    #[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+    #[clap(flatten)]
+    pub execution_limits: crate::sdf::wasm::limits::CliExecutionLimits,
+    #[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+    #[clap(flatten)]
+    pub instance_pool: crate::sdf::wasm::pool::CliInstancePool,
+    #[cfg(feature = "wasminterpreters")]
+    #[clap(flatten)]
+    pub wasm_backend: crate::sdf::wasm::backend::CliWasmBackend,
+    #[cfg(feature = "voxel_grids")]
+    #[clap(flatten)]
+    pub voxel_grid: crate::sdf::voxels::CliVoxelGrid,
 }
 
 #[derive(Parser, Debug)]
@@ -1,4 +1,6 @@
 use tracing::info;
+#[cfg(all(feature = "wasminterpreters", not(target_arch = "wasm32")))]
+use tracing_subscriber::layer::SubscriberExt;
 
 use crate::cli::{Cli, Commands};
 use crate::metadata::log_version_info;
@@ -24,6 +26,16 @@ pub async fn setup_app() -> AppCreator {
     // Note that the window is already created and we could do this earlier.
     let args = Cli::parse_args();
     info!("Arguments: {:?}", args);
+    #[cfg(all(feature = "module_cache", not(target_arch = "wasm32")))]
+    args.module_cache.apply();
+    #[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+    args.execution_limits.apply();
+    #[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+    args.instance_pool.apply();
+    #[cfg(feature = "wasminterpreters")]
+    args.wasm_backend.apply();
+    #[cfg(feature = "voxel_grids")]
+    args.voxel_grid.apply();
 
     match args.command {
         #[cfg(feature = "app")]
@@ -39,6 +51,7 @@ pub async fn setup_app() -> AppCreator {
         }
         #[cfg(feature = "meshers")]
         Commands::Mesh(mesher) => { // Run the meshing algorithm and exit
+            crate::sdf::wasm::console::set_forward_to_stderr(true); // There is no console to display the output of the SDF
             mesher.run_cli().await.unwrap();
             None
         }
@@ -57,6 +70,16 @@ pub fn setup_app_sync() -> AppCreator {
     // Note that the window is already created and we could do this earlier.
     let args = Cli::parse_args();
     info!("Arguments: {:?}", args);
+    #[cfg(all(feature = "module_cache", not(target_arch = "wasm32")))]
+    args.module_cache.apply();
+    #[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+    args.execution_limits.apply();
+    #[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+    args.instance_pool.apply();
+    #[cfg(feature = "wasminterpreters")]
+    args.wasm_backend.apply();
+    #[cfg(feature = "voxel_grids")]
+    args.voxel_grid.apply();
 
     match args.command {
         #[cfg(feature = "app")]
@@ -78,6 +101,7 @@ pub fn setup_app_sync() -> AppCreator {
         },
         #[cfg(feature = "meshers")]
         Commands::Mesh(mesher) => {
+            crate::sdf::wasm::console::set_forward_to_stderr(true); // There is no console to display the output of the SDF
             tokio::runtime::Builder::new_multi_thread()
                 .enable_all()
                 .build()
@@ -94,8 +118,11 @@ pub fn setup_app_sync() -> AppCreator {
 #[cfg(not(any(target_arch = "wasm32")))]
 #[allow(dead_code)] // False positive
 pub fn native_main(sync: bool, event_loop_builder: EventLoopBuilderHook) -> std::pin::Pin<Box<dyn std::future::Future<Output=()>>> {
-    // Setup logging
-    tracing::subscriber::set_global_default(tracing_subscriber::FmtSubscriber::default())
+    // Setup logging (also to the console of the app)
+    let subscriber = tracing_subscriber::FmtSubscriber::default();
+    #[cfg(feature = "wasminterpreters")]
+    let subscriber = subscriber.with(crate::sdf::wasm::console::ConsoleLayer);
+    tracing::subscriber::set_global_default(subscriber)
         .expect("Failed to set global default subscriber");
     // If no arguments are provided, run a GUI interface for configuring the CLI arguments ;)
     if Cli::get_args().get(1).is_none() {
@@ -0,0 +1,70 @@
+//! Versioning of the WebAssembly API (see [`crate::sdf::wasm`]), shared by the host that loads
+//! the modules and the [`crate::sdf::ffi`] implementation that is compiled into them.
+
+/// The version of the memory layout and function signatures of the API. It must be increased
+/// whenever a change would make old modules and new hosts (or vice versa) misinterpret each other.
+///
+/// - 1: the original layout, used by modules that do not export `sdf_abi_version`.
+/// - 2: parameter values have a payload of 3 words (for the Vec3, Color and Angle parameter kinds).
+pub const SDF_ABI_VERSION: u32 = 2;
+
+/// The oldest version of the API that the host still knows how to talk to.
+pub const SDF_ABI_MIN_SUPPORTED_VERSION: u32 = 1;
+
+/// The version assumed for modules that do not export `sdf_abi_version`.
+pub const SDF_ABI_LEGACY_VERSION: u32 = 1;
+
+/// The module exports `sample_batch`, `sample_batch_free` and `sample_batch_points`.
+pub const CAPABILITY_SAMPLE_BATCH: u32 = 1 << 0;
+
+/// The module exports `glsl` (and optionally `glsl_free`).
+pub const CAPABILITY_GLSL: u32 = 1 << 1;
+
+/// The module exports `alloc` and `dealloc`, which the host uses for the buffers it writes.
+/// Modules that export `malloc` and `free` instead (like TinyGo or Emscripten ones) are also supported.
+pub const CAPABILITY_ALLOCATOR: u32 = 1 << 2;
+
+/// All the capabilities known by this version of the host, with their names and required exports.
+pub const CAPABILITIES: [(u32, &str, &[&str]); 3] = [
+    (CAPABILITY_SAMPLE_BATCH, "batched sampling", &["sample_batch", "sample_batch_free", "sample_batch_points"]),
+    (CAPABILITY_GLSL, "custom GLSL", &["glsl"]),
+    (CAPABILITY_ALLOCATOR, "allocator", &["alloc", "dealloc"]),
+];
+
+/// The number of 32-bit words used by the payload of a parameter value (the largest variant) for
+/// the given version of the API. This changes the arity of `set_parameter` and the size of each
+/// parameter returned by `parameters`, so the WebAssembly guests assert it at compile time.
+pub const fn param_value_payload_words(abi_version: u32) -> usize {
+    if abi_version >= 2 { 3 } else { 2 }
+}
+
+#[cfg(all(test, feature = "wasminterpreters"))]
+mod tests {
+    use crate::sdf::SDFParamValue;
+    use crate::sdf::wasm::backend::WasmBackend;
+    use crate::sdf::wasm::fixtures::{load, load_with_each_backend, wasm, with_exports, SPHERE_WAT};
+    use crate::sdf::wasm::wasi::CliWasiEnv;
+
+    #[test]
+    fn test_backends_negotiate_the_abi_version() {
+        // Modules without sdf_abi_version use the first version, which can't receive the new kinds of values
+        let legacy_wat = with_exports(SPHERE_WAT, r#"(func (export "set_parameter") (param i32 i32 i32 i32 i32) (result i32)
+            (i32.store (i32.const 320) (i32.const 0))
+            (i32.const 320))"#);
+        for (backend, sdf) in load_with_each_backend(&legacy_wat) {
+            assert_eq!(sdf.set_parameter(0, &SDFParamValue::Float(1.0)), Ok(()), "{:?}", backend);
+            let err = sdf.set_parameter(0, &SDFParamValue::Angle(1.0)).unwrap_err();
+            assert!(err.contains("ABI version 1"), "{:?}: {}", backend, err);
+        }
+
+        let with_version = |version: i32| wasm(&with_exports(SPHERE_WAT,
+            &format!(r#"(func (export "sdf_abi_version") (result i32) (i32.const {version}))"#)));
+        for backend in WasmBackend::ALL {
+            let err = load(*backend, &with_version(99), &CliWasiEnv::default()).err().unwrap();
+            assert!(format!("{err:#}").contains("it uses ABI version 99") && format!("{err:#}").contains("update the viewer"),
+                    "{:?}: {:#}", backend, err);
+            let err = load(*backend, &with_version(0), &CliWasiEnv::default()).err().unwrap();
+            assert!(format!("{err:#}").contains("rebuild the module"), "{:?}: {:#}", backend, err);
+        }
+    }
+}
@@ -1,6 +1,13 @@
 use cgmath::{InnerSpace, Vector3};
 
-use crate::sdf::{SDFParam, SDFParamValue, SDFSurface};
+use crate::sdf::error::SDFError;
+use crate::sdf::{SDFMetadata, SDFParam, SDFParamValue, SDFSample, SDFSurface};
+
+/// Just a default implementation
+#[doc(hidden)]
+pub fn sample_batch_default_impl(slf: impl SDFSurface, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+    points.iter().map(|p| slf.sample(*p, distance_only)).collect()
+}
 
 /// Just a default implementation
 #[doc(hidden)]
@@ -44,6 +51,30 @@ pub fn changed_default_impl(slf: impl SDFSurface) -> Option<[Vector3<f32>; 2]> {
     None
 }
 
+/// Just a default implementation
+#[doc(hidden)]
+pub fn glsl_default_impl(_slf: impl SDFSurface) -> String {
+    String::new()
+}
+
+/// Just a default implementation
+#[doc(hidden)]
+pub fn metadata_default_impl(_slf: impl SDFSurface) -> Option<SDFMetadata> {
+    None
+}
+
+/// Just a default implementation
+#[doc(hidden)]
+pub fn fault_default_impl(_slf: impl SDFSurface) -> Option<SDFError> {
+    None
+}
+
+/// Just a default implementation
+#[doc(hidden)]
+pub fn take_errors_default_impl(_slf: impl SDFSurface) -> Vec<SDFError> {
+    vec![]
+}
+
 /// Just a default implementation
 #[doc(hidden)]
 pub fn normal_default_impl(slf: impl SDFSurface, p: Vector3<f32>, eps: Option<f32>) -> Vector3<f32> {
@@ -55,6 +86,32 @@ pub fn normal_default_impl(slf: impl SDFSurface, p: Vector3<f32>, eps: Option<f3
         Vector3::new(1., 1., 1.) * slf.sample(p + Vector3::new(eps, eps, eps), true).distance).normalize()
 }
 
+/// Just a default implementation
+#[doc(hidden)]
+pub fn normal_batch_default_impl(slf: impl SDFSurface, points: &[Vector3<f32>], eps: Option<f32>) -> Vec<Vector3<f32>> {
+    points.iter().map(|p| slf.normal(*p, eps)).collect()
+}
+
+/// The directions of the samples around a point that compute its normal in [`normal_default_impl`].
+const NORMAL_TAPS: [Vector3<f32>; 4] = [
+    Vector3::new(1., -1., -1.), Vector3::new(-1., 1., -1.),
+    Vector3::new(-1., -1., 1.), Vector3::new(1., 1., 1.),
+];
+
+/// Computes the same normals as [`normal_default_impl`] for all the given points, but with a single
+/// batch of samples. Only for SDFs that don't override [`SDFSurface::normal`].
+pub fn normal_batch_from_samples(slf: impl SDFSurface, points: &[Vector3<f32>], eps: Option<f32>) -> Vec<Vector3<f32>> {
+    let eps = eps.unwrap_or(0.001);
+    let taps = points.iter()
+        .flat_map(|p| NORMAL_TAPS.iter().map(move |tap| p + tap * eps))
+        .collect::<Vec<_>>();
+    slf.sample_batch(&taps, true).chunks_exact(NORMAL_TAPS.len())
+        .map(|samples| NORMAL_TAPS.iter().zip(samples)
+            .map(|(tap, sample)| tap * sample.distance)
+            .sum::<Vector3<f32>>().normalize())
+        .collect()
+}
+
 /// Merges two bounding boxes by performing the union.
 pub fn merge_bounding_boxes(bbox: &[Vector3<f32>; 2], bbox2: &[Vector3<f32>; 2]) -> [Vector3<f32>; 2] {
     [ // Merge both bounding boxes
@@ -1,8 +1,4 @@
 use crate::sdf::demo::SDFDemo;
-use crate::sdf::ffi::set_root_sdf;
 
-/// Entrypoint: only needs to set the root SDFSurface implementation.
-#[no_mangle]
-pub extern "C" fn init() {
-    set_root_sdf(Box::new(SDFDemo::default()));
-}
\ No newline at end of file
+// Entrypoint: only needs to set the root SDFSurface implementation.
+crate::export_sdf!(SDFDemo);
@@ -13,7 +13,7 @@ use crate::sdf::demo::sphere::SDFDemoSphere;
 
 pub mod cube;
 pub mod sphere;
-#[cfg(feature = "sdfdemoffi")]
+#[cfg(all(feature = "sdfdemoffi", not(test)))] // The tests of ffi export their own SDF
 pub mod ffi;
 
 /// An embedded demo `Sdf` implementation to showcase/test most features. Subtracts a cube and a sphere.
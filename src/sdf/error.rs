@@ -0,0 +1,143 @@
+//! Errors of SDFs that are reported to the user (instead of only being logged), with as much
+//! context as available to find their cause.
+
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// The maximum number of errors waiting to be taken from a [`SDFErrorLog`], dropping the oldest ones.
+const MAX_PENDING_ERRORS: usize = 32;
+
+/// An error while loading or querying a SDF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SDFError {
+    /// The main message (like the trap message of a WebAssembly call).
+    pub message: String,
+    /// The path or URL that was being loaded, if known.
+    pub url: Option<String>,
+    /// The function exported by the module that failed, if any.
+    pub export: Option<String>,
+    /// The ID of the SDF that was being queried, if any.
+    pub sdf_id: Option<u32>,
+    /// The call stack inside the module when it failed, innermost frame first (may be empty).
+    pub backtrace: Vec<String>,
+}
+
+impl SDFError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into(), url: None, export: None, sdf_id: None, backtrace: vec![] }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    pub fn with_export(mut self, export: impl Into<String>) -> Self {
+        self.export = Some(export.into());
+        self
+    }
+
+    pub fn with_sdf_id(mut self, sdf_id: u32) -> Self {
+        self.sdf_id = Some(sdf_id);
+        self
+    }
+
+    pub fn with_backtrace(mut self, backtrace: Vec<String>) -> Self {
+        self.backtrace = backtrace;
+        self
+    }
+}
+
+impl Display for SDFError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(export) = &self.export {
+            write!(f, " (in `{export}`")?;
+            if let Some(sdf_id) = self.sdf_id {
+                write!(f, " of SDF with ID {sdf_id}")?;
+            }
+            write!(f, ")")?;
+        }
+        if let Some(url) = &self.url {
+            write!(f, " while loading {url:?}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SDFError {}
+
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for SDFError {
+    /// Keeps the context of wrapped [`SDFError`]s, or uses the whole chain of causes as the message.
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<SDFError>() {
+            Ok(err) => err,
+            Err(err) => Self::new(format!("{err:#}")),
+        }
+    }
+}
+
+/// The errors of a SDF (and the rest of its hierarchy, as cloning it shares the log) that were not
+/// yet reported to the user, and the fault that stopped it, if any (see [`crate::sdf::SDFSurface::fault`]).
+#[derive(Debug, Clone, Default)]
+pub struct SDFErrorLog(Arc<Mutex<SDFErrorLogState>>);
+
+#[derive(Debug, Default)]
+struct SDFErrorLogState {
+    fault: Option<SDFError>,
+    pending: VecDeque<SDFError>,
+}
+
+impl SDFErrorLog {
+    /// Why the SDF stopped working, if it did.
+    pub fn fault(&self) -> Option<SDFError> {
+        self.0.lock().unwrap().fault.clone()
+    }
+
+    /// Marks the SDF as faulted (keeping the first reason).
+    pub fn set_fault(&self, err: SDFError) {
+        self.0.lock().unwrap().fault.get_or_insert(err);
+    }
+
+    /// Records an error to be reported. Repeated errors (like a trap for every sample of a frame)
+    /// are only recorded once.
+    pub fn push(&self, err: SDFError) {
+        let mut state = self.0.lock().unwrap();
+        if state.pending.back() == Some(&err) {
+            return;
+        }
+        if state.pending.len() >= MAX_PENDING_ERRORS {
+            state.pending.pop_front();
+        }
+        state.pending.push_back(err);
+    }
+
+    /// Takes all the recorded errors, oldest first.
+    pub fn take(&self) -> Vec<SDFError> {
+        self.0.lock().unwrap().pending.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sdf::error::{SDFError, SDFErrorLog};
+
+    #[test]
+    fn test_error_context() {
+        let err = SDFError::new("unreachable").with_export("sample").with_sdf_id(3).with_url("demo.wasm");
+        assert_eq!(err.to_string(), "unreachable (in `sample` of SDF with ID 3) while loading \"demo.wasm\"");
+        #[cfg(feature = "anyhow")]
+        assert_eq!(SDFError::from(anyhow::Error::from(err.clone()).context("ignored")), err);
+        #[cfg(feature = "anyhow")]
+        assert_eq!(SDFError::from(anyhow::anyhow!("root").context("outer")).message, "outer: root");
+
+        let log = SDFErrorLog::default();
+        log.clone().push(err.clone());
+        log.push(err.clone());
+        log.push(SDFError::new("other"));
+        assert_eq!(log.take(), vec![err, SDFError::new("other")]);
+        assert!(log.take().is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+//! The type-checked representation of an expression and its evaluation.
+
+use std::ops::Range;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::sdf::primitives::{Capsule, Cone, Cuboid, Cylinder, Ellipsoid, HexPrism, RoundedCuboid, Shape, Sphere, Torus};
+
+/// The type of a value of the language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Float,
+    Vec3,
+}
+
+impl Type {
+    /// The user-facing name of the type, for error messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Float => "a number",
+            Type::Vec3 => "a vector",
+        }
+    }
+}
+
+/// A value of the language. Numbers are converted to vectors (with the same value in all components)
+/// where vectors are expected, which is checked while parsing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Float(f32),
+    Vec3(Vector3<f32>),
+}
+
+impl Value {
+    pub fn ty(&self) -> Type {
+        match self {
+            Value::Float(_) => Type::Float,
+            Value::Vec3(_) => Type::Vec3,
+        }
+    }
+
+    /// The number, or the first component of a vector (which the type checker doesn't allow).
+    pub fn float(&self) -> f32 {
+        match self {
+            Value::Float(value) => *value,
+            Value::Vec3(value) => value.x,
+        }
+    }
+
+    /// The vector, or a number repeated in all components.
+    pub fn vec3(&self) -> Vector3<f32> {
+        match self {
+            Value::Float(value) => Vector3::new(*value, *value, *value),
+            Value::Vec3(value) => *value,
+        }
+    }
+
+    /// Applies the function to each component.
+    fn map(self, f: impl Fn(f32) -> f32) -> Value {
+        match self {
+            Value::Float(a) => Value::Float(f(a)),
+            Value::Vec3(a) => Value::Vec3(Vector3::new(f(a.x), f(a.y), f(a.z))),
+        }
+    }
+
+    /// Applies the function to each pair of components, converting numbers to vectors if needed.
+    fn zip(self, other: Value, f: impl Fn(f32, f32) -> f32) -> Value {
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => Value::Float(f(a, b)),
+            (a, b) => {
+                let (a, b) = (a.vec3(), b.vec3());
+                Value::Vec3(Vector3::new(f(a.x, b.x), f(a.y, b.y), f(a.z, b.z)))
+            }
+        }
+    }
+
+    /// Applies the function to each triple of components, converting numbers to vectors if needed.
+    fn zip3(self, b: Value, c: Value, f: impl Fn(f32, f32, f32) -> f32) -> Value {
+        match (self, b, c) {
+            (Value::Float(a), Value::Float(b), Value::Float(c)) => Value::Float(f(a, b, c)),
+            (a, b, c) => {
+                let (a, b, c) = (a.vec3(), b.vec3(), c.vec3());
+                Value::Vec3(Vector3::new(f(a.x, b.x, c.x), f(a.y, b.y, c.y), f(a.z, b.z, c.z)))
+            }
+        }
+    }
+}
+
+/// A binary arithmetic operator, applied component-wise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinOp {
+    fn apply(&self, a: f32, b: f32) -> f32 {
+        match self {
+            BinOp::Add => a + b,
+            BinOp::Sub => a - b,
+            BinOp::Mul => a * b,
+            BinOp::Div => a / b,
+        }
+    }
+}
+
+/// A node of the (already type-checked) expression tree.
+#[derive(Debug, Clone)]
+pub enum Node {
+    /// A literal or a named constant.
+    Const(Value),
+    /// The point being sampled (`p`).
+    Point,
+    /// The value of a previous assignment.
+    Local(usize),
+    Neg(Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+    /// A component of a vector (`.x`, `.y` or `.z`).
+    Component(Box<Node>, usize),
+    Call(&'static Func, Vec<Node>),
+}
+
+impl Node {
+    /// Evaluates the node at the given point, given the values of the previous assignments.
+    pub fn eval(&self, p: Vector3<f32>, locals: &[Value]) -> Value {
+        match self {
+            Node::Const(value) => *value,
+            Node::Point => Value::Vec3(p),
+            Node::Local(slot) => locals[*slot],
+            Node::Neg(node) => node.eval(p, locals).map(|a| -a),
+            Node::Binary(op, a, b) => a.eval(p, locals).zip(b.eval(p, locals), |a, b| op.apply(a, b)),
+            Node::Component(node, index) => Value::Float(node.eval(p, locals).vec3()[*index]),
+            Node::Call(func, args) => {
+                let mut values = [Value::Float(0.0); MAX_ARGS];
+                for (value, arg) in values.iter_mut().zip(args) {
+                    *value = arg.eval(p, locals);
+                }
+                (func.eval)(&values[..args.len()])
+            }
+        }
+    }
+}
+
+/// A named constant of the expression (`name = literal`), which is exposed as a parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constant {
+    pub name: String,
+    pub value: Value,
+    /// Whether the constant was written as a color (with `rgb(...)` or named `color`).
+    pub is_color: bool,
+    /// The range of the source with the literal, which is replaced when the parameter is modified.
+    pub span: Range<usize>,
+    /// The comment at the end of the line of the constant, if any.
+    pub description: String,
+}
+
+/// A parsed and type-checked expression, ready to be evaluated.
+#[derive(Debug, Clone)]
+pub struct Program {
+    /// The named constants, in order of appearance.
+    pub constants: Vec<Constant>,
+    /// The assignments, each one stored in the slot of its index.
+    pub locals: Vec<Node>,
+    /// The final expression, which computes the distance.
+    pub distance: Node,
+    /// The expression for the color of the surface, if assigned to `color`.
+    pub color: Option<Node>,
+    /// The half size of the bounding box, if assigned to `bounds`.
+    pub bounds: Option<f32>,
+}
+
+impl Program {
+    /// Evaluates the distance (and color, if requested and available) at the given point.
+    /// The buffer for the assignments is reused between calls to avoid allocations.
+    pub fn eval(&self, p: Vector3<f32>, with_color: bool, locals: &mut Vec<Value>) -> (f32, Option<Vector3<f32>>) {
+        locals.clear();
+        for node in &self.locals {
+            let value = node.eval(p, locals);
+            locals.push(value);
+        }
+        let distance = self.distance.eval(p, locals).float();
+        let color = if with_color { self.color.as_ref().map(|node| node.eval(p, locals).vec3()) } else { None };
+        (distance, color)
+    }
+}
+
+/// The type of an argument of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arg {
+    Float,
+    Vec3,
+    /// A number or a vector (numbers are used as vectors with the same value in all components).
+    Any,
+}
+
+/// The type of the result of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ret {
+    Float,
+    Vec3,
+    /// A vector if any argument is a vector, or a number otherwise (for component-wise functions).
+    Broadcast,
+}
+
+/// A built-in function of the language.
+#[derive(Debug)]
+pub struct Func {
+    pub name: &'static str,
+    /// How to call the function, for error messages and the documentation.
+    pub usage: &'static str,
+    pub args: &'static [Arg],
+    pub ret: Ret,
+    pub eval: fn(&[Value]) -> Value,
+}
+
+/// The maximum number of arguments of any function.
+const MAX_ARGS: usize = 4;
+
+/// Polynomial smooth minimum (see https://iquilezles.org/articles/smin/), like [`crate::sdf::ops::Boolean`].
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b * (1.0 - h) + a * h - k * h * (1.0 - h)
+}
+
+/// Rotates the point around the given axis (0, 1 or 2) by the given angle in radians.
+fn rotate(p: Vector3<f32>, axis: usize, angle: f32) -> Value {
+    let (sin, cos) = angle.sin_cos();
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut res = p;
+    res[u] = p[u] * cos - p[v] * sin;
+    res[v] = p[u] * sin + p[v] * cos;
+    Value::Vec3(res)
+}
+
+/// All the built-in functions. Names may be repeated with a different number of arguments.
+pub static FUNCTIONS: &[Func] = &[
+    // ===== MATH (component-wise) =====
+    Func { name: "abs", usage: "abs(x)", args: &[Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].map(f32::abs) },
+    Func { name: "sign", usage: "sign(x)", args: &[Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].map(f32::signum) },
+    Func { name: "sqrt", usage: "sqrt(x)", args: &[Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].map(f32::sqrt) },
+    Func { name: "exp", usage: "exp(x)", args: &[Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].map(f32::exp) },
+    Func { name: "sin", usage: "sin(x)", args: &[Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].map(f32::sin) },
+    Func { name: "cos", usage: "cos(x)", args: &[Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].map(f32::cos) },
+    Func { name: "tan", usage: "tan(x)", args: &[Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].map(f32::tan) },
+    Func { name: "floor", usage: "floor(x)", args: &[Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].map(f32::floor) },
+    Func { name: "fract", usage: "fract(x)", args: &[Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].map(|x| x - x.floor()) },
+    Func { name: "min", usage: "min(a, b)", args: &[Arg::Any, Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].zip(a[1], f32::min) },
+    Func { name: "max", usage: "max(a, b)", args: &[Arg::Any, Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].zip(a[1], f32::max) },
+    Func { name: "pow", usage: "pow(x, exponent)", args: &[Arg::Any, Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].zip(a[1], f32::powf) },
+    Func { name: "mod", usage: "mod(x, period)", args: &[Arg::Any, Arg::Any], ret: Ret::Broadcast, eval: |a| a[0].zip(a[1], f32::rem_euclid) },
+    Func { name: "clamp", usage: "clamp(x, min, max)", args: &[Arg::Any, Arg::Any, Arg::Any], ret: Ret::Broadcast,
+        eval: |a| a[0].zip3(a[1], a[2], |x, lo, hi| x.max(lo).min(hi)) },
+    Func { name: "mix", usage: "mix(a, b, t)", args: &[Arg::Any, Arg::Any, Arg::Any], ret: Ret::Broadcast,
+        eval: |a| a[0].zip3(a[1], a[2], |a, b, t| a * (1.0 - t) + b * t) },
+    Func { name: "smin", usage: "smin(a, b, smoothness)", args: &[Arg::Any, Arg::Any, Arg::Any], ret: Ret::Broadcast,
+        eval: |a| a[0].zip3(a[1], a[2], smooth_min) },
+    Func { name: "smax", usage: "smax(a, b, smoothness)", args: &[Arg::Any, Arg::Any, Arg::Any], ret: Ret::Broadcast,
+        eval: |a| a[0].zip3(a[1], a[2], |a, b, k| -smooth_min(-a, -b, k)) },
+    // ===== VECTORS =====
+    Func { name: "vec3", usage: "vec3(x, y, z)", args: &[Arg::Float, Arg::Float, Arg::Float], ret: Ret::Vec3,
+        eval: |a| Value::Vec3(Vector3::new(a[0].float(), a[1].float(), a[2].float())) },
+    Func { name: "vec3", usage: "vec3(xyz)", args: &[Arg::Float], ret: Ret::Vec3, eval: |a| Value::Vec3(a[0].vec3()) },
+    Func { name: "rgb", usage: "rgb(red, green, blue)", args: &[Arg::Float, Arg::Float, Arg::Float], ret: Ret::Vec3,
+        eval: |a| Value::Vec3(Vector3::new(a[0].float(), a[1].float(), a[2].float())) },
+    Func { name: "length", usage: "length(v)", args: &[Arg::Vec3], ret: Ret::Float, eval: |a| Value::Float(a[0].vec3().magnitude()) },
+    Func { name: "dot", usage: "dot(a, b)", args: &[Arg::Vec3, Arg::Vec3], ret: Ret::Float, eval: |a| Value::Float(a[0].vec3().dot(a[1].vec3())) },
+    Func { name: "cross", usage: "cross(a, b)", args: &[Arg::Vec3, Arg::Vec3], ret: Ret::Vec3, eval: |a| Value::Vec3(a[0].vec3().cross(a[1].vec3())) },
+    Func { name: "normalize", usage: "normalize(v)", args: &[Arg::Vec3], ret: Ret::Vec3, eval: |a| Value::Vec3(a[0].vec3().normalize()) },
+    Func { name: "rotate_x", usage: "rotate_x(p, radians)", args: &[Arg::Vec3, Arg::Float], ret: Ret::Vec3, eval: |a| rotate(a[0].vec3(), 0, a[1].float()) },
+    Func { name: "rotate_y", usage: "rotate_y(p, radians)", args: &[Arg::Vec3, Arg::Float], ret: Ret::Vec3, eval: |a| rotate(a[0].vec3(), 1, a[1].float()) },
+    Func { name: "rotate_z", usage: "rotate_z(p, radians)", args: &[Arg::Vec3, Arg::Float], ret: Ret::Vec3, eval: |a| rotate(a[0].vec3(), 2, a[1].float()) },
+    // ===== SHAPES (see crate::sdf::primitives) =====
+    Func { name: "sphere", usage: "sphere(p, radius)", args: &[Arg::Vec3, Arg::Float], ret: Ret::Float,
+        eval: |a| Value::Float(Sphere::new(a[1].float()).distance(a[0].vec3())) },
+    Func { name: "box", usage: "box(p, half_size)", args: &[Arg::Vec3, Arg::Any], ret: Ret::Float,
+        eval: |a| Value::Float(Cuboid::new(a[1].vec3()).distance(a[0].vec3())) },
+    Func { name: "round_box", usage: "round_box(p, half_size, radius)", args: &[Arg::Vec3, Arg::Any, Arg::Float], ret: Ret::Float,
+        eval: |a| Value::Float(RoundedCuboid::new(a[1].vec3(), a[2].float()).distance(a[0].vec3())) },
+    Func { name: "ellipsoid", usage: "ellipsoid(p, radii)", args: &[Arg::Vec3, Arg::Vec3], ret: Ret::Float,
+        eval: |a| Value::Float(Ellipsoid::new(a[1].vec3()).distance(a[0].vec3())) },
+    Func { name: "torus", usage: "torus(p, major_radius, minor_radius)", args: &[Arg::Vec3, Arg::Float, Arg::Float], ret: Ret::Float,
+        eval: |a| Value::Float(Torus::new(a[1].float(), a[2].float()).distance(a[0].vec3())) },
+    Func { name: "capsule", usage: "capsule(p, half_height, radius)", args: &[Arg::Vec3, Arg::Float, Arg::Float], ret: Ret::Float,
+        eval: |a| Value::Float(Capsule::new(a[1].float(), a[2].float()).distance(a[0].vec3())) },
+    Func { name: "cylinder", usage: "cylinder(p, half_height, radius)", args: &[Arg::Vec3, Arg::Float, Arg::Float], ret: Ret::Float,
+        eval: |a| Value::Float(Cylinder::new(a[1].float(), a[2].float()).distance(a[0].vec3())) },
+    Func { name: "cone", usage: "cone(p, half_height, bottom_radius, top_radius)", args: &[Arg::Vec3, Arg::Float, Arg::Float, Arg::Float], ret: Ret::Float,
+        eval: |a| Value::Float(Cone::new(a[1].float(), a[2].float(), a[3].float()).distance(a[0].vec3())) },
+    Func { name: "hex_prism", usage: "hex_prism(p, radius, half_height)", args: &[Arg::Vec3, Arg::Float, Arg::Float], ret: Ret::Float,
+        eval: |a| Value::Float(HexPrism::new(a[1].float(), a[2].float()).distance(a[0].vec3())) },
+];
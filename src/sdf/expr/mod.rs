@@ -0,0 +1,233 @@
+//! An SDF defined by a small math expression language, which can be edited while the app is running.
+//!
+//! The source is a list of assignments followed by the expression of the distance to the surface,
+//! in terms of the sampled point `p`:
+//!
+//! ```text
+//! radius = 1.2          // Assignments of literals are exposed as parameters (with this comment)
+//! size = vec3(1, 0.8, 1)
+//! color = rgb(0.8, 0.3, 0.2)
+//! q = rotate_y(p, p.y * 0.5)
+//! max(box(q, size), -sphere(p, radius))
+//! ```
+//!
+//! - Values are numbers or vectors (`vec3(x, y, z)`, `vec3(xyz)` or `p`, with components `.x`, `.y` and `.z`).
+//!   Arithmetic (`+ - * /`) and most math functions apply to each component, mixing numbers and vectors.
+//! - Math: `abs sign sqrt exp sin cos tan floor fract min max pow mod clamp mix smin smax` and `pi`.
+//! - Vectors: `length dot cross normalize rotate_x rotate_y rotate_z`.
+//! - Shapes (see [`crate::sdf::primitives`]): `sphere box round_box ellipsoid torus capsule cylinder cone hex_prism`,
+//!   whose first argument is the point.
+//! - Statements end with a new line or `;`, and comments start with `//` or `#`.
+//! - The special names `color` (a vector, which may depend on `p`) and `bounds` (the half size of the
+//!   bounding box, which defaults to 2) configure the surface.
+//!
+//! Modifying a parameter rewrites its literal in the source, so that the source is always up to date.
+
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use cgmath::Vector3;
+
+use crate::sdf::{SDFParam, SDFParamKind, SDFParamValue, SDFSample, SDFSurface};
+use crate::sdf::defaults::merge_bounding_boxes;
+use crate::sdf::expr::eval::{Program, Value};
+use crate::sdf::shared::{ChangedBox, float_param, next_id, Shared, unknown_param_error, vec3_param};
+
+mod eval;
+mod parse;
+
+pub use parse::ExprError;
+
+/// The half size of the bounding box if the expression doesn't assign `bounds`.
+const DEFAULT_BOUNDS: f32 = 2.0;
+
+/// The color of the surface if the expression doesn't assign `color`.
+const DEFAULT_COLOR: Vector3<f32> = Vector3::new(0.8, 0.8, 0.8);
+
+/// An SDF evaluated from the source of an expression (see the [module docs](self)).
+#[derive(Debug)]
+pub struct ExprSDF {
+    id: u32,
+    state: Shared<ExprState>,
+    changed: ChangedBox,
+}
+
+/// The source and its parsed version, which are always replaced together.
+#[derive(Debug)]
+struct ExprState {
+    source: String,
+    program: Arc<Program>,
+}
+
+impl Clone for ExprSDF {
+    fn clone(&self) -> Self {
+        Self { id: self.id, state: self.state.clone(), changed: self.changed.clone() }
+    }
+}
+
+impl ExprSDF {
+    /// The expression of the example in the [module docs](self).
+    pub const EXAMPLE: &'static str = "radius = 1.2 // The radius of the hole\nsize = vec3(1, 0.8, 1)\n\
+        color = rgb(0.8, 0.3, 0.2)\nq = rotate_y(p, p.y * 0.5)\nmax(box(q, size), -sphere(p, radius))\n";
+
+    pub fn new(source: &str) -> Result<Self, ExprError> {
+        let program = parse::parse(source)?;
+        let state = ExprState { source: source.to_string(), program: Arc::new(program) };
+        Ok(Self { id: next_id(), state: Shared::new(state), changed: ChangedBox::default() })
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// The current source of the expression, including the changes to the parameters.
+    pub fn source(&self) -> String {
+        self.state.with(|state| state.source.clone())
+    }
+
+    /// Replaces the expression, notifying the change of the whole surface.
+    /// If the new source is not valid, the previous expression is kept.
+    pub fn set_source(&self, source: &str) -> Result<(), ExprError> {
+        let program = parse::parse(source)?;
+        let old_box = self.bounding_box();
+        self.state.set(ExprState { source: source.to_string(), program: Arc::new(program) });
+        self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+        Ok(())
+    }
+
+    fn program(&self) -> Arc<Program> {
+        self.state.with(|state| Arc::clone(&state.program))
+    }
+}
+
+/// Formats a number for the source, without unnecessary digits.
+fn format_number(value: f32) -> String {
+    let text = format!("{value:.4}");
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" { "0".to_string() } else { text.to_string() }
+}
+
+/// The range of the slider of a parameter: the next power of 10 that contains the value, so that
+/// it doesn't change while dragging.
+fn param_range(magnitude: f32) -> RangeInclusive<f32> {
+    let limit = 10f32.powf(magnitude.abs().max(1.0).log10().ceil());
+    -limit..=limit
+}
+
+impl SDFSurface for ExprSDF {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let bounds = self.program().bounds.unwrap_or(DEFAULT_BOUNDS);
+        [Vector3::new(-bounds, -bounds, -bounds), Vector3::new(bounds, bounds, bounds)]
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        let (distance, color) = self.program().eval(p, !distance_only, &mut vec![]);
+        SDFSample::new(distance, color.unwrap_or(DEFAULT_COLOR))
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let program = self.program();
+        let mut locals = Vec::with_capacity(program.locals.len());
+        points.iter().map(|p| {
+            let (distance, color) = program.eval(*p, !distance_only, &mut locals);
+            SDFSample::new(distance, color.unwrap_or(DEFAULT_COLOR))
+        }).collect()
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        "Expression".to_string()
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        self.program().constants.iter().enumerate().map(|(id, constant)| match constant.value {
+            Value::Float(value) if constant.name == "bounds" =>
+                float_param(id as u32, &constant.name, value, 0.01..=*param_range(value).end(), &constant.description),
+            Value::Float(value) => float_param(id as u32, &constant.name, value, param_range(value), &constant.description),
+            Value::Vec3(value) if constant.is_color => SDFParam {
+                id: id as u32,
+                name: constant.name.clone(),
+                kind: SDFParamKind::Color,
+                value: SDFParamValue::Color(value),
+                description: constant.description.clone(),
+            },
+            Value::Vec3(value) => vec3_param(id as u32, &constant.name, value,
+                                             param_range(value.x.abs().max(value.y.abs()).max(value.z.abs())), 0.01, &constant.description),
+        }).collect()
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        let program = self.program();
+        let Some(constant) = program.constants.get(param_id as usize) else {
+            return unknown_param_error(param_id, param_value);
+        };
+        let literal = match (constant.value, param_value) {
+            (Value::Float(_), SDFParamValue::Float(value)) => format_number(*value),
+            (Value::Vec3(_), SDFParamValue::Vec3(value)) if !constant.is_color =>
+                format!("vec3({}, {}, {})", format_number(value.x), format_number(value.y), format_number(value.z)),
+            (Value::Vec3(_), SDFParamValue::Color(value)) if constant.is_color =>
+                format!("rgb({}, {}, {})", format_number(value.x), format_number(value.y), format_number(value.z)),
+            _ => return unknown_param_error(param_id, param_value),
+        };
+        let mut source = self.source();
+        source.replace_range(constant.span.clone(), &literal);
+        self.set_source(&source).map_err(|err| err.to_string())
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        self.changed.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Zero};
+
+    use crate::sdf::{SDFParamValue, SDFSurface};
+    use crate::sdf::expr::ExprSDF;
+
+    #[test]
+    fn test_expr_distances() {
+        let sdf = ExprSDF::new("max(box(p, vec3(1)), -sphere(p, 1.2))").unwrap();
+        assert!(sdf.sample(Vector3::zero(), true).distance > 0.0); // Hollowed by the sphere
+        assert!(sdf.sample(Vector3::new(0.95, 0.95, 0.95), true).distance < 0.0); // Corner of the box
+        let sdf = ExprSDF::new("r = 0.5; d = length(p) - r * 2\nd").unwrap();
+        assert_eq!(sdf.sample(Vector3::new(3.0, 0.0, 0.0), true).distance, 2.0);
+        let sdf = ExprSDF::new("color = rgb(1, 0, p.x)\nbounds = 3\nsphere(p, 1)").unwrap();
+        assert_eq!(sdf.sample(Vector3::new(0.5, 0.0, 0.0), false).color, Vector3::new(1.0, 0.0, 0.5));
+        assert_eq!(sdf.bounding_box()[1], Vector3::new(3.0, 3.0, 3.0));
+    }
+
+    #[test]
+    fn test_expr_parameters() {
+        let sdf = ExprSDF::new(ExprSDF::EXAMPLE).unwrap();
+        let params = sdf.parameters();
+        assert_eq!(params.iter().map(|param| param.name.as_str()).collect::<Vec<_>>(), ["radius", "size", "color"]);
+        assert_eq!(params[0].description, "The radius of the hole");
+        assert!(sdf.changed().is_none());
+        sdf.set_parameter(0, &SDFParamValue::Float(0.25)).unwrap();
+        assert!(sdf.source().starts_with("radius = 0.25 // The radius of the hole\n"));
+        assert!(sdf.changed().is_some());
+        sdf.set_parameter(2, &SDFParamValue::Color(Vector3::new(0.0, 1.0, 0.5))).unwrap();
+        assert!(sdf.source().contains("color = rgb(0, 1, 0.5)\n"));
+        assert!(sdf.set_parameter(1, &SDFParamValue::Float(1.0)).is_err());
+    }
+
+    #[test]
+    fn test_expr_errors() {
+        let err = ExprSDF::new("a = 1\nsphere(p, a, 2)").err().unwrap();
+        assert_eq!((err.line, err.column), (2, 1));
+        let err = ExprSDF::new("sphere(p, vec3(1))").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 11));
+        assert!(ExprSDF::new("q = p * 2").is_err()); // Missing the distance
+        assert!(ExprSDF::new("unknown(p)").is_err());
+        let sdf = ExprSDF::new("sphere(p, 1)").unwrap();
+        assert!(sdf.set_source("sphere(p, ").is_err());
+        assert_eq!(sdf.source(), "sphere(p, 1)");
+    }
+}
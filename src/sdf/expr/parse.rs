@@ -0,0 +1,409 @@
+//! Parsing of the source of an expression into a type-checked [`Program`], reporting the position
+//! of the first error.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use cgmath::{Vector3, Zero};
+
+use crate::sdf::expr::eval::{Arg, BinOp, Constant, FUNCTIONS, Func, Node, Program, Ret, Type, Value};
+
+/// An error in the source of an expression, with its position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    pub message: String,
+    /// The byte range of the source that caused the error.
+    pub span: Range<usize>,
+    /// The line of the start of the span (starting at 1).
+    pub line: usize,
+    /// The column of the start of the span, in characters (starting at 1).
+    pub column: usize,
+}
+
+impl ExprError {
+    fn new(source: &str, span: Range<usize>, message: impl Into<String>) -> Self {
+        let before = &source[..span.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        Self { message: message.into(), span, line, column }
+    }
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at line {}, column {})", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Symbol(char),
+    End,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "`{number}`"),
+            Token::Ident(ident) => write!(f, "`{ident}`"),
+            Token::Symbol(symbol) => write!(f, "`{symbol}`"),
+            Token::End => write!(f, "the end of the expression"),
+        }
+    }
+}
+
+/// A token with its position and whether it is the first one of its line (which ends the previous statement).
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    span: Range<usize>,
+    line_start: bool,
+}
+
+/// Splits the source into tokens, also returning the comments (`// ...` or `# ...`) of each line.
+fn tokenize(source: &str) -> Result<(Vec<Spanned>, HashMap<usize, String>), ExprError> {
+    let mut tokens = vec![];
+    let mut comments = HashMap::new();
+    let mut line = 1;
+    let mut line_start = true;
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        let token = match c {
+            '\n' => {
+                line += 1;
+                line_start = true;
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            '#' | '/' if c == '#' || source[end..].starts_with('/') => {
+                let comment_end = source[start..].find('\n').map(|len| start + len).unwrap_or(source.len());
+                comments.insert(line, source[start..comment_end].trim_start_matches(['#', '/']).trim().to_string());
+                while chars.next_if(|(i, _)| *i < comment_end).is_some() {}
+                continue;
+            }
+            c if c.is_ascii_digit() || c == '.' && source[end..].starts_with(|c: char| c.is_ascii_digit()) => {
+                let mut prev = c;
+                while let Some((i, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '.' ||
+                    (*c == '-' || *c == '+') && (prev == 'e' || prev == 'E')) {
+                    end = i + c.len_utf8();
+                    prev = c;
+                }
+                let text = &source[start..end];
+                match text.parse::<f32>() {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => return Err(ExprError::new(source, start..end, format!("invalid number `{text}`"))),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while let Some((i, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                    end = i + c.len_utf8();
+                }
+                Token::Ident(source[start..end].to_string())
+            }
+            '+' | '-' | '*' | '/' | '(' | ')' | ',' | ';' | '=' | '.' => Token::Symbol(c),
+            c => return Err(ExprError::new(source, start..end, format!("unexpected character `{c}`"))),
+        };
+        tokens.push(Spanned { token, span: start..end, line_start });
+        line_start = false;
+    }
+    tokens.push(Spanned { token: Token::End, span: source.len()..source.len(), line_start: true });
+    Ok((tokens, comments))
+}
+
+/// What a name refers to.
+#[derive(Debug, Clone)]
+enum Binding {
+    /// A value that is the same for all points (including named constants).
+    Const(Value),
+    Local(usize, Type),
+}
+
+/// A parsed expression, with its type and position.
+struct Typed {
+    node: Node,
+    ty: Type,
+    span: Range<usize>,
+    /// Whether it only contains numbers (like `-1`, `2 * pi` or `vec3(1, 2, 3)`), which can be replaced
+    /// by its value in the source.
+    literal: bool,
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Spanned>,
+    comments: HashMap<usize, String>,
+    pos: usize,
+    scope: HashMap<String, Binding>,
+}
+
+/// Parses and type-checks the source of an expression (see the [module docs](super) for the syntax).
+pub fn parse(source: &str) -> Result<Program, ExprError> {
+    let (tokens, comments) = tokenize(source)?;
+    let mut parser = Parser { source, tokens, comments, pos: 0, scope: HashMap::new() };
+    parser.program()
+}
+
+/// Evaluates a node whose operands are all constant once, as it is the same for all points.
+fn fold(node: Node) -> Node {
+    Node::Const(node.eval(Vector3::zero(), &[]))
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Spanned {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Spanned {
+        let token = self.tokens[self.pos].clone();
+        if token.token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, span: Range<usize>, message: impl Into<String>) -> Result<T, ExprError> {
+        Err(ExprError::new(self.source, span, message))
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<Range<usize>, ExprError> {
+        let next = self.next();
+        if next.token == Token::Symbol(symbol) {
+            Ok(next.span)
+        } else {
+            self.error(next.span, format!("expected `{symbol}`, found {}", next.token))
+        }
+    }
+
+    /// program := (name `=` expression (`;` | new line))* expression `;`?
+    fn program(&mut self) -> Result<Program, ExprError> {
+        let mut program = Program { constants: vec![], locals: vec![], distance: Node::Point, color: None, bounds: None };
+        loop {
+            while self.peek().token == Token::Symbol(';') {
+                self.next();
+            }
+            let is_assignment = matches!(self.peek().token, Token::Ident(_)) &&
+                self.tokens.get(self.pos + 1).is_some_and(|next| next.token == Token::Symbol('='));
+            if !is_assignment {
+                break;
+            }
+            self.assignment(&mut program)?;
+            let next = self.peek();
+            if !next.line_start && next.token != Token::Symbol(';') {
+                return self.error(next.span.clone(), format!("expected `;` or a new line, found {}", next.token));
+            }
+        }
+        let next = self.peek();
+        if next.token == Token::End {
+            return self.error(next.span.clone(), "missing the final expression with the distance to the surface");
+        }
+        let distance = self.expression()?;
+        if distance.ty != Type::Float {
+            return self.error(distance.span, format!(
+                "the final expression must be a distance (a number), found {}", distance.ty.name()));
+        }
+        program.distance = distance.node;
+        while self.peek().token == Token::Symbol(';') {
+            self.next();
+        }
+        let next = self.peek();
+        if next.token != Token::End {
+            return self.error(next.span.clone(), format!(
+                "unexpected {} after the final expression (only the last statement may be an expression without a name)", next.token));
+        }
+        Ok(program)
+    }
+
+    /// Parses `name = expression`, exposing it as a parameter if the expression is a literal.
+    fn assignment(&mut self, program: &mut Program) -> Result<(), ExprError> {
+        let name_token = self.next();
+        let Token::Ident(name) = name_token.token else { unreachable!("checked by the caller") };
+        self.expect('=')?;
+        if name == "p" || name == "pi" || self.scope.contains_key(&name) {
+            return self.error(name_token.span, format!("`{name}` is already defined"));
+        }
+        let value = self.expression()?;
+        match name.as_str() {
+            "bounds" => match value.node {
+                Node::Const(Value::Float(bounds)) if bounds > 0.0 => program.bounds = Some(bounds),
+                _ => return self.error(value.span, "`bounds` must be a positive number (the half size of the bounding box)"),
+            },
+            "color" if value.ty != Type::Vec3 => return self.error(value.span, format!(
+                "`color` must be a vector (like rgb(1, 0.5, 0)), found {}", value.ty.name())),
+            _ => {}
+        }
+        let binding = match value.node {
+            Node::Const(constant) => {
+                if value.literal {
+                    let is_color = name == "color" || self.source[value.span.clone()].starts_with("rgb");
+                    let line = ExprError::new(self.source, name_token.span.clone(), "").line;
+                    let description = self.comments.get(&line).cloned()
+                        .unwrap_or_else(|| format!("The constant `{name}` defined at line {line}."));
+                    program.constants.push(Constant { name: name.clone(), value: constant, is_color, span: value.span, description });
+                }
+                Binding::Const(constant)
+            }
+            node => {
+                program.locals.push(node);
+                Binding::Local(program.locals.len() - 1, value.ty)
+            }
+        };
+        if name == "color" {
+            program.color = Some(match binding {
+                Binding::Const(constant) => Node::Const(constant),
+                Binding::Local(slot, _) => Node::Local(slot),
+            });
+        }
+        self.scope.insert(name, binding);
+        Ok(())
+    }
+
+    /// expression := term ((`+` | `-`) term)*
+    fn expression(&mut self) -> Result<Typed, ExprError> {
+        let mut left = self.term()?;
+        while let Token::Symbol(op @ ('+' | '-')) = self.peek().token {
+            self.next();
+            let right = self.term()?;
+            left = Self::binary(if op == '+' { BinOp::Add } else { BinOp::Sub }, left, right);
+        }
+        Ok(left)
+    }
+
+    /// term := unary ((`*` | `/`) unary)*
+    fn term(&mut self) -> Result<Typed, ExprError> {
+        let mut left = self.unary()?;
+        while let Token::Symbol(op @ ('*' | '/')) = self.peek().token {
+            self.next();
+            let right = self.unary()?;
+            left = Self::binary(if op == '*' { BinOp::Mul } else { BinOp::Div }, left, right);
+        }
+        Ok(left)
+    }
+
+    /// Numbers and vectors can be mixed, applying the number to all components.
+    fn binary(op: BinOp, left: Typed, right: Typed) -> Typed {
+        let ty = if left.ty == Type::Vec3 || right.ty == Type::Vec3 { Type::Vec3 } else { Type::Float };
+        let operands_const = matches!((&left.node, &right.node), (Node::Const(_), Node::Const(_)));
+        let node = Node::Binary(op, Box::new(left.node), Box::new(right.node));
+        let node = if operands_const { fold(node) } else { node };
+        Typed { node, ty, span: left.span.start..right.span.end, literal: left.literal && right.literal }
+    }
+
+    /// unary := `-` unary | postfix
+    fn unary(&mut self) -> Result<Typed, ExprError> {
+        if self.peek().token != Token::Symbol('-') {
+            return self.postfix();
+        }
+        let start = self.next().span.start;
+        let operand = self.unary()?;
+        let node = match operand.node {
+            node @ Node::Const(_) => fold(Node::Neg(Box::new(node))),
+            node => Node::Neg(Box::new(node)),
+        };
+        Ok(Typed { node, span: start..operand.span.end, ..operand })
+    }
+
+    /// postfix := primary (`.` (`x` | `y` | `z`))*
+    fn postfix(&mut self) -> Result<Typed, ExprError> {
+        let mut value = self.primary()?;
+        while self.peek().token == Token::Symbol('.') {
+            self.next();
+            let component_token = self.next();
+            let index = match &component_token.token {
+                Token::Ident(component) if component == "x" => 0,
+                Token::Ident(component) if component == "y" => 1,
+                Token::Ident(component) if component == "z" => 2,
+                other => return self.error(component_token.span, format!("expected `x`, `y` or `z` after `.`, found {other}")),
+            };
+            let span = value.span.start..component_token.span.end;
+            if value.ty != Type::Vec3 {
+                return self.error(span, format!("only vectors have components, found {}", value.ty.name()));
+            }
+            let node = match value.node {
+                node @ Node::Const(_) => fold(Node::Component(Box::new(node), index)),
+                node => Node::Component(Box::new(node), index),
+            };
+            value = Typed { node, ty: Type::Float, span, ..value };
+        }
+        Ok(value)
+    }
+
+    /// primary := number | name | name `(` (expression (`,` expression)*)? `)` | `(` expression `)`
+    fn primary(&mut self) -> Result<Typed, ExprError> {
+        let token = self.next();
+        let span = token.span.clone();
+        match token.token {
+            Token::Number(number) => Ok(Typed { node: Node::Const(Value::Float(number)), ty: Type::Float, span, literal: true }),
+            Token::Symbol('(') => {
+                let inner = self.expression()?;
+                let end = self.expect(')')?.end;
+                Ok(Typed { span: span.start..end, ..inner })
+            }
+            Token::Ident(name) if self.peek().token == Token::Symbol('(') => self.call(name, span),
+            Token::Ident(name) if name == "p" => Ok(Typed { node: Node::Point, ty: Type::Vec3, span, literal: false }),
+            Token::Ident(name) if name == "pi" =>
+                Ok(Typed { node: Node::Const(Value::Float(std::f32::consts::PI)), ty: Type::Float, span, literal: true }),
+            Token::Ident(name) => match self.scope.get(&name) {
+                Some(Binding::Const(value)) => Ok(Typed { node: Node::Const(*value), ty: value.ty(), span, literal: false }),
+                Some(Binding::Local(slot, ty)) => Ok(Typed { node: Node::Local(*slot), ty: *ty, span, literal: false }),
+                None => match FUNCTIONS.iter().find(|func| func.name == name) {
+                    Some(func) => self.error(span, format!("`{name}` is a function, call it like `{}`", func.usage)),
+                    None => self.error(span, format!("unknown name `{name}`")),
+                },
+            },
+            other => self.error(span, format!("expected a value, found {other}")),
+        }
+    }
+
+    /// Parses the arguments of a call and checks them against the signatures of the function.
+    fn call(&mut self, name: String, name_span: Range<usize>) -> Result<Typed, ExprError> {
+        self.expect('(')?;
+        let mut args = vec![];
+        if self.peek().token != Token::Symbol(')') {
+            loop {
+                args.push(self.expression()?);
+                if self.peek().token != Token::Symbol(',') {
+                    break;
+                }
+                self.next();
+            }
+        }
+        let span = name_span.start..self.expect(')')?.end;
+        let overloads: Vec<&'static Func> = FUNCTIONS.iter().filter(|func| func.name == name).collect();
+        if overloads.is_empty() {
+            return self.error(name_span, format!("unknown function `{name}`"));
+        }
+        let Some(func) = overloads.iter().find(|func| func.args.len() == args.len()) else {
+            let usages: Vec<&str> = overloads.iter().map(|func| func.usage).collect();
+            return self.error(span, format!("wrong number of arguments, expected `{}`", usages.join("` or `")));
+        };
+        for (i, (arg, expected)) in args.iter().zip(func.args).enumerate() {
+            let expected = match expected {
+                Arg::Float => Type::Float,
+                Arg::Vec3 => Type::Vec3,
+                Arg::Any => continue,
+            };
+            if arg.ty != expected {
+                return self.error(arg.span.clone(), format!(
+                    "argument {} of `{}` must be {}, found {}", i + 1, func.usage, expected.name(), arg.ty.name()));
+            }
+        }
+        let ty = match func.ret {
+            Ret::Float => Type::Float,
+            Ret::Vec3 => Type::Vec3,
+            Ret::Broadcast if args.iter().any(|arg| arg.ty == Type::Vec3) => Type::Vec3,
+            Ret::Broadcast => Type::Float,
+        };
+        let literal = args.iter().all(|arg| arg.literal);
+        let nodes: Vec<Node> = args.into_iter().map(|arg| arg.node).collect();
+        let operands_const = nodes.iter().all(|node| matches!(node, Node::Const(_)));
+        let node = Node::Call(func, nodes);
+        let node = if operands_const { fold(node) } else { node };
+        Ok(Typed { node, ty, span, literal })
+    }
+}
@@ -1,29 +1,99 @@
 //! This provides a external API for the SDF library. It matches the WebAssembly specification
 //! defined at [crate::sdf::wasm].
 //!
-//! See [crate::sdf::demo::ffi::init] for a usage example.
+//! Use [`export_sdf!`](crate::export_sdf) to generate the entrypoint of your module, as done by
+//! [crate::sdf::demo::ffi].
 
+use std::alloc::Layout;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::mem::size_of;
 use std::ops::Range;
+use std::rc::Rc;
 
 use cgmath::{Vector3, Zero};
 
-use crate::sdf::{SDFParamKind, SDFParamValue, SDFSample, SDFSurface};
+use crate::sdf::{abi, SDFParamKind, SDFParamValue, SDFSample, SDFSurface};
 
 thread_local! {
     pub static REGISTRY: RefCell<HashMap<u32, Box<dyn SDFSurface>>> = RefCell::new(HashMap::new());
 }
 
+/// Generates the whole WebAssembly API for the given [`SDFSurface`] type, which becomes the root SDF.
+///
+/// It generates the `init` entrypoint, which builds the root SDF using [`Default`] (or the given
+/// expression) and registers it with [`set_root_sdf`]. The type is checked at compile time to be
+/// `SDFSurface + 'static`. It also embeds the name and version of the calling crate in a custom
+/// section of the produced `.wasm` (see [`SDFMetadata`](crate::sdf::SDFMetadata)), so that the
+/// viewer can display where a model came from. No other items are added to the calling module.
+///
+/// ```ignore
+/// sdf_viewer::export_sdf!(MySDF);
+/// // Or, to customize the construction of the root SDF:
+/// sdf_viewer::export_sdf!(MySDF, MySDF::new(1.0));
+/// ```
+#[macro_export]
+macro_rules! export_sdf {
+    ($sdf:ty) => {
+        $crate::export_sdf!($sdf, <$sdf as ::core::default::Default>::default());
+    };
+    ($sdf:ty, $root:expr) => {
+        const _: () = {
+            /// Entrypoint: only needs to set the root SDFSurface implementation.
+            #[no_mangle]
+            pub extern "C" fn init() {
+                fn assert_exportable<T: $crate::sdf::SDFSurface + 'static>(root: T) -> T {
+                    root
+                }
+                let root: $sdf = assert_exportable($root);
+                $crate::sdf::ffi::set_root_sdf(::std::boxed::Box::new(root));
+            }
+
+            // The section name must match SDFMetadata::CUSTOM_SECTION (attributes only accept literals)
+            #[used]
+            #[cfg_attr(target_arch = "wasm32", link_section = "sdf_viewer_metadata")]
+            static SDF_VIEWER_METADATA_SECTION: [u8; $crate::__sdf_viewer_metadata!().len()] =
+                $crate::sdf::ffi::metadata_section($crate::__sdf_viewer_metadata!());
+        };
+    };
+}
+
+/// The metadata of the crate that expands it, as embedded by [`export_sdf!`](crate::export_sdf)
+/// and parsed by [`SDFMetadata::parse`](crate::sdf::SDFMetadata::parse).
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __sdf_viewer_metadata {
+    () => {
+        concat!("name=", env!("CARGO_PKG_NAME"), "\nversion=", env!("CARGO_PKG_VERSION"), "\n")
+    };
+}
+
+/// Converts the metadata to a fixed-size array that can be placed in a custom section by [`export_sdf!`](crate::export_sdf).
+#[doc(hidden)]
+pub const fn metadata_section<const N: usize>(metadata: &str) -> [u8; N] {
+    let bytes = metadata.as_bytes();
+    let mut res = [0u8; N];
+    let mut i = 0;
+    while i < N {
+        res[i] = bytes[i];
+        i += 1;
+    }
+    res
+}
+
 /// Creates the SDF scene. It only gets called once
 pub fn set_root_sdf(root_sdf: Box<dyn SDFSurface>) {
     REGISTRY.with(|registry| {
         let mut registry_ref_mut = registry.borrow_mut();
+        // The root SDF must always be reachable at ID 0, even if it has an automatically assigned ID
+        let root_sdf: Box<dyn SDFSurface> = if root_sdf.id() != 0 {
+            let root_sdf: Rc<dyn SDFSurface> = Rc::from(root_sdf);
+            registry_ref_mut.insert(0, Box::new(Rc::clone(&root_sdf)));
+            Box::new(root_sdf)
+        } else { root_sdf };
         // Find all children and store them
         let mut to_process = vec![root_sdf];
-        while !to_process.is_empty() {
-            let cur_sdf = to_process.pop().unwrap();
+        while let Some(cur_sdf) = to_process.pop() {
             for ch in cur_sdf.children() {
                 to_process.push(ch);
             }
@@ -35,10 +105,46 @@ pub fn set_root_sdf(root_sdf: Box<dyn SDFSurface>) {
 /// Returns the reference to the already initialized SDF registry, which links each ID to the [`SDFSurface`] implementation.
 fn sdf_registry<R>(f: impl FnOnce(&HashMap<u32, Box<dyn SDFSurface>>) -> R) -> R {
     REGISTRY.with(|registry| {
-        f(&*registry.borrow())
+        f(&registry.borrow())
     })
 }
 
+/// The version of the API implemented by this module (see [`crate::sdf::abi`]).
+#[no_mangle]
+pub extern "C" fn sdf_abi_version() -> u32 {
+    abi::SDF_ABI_VERSION
+}
+
+/// The optional features implemented by this module, as a bitfield (see [`crate::sdf::abi`]).
+#[no_mangle]
+pub extern "C" fn capabilities() -> u32 {
+    abi::CAPABILITY_SAMPLE_BATCH | abi::CAPABILITY_GLSL | abi::CAPABILITY_ALLOCATOR
+}
+
+/// Reserves memory for a buffer that the host will write to (like string parameter values).
+/// Returns a null pointer if the allocation fails.
+#[no_mangle]
+pub extern "C" fn alloc(size: usize, align: usize) -> *mut u8 {
+    match Layout::from_size_align(size.max(1), align.max(1)) {
+        // SAFETY: The layout has a non-zero size.
+        Ok(layout) => unsafe { std::alloc::alloc(layout) },
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Releases the memory previously reserved by [`alloc`] with the same size and alignment.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)] // The host can't call unsafe functions anyway
+pub extern "C" fn dealloc(ptr: *mut u8, size: usize, align: usize) {
+    if ptr.is_null() {
+        return;
+    }
+    if let Ok(layout) = Layout::from_size_align(size.max(1), align.max(1)) {
+        // SAFETY: We assume that the host passes back the same pointer and layout it got from alloc.
+        unsafe { std::alloc::dealloc(ptr, layout) }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn bounding_box(sdf_id: u32) -> Box<[Vector3<f32>; 2]> {
     Box::new(sdf_registry(|r| r.get(&sdf_id)
@@ -69,6 +175,42 @@ pub extern "C" fn sample_free(ret: Box<SDFSample>) {
     drop(ret); // The function is required to free memory. The drop() call is optional but specifies what this should do
 }
 
+thread_local! {
+    /// The input buffer for [`sample_batch`], reused between calls to avoid allocations.
+    static SAMPLE_BATCH_POINTS: RefCell<Vec<Vector3<f32>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns a pointer to a buffer of (at least) `len_bytes` bytes owned by the module, where the caller
+/// should write the points to pass to [`sample_batch`]. It is valid until the next call to this method.
+#[no_mangle]
+pub extern "C" fn sample_batch_points(len_bytes: usize) -> *mut Vector3<f32> {
+    SAMPLE_BATCH_POINTS.with(|points| {
+        let mut points = points.borrow_mut();
+        points.resize(len_bytes / size_of::<Vector3<f32>>(), Vector3::zero());
+        points.as_mut_ptr()
+    })
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)] // The host can't call unsafe functions anyway
+pub extern "C" fn sample_batch(sdf_id: u32, points_ptr: *const Vector3<f32>, points_len_bytes: usize, distance_only: bool) -> Box<PointerLength<SDFSample>> {
+    // SAFETY: We assume that the pointer is valid and that the length is correct (usually from sample_batch_points).
+    let points = if points_ptr.is_null() { &[] } else {
+        unsafe { std::slice::from_raw_parts(points_ptr, points_len_bytes / size_of::<Vector3<f32>>()) }
+    };
+    Box::new(sdf_registry(|r| r.get(&sdf_id)
+        .map(|sdf| PointerLength::from_vec(sdf.sample_batch(points, distance_only)))
+        .unwrap_or_else(|| {
+            eprintln!("Failed to find SDF with ID {}", sdf_id);
+            PointerLength::null()
+        })))
+}
+
+#[no_mangle]
+pub extern "C" fn sample_batch_free(ret: Box<PointerLength<SDFSample>>) {
+    ret.own_again();
+}
+
 /// The structure returned for strings and other arrays.
 #[derive(Debug, Clone)]
 #[repr(C)]
@@ -98,6 +240,15 @@ impl<T> PointerLength<T> {
         unsafe { Vec::from_raw_parts(self.ptr as *mut T, self.len_bytes / size_of::<T>(), self.len_bytes / size_of::<T>()) }
     }
 
+    /// Borrows the referenced memory, without taking ownership.
+    fn as_slice(&self) -> &[T] {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        // SAFETY: We assume that the pointer is valid and that the length is correct.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const T, self.len_bytes / size_of::<T>()) }
+    }
+
     fn null() -> Self {
         PointerLength {
             ptr: std::ptr::null(),
@@ -183,6 +334,20 @@ pub enum SDFParamKindC {
         /// The available options to select from for the parameter. If empty, any string is valid.
         choices: PointerLength<PointerLength<u8>>,
     },
+    Vec3 {
+        /// The range (inclusive) that must contain each component of the value.
+        range: Range<f32>,
+        /// The step size for dragging each component.
+        step: f32,
+    },
+    // No parameters required for colors
+    Color,
+    Angle {
+        /// The range (inclusive, in radians) that must contain the value.
+        range: Range<f32>,
+        /// The step size (in radians) for the slider.
+        step: f32,
+    },
 }
 
 impl SDFParamKindC {
@@ -195,6 +360,9 @@ impl SDFParamKindC {
                 choices: PointerLength::from_vec(choices.iter()
                     .map(|s| PointerLength::from_vec(s.as_bytes().to_vec())).collect()),
             },
+            SDFParamKind::Vec3 { range, step } => SDFParamKindC::Vec3 { range: *range.start()..*range.end(), step: *step },
+            SDFParamKind::Color => SDFParamKindC::Color,
+            SDFParamKind::Angle { range, step } => SDFParamKindC::Angle { range: *range.start()..*range.end(), step: *step },
         }
     }
 }
@@ -207,8 +375,15 @@ pub enum SDFParamValueC {
     Int(i32),
     Float(f32),
     String(PointerLength<u8>),
+    Vec3(Vector3<f32>),
+    Color(Vector3<f32>),
+    Angle(f32),
 }
 
+// Changing the payload of the values requires a new version of the API (see [`crate::sdf::abi`])
+#[cfg(target_arch = "wasm32")]
+const _: () = assert!(size_of::<SDFParamValueC>() == size_of::<u32>() * (1 + abi::param_value_payload_words(abi::SDF_ABI_VERSION)));
+
 impl SDFParamValueC {
     fn from_api(value: &SDFParamValue) -> Self {
         match value {
@@ -217,6 +392,9 @@ impl SDFParamValueC {
             SDFParamValue::Float(f) => SDFParamValueC::Float(*f),
             SDFParamValue::String(s) => SDFParamValueC::String(
                 PointerLength::from_vec(s.as_bytes().to_vec())),
+            SDFParamValue::Vec3(v) => SDFParamValueC::Vec3(*v),
+            SDFParamValue::Color(c) => SDFParamValueC::Color(*c),
+            SDFParamValue::Angle(a) => SDFParamValueC::Angle(*a),
         }
     }
 
@@ -226,7 +404,10 @@ impl SDFParamValueC {
             SDFParamValueC::Int(i) => SDFParamValue::Int(*i),
             SDFParamValueC::Float(f) => SDFParamValue::Float(*f),
             SDFParamValueC::String(s) => SDFParamValue::String(
-                String::from_utf8_lossy(s.clone().own_again().as_slice()).to_string()),
+                String::from_utf8_lossy(s.as_slice()).to_string()), // Borrowed: the host frees it
+            SDFParamValueC::Vec3(v) => SDFParamValue::Vec3(*v),
+            SDFParamValueC::Color(c) => SDFParamValue::Color(*c),
+            SDFParamValueC::Angle(a) => SDFParamValue::Angle(*a),
         }
     }
 }
@@ -262,7 +443,7 @@ pub extern "C" fn parameters_free(ret: Box<PointerLength<SDFParamC>>) {
         param.name.own_again();
         param.description.own_again();
         match param.kind {
-            SDFParamKindC::Boolean { .. } => {}
+            SDFParamKindC::Boolean => {}
             SDFParamKindC::Int { .. } => {}
             SDFParamKindC::Float { .. } => {}
             SDFParamKindC::String { choices } => {
@@ -270,6 +451,9 @@ pub extern "C" fn parameters_free(ret: Box<PointerLength<SDFParamC>>) {
                     previous_value.own_again();
                 }
             }
+            SDFParamKindC::Vec3 { .. } => {}
+            SDFParamKindC::Color => {}
+            SDFParamKindC::Angle { .. } => {}
         }
         match param.value {
             SDFParamValueC::Boolean(_) => {}
@@ -278,6 +462,9 @@ pub extern "C" fn parameters_free(ret: Box<PointerLength<SDFParamC>>) {
             SDFParamValueC::String(s) => {
                 s.own_again();
             }
+            SDFParamValueC::Vec3(_) => {}
+            SDFParamValueC::Color(_) => {}
+            SDFParamValueC::Angle(_) => {}
         }
     }
 }
@@ -319,6 +506,23 @@ pub extern "C" fn changed_free(ret: Box<Option<[Vector3<f32>; 2]>>) {
     drop(ret); // The function is required to free memory. The drop() call is optional but specifies what this should do
 }
 
+#[no_mangle]
+pub extern "C" fn glsl(sdf_id: u32) -> Box<PointerLength<u8>> { // Return pointer and length
+    Box::new(sdf_registry(|r| r.get(&sdf_id)
+        .map(|sdf| {
+            PointerLength::from_vec(sdf.glsl().into_bytes())
+        })
+        .unwrap_or_else(|| {
+            eprintln!("Failed to find SDF with ID {}", sdf_id);
+            PointerLength::null()
+        })))
+}
+
+#[no_mangle]
+pub extern "C" fn glsl_free(ret: Box<PointerLength<u8>>) {
+    ret.own_again();
+}
+
 #[no_mangle]
 pub extern "C" fn normal(sdf_id: u32, p: Vector3<f32>, eps: f32) -> Box<Vector3<f32>> {
     Box::new(sdf_registry(|r| r.get(&sdf_id)
@@ -335,3 +539,94 @@ pub extern "C" fn normal(sdf_id: u32, p: Vector3<f32>, eps: f32) -> Box<Vector3<
 pub extern "C" fn normal_free(ret: Box<Vector3<f32>>) {
     drop(ret); // The function is required to free memory. The drop() call is optional but specifies what this should do
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::sdf::ffi::{glsl, glsl_free, sample, sample_batch, sample_batch_free, sample_batch_points, set_root_sdf};
+    use crate::sdf::primitives::Primitive;
+    use crate::sdf::primitives::solids::Sphere;
+    use crate::sdf::{SDFMetadata, SDFSample, SDFSurface};
+
+    fn fields(s: &SDFSample) -> (f32, Vector3<f32>, f32, f32, f32) {
+        (s.distance, s.color, s.metallic, s.roughness, s.occlusion)
+    }
+
+    #[test]
+    fn test_sample_batch_matches_sample() {
+        set_root_sdf(Box::new(Primitive::new(Sphere::new(1.0))));
+        let points = (0..50).map(|i| Vector3::new(i as f32 * 0.1, -1.0, 0.5)).collect::<Vec<_>>();
+        let len_bytes = std::mem::size_of_val(points.as_slice());
+        let points_ptr = sample_batch_points(len_bytes);
+        // SAFETY: The buffer has room for len_bytes bytes, as the host would write them.
+        unsafe { std::ptr::copy_nonoverlapping(points.as_ptr(), points_ptr, points.len()) };
+        let batch = sample_batch(0, points_ptr, len_bytes, false);
+        let batch_fields = batch.as_slice().iter().map(fields).collect::<Vec<_>>();
+        let single_fields = points.iter().map(|p| fields(&sample(0, *p, false))).collect::<Vec<_>>();
+        assert_eq!(batch_fields, single_fields);
+        sample_batch_free(batch);
+    }
+
+    #[test]
+    fn test_sample_batch_without_points() {
+        set_root_sdf(Box::new(Primitive::new(Sphere::new(1.0))));
+        for (points_ptr, len_bytes) in [(std::ptr::null(), 0), (std::ptr::null(), 12), (sample_batch_points(0) as *const _, 0)] {
+            let batch = sample_batch(0, points_ptr, len_bytes, false);
+            assert!(batch.as_slice().is_empty());
+            sample_batch_free(batch);
+        }
+    }
+
+    /// A sphere that provides custom GLSL code.
+    #[derive(Default)]
+    struct CustomGLSL;
+
+    // The API generated for CustomGLSL, like a module would do
+    crate::export_sdf!(CustomGLSL);
+
+    extern "C" {
+        /// The entrypoint generated by export_sdf!, which is only reachable through its symbol.
+        fn init();
+    }
+
+    impl SDFSurface for CustomGLSL {
+        fn bounding_box(&self) -> [Vector3<f32>; 2] {
+            Primitive::new(Sphere::new(1.0)).bounding_box()
+        }
+
+        fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+            Primitive::new(Sphere::new(1.0)).sample(p, distance_only)
+        }
+
+        fn glsl(&self) -> String {
+            "float sdfCustomDistance(vec3 p) { return length(p) - 1.0; }".to_string()
+        }
+    }
+
+    #[test]
+    fn test_glsl_default_and_custom() {
+        set_root_sdf(Box::new(Primitive::new(Sphere::new(1.0))));
+        let code = glsl(0);
+        assert!(code.as_slice().is_empty());
+        glsl_free(code);
+
+        set_root_sdf(Box::new(CustomGLSL));
+        let code = glsl(0);
+        assert_eq!(code.as_slice(), CustomGLSL.glsl().as_bytes());
+        glsl_free(code);
+    }
+
+    #[test]
+    fn test_export_sdf() {
+        // SAFETY: It is the function defined by export_sdf!, which takes no arguments.
+        unsafe { init() };
+        let code = glsl(0);
+        assert_eq!(code.as_slice(), CustomGLSL.glsl().as_bytes());
+        glsl_free(code);
+        assert_eq!(SDFMetadata::parse(crate::__sdf_viewer_metadata!().as_bytes()), Some(SDFMetadata {
+            crate_name: env!("CARGO_PKG_NAME").to_string(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        }));
+    }
+}
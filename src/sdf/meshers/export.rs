@@ -0,0 +1,313 @@
+//! The file formats that meshes can be exported to, other than PLY (see [`Mesh::serialize_ply`]).
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use crate::metadata::short_version_info;
+use crate::sdf::meshers::mesh::{Mesh, Vertex};
+
+/// The name of the only material of the exported models.
+const MATERIAL_NAME: &str = "sdf";
+
+/// A file format for the exported mesh.
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshFormat {
+    /// ASCII PLY, with all the data of the vertices (including the materials).
+    Ply,
+    /// Binary STL, with only the triangles (as used by slicers for 3D printing).
+    Stl,
+    /// ASCII STL, with only the triangles.
+    StlAscii,
+    /// Wavefront OBJ with vertex colors, and an MTL material library with the average material.
+    Obj,
+    /// Binary glTF, with vertex colors and the other PBR values as custom vertex attributes.
+    Glb,
+}
+
+impl MeshFormat {
+    /// Detects the format from the extension of the given path.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "ply" => Some(Self::Ply),
+            "stl" => Some(Self::Stl),
+            "obj" => Some(Self::Obj),
+            "glb" => Some(Self::Glb),
+            _ => None,
+        }
+    }
+
+    /// Whether the output is not text, so it can't be displayed.
+    pub fn is_binary(self) -> bool {
+        matches!(self, Self::Stl | Self::Glb)
+    }
+
+    /// Serializes the mesh in this format. OBJ models reference the material library with the given
+    /// file name, if any (see [`Mesh::serialize_mtl`]).
+    pub fn serialize<T: Write>(self, mesh: &Mesh, out: &mut T, mtllib: Option<&str>) -> std::io::Result<usize> {
+        match self {
+            Self::Ply => mesh.serialize_ply(out),
+            Self::Stl => mesh.serialize_stl(out),
+            Self::StlAscii => mesh.serialize_stl_ascii(out),
+            Self::Obj => mesh.serialize_obj(out, mtllib),
+            Self::Glb => mesh.serialize_glb(out),
+        }
+    }
+}
+
+impl Mesh {
+    /// The corners and the normal of each triangle.
+    fn triangles(&self) -> impl Iterator<Item=([Vector3<f32>; 3], Vector3<f32>)> + '_ {
+        self.indices.chunks_exact(3).map(|triangle| {
+            let corners = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize].position);
+            let normal = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+            let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { Vector3::zero() };
+            (corners, normal)
+        })
+    }
+
+    /// The average of the given value over all vertices.
+    fn average<V: std::ops::Add<Output=V> + std::ops::Div<f32, Output=V>>(&self, zero: V, value: impl Fn(&Vertex) -> V) -> V {
+        self.vertices.iter().fold(zero, |sum, vertex| sum + value(vertex)) / self.vertices.len().max(1) as f32
+    }
+
+    /// Serializes the mesh to a binary STL model file (only the geometry).
+    pub fn serialize_stl<T: Write>(&self, out: &mut T) -> std::io::Result<usize> {
+        let mut header = [b' '; 80];
+        let comment = format!("Created with {}", short_version_info());
+        let comment = &comment.as_bytes()[..comment.len().min(header.len())];
+        header[..comment.len()].copy_from_slice(comment);
+        let triangle_count = self.indices.len() / 3;
+        let mut bytes = Vec::with_capacity(84 + triangle_count * 50);
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(&(triangle_count as u32).to_le_bytes());
+        for (corners, normal) in self.triangles() {
+            for v in [normal, corners[0], corners[1], corners[2]] {
+                for c in [v.x, v.y, v.z] {
+                    bytes.extend_from_slice(&c.to_le_bytes());
+                }
+            }
+            bytes.extend_from_slice(&0u16.to_le_bytes()); // Attribute byte count
+        }
+        out.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    /// Serializes the mesh to an ASCII STL model file (only the geometry).
+    pub fn serialize_stl_ascii<T: Write>(&self, out: &mut T) -> std::io::Result<usize> {
+        let mut text = format!("solid {MATERIAL_NAME}\n");
+        for (corners, n) in self.triangles() {
+            let _ = writeln!(text, "  facet normal {:e} {:e} {:e}\n    outer loop", n.x, n.y, n.z);
+            for p in corners {
+                let _ = writeln!(text, "      vertex {:e} {:e} {:e}", p.x, p.y, p.z);
+            }
+            text.push_str("    endloop\n  endfacet\n");
+        }
+        let _ = writeln!(text, "endsolid {MATERIAL_NAME}");
+        out.write_all(text.as_bytes())?;
+        Ok(text.len())
+    }
+
+    /// Serializes the mesh to a Wavefront OBJ model file, with the (non-standard but widely supported)
+    /// vertex colors after the positions. It uses the material of the given material library, if any.
+    pub fn serialize_obj<T: Write>(&self, out: &mut T, mtllib: Option<&str>) -> std::io::Result<usize> {
+        let mut text = format!("# Created with {}\n", short_version_info());
+        if let Some(mtllib) = mtllib {
+            let _ = writeln!(text, "mtllib {mtllib}\nusemtl {MATERIAL_NAME}");
+        }
+        for v in &self.vertices {
+            let (p, c) = (v.position, v.color);
+            let _ = writeln!(text, "v {} {} {} {} {} {}", p.x, p.y, p.z, c.x, c.y, c.z);
+        }
+        for v in &self.vertices {
+            let _ = writeln!(text, "vn {} {} {}", v.normal.x, v.normal.y, v.normal.z);
+        }
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] + 1);
+            let _ = writeln!(text, "f {a}//{a} {b}//{b} {c}//{c}");
+        }
+        out.write_all(text.as_bytes())?;
+        Ok(text.len())
+    }
+
+    /// Serializes the material library of [`Mesh::serialize_obj`]: a single material with the average
+    /// color of the vertices, and the average metallic and roughness (PBR extension of MTL).
+    pub fn serialize_mtl<T: Write>(&self, out: &mut T) -> std::io::Result<usize> {
+        let color = self.average(Vector3::zero(), |v| v.color);
+        let metallic = self.average(0.0, |v| v.metallic);
+        let roughness = self.average(0.0, |v| v.roughness);
+        let text = format!("# Created with {}\nnewmtl {MATERIAL_NAME}\nKa 0 0 0\nKd {} {} {}\nKs 0 0 0\nd 1\nillum 1\nPm {metallic}\nPr {roughness}\n",
+                           short_version_info(), color.x, color.y, color.z);
+        out.write_all(text.as_bytes())?;
+        Ok(text.len())
+    }
+
+    /// Serializes the mesh to a binary glTF (GLB) model file.
+    ///
+    /// The colors of the vertices are stored as `COLOR_0`, which multiplies the white base color of
+    /// the PBR material. glTF only supports varying metallic, roughness and occlusion through
+    /// textures, so they are stored as the custom `_METALLIC`, `_ROUGHNESS` and `_OCCLUSION` vertex
+    /// attributes instead, and the material uses the average metallic and roughness of the vertices.
+    /// Empty meshes can't be exported, as glTF requires some geometry.
+    pub fn serialize_glb<T: Write>(&self, out: &mut T) -> std::io::Result<usize> {
+        const ARRAY_BUFFER: u32 = 34962;
+        const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+        const FLOAT: u32 = 5126;
+        const UNSIGNED_INT: u32 = 5125;
+
+        if self.indices.len() < 3 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Can't export an empty mesh as glTF"));
+        }
+
+        // Normals must have unit length, so fall back to the normal of the faces for missing ones
+        let mut face_normals = vec![Vector3::zero(); self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize].position);
+            let area_normal = (b - a).cross(c - a);
+            triangle.iter().for_each(|i| face_normals[*i as usize] += area_normal);
+        }
+        let normals = self.vertices.iter().zip(face_normals).map(|(v, face_normal)| {
+            if v.normal.magnitude2() > 0.0 { v.normal.normalize() }
+            else if face_normal.magnitude2() > 0.0 { face_normal.normalize() } else { Vector3::unit_z() }
+        }).collect::<Vec<_>>();
+
+        // The binary buffer has a view and an accessor for each attribute, and another one for the indices
+        let mut buffer = Vec::<u8>::new();
+        let mut buffer_views = vec![];
+        let mut accessors = vec![];
+        let mut add_view = |buffer: &mut Vec<u8>, values: Vec<[u8; 4]>, target: u32, component_type: u32, kind: &str, extra: String| {
+            let offset = buffer.len();
+            let count = values.len() / if kind == "VEC3" { 3 } else { 1 };
+            buffer.extend(values.into_iter().flatten());
+            buffer_views.push(format!(r#"{{"buffer":0,"byteOffset":{offset},"byteLength":{},"target":{target}}}"#, buffer.len() - offset));
+            accessors.push(format!(r#"{{"bufferView":{},"componentType":{component_type},"count":{count},"type":"{kind}"{extra}}}"#,
+                                   buffer_views.len() - 1));
+            accessors.len() - 1
+        };
+        let vec3s = |values: &mut dyn Iterator<Item=Vector3<f32>>|
+            values.flat_map(|v| [v.x, v.y, v.z].map(f32::to_le_bytes)).collect::<Vec<_>>();
+        let scalars = |value: fn(&Vertex) -> f32| self.vertices.iter().map(|v| value(v).to_le_bytes()).collect::<Vec<_>>();
+
+        let (min, max) = self.vertices.iter().fold(
+            (Vector3::new(f32::MAX, f32::MAX, f32::MAX), Vector3::new(f32::MIN, f32::MIN, f32::MIN)),
+            |(min, max), v| (min.zip(v.position, f32::min), max.zip(v.position, f32::max)));
+        let bounds = format!(r#","min":[{},{},{}],"max":[{},{},{}]"#, min.x, min.y, min.z, max.x, max.y, max.z);
+        let position = add_view(&mut buffer, vec3s(&mut self.vertices.iter().map(|v| v.position)), ARRAY_BUFFER, FLOAT, "VEC3", bounds);
+        let normal = add_view(&mut buffer, vec3s(&mut normals.into_iter()), ARRAY_BUFFER, FLOAT, "VEC3", String::new());
+        let color = add_view(&mut buffer, vec3s(&mut self.vertices.iter().map(|v| v.color)), ARRAY_BUFFER, FLOAT, "VEC3", String::new());
+        let metallic = add_view(&mut buffer, scalars(|v| v.metallic), ARRAY_BUFFER, FLOAT, "SCALAR", String::new());
+        let roughness = add_view(&mut buffer, scalars(|v| v.roughness), ARRAY_BUFFER, FLOAT, "SCALAR", String::new());
+        let occlusion = add_view(&mut buffer, scalars(|v| v.occlusion), ARRAY_BUFFER, FLOAT, "SCALAR", String::new());
+        let indices = add_view(&mut buffer, self.indices.iter().map(|i| i.to_le_bytes()).collect(),
+                               ELEMENT_ARRAY_BUFFER, UNSIGNED_INT, "SCALAR", String::new());
+
+        let mut json = format!(concat!(
+            r#"{{"asset":{{"version":"2.0","generator":"{generator}"}},"scene":0,"scenes":[{{"nodes":[0]}}],"#,
+            r#""nodes":[{{"mesh":0}}],"meshes":[{{"name":"{name}","primitives":[{{"attributes":{{"#,
+            r#""POSITION":{position},"NORMAL":{normal},"COLOR_0":{color},"#,
+            r#""_METALLIC":{metallic},"_ROUGHNESS":{roughness},"_OCCLUSION":{occlusion}}},"#,
+            r#""indices":{indices},"material":0,"mode":4}}]}}],"#,
+            r#""materials":[{{"name":"{name}","pbrMetallicRoughness":{{"baseColorFactor":[1,1,1,1],"#,
+            r#""metallicFactor":{metallic_factor},"roughnessFactor":{roughness_factor}}}}}],"#,
+            r#""buffers":[{{"byteLength":{buffer_length}}}],"bufferViews":[{buffer_views}],"accessors":[{accessors}]}}"#),
+            generator = short_version_info().replace(['"', '\\'], ""), name = MATERIAL_NAME,
+            position = position, normal = normal, color = color, metallic = metallic, roughness = roughness,
+            occlusion = occlusion, indices = indices,
+            metallic_factor = self.average(0.0, |v| v.metallic).clamp(0.0, 1.0),
+            roughness_factor = self.average(0.0, |v| v.roughness).clamp(0.0, 1.0),
+            buffer_length = buffer.len(), buffer_views = buffer_views.join(","), accessors = accessors.join(","));
+
+        // Chunks must be aligned to 4 bytes, padding the JSON with spaces and the buffer with zeros
+        while json.len() % 4 != 0 {
+            json.push(' ');
+        }
+        buffer.resize(buffer.len().div_ceil(4) * 4, 0);
+        let total_length = 12 + 8 + json.len() + 8 + buffer.len();
+        let mut bytes = Vec::with_capacity(total_length);
+        bytes.extend_from_slice(b"glTF");
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&(total_length as u32).to_le_bytes());
+        bytes.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"JSON");
+        bytes.extend_from_slice(json.as_bytes());
+        bytes.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"BIN\0");
+        bytes.extend_from_slice(&buffer);
+        out.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use cgmath::vec3;
+
+    use crate::sdf::meshers::export::MeshFormat;
+    use crate::sdf::meshers::mesh::{Mesh, Vertex};
+
+    /// A tetrahedron with a different material in each vertex.
+    fn tetrahedron() -> Mesh {
+        let positions = [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0)];
+        Mesh {
+            vertices: positions.iter().enumerate().map(|(i, position)| Vertex {
+                position: *position,
+                normal: *position,
+                color: vec3(i as f32 / 4.0, 0.5, 1.0),
+                metallic: i as f32 / 4.0,
+                roughness: 0.5,
+                occlusion: 1.0,
+            }).collect(),
+            indices: vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn test_export_formats() {
+        assert_eq!(MeshFormat::from_path(Path::new("out/mesh.GLB")), Some(MeshFormat::Glb));
+        assert_eq!(MeshFormat::from_path(Path::new("mesh")), None);
+        let mesh = tetrahedron();
+
+        let mut stl = vec![];
+        assert_eq!(MeshFormat::Stl.serialize(&mesh, &mut stl, None).unwrap(), 84 + 4 * 50);
+        let mut stl_ascii = vec![];
+        MeshFormat::StlAscii.serialize(&mesh, &mut stl_ascii, None).unwrap();
+        let mut obj = vec![];
+        MeshFormat::Obj.serialize(&mesh, &mut obj, Some("mesh.mtl")).unwrap();
+        assert!(String::from_utf8(obj.clone()).unwrap().contains("mtllib mesh.mtl\nusemtl sdf\n"));
+        let mut mtl = vec![];
+        mesh.serialize_mtl(&mut mtl).unwrap();
+        assert!(String::from_utf8(mtl).unwrap().contains("Pm 0.375\nPr 0.5\n"));
+        #[cfg(feature = "mesh_inputs")]
+        for (bytes, path) in [(&stl, "mesh.stl"), (&stl_ascii, "mesh.stl"), (&obj, "mesh.obj")] {
+            let parsed = crate::sdf::trimesh::format::parse_mesh(bytes, Some(path)).unwrap();
+            assert_eq!(parsed.triangles.len(), 4, "{path}");
+        }
+
+        let mut glb = vec![];
+        let length = MeshFormat::Glb.serialize(&mesh, &mut glb, None).unwrap();
+        assert_eq!(length, glb.len());
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize, glb.len());
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(json_length % 4, 0);
+        let json = std::str::from_utf8(&glb[20..20 + json_length]).unwrap();
+        assert!(json.contains(r#""_METALLIC":3,"_ROUGHNESS":4,"_OCCLUSION":5},"indices":6"#), "{json}");
+        assert!(json.contains(r#""metallicFactor":0.375,"roughnessFactor":0.5"#), "{json}");
+        let buffer_length = u32::from_le_bytes(glb[20 + json_length..24 + json_length].try_into().unwrap()) as usize;
+        assert_eq!(buffer_length, 4 * (3 * 3 * 4 + 3 * 4 + 12)); // The vertices are shared by the triangles
+        let buffer = &glb[28 + json_length..28 + json_length + buffer_length];
+        let floats = |offset: usize, count: usize| buffer[offset..offset + 4 * count].chunks_exact(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect::<Vec<_>>();
+        // The normals are normalized, falling back to the normal of the faces
+        let s = 1.0 / 3f32.sqrt();
+        assert_eq!(floats(48, 6), vec![-s, -s, -s, 1.0, 0.0, 0.0]);
+        assert_eq!(floats(3 * 48, 4), vec![0.0, 0.25, 0.5, 0.75]);
+
+        let empty = Mesh::default();
+        assert!(MeshFormat::Glb.serialize(&empty, &mut vec![], None).is_err());
+    }
+}
@@ -1,3 +1,12 @@
+//! The meshers of the isosurface crate, which polygonize the whole volume on a single thread.
+//!
+//! They can't be split into blocks like `parallel-marching-tetrahedra`: each algorithm
+//! extracts the full unit cube from a single source in one pass (the hashed and dual contouring
+//! ones also build an octree of the whole volume), with no way to mesh a sub-volume and stitch the
+//! borders. Only their sampling runs in parallel (see [`SDFSurfaceWrapper`]).
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::ops::{Add, Sub};
 
 use cgmath::{ElementWise, vec3, Vector3};
@@ -11,15 +20,17 @@ use isosurface::source::{HermiteSource, ScalarSource};
 
 use crate::sdf::meshers::Config;
 use crate::sdf::meshers::mesh::{Mesh, Vertex};
+use crate::sdf::meshers::progress::{Cancelled, MeshingProgress};
 use crate::sdf::SDFSurface;
 
-pub(crate) fn mesh(algorithm: u8, cfg: Config, sdf: &dyn SDFSurface) -> Mesh {
+pub(crate) fn mesh(algorithm: u8, cfg: Config, sdf: &(dyn SDFSurface + Send + Sync), progress: &MeshingProgress) -> Result<Mesh, Cancelled> {
     // Set up algorithm outputs
     let mut vertices = vec![];
     let mut indices = vec![];
 
-    // Run the algorithm
-    let surface_wrapper = SDFSurfaceWrapper { sdf };
+    // Run the algorithm (reporting each new slice of the grid as progress)
+    progress.start_stage("Meshing", cfg.max_voxels_per_axis as u64 + 1);
+    let surface_wrapper = SDFSurfaceWrapper::new(sdf, cfg.max_voxels_per_axis, progress.clone());
     let mut extractor = IndexedInterleavedNormals::new(&mut vertices, &mut indices, &surface_wrapper);
     match algorithm {
         0 => { // Marching Cubes
@@ -47,6 +58,8 @@ pub(crate) fn mesh(algorithm: u8, cfg: Config, sdf: &dyn SDFSurface) -> Mesh {
         // TODO: More algorithms
         _ => panic!("Unsupported algorithm"),
     };
+    // The algorithms can't be interrupted, but they finish quickly without sampling after a cancellation
+    progress.check_cancelled()?;
 
     // Convert outputs to our mesh
     let vertices = vertices
@@ -59,42 +72,181 @@ pub(crate) fn mesh(algorithm: u8, cfg: Config, sdf: &dyn SDFSurface) -> Mesh {
                 ..Vertex::default() // NOTE: Unsupported by this mesher (use post-processing shared tool)
             }
         }).collect();
-    Mesh {
+    Ok(Mesh {
         vertices,
         indices,
-    }
+    })
 }
 
-struct SDFSurfaceWrapper<S: SDFSurface> {
+/// The maximum number of cached slices of the sampling grid (see [`SDFSurfaceWrapper`]).
+const MAX_CACHED_SLICES: usize = 4;
+
+/// How far (in the unit cube) a point can be from a point of the grid to use its cached sample. It only
+/// covers the rounding errors of computing the points of the grid, so that points inside the cells
+/// (like the vertices placed by some algorithms) are always sampled where requested.
+const GRID_TOLERANCE: f32 = 8.0 * f32::EPSILON;
+
+/// Adapts an [`SDFSurface`] to the sources required by the meshing algorithms.
+///
+/// Meshers sample the SDF one point at a time, but mostly in a regular grid. To reduce the per-call
+/// overhead, each slice (constant Z) of the grid is sampled at once using [`SDFSurface::sample_batch`]
+/// the first time one of its points is requested (splitting it in rows that are sampled in parallel,
+/// if enabled), and cached for the following requests.
+///
+/// The first time that each slice is sampled is reported as progress. This is exact for algorithms
+/// that sweep the grid (like marching cubes) and an approximation for the rest. Once the job is
+/// cancelled, the SDF is no longer sampled and the whole volume is reported as empty.
+struct SDFSurfaceWrapper<S: SDFSurface + Sync> {
     sdf: S,
+    /// A cache of the bounding box of the SDF.
+    bb: [Vector3<f32>; 2],
+    /// The number of cells per axis of the regular grid.
+    grid_cells: usize,
+    /// The most recently used slices of the grid, indexed by Z.
+    slices: RefCell<VecDeque<(usize, Vec<f32>)>>,
+    /// The maximum number of slices to cache, or 0 to sample every point on its own.
+    max_cached_slices: usize,
+    /// Whether each slice was already sampled, to report the progress only once per slice.
+    sampled_slices: RefCell<Vec<bool>>,
+    progress: MeshingProgress,
 }
 
-impl<S: SDFSurface> Sample<Signed> for SDFSurfaceWrapper<S> {
+impl<S: SDFSurface + Sync> Sample<Signed> for SDFSurfaceWrapper<S> {
     fn sample(&self, p: Vec3) -> Signed {
         self.sample_scalar(p)
     }
 }
 
-impl<S: SDFSurface> ScalarSource for SDFSurfaceWrapper<S> {
+impl<S: SDFSurface + Sync> ScalarSource for SDFSurfaceWrapper<S> {
     fn sample_scalar(&self, p: Vec3) -> Signed {
+        if self.progress.is_cancelled() {
+            return Signed(1.0);
+        }
+        // Use the batched grid samples if possible
+        if let Some(distance) = self.sample_grid(p) {
+            return Signed(distance);
+        }
         // Perform the sample
         let sample = self.sdf.sample(self.vert_pos_to(p), true);
         Signed(sample.distance)
     }
 }
 
-impl<S: SDFSurface> HermiteSource for SDFSurfaceWrapper<S> {
+impl<S: SDFSurface + Sync> HermiteSource for SDFSurfaceWrapper<S> {
     fn sample_normal(&self, p: Vec3) -> Vec3 {
+        if self.progress.is_cancelled() {
+            return Vec3::new(0.0, 0.0, 1.0);
+        }
         // Perform the sample
         let sample = self.sdf.normal(self.vert_pos_to(p), None);
         Vec3::new(sample.x, sample.y, sample.z) // NOTE: Flip normals for this mesher
     }
 }
 
-impl<S: SDFSurface> SDFSurfaceWrapper<S> {
+impl<S: SDFSurface + Sync> SDFSurfaceWrapper<S> {
+    fn new(sdf: S, grid_cells: usize, progress: MeshingProgress) -> Self {
+        let bb = sdf.bounding_box();
+        Self {
+            sdf,
+            bb,
+            grid_cells,
+            slices: RefCell::new(VecDeque::with_capacity(MAX_CACHED_SLICES)),
+            max_cached_slices: MAX_CACHED_SLICES,
+            sampled_slices: RefCell::new(vec![false; grid_cells + 1]),
+            progress,
+        }
+    }
+
     fn vert_pos_to(&self, p: Vec3) -> Vector3<f32> {
         // Convert (0,0,0)-(1,1,1) to the bounding box
-        let bb = self.sdf.bounding_box();
-        vec3(p.x, p.y, p.z).mul_element_wise(bb[1].sub(bb[0])).add(bb[0])
+        vec3(p.x, p.y, p.z).mul_element_wise(self.bb[1].sub(self.bb[0])).add(self.bb[0])
+    }
+
+    /// Returns the distance at the given point if it lies on the regular grid, sampling the whole
+    /// slice that contains it if it was not cached.
+    fn sample_grid(&self, p: Vec3) -> Option<f32> {
+        if self.max_cached_slices == 0 {
+            return None;
+        }
+        let to_grid_index = |v: f32| {
+            // The indices are in cells of size 1/grid_cells, which scales the tolerance
+            let index = v * self.grid_cells as f32;
+            let index_rounded = index.round();
+            if (index - index_rounded).abs() > GRID_TOLERANCE * self.grid_cells as f32
+                || index_rounded < 0.0 || index_rounded > self.grid_cells as f32 {
+                None
+            } else {
+                Some(index_rounded as usize)
+            }
+        };
+        let (x, y, z) = (to_grid_index(p.x)?, to_grid_index(p.y)?, to_grid_index(p.z)?);
+        let side = self.grid_cells + 1;
+        let mut slices = self.slices.borrow_mut();
+        let slice_index = match slices.iter().position(|(slice_z, _)| *slice_z == z) {
+            Some(slice_index) => slice_index,
+            None => {
+                let points = (0..side * side)
+                    .map(|i| self.vert_pos_to(Vec3::new(
+                        (i % side) as f32 / self.grid_cells as f32,
+                        (i / side) as f32 / self.grid_cells as f32,
+                        z as f32 / self.grid_cells as f32)))
+                    .collect::<Vec<_>>();
+                #[cfg(all(feature = "parallel_meshers", not(target_arch = "wasm32")))]
+                let samples: Vec<_> = {
+                    use rayon::prelude::*;
+                    let sdf = &self.sdf; // The rest of the wrapper is not thread-safe
+                    points.par_chunks(side).flat_map_iter(|row| sdf.sample_batch(row, true)).collect()
+                };
+                #[cfg(not(all(feature = "parallel_meshers", not(target_arch = "wasm32"))))]
+                let samples = self.sdf.sample_batch(&points, true);
+                let distances = samples.into_iter().map(|sample| sample.distance).collect();
+                if slices.len() >= self.max_cached_slices {
+                    slices.pop_back();
+                }
+                slices.push_front((z, distances));
+                if !std::mem::replace(&mut self.sampled_slices.borrow_mut()[z], true) {
+                    self.progress.advance(1);
+                }
+                0
+            }
+        };
+        Some(slices[slice_index].1[y * side + x])
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use isosurface::distance::Signed;
+    use isosurface::extractor::IndexedInterleavedNormals;
+    use isosurface::MarchingCubes;
+
+    use crate::sdf::meshers::isosurface::SDFSurfaceWrapper;
+    use crate::sdf::meshers::progress::MeshingProgress;
+    use crate::sdf::primitives::Primitive;
+    use crate::sdf::primitives::solids::Sphere;
+
+    #[test]
+    fn test_slice_cache_matches_direct_samples() {
+        let sdf = Primitive::new(Sphere::new(1.0));
+        let grid_cells = 16;
+        let extract = |max_cached_slices: usize| {
+            let mut surface_wrapper = SDFSurfaceWrapper::new(&sdf, grid_cells, MeshingProgress::default());
+            surface_wrapper.max_cached_slices = max_cached_slices;
+            let (mut vertices, mut indices) = (vec![], vec![]);
+            let mut extractor = IndexedInterleavedNormals::new(&mut vertices, &mut indices, &surface_wrapper);
+            MarchingCubes::<Signed>::new(grid_cells).extract(&surface_wrapper, &mut extractor);
+            let sampled_slices = surface_wrapper.sampled_slices.borrow().iter().filter(|sampled| **sampled).count();
+            (vertices, indices, sampled_slices)
+        };
+        let (cached_vertices, cached_indices, sampled_slices) = extract(super::MAX_CACHED_SLICES);
+        let (vertices, indices, _) = extract(0);
+        // Marching cubes only samples the points of the grid, so every slice comes from the cache
+        assert_eq!(sampled_slices, grid_cells + 1);
+        assert!(!indices.is_empty());
+        assert_eq!(cached_indices, indices);
+        assert_eq!(cached_vertices.len(), vertices.len());
+        for (cached, direct) in cached_vertices.iter().zip(&vertices) {
+            assert!((cached - direct).abs() < 1e-5, "{cached} != {direct}");
+        }
+    }
+}
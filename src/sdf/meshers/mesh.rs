@@ -3,8 +3,15 @@ use std::io::Write;
 use cgmath::{MetricSpace, vec3, Vector3, Zero};
 
 use crate::metadata::short_version_info;
+use crate::sdf::meshers::progress::{Cancelled, MeshingProgress};
 use crate::sdf::SDFSurface;
 
+/// The number of vertices that are post-processed at once, between progress reports.
+const POSTPROC_BATCH_SIZE: usize = 4096;
+
+/// The distance to the samples that compute the normals.
+const NORMAL_EPS: f32 = 0.001;
+
 /// Mesh stores all data that can be obtained from a [`sdf_viewer::sdf::SDFSurface`] trait.
 /// Note that this is a lossy representation of the SDF, you should store the wasm file to be able
 /// to recreate a mesh with more quality later.
@@ -19,17 +26,42 @@ pub struct Mesh {
 impl Mesh {
     /// Retrieves the materials for each vertex from the SDF. It also fills the normals if unset.
     /// This is useful for meshers that don't write materials (most of them).
-    pub fn postproc<S: SDFSurface>(&mut self, sdf: &S) {
-        for v in &mut self.vertices {
-            let sample = sdf.sample(vec3(v.position[0], v.position[1], v.position[2]), false);
-            if v.normal.distance2(Vector3::zero()) < 0.0001 {
-                v.normal = sdf.normal(vec3(v.position[0], v.position[1], v.position[2]), None);
+    /// Batches of vertices are processed in parallel, if enabled.
+    pub fn postproc<S: SDFSurface + Sync>(&mut self, sdf: &S, progress: &MeshingProgress) -> Result<(), Cancelled> {
+        progress.start_stage("Post-processing", self.vertices.len() as u64);
+        let postproc_batch = |vertices: &mut [Vertex]| {
+            progress.check_cancelled()?;
+            let positions = vertices.iter()
+                .map(|v| vec3(v.position[0], v.position[1], v.position[2]))
+                .collect::<Vec<_>>();
+            let samples = sdf.sample_batch(&positions, false);
+            // The missing normals are computed at once, which SDFs without custom normals batch
+            let missing_normals = vertices.iter().enumerate()
+                .filter(|(_, v)| v.normal.distance2(Vector3::zero()) < 0.0001)
+                .map(|(i, _)| i).collect::<Vec<_>>();
+            if !missing_normals.is_empty() {
+                let missing_positions = missing_normals.iter().map(|i| positions[*i]).collect::<Vec<_>>();
+                let normals = sdf.normal_batch(&missing_positions, Some(NORMAL_EPS));
+                for (i, normal) in missing_normals.into_iter().zip(normals) {
+                    vertices[i].normal = normal;
+                }
+            }
+            for (v, sample) in vertices.iter_mut().zip(samples) {
+                v.color = sample.color;
+                v.metallic = sample.metallic;
+                v.roughness = sample.roughness;
+                v.occlusion = sample.occlusion;
             }
-            v.color = sample.color;
-            v.metallic = sample.metallic;
-            v.roughness = sample.roughness;
-            v.occlusion = sample.occlusion;
+            progress.advance(vertices.len() as u64);
+            Ok(())
+        };
+        #[cfg(all(feature = "parallel_meshers", not(target_arch = "wasm32")))]
+        {
+            use rayon::prelude::*;
+            self.vertices.par_chunks_mut(POSTPROC_BATCH_SIZE).try_for_each(postproc_batch)
         }
+        #[cfg(not(all(feature = "parallel_meshers", not(target_arch = "wasm32"))))]
+        self.vertices.chunks_mut(POSTPROC_BATCH_SIZE).try_for_each(postproc_batch)
     }
 
     /// Serializes the mesh to a PLY model file.
@@ -153,3 +185,66 @@ impl Default for Vertex {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Vector3};
+
+    use crate::sdf::meshers::mesh::{Mesh, Vertex};
+    use crate::sdf::meshers::progress::MeshingProgress;
+    use crate::sdf::primitives::Primitive;
+    use crate::sdf::primitives::solids::Sphere;
+    use crate::sdf::{SDFSample, SDFSurface};
+
+    /// A unit sphere whose custom normals always point up, unlike its gradient.
+    struct UpNormals;
+
+    impl SDFSurface for UpNormals {
+        fn bounding_box(&self) -> [Vector3<f32>; 2] {
+            [Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0)]
+        }
+
+        fn sample(&self, p: Vector3<f32>, _distance_only: bool) -> SDFSample {
+            SDFSample::new(p.magnitude() - 1.0, Vector3::new(1.0, 0.0, 0.0))
+        }
+
+        fn normal(&self, _p: Vector3<f32>, _eps: Option<f32>) -> Vector3<f32> {
+            Vector3::new(0.0, 0.0, 1.0)
+        }
+    }
+
+    #[test]
+    fn test_postproc_fills_missing_normals() {
+        let given_normal = Vector3::new(0.0, 1.0, 0.0);
+        let mut mesh = Mesh {
+            vertices: vec![
+                Vertex { position: Vector3::new(1.0, 0.0, 0.0), ..Vertex::default() },
+                Vertex { position: Vector3::new(0.0, 0.6, 0.8), ..Vertex::default() },
+                Vertex { position: Vector3::new(0.0, 0.0, 1.0), normal: given_normal, ..Vertex::default() },
+            ],
+            indices: vec![0, 1, 2],
+        };
+        mesh.postproc(&Primitive::new(Sphere::new(1.0)), &MeshingProgress::default()).unwrap();
+        for vertex in &mesh.vertices[..2] {
+            assert!((vertex.normal - vertex.position).magnitude() < 1e-3, "{:?}", vertex);
+        }
+        assert_eq!(mesh.vertices[2].normal, given_normal);
+    }
+
+    #[test]
+    fn test_postproc_uses_custom_normals() {
+        let mut mesh = Mesh {
+            vertices: vec![
+                Vertex { position: Vector3::new(1.0, 0.0, 0.0), ..Vertex::default() },
+                Vertex { position: Vector3::new(0.0, 0.6, 0.8), ..Vertex::default() },
+                Vertex { position: Vector3::new(0.0, 0.0, 1.0), ..Vertex::default() },
+            ],
+            indices: vec![0, 1, 2],
+        };
+        mesh.postproc(&UpNormals, &MeshingProgress::default()).unwrap();
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal, Vector3::new(0.0, 0.0, 1.0), "{:?}", vertex);
+            assert_eq!(vertex.color, Vector3::new(1.0, 0.0, 0.0), "{:?}", vertex);
+        }
+    }
+}
@@ -7,37 +7,69 @@ use std::path::PathBuf;
 use clap::ValueHint;
 use tokio::sync::mpsc;
 
+use export::MeshFormat;
 use mesh::Mesh;
+use progress::{Cancelled, MeshingProgress};
 
 use crate::sdf::SDFSurface;
 use crate::sdf::wasm::load;
 use crate::sdf::wasm::load::spawn_async;
+use crate::sdf::wasm::wasi::CliWasiEnv;
 
+pub mod export;
 mod mesh;
+pub mod progress;
 
 #[cfg(feature = "isosurface")]
 mod isosurface;
 
+#[cfg(all(feature = "parallel_meshers", not(target_arch = "wasm32")))]
+mod parallel;
+
 /// Export your SDF by converting it to a triangle mesh compatible with most 3D modelling tools.
 #[derive(clap::Parser, Debug, Clone, PartialEq, Eq, Default)]
 pub struct CliMesher {
-    /// Input file or URL: .wasm file representing a SDF.
+    /// Input file or URL: .wasm file representing a SDF (or a native .so, .dylib or .dll library with the same API).
+    /// It may also be a voxel grid: a NRRD (.nrrd, .nhdr) or MetaImage (.mha, .mhd) volume, or a
+    /// triangle mesh (.obj, .stl or .ply) to be remeshed from its signed distance.
+    /// Set to "-" to read the SDF from stdin, like `cargo build ... && cat sdf.wasm | sdf-viewer mesh -i - ...`.
     /// If using the GUI, this will be overwritten with the current root SDF.
     #[clap(short, long = "input", default_value = "")]
     pub input: String,
-    /// Output file: .ply 3D model made of triangles. Set to "-" to write to stdout/GUI window.
+    /// Output file: .ply, .stl, .obj (with a .mtl file next to it) or .glb 3D model made of triangles.
+    /// Set to "-" to write to stdout/GUI window.
     /// WARNING: Output to GUI window may be too laggy for large models.
     #[clap(short, long = "output", parse(from_os_str), value_hint = ValueHint::FilePath, default_value = "mesh.ply")]
     pub output_file: PathBuf,
+    /// The format of the output model. Defaults to the one of the output file extension, or PLY.
+    #[clap(long, arg_enum)]
+    pub format: Option<MeshFormat>,
     #[clap(flatten)]
     pub cfg: Config,
+    #[clap(flatten)]
+    pub wasi: CliWasiEnv,
     #[clap(subcommand)]
     pub mesher: Meshers,
 }
 
 impl CliMesher {
-    /// Runs the CLI for the mesher, using all the configured parameters.
+    /// Runs the CLI for the mesher, using all the configured parameters and drawing a progress bar
+    /// with the estimated time left on the terminal.
     pub async fn run_cli(self) -> anyhow::Result<()> {
+        let progress = MeshingProgress::default();
+        #[cfg(not(target_arch = "wasm32"))]
+        let _progress_bar = progress.draw_in_terminal();
+        self.run_file_out(&progress).await
+    }
+
+    /// The format of the output model, as configured or detected from the output file.
+    pub fn format(&self) -> MeshFormat {
+        self.format.or_else(|| MeshFormat::from_path(&self.output_file)).unwrap_or(MeshFormat::Ply)
+    }
+
+    /// Runs the mesher and writes the output to the configured file (or stdout).
+    /// OBJ models also write their material library next to the output file.
+    pub async fn run_file_out(self, progress: &MeshingProgress) -> anyhow::Result<()> {
         let output_is_stdout = self.output_file.to_str()
             .map(|s| s.is_empty() || s.eq("-")).unwrap_or(false);
         if output_is_stdout { // This if-else can't be merged because of async/await?
@@ -45,45 +77,86 @@ impl CliMesher {
             let f = io::stdout();
             let mut f = BufWriter::new(f);
             // Run as usual
-            self.run_custom_out(&mut f).await?;
+            self.run_custom_out(&mut f, progress).await?;
         } else {
-            // Check that the output file does not exist yet or fail
+            // Check that the output files do not exist yet or fail
             let output_file = self.output_file.to_str().unwrap().to_string();
-            if File::open(&output_file).is_ok() {
-                anyhow::bail!("Output file already exists");
+            let mtl_file = (self.format() == MeshFormat::Obj).then(|| self.output_file.with_extension("mtl"));
+            for path in std::iter::once(PathBuf::from(&output_file)).chain(mtl_file.clone()) {
+                if File::open(&path).is_ok() {
+                    anyhow::bail!("Output file {:?} already exists", path);
+                }
             }
-            // Create/truncate the output file or fail
+            // Create/truncate the output files or fail
             let f = File::create(&output_file)?;
+            let mtl_f = mtl_file.as_ref().map(File::create).transpose()?;
             // Buffer writes for faster performance
             let mut f = BufWriter::new(f);
-            // Run as usual
-            self.run_custom_out(&mut f).await?;
+            let mut mtl_f = mtl_f.map(BufWriter::new);
+            // Run as usual, but don't leave incomplete files behind if it fails or is cancelled
+            if let Err(err) = self.run_custom_out_with_materials(&mut f, mtl_f.as_mut(), progress).await {
+                drop((f, mtl_f));
+                let _ = std::fs::remove_file(&output_file);
+                if let Some(mtl_file) = mtl_file {
+                    let _ = std::fs::remove_file(mtl_file);
+                }
+                return Err(err);
+            }
         };
         Ok(())
     }
 
     /// Runs the mesher and writes the output to the given writer instead of the configured file.
-    pub async fn run_custom_out<W: Write>(self, w: &mut W) -> anyhow::Result<usize> {
+    /// The job reports its progress and stops with [`Cancelled`] if cancelled through the given handle,
+    /// which is marked as finished when this returns.
+    pub async fn run_custom_out<W: Write>(self, w: &mut W, progress: &MeshingProgress) -> anyhow::Result<usize> {
+        self.run_custom_out_with_materials(w, None::<&mut io::Sink>, progress).await
+    }
+
+    /// Like [`CliMesher::run_custom_out`], but OBJ models also write their material library to the
+    /// given writer, referencing it as the output file with the .mtl extension.
+    pub async fn run_custom_out_with_materials<W: Write, M: Write>(self, w: &mut W, mtl: Option<&mut M>, progress: &MeshingProgress) -> anyhow::Result<usize> {
+        let result = self.run_custom_out_inner(w, mtl, progress).await;
+        progress.finish();
+        result
+    }
+
+    async fn run_custom_out_inner<W: Write, M: Write>(self, w: &mut W, mtl: Option<&mut M>, progress: &MeshingProgress) -> anyhow::Result<usize> {
+        let format = self.format();
+        let mtllib = self.output_file.with_extension("mtl").file_name().map(|name| name.to_string_lossy().to_string());
         // Start loading input SDF (using common code with the app)
         tracing::info!("Loading SDF from {:?}...", self.input);
+        progress.start_stage("Loading the SDF", 0);
         let (sender_of_updates, mut receiver_of_updates) = mpsc::channel(1);
-        spawn_async(async move { load::load_sdf_from_path_or_url(sender_of_updates, self.input.clone()) }, false);
+        spawn_async(async move { load::load_sdf_from_path_or_url(sender_of_updates, self.input.clone(), self.wasi.clone()) }, false);
         // Wait for the loaded SDF to be ready
         let input_sdf = receiver_of_updates
             .recv().await.ok_or_else(|| anyhow::anyhow!("No SDF found"))?
-            .recv().await.ok_or_else(|| anyhow::anyhow!("No SDF found"))?;
+            .recv().await.ok_or_else(|| anyhow::anyhow!("No SDF found"))??;
         drop(receiver_of_updates);
+        progress.check_cancelled()?;
         // Apply the meshing algorithm as configured
         tracing::info!("Running the meshing algorithm with {:?} {:?}...", self.cfg, self.mesher);
-        // TODO: Progress reporting + ETA?
         // TODO: Async for avoiding freezes on wasm32
-        let mut mesh = self.mesher.mesh(&input_sdf, self.cfg);
+        let mut mesh = self.mesher.mesh(&input_sdf, self.cfg, progress)?;
         // Post-process the mesh to get the materials
         tracing::info!("Post-processing the mesh ({} vertices, {} triangles)...", mesh.vertices.len(), mesh.indices.len() / 3);
-        mesh.postproc(&input_sdf);
+        mesh.postproc(&input_sdf, progress)?;
+        if let Some(fault) = input_sdf.fault() {
+            anyhow::bail!("The SDF stopped working while meshing: {}", fault);
+        }
+        for err in input_sdf.take_errors() {
+            tracing::warn!("The SDF failed while meshing, so the mesh may be wrong: {}", err);
+        }
         // Write the mesh to the output file or fail
-        tracing::info!("Serializing output mesh...");
-        Ok(mesh.serialize_ply(w)?)
+        tracing::info!("Serializing output mesh as {:?}...", format);
+        progress.start_stage("Writing the mesh", 0);
+        let mtllib = mtl.as_ref().and(mtllib);
+        let written = format.serialize(&mesh, w, mtllib.as_deref())?;
+        if let (MeshFormat::Obj, Some(mtl)) = (format, mtl) {
+            mesh.serialize_mtl(mtl)?;
+        }
+        Ok(written)
     }
 }
 
@@ -94,6 +167,11 @@ pub struct Config {
     /// Some algorithms require this to be a power of two.
     #[clap(short = 'v', long, default_value = "64")]
     pub max_voxels_per_axis: usize,
+    /// The number of cells per axis of the blocks that are meshed in parallel. It is only used by
+    /// the parallel meshers (like `parallel-marching-tetrahedra`), and ignored by the others.
+    #[cfg_attr(not(feature = "parallel_meshers"), clap(skip = 32usize))]
+    #[cfg_attr(feature = "parallel_meshers", clap(long, default_value = "32"))]
+    pub block_cells_per_axis: usize,
 }
 
 impl Default for Config {
@@ -105,10 +183,15 @@ impl Default for Config {
 
 pub trait Mesher: Debug {
     /// Mesh reconstructs a mesh from a [`sdf_viewer::sdf::SDFSurface`] trait.
-    fn mesh(&self, sdf: &dyn SDFSurface, cfg: Config) -> Mesh;
+    /// It reports its progress through the given handle, and stops early if it is cancelled.
+    fn mesh(&self, sdf: &(dyn SDFSurface + Send + Sync), cfg: Config, progress: &MeshingProgress) -> Result<Mesh, Cancelled>;
 }
 
 /// Meshers holds the list of currently implemented meshing algorithms.
+///
+/// Only `parallel-marching-tetrahedra` (the default on native) meshes blocks of the volume in
+/// parallel. The isosurface meshers polygonize the whole volume on a single thread, and only sample
+/// it in parallel.
 #[derive(clap::Parser, Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum Meshers {
@@ -122,27 +205,36 @@ pub enum Meshers {
     DualContouringMinimizeQEF,
     #[cfg(feature = "isosurface")]
     DualContouringParticleBasedMinimization,
+    /// Marching tetrahedra on blocks of the volume that are meshed in parallel (see --block-cells-per-axis).
+    #[cfg(all(feature = "parallel_meshers", not(target_arch = "wasm32")))]
+    ParallelMarchingTetrahedra,
 }
 
 impl Default for Meshers {
     fn default() -> Self {
+        // The only mesher that also polygonizes in parallel (see the isosurface module)
+        #[cfg(all(feature = "parallel_meshers", not(target_arch = "wasm32")))]
+        return Self::ParallelMarchingTetrahedra;
+        #[cfg(not(all(feature = "parallel_meshers", not(target_arch = "wasm32"))))]
         Self::MarchingCubes
     }
 }
 
 impl Mesher for Meshers {
-    fn mesh(&self, s: &dyn SDFSurface, cfg: Config) -> Mesh {
+    fn mesh(&self, s: &(dyn SDFSurface + Send + Sync), cfg: Config, progress: &MeshingProgress) -> Result<Mesh, Cancelled> {
         match self {
             #[cfg(feature = "isosurface")]
-            Meshers::MarchingCubes => isosurface::mesh(0, cfg, s),
+            Meshers::MarchingCubes => isosurface::mesh(0, cfg, s, progress),
             #[cfg(feature = "isosurface")]
-            Meshers::LinearHashedMarchingCubes => isosurface::mesh(1, cfg, s),
+            Meshers::LinearHashedMarchingCubes => isosurface::mesh(1, cfg, s, progress),
             // #[cfg(feature = "isosurface")]
             // Meshers::ExtendedMarchingCubes => isosurface::mesh(2, cfg, s),
             #[cfg(feature = "isosurface")]
-            Meshers::DualContouringMinimizeQEF => isosurface::mesh(3, cfg, s),
+            Meshers::DualContouringMinimizeQEF => isosurface::mesh(3, cfg, s, progress),
             #[cfg(feature = "isosurface")]
-            Meshers::DualContouringParticleBasedMinimization => isosurface::mesh(4, cfg, s),
+            Meshers::DualContouringParticleBasedMinimization => isosurface::mesh(4, cfg, s, progress),
+            #[cfg(all(feature = "parallel_meshers", not(target_arch = "wasm32")))]
+            Meshers::ParallelMarchingTetrahedra => parallel::mesh(cfg, s, progress),
         }
     }
 }
@@ -0,0 +1,233 @@
+//! A chunked meshing pipeline that splits the volume into blocks of cells, which are sampled and
+//! polygonized in parallel on a thread pool (native-only, where it is the default mesher).
+//!
+//! Blocks are polygonized with marching tetrahedra: each cubic cell is split into 6 tetrahedra along
+//! its main diagonal, which needs no lookup tables and never leaves holes between cells. Every vertex
+//! lies on an edge of the global grid and is computed only from the samples at its ends, so the
+//! blocks are stitched by merging the vertices of the same edge, without duplicates.
+
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3};
+use rayon::prelude::*;
+
+use crate::sdf::meshers::Config;
+use crate::sdf::meshers::mesh::{Mesh, Vertex};
+use crate::sdf::meshers::progress::{Cancelled, MeshingProgress};
+use crate::sdf::SDFSurface;
+
+/// The tetrahedra of a cell, as indices of its corners (whose bits 0, 1 and 2 are the X, Y and Z
+/// offsets from the first corner). Each one is a path from the first to the last corner that
+/// follows the axes in a different order, so that the faces of neighboring cells match.
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 1, 5, 7],
+    [0, 2, 3, 7],
+    [0, 2, 6, 7],
+    [0, 4, 5, 7],
+    [0, 4, 6, 7],
+];
+
+/// The regular grid of cubic cells that covers the bounding box of the SDF.
+#[derive(Debug, Clone, Copy)]
+struct Grid {
+    min: Vector3<f32>,
+    cell_size: f32,
+    /// The number of cells per axis.
+    cells: [usize; 3],
+}
+
+impl Grid {
+    fn new(bounding_box: [Vector3<f32>; 2], max_cells_per_axis: usize) -> Self {
+        let size = bounding_box[1] - bounding_box[0];
+        let cell_size = size.x.max(size.y).max(size.z).max(f32::EPSILON) / max_cells_per_axis.max(1) as f32;
+        let cells = [size.x, size.y, size.z].map(|side| ((side / cell_size).ceil() as usize).max(1));
+        Self { min: bounding_box[0], cell_size, cells }
+    }
+
+    fn point(&self, index: [usize; 3]) -> Vector3<f32> {
+        self.min + Vector3::new(index[0] as f32, index[1] as f32, index[2] as f32) * self.cell_size
+    }
+
+    /// A unique identifier of the point at the given index.
+    fn point_id(&self, index: [usize; 3]) -> u64 {
+        let (side_x, side_y) = (self.cells[0] as u64 + 1, self.cells[1] as u64 + 1);
+        index[0] as u64 + side_x * (index[1] as u64 + side_y * index[2] as u64)
+    }
+}
+
+/// The polygonized surface of a block, before stitching it to the others.
+#[derive(Debug, Default)]
+struct BlockMesh {
+    /// The global grid edge of each vertex (see [`BlockMesh::vertex`]).
+    edges: Vec<u64>,
+    positions: Vec<Vector3<f32>>,
+    triangles: Vec<[u32; 3]>,
+    /// The vertex of each edge of this block.
+    vertex_of_edge: HashMap<u64, u32>,
+}
+
+impl BlockMesh {
+    /// Polygonizes the cells of the grid from `start` (inclusive) to `end` (exclusive).
+    fn new(sdf: &dyn SDFSurface, grid: &Grid, start: [usize; 3], end: [usize; 3]) -> Self {
+        let side = [0, 1, 2].map(|axis| end[axis] - start[axis] + 1);
+        let mut points = Vec::with_capacity(side[0] * side[1] * side[2]);
+        for z in start[2]..=end[2] {
+            for y in start[1]..=end[1] {
+                for x in start[0]..=end[0] {
+                    points.push(grid.point([x, y, z]));
+                }
+            }
+        }
+        let distances: Vec<f32> = sdf.sample_batch(&points, true).into_iter().map(|sample| sample.distance).collect();
+        let distance = |index: [usize; 3]| distances[
+            (index[0] - start[0]) + side[0] * ((index[1] - start[1]) + side[1] * (index[2] - start[2]))];
+
+        let mut block = Self::default();
+        for z in start[2]..end[2] {
+            for y in start[1]..end[1] {
+                for x in start[0]..end[0] {
+                    let corners: [[usize; 3]; 8] = std::array::from_fn(|c| [x + (c & 1), y + (c >> 1 & 1), z + (c >> 2 & 1)]);
+                    let values = corners.map(distance);
+                    let inside = values.iter().filter(|value| **value < 0.0).count();
+                    if inside == 0 || inside == 8 {
+                        continue; // Fast path: the surface doesn't cross this cell
+                    }
+                    for tetrahedron in TETRAHEDRA {
+                        block.add_tetrahedron(grid, tetrahedron.map(|c| corners[c]), tetrahedron.map(|c| values[c]));
+                    }
+                }
+            }
+        }
+        block
+    }
+
+    /// Adds the triangles where the surface crosses the tetrahedron, oriented towards the outside.
+    fn add_tetrahedron(&mut self, grid: &Grid, corners: [[usize; 3]; 4], values: [f32; 4]) {
+        let (inside, outside): (Vec<usize>, Vec<usize>) = (0..4).partition(|corner| values[*corner] < 0.0);
+        let edges = match (inside.as_slice(), outside.as_slice()) {
+            ([i], outside) => outside.iter().map(|o| (*i, *o)).collect(),
+            (inside, [o]) => inside.iter().map(|i| (*i, *o)).collect(),
+            ([i0, i1], [o0, o1]) => vec![(*i0, *o0), (*i0, *o1), (*i1, *o1), (*i1, *o0)], // A quad
+            _ => return,
+        };
+        // Orient the polygon from the midpoints of its edges, which is never degenerate (unlike the
+        // vertices, which may coincide if the surface crosses a point of the grid)
+        let point = |corner: usize| grid.point(corners[corner]);
+        let midpoints: Vec<Vector3<f32>> = edges.iter().map(|(a, b)| (point(*a) + point(*b)) / 2.0).collect();
+        let center = |corners_subset: &[usize]| corners_subset.iter()
+            .map(|corner| point(*corner)).sum::<Vector3<f32>>() / corners_subset.len() as f32;
+        let outward = center(&outside) - center(&inside);
+        let flip = (midpoints[1] - midpoints[0]).cross(midpoints[2] - midpoints[0]).dot(outward) < 0.0;
+        let vertices: Vec<u32> = edges.into_iter()
+            .map(|(a, b)| self.vertex(grid, (corners[a], values[a]), (corners[b], values[b])))
+            .collect();
+        for i in 1..vertices.len() - 1 {
+            let [a, b, c] = [vertices[0], vertices[i], vertices[i + 1]];
+            self.triangles.push(if flip { [a, c, b] } else { [a, b, c] });
+        }
+    }
+
+    /// Returns the vertex where the surface crosses the edge between the given points, creating it
+    /// the first time. The edges of tetrahedra always go from a point to another with greater or
+    /// equal coordinates, so each one is identified by its first point and its direction.
+    fn vertex(&mut self, grid: &Grid, a: ([usize; 3], f32), b: ([usize; 3], f32)) -> u32 {
+        let ((low, low_value), (high, high_value)) = if a.0 <= b.0 { (a, b) } else { (b, a) };
+        let direction = (high[0] - low[0]) | (high[1] - low[1]) << 1 | (high[2] - low[2]) << 2;
+        let edge = grid.point_id(low) * 8 + direction as u64;
+        *self.vertex_of_edge.entry(edge).or_insert_with(|| {
+            // Always interpolate in the same order, so that all blocks compute the same position
+            let t = (low_value / (low_value - high_value)).clamp(0.0, 1.0);
+            let (low_point, high_point) = (grid.point(low), grid.point(high));
+            self.edges.push(edge);
+            self.positions.push(low_point + (high_point - low_point) * t);
+            self.positions.len() as u32 - 1
+        })
+    }
+}
+
+/// Meshes the SDF with marching tetrahedra, splitting the volume into blocks that are meshed in
+/// parallel (see the [module docs](self)).
+pub(crate) fn mesh(cfg: Config, sdf: &(dyn SDFSurface + Send + Sync), progress: &MeshingProgress) -> Result<Mesh, Cancelled> {
+    let grid = Grid::new(sdf.bounding_box(), cfg.max_voxels_per_axis);
+    let block_cells = cfg.block_cells_per_axis.max(1);
+    let block_counts = grid.cells.map(|cells| cells.div_ceil(block_cells));
+    let blocks: Vec<([usize; 3], [usize; 3])> = (0..block_counts[2])
+        .flat_map(|z| (0..block_counts[1]).flat_map(move |y| (0..block_counts[0]).map(move |x| [x, y, z])))
+        .map(|block| {
+            let start = [0, 1, 2].map(|axis| block[axis] * block_cells);
+            (start, [0, 1, 2].map(|axis| (start[axis] + block_cells).min(grid.cells[axis])))
+        })
+        .collect();
+
+    progress.start_stage("Meshing", blocks.len() as u64);
+    let block_meshes = blocks.par_iter().map(|(start, end)| {
+        progress.check_cancelled()?;
+        let block = BlockMesh::new(sdf, &grid, *start, *end);
+        progress.advance(1);
+        Ok(block)
+    }).collect::<Result<Vec<_>, Cancelled>>()?;
+
+    // Stitch the blocks, merging the vertices on the shared faces
+    let mut mesh = Mesh::default();
+    let mut vertex_of_edge = HashMap::new();
+    for block in block_meshes {
+        let global_vertices: Vec<u32> = block.edges.iter().zip(&block.positions).map(|(edge, position)| {
+            *vertex_of_edge.entry(*edge).or_insert_with(|| {
+                mesh.vertices.push(Vertex { position: *position, ..Vertex::default() });
+                mesh.vertices.len() as u32 - 1
+            })
+        }).collect();
+        mesh.indices.extend(block.triangles.iter().flatten().map(|vertex| global_vertices[*vertex as usize]));
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::InnerSpace;
+
+    use crate::sdf::meshers::Config;
+    use crate::sdf::meshers::progress::MeshingProgress;
+    use crate::sdf::primitives::Primitive;
+    use crate::sdf::primitives::solids::Sphere;
+
+    #[test]
+    fn test_parallel_blocks_are_stitched() {
+        let sdf = Primitive::new(Sphere::new(1.0));
+        // Blocks that don't divide the grid evenly, to test the borders
+        let cfg = Config { max_voxels_per_axis: 16, block_cells_per_axis: 5 };
+        let mesh = super::mesh(cfg, &sdf, &MeshingProgress::default()).unwrap();
+        assert!(!mesh.indices.is_empty());
+        for vertex in &mesh.vertices {
+            assert!((vertex.position.magnitude() - 1.0).abs() < 0.2, "{:?}", vertex.position);
+        }
+
+        // The same as meshing everything at once
+        let single_block = Config { max_voxels_per_axis: 16, block_cells_per_axis: 16 };
+        let single_block = super::mesh(single_block, &sdf, &MeshingProgress::default()).unwrap();
+        assert_eq!(mesh.vertices.len(), single_block.vertices.len());
+        assert_eq!(mesh.indices.len(), single_block.indices.len());
+
+        // Closed and consistently oriented: each edge is used once in each direction
+        let mut edges = HashMap::new();
+        for triangle in mesh.indices.chunks_exact(3) {
+            for i in 0..3 {
+                *edges.entry((triangle[i], triangle[(i + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        for ((a, b), count) in &edges {
+            assert_eq!(*count, 1, "edge {a}-{b} used {count} times");
+            assert!(edges.contains_key(&(*b, *a)), "edge {a}-{b} has no twin");
+        }
+
+        // Facing outwards: the signed volume is positive (and close to the volume of the sphere)
+        let volume: f32 = mesh.indices.chunks_exact(3).map(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize].position);
+            a.dot(b.cross(c)) / 6.0
+        }).sum();
+        assert!((volume - 4.0 / 3.0 * std::f32::consts::PI).abs() < 0.3, "{volume}");
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use instant::{Duration, Instant};
+
+/// A handle to report the progress of a meshing job and to cancel it, shared between the job and
+/// whoever is waiting for it (the terminal or the app). Clones refer to the same job.
+///
+/// The job is split into stages (like loading, meshing or post-processing), each with its own
+/// amount of work to do, so that the estimated time left is only based on the current stage.
+#[derive(Debug, Clone, Default)]
+pub struct MeshingProgress {
+    state: Arc<ProgressState>,
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    /// The name of the current stage and when it started.
+    stage: Mutex<(String, Option<Instant>)>,
+    done: AtomicU64,
+    /// The amount of work of the current stage, or 0 if unknown.
+    total: AtomicU64,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+}
+
+/// The error returned by jobs that stopped because they were cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Meshing cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl MeshingProgress {
+    /// Starts a new stage of the job, with the given amount of work (0 if unknown).
+    pub fn start_stage(&self, name: &str, total: u64) {
+        *self.state.stage.lock().unwrap() = (name.to_string(), Some(Instant::now()));
+        self.state.done.store(0, Ordering::Relaxed);
+        self.state.total.store(total, Ordering::Relaxed);
+    }
+
+    /// Reports that some work of the current stage was done.
+    pub fn advance(&self, amount: u64) {
+        self.state.done.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    /// Marks the job as finished (successfully or not), so that nothing waits for more progress.
+    pub fn finish(&self) {
+        self.state.finished.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.finished.load(Ordering::Relaxed)
+    }
+
+    /// Asks the job to stop as soon as possible. The job will return [`Cancelled`].
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails with [`Cancelled`] if the job was cancelled, to stop it with `?`.
+    pub fn check_cancelled(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() { Err(Cancelled) } else { Ok(()) }
+    }
+
+    /// The name of the current stage.
+    pub fn stage(&self) -> String {
+        self.state.stage.lock().unwrap().0.clone()
+    }
+
+    /// The fraction of the current stage that is done, if its amount of work is known.
+    pub fn fraction(&self) -> Option<f32> {
+        let total = self.state.total.load(Ordering::Relaxed);
+        (total > 0).then(|| (self.state.done.load(Ordering::Relaxed).min(total) as f64 / total as f64) as f32)
+    }
+
+    /// The estimated time left for the current stage, assuming that the work is done at a constant rate.
+    pub fn eta(&self) -> Option<Duration> {
+        let started = self.state.stage.lock().unwrap().1?;
+        let fraction = self.fraction()?;
+        if fraction <= 0.0 {
+            return None;
+        }
+        Some(started.elapsed().mul_f32((1.0 - fraction) / fraction))
+    }
+
+    /// A short description of the status, like `Meshing: 42% (ETA 1m 05s)`.
+    pub fn status(&self) -> String {
+        match (self.fraction(), self.eta()) {
+            (Some(fraction), Some(eta)) => format!("{}: {:.0}% (ETA {})", self.stage(), fraction * 100.0, format_duration(eta)),
+            (Some(fraction), None) => format!("{}: {:.0}%", self.stage(), fraction * 100.0),
+            _ => format!("{}...", self.stage()),
+        }
+    }
+
+    /// Draws a progress bar on the terminal (stderr) until the returned guard is dropped. Nothing is
+    /// drawn if stderr is not a terminal (e.g. redirected to a log file).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn draw_in_terminal(&self) -> TerminalProgressBar {
+        use std::io::IsTerminal;
+        if !std::io::stderr().is_terminal() {
+            return TerminalProgressBar { progress: self.clone(), thread: None };
+        }
+        let progress = self.clone();
+        let thread = std::thread::spawn(move || {
+            while !progress.is_finished() {
+                eprint!("\r\x1b[2K{}", progress.terminal_line());
+                std::thread::sleep(Duration::from_millis(200));
+            }
+            eprintln!("\r\x1b[2K{}", progress.terminal_line());
+        });
+        TerminalProgressBar { progress: self.clone(), thread: Some(thread) }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn terminal_line(&self) -> String {
+        const WIDTH: usize = 30;
+        let filled = self.fraction().map(|fraction| (fraction * WIDTH as f32) as usize).unwrap_or(0);
+        format!("[{}{}] {}", "#".repeat(filled), "-".repeat(WIDTH - filled), self.status())
+    }
+}
+
+/// Stops drawing the progress bar of [`MeshingProgress::draw_in_terminal`] when dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct TerminalProgressBar {
+    progress: MeshingProgress,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for TerminalProgressBar {
+    fn drop(&mut self) {
+        self.progress.finish();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Formats a duration for humans, like `1h 02m`, `1m 05s` or `12s`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60)
+    } else if seconds >= 60 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use instant::Duration;
+
+    use crate::sdf::meshers::progress::{format_duration, MeshingProgress};
+
+    #[test]
+    fn test_progress_stages() {
+        let progress = MeshingProgress::default();
+        progress.start_stage("Loading", 0);
+        assert_eq!(progress.fraction(), None);
+        assert_eq!(progress.status(), "Loading...");
+        progress.start_stage("Meshing", 4);
+        progress.clone().advance(1);
+        assert_eq!(progress.fraction(), Some(0.25));
+        assert!(progress.status().starts_with("Meshing: 25% (ETA "));
+        progress.advance(10);
+        assert_eq!(progress.fraction(), Some(1.0));
+        assert!(progress.check_cancelled().is_ok());
+        progress.clone().cancel();
+        assert!(progress.check_cancelled().is_err());
+        assert_eq!(format_duration(Duration::from_secs(65)), "1m 05s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h 02m");
+    }
+}
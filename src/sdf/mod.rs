@@ -6,7 +6,7 @@ use cgmath::Vector3;
 #[cfg(feature = "sdfdemo")]
 pub mod demo;
 
-#[cfg(feature = "sdfffi")]
+#[cfg(any(feature = "sdfffi", test))] // The hosts test that they decode what it encodes
 pub mod ffi;
 
 #[cfg(feature = "wasminterpreters")] // Main WebAssembly SDF implementations
@@ -15,11 +15,28 @@ pub mod wasm;
 #[cfg(feature = "meshers")]
 pub mod meshers;
 
-// TODO: Native library external SDF implementation?
-// TODO: Remote REST API SDF implementation?
+pub mod remote;
+
+#[cfg(feature = "voxel_grids")]
+pub mod voxels;
+
+#[cfg(feature = "mesh_inputs")]
+pub mod trimesh;
 
 pub mod defaults;
 
+pub mod error;
+
+pub mod abi;
+
+pub mod ops;
+
+pub mod expr;
+
+pub mod primitives;
+
+pub mod shared;
+
 /// CPU-side version of the SDF. It is the source from which to extract data to render on GPU.
 /// It is fully queryable with read-only references for simplicity.
 ///
@@ -36,12 +53,20 @@ pub trait SDFSurface {
     /// All operations MUST be inside this bounding box.
     fn bounding_box(&self) -> [Vector3<f32>; 2];
 
-    // TODO: Batched sampling to speed up operations for a possible REST API.
     /// Samples the surface at the given point. It should include the effect of all of its children
     /// and none of its parents. See [`SDFSample`] for more information.
     /// `distance_only` is a hint to the implementation that the caller only needs the distance.
     fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample;
 
+    // ============ OPTIONAL: BATCHING ============
+    /// Samples the surface at all the given points, returning one sample per point (in the same order).
+    /// Implementations with a high per-call overhead (like external SDFs) should override this to
+    /// amortize the cost of each call. The default implementation just calls [`sample`](#method.sample)
+    /// for each point.
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        defaults::sample_batch_default_impl(self, points, distance_only)
+    }
+
     // ============ OPTIONAL: HIERARCHY (perform the same operations on any sub-SDF) ============
     /// Returns the list of sub-SDFs that are directly children of this node.
     /// Note that modifications to the parameters of the returned children MUST affect this node.
@@ -89,7 +114,44 @@ pub trait SDFSurface {
     }
 
     // ============ OPTIONAL: CUSTOM MATERIALS (GLSL CODE) ============
+    /// Returns a GLSL snippet that customizes how this SDF is rendered on the GPU, or an empty string
+    /// to use the default shader. The snippet is inserted into the fragment shader before the raymarcher,
+    /// and it may `#define` any of the following hooks (implementing the matching function):
+    ///
+    /// - `SDF_CUSTOM_DISTANCE`: `float sdfCustomDistance(vec3 p)` returns the exact distance to the
+    ///   surface, which is used instead of the (voxelized) 3D texture for pixel-perfect surfaces.
+    /// - `SDF_CUSTOM_SHADING`: `vec3 sdfCustomShading(vec3 cameraPos, vec3 hitPos, vec3 normal, vec3 color,
+    ///   float metallic, float roughness, float occlusion)` returns the color of the surface at the hit
+    ///   position, replacing the default lighting model (useful for procedural textures).
+    ///
+    /// If the snippet fails to compile, the error is reported to the user and the default shader is used.
+    /// It is queried again after [`SDFSurface::changed`] reports a change, so it may depend on parameters.
+    fn glsl(&self) -> String {
+        defaults::glsl_default_impl(self)
+    }
 
+    // ============ OPTIONAL: METADATA ============
+    /// Describes where this SDF came from, if known, so that the viewer can display it.
+    /// This is not queried through the WebAssembly API: it is read from the custom section that
+    /// [`export_sdf!`](crate::export_sdf) embeds in the module (see [`SDFMetadata::CUSTOM_SECTION`]).
+    fn metadata(&self) -> Option<SDFMetadata> {
+        defaults::metadata_default_impl(self)
+    }
+
+    // ============ OPTIONAL: ERRORS ============
+    /// Returns why this SDF stopped working (like a WebAssembly SDF that exceeded its execution
+    /// limits), after which it only returns placeholder values. The viewer displays it to the user.
+    /// This is not queried through the WebAssembly API: it is reported by the host that runs the SDF.
+    fn fault(&self) -> Option<error::SDFError> {
+        defaults::fault_default_impl(self)
+    }
+
+    /// Takes the errors that happened since the last call (like traps of a WebAssembly SDF), which
+    /// were answered with placeholder values. The viewer calls this on the root SDF every frame to
+    /// display them to the user, so it must be fast and it should report the errors of the whole hierarchy.
+    fn take_errors(&self) -> Vec<error::SDFError> {
+        defaults::take_errors_default_impl(self)
+    }
 
     // ============ OPTIONAL: UTILITIES ============
     /// Returns the normal at the given point.
@@ -98,6 +160,39 @@ pub trait SDFSurface {
     fn normal(&self, p: Vector3<f32>, eps: Option<f32>) -> Vector3<f32> {
         defaults::normal_default_impl(self, p, eps)
     }
+
+    /// Returns the normals at all the given points (in the same order), like
+    /// [`normal`](#method.normal). The default implementation just calls [`normal`](#method.normal)
+    /// for each point. Implementations with a high per-call overhead that don't override
+    /// [`normal`](#method.normal) may use [`defaults::normal_batch_from_samples`] instead.
+    fn normal_batch(&self, points: &[Vector3<f32>], eps: Option<f32>) -> Vec<Vector3<f32>> {
+        defaults::normal_batch_default_impl(self, points, eps)
+    }
+}
+
+/// Describes the crate that exported an SDF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SDFMetadata {
+    /// The name of the crate that exported the SDF.
+    pub crate_name: String,
+    /// The version of the crate that exported the SDF.
+    pub crate_version: String,
+}
+
+impl SDFMetadata {
+    /// The name of the WebAssembly custom section that contains the metadata, as a list of
+    /// `key=value` lines (`name` and `version` are required).
+    pub const CUSTOM_SECTION: &'static str = "sdf_viewer_metadata";
+
+    /// Parses the contents of the custom section, returning None if it is not valid.
+    pub fn parse(section: &[u8]) -> Option<Self> {
+        let section = std::str::from_utf8(section).ok()?;
+        let value_of = |key: &str| section.lines()
+            .filter_map(|line| line.split_once('='))
+            .find(|(k, _)| k.trim() == key)
+            .map(|(_, v)| v.trim().to_string());
+        Some(Self { crate_name: value_of("name")?, crate_version: value_of("version")? })
+    }
 }
 
 /// The result of sampling the SDF at the given coordinates.
@@ -161,6 +256,20 @@ pub enum SDFParamKind {
         /// The available options to select from for the parameter. If empty, any string is valid.
         choices: Vec<String>,
     },
+    Vec3 {
+        /// The range (inclusive) that must contain each component of the value.
+        range: RangeInclusive<f32>,
+        /// The step size for dragging each component.
+        step: f32,
+    },
+    // No parameters required for colors (RGB components are always in the [0, 1] range)
+    Color,
+    Angle {
+        /// The range (inclusive, in radians) that must contain the value.
+        range: RangeInclusive<f32>,
+        /// The step size (in radians) for the slider.
+        step: f32,
+    },
 }
 
 /// The type's value.
@@ -170,6 +279,11 @@ pub enum SDFParamValue {
     Int(i32),
     Float(f32),
     String(String),
+    Vec3(Vector3<f32>),
+    /// The RGB components of the color, in the [0, 1] range.
+    Color(Vector3<f32>),
+    /// The angle in radians.
+    Angle(f32),
 }
 
 #[cfg(feature = "app")]
@@ -221,8 +335,105 @@ impl SDFParam {
                     _ => false, // Ignore invalid values
                 }
             }
+            SDFParamKind::Vec3 { range, step } => {
+                match &mut self.value {
+                    SDFParamValue::Vec3(value) =>
+                        ui.horizontal(|ui| {
+                            let mut changed = false;
+                            for component in [&mut value.x, &mut value.y, &mut value.z] {
+                                changed |= ui.add(egui::DragValue::new(component)
+                                    .range(range.clone()).speed(*step as f64)).changed();
+                            }
+                            changed
+                        }).inner,
+                    _ => false, // Ignore invalid values
+                }
+            }
+            SDFParamKind::Color => {
+                match &mut self.value {
+                    SDFParamValue::Color(value) => {
+                        let mut rgb = [value.x, value.y, value.z];
+                        let changed = ui.color_edit_button_rgb(&mut rgb).changed();
+                        if changed {
+                            *value = rgb.into();
+                        }
+                        changed
+                    }
+                    _ => false, // Ignore invalid values
+                }
+            }
+            SDFParamKind::Angle { range, step } => {
+                match &mut self.value {
+                    SDFParamValue::Angle(value) => {
+                        // Edit in degrees, which are more user-friendly
+                        let mut degrees = value.to_degrees();
+                        let changed = ui.add(Slider::new(&mut degrees, range.start().to_degrees()..=range.end().to_degrees())
+                            .step_by(step.to_degrees() as f64).suffix("°")).changed();
+                        if changed {
+                            *value = degrees.to_radians();
+                        }
+                        changed
+                    }
+                    _ => false, // Ignore invalid values
+                }
+            }
         };
         ui.end_row();
         changed
     }
-}
\ No newline at end of file
+}
+#[cfg(all(test, feature = "wasminterpreters"))]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::sdf::SDFMetadata;
+    use crate::sdf::wasm::fixtures::{load_with_each_backend, with_exports, SPHERE_WAT};
+
+    /// A module implementing version 2 of the API that provides custom GLSL code. Its distance is the
+    /// X coordinate and its color counts the times that the host freed the GLSL code.
+    const GLSL_WAT: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $freed (mut i32) (i32.const 0))
+        (data (i32.const 1024) "float sdfCustomDistance(vec3 p) { return length(p) - 1.0; }")
+        (func (export "sdf_abi_version") (result i32) (i32.const 2))
+        (func (export "capabilities") (result i32) (i32.const 2))
+        (func (export "bounding_box") (param i32) (result i32) (i32.const 0))
+        (func (export "sample") (param i32 f32 f32 f32 i32) (result i32)
+            (f32.store (i32.const 64) (local.get 1))
+            (f32.store (i32.const 68) (f32.convert_i32_s (global.get $freed)))
+            (i32.const 64))
+        (func (export "glsl") (param i32) (result i32)
+            (i32.store (i32.const 128) (i32.const 1024))
+            (i32.store (i32.const 132) (i32.const 59))
+            (i32.const 128))
+        (func (export "glsl_free") (param i32)
+            (global.set $freed (i32.add (global.get $freed) (i32.const 1)))))"#;
+
+    #[test]
+    fn test_wasm_custom_glsl() {
+        for (backend, sdf) in load_with_each_backend(GLSL_WAT) {
+            assert_eq!(sdf.glsl(), "float sdfCustomDistance(vec3 p) { return length(p) - 1.0; }", "{:?}", backend);
+            // The code was copied out of the module before freeing it
+            assert_eq!(sdf.sample(Vector3::new(0.0, 0.0, 0.0), false).color.x, 1.0, "{:?}", backend);
+            assert!(sdf.take_errors().is_empty(), "{:?} recorded errors", backend);
+        }
+        // Modules that don't export the function use the default shader
+        for (backend, sdf) in load_with_each_backend(SPHERE_WAT) {
+            assert_eq!(sdf.glsl(), "", "{:?}", backend);
+            assert!(sdf.take_errors().is_empty(), "{:?} recorded errors", backend);
+        }
+    }
+
+    #[test]
+    fn test_wasm_metadata() {
+        // Like the custom section embedded by export_sdf!
+        let metadata_wat = with_exports(SPHERE_WAT, r#"(@custom "sdf_viewer_metadata" "name=my-sdf\nversion=1.2.3\n")"#);
+        for (backend, sdf) in load_with_each_backend(&metadata_wat) {
+            let expected = SDFMetadata { crate_name: "my-sdf".to_string(), crate_version: "1.2.3".to_string() };
+            assert_eq!(sdf.metadata(), Some(expected), "{:?}", backend);
+        }
+        for (backend, sdf) in load_with_each_backend(SPHERE_WAT) {
+            assert_eq!(sdf.metadata(), None, "{:?}", backend);
+        }
+    }
+}
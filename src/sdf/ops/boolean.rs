@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use cgmath::Vector3;
+
+use crate::sdf::{SDFParam, SDFParamValue, SDFSample, SDFSurface};
+use crate::sdf::defaults::merge_bounding_boxes;
+use crate::sdf::ops::{expand_bounding_box, mix_samples};
+use crate::sdf::shared::{ChangedBox, float_param, next_id, Shared, unknown_param_error};
+
+/// The kind of constructive solid geometry operation performed by [`Boolean`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BooleanKind {
+    /// Keeps the space inside any of the operands.
+    Union,
+    /// Keeps the space inside both operands.
+    Intersection,
+    /// Keeps the space inside the first operand but not inside the second one.
+    Difference,
+}
+
+/// Combines two SDFs using a (possibly smooth) constructive solid geometry operation.
+/// The material of the closest surface is used, blending both materials in smooth transitions.
+#[derive(Debug)]
+pub struct Boolean<A, B> {
+    a: Arc<A>,
+    b: Arc<B>,
+    kind: BooleanKind,
+    id: u32,
+    smoothness: Shared<f32>,
+    changed: ChangedBox,
+}
+
+impl<A, B> Clone for Boolean<A, B> {
+    fn clone(&self) -> Self {
+        Self {
+            a: Arc::clone(&self.a),
+            b: Arc::clone(&self.b),
+            kind: self.kind,
+            id: self.id,
+            smoothness: self.smoothness.clone(),
+            changed: self.changed.clone(),
+        }
+    }
+}
+
+impl<A, B> Boolean<A, B> {
+    const ID_SMOOTHNESS: u32 = 0;
+
+    /// Creates a new sharp boolean operation (see [`with_smoothness`](#method.with_smoothness)).
+    pub fn new(a: A, b: B, kind: BooleanKind) -> Self {
+        Self {
+            a: Arc::new(a),
+            b: Arc::new(b),
+            kind,
+            id: next_id(),
+            smoothness: Shared::new(0.0),
+            changed: ChangedBox::default(),
+        }
+    }
+
+    /// The union of both SDFs.
+    pub fn union(a: A, b: B) -> Self {
+        Self::new(a, b, BooleanKind::Union)
+    }
+
+    /// The intersection of both SDFs.
+    pub fn intersection(a: A, b: B) -> Self {
+        Self::new(a, b, BooleanKind::Intersection)
+    }
+
+    /// Subtracts the second SDF from the first one.
+    pub fn difference(a: A, b: B) -> Self {
+        Self::new(a, b, BooleanKind::Difference)
+    }
+
+    /// Sets the radius of the smooth transition between both surfaces (0 for a sharp transition).
+    pub fn with_smoothness(self, smoothness: f32) -> Self {
+        self.smoothness.set(smoothness.max(0.0));
+        self
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Combines the samples of both operands at the same point.
+    fn combine(&self, a: SDFSample, b: SDFSample, smoothness: f32) -> SDFSample {
+        // Based on https://iquilezles.org/articles/smin/ (polynomial smooth min)
+        let (da, db) = (a.distance, b.distance);
+        match self.kind {
+            BooleanKind::Union => if smoothness > 0.0 {
+                let h = (0.5 + 0.5 * (db - da) / smoothness).clamp(0.0, 1.0);
+                let dist = db * (1.0 - h) + da * h - smoothness * h * (1.0 - h);
+                mix_samples(b, a, h, dist)
+            } else if da <= db { a } else { b },
+            BooleanKind::Intersection => if smoothness > 0.0 {
+                let h = (0.5 - 0.5 * (db - da) / smoothness).clamp(0.0, 1.0);
+                let dist = db * (1.0 - h) + da * h + smoothness * h * (1.0 - h);
+                mix_samples(b, a, h, dist)
+            } else if da >= db { a } else { b },
+            BooleanKind::Difference => if smoothness > 0.0 {
+                let h = (0.5 - 0.5 * (da + db) / smoothness).clamp(0.0, 1.0);
+                let dist = da * (1.0 - h) - db * h + smoothness * h * (1.0 - h);
+                mix_samples(a, b, h, dist)
+            } else if da >= -db { a } else {
+                SDFSample { distance: -db, ..b }
+            },
+        }
+    }
+}
+
+impl<A: SDFSurface + 'static, B: SDFSurface + 'static> SDFSurface for Boolean<A, B> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let (bb_a, bb_b) = (self.a.bounding_box(), self.b.bounding_box());
+        match self.kind {
+            // A smooth union may grow up to a quarter of the smoothness outside the operands
+            BooleanKind::Union => expand_bounding_box(&merge_bounding_boxes(&bb_a, &bb_b), self.smoothness.get() / 4.0),
+            BooleanKind::Intersection => {
+                let min = Vector3::new(bb_a[0].x.max(bb_b[0].x), bb_a[0].y.max(bb_b[0].y), bb_a[0].z.max(bb_b[0].z));
+                let max = Vector3::new(bb_a[1].x.min(bb_b[1].x), bb_a[1].y.min(bb_b[1].y), bb_a[1].z.min(bb_b[1].z));
+                // Disjoint operands have an empty intersection, collapsed to the middle of the gap
+                let mid = (min + max) / 2.0;
+                [Vector3::new(min.x.min(mid.x), min.y.min(mid.y), min.z.min(mid.z)),
+                    Vector3::new(max.x.max(mid.x), max.y.max(mid.y), max.z.max(mid.z))]
+            }
+            BooleanKind::Difference => bb_a,
+        }
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        self.combine(self.a.sample(p, distance_only), self.b.sample(p, distance_only), self.smoothness.get())
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let smoothness = self.smoothness.get();
+        self.a.sample_batch(points, distance_only).into_iter()
+            .zip(self.b.sample_batch(points, distance_only))
+            .map(|(a, b)| self.combine(a, b, smoothness))
+            .collect()
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        vec![Box::new(Arc::clone(&self.a)), Box::new(Arc::clone(&self.b))]
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        format!("{:?}", self.kind)
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![float_param(Self::ID_SMOOTHNESS, "smoothness", self.smoothness.get(), 0.0..=1.0,
+                         "The radius of the smooth transition between both surfaces (0 for a sharp transition).")]
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_SMOOTHNESS, SDFParamValue::Float(value)) => {
+                let old_box = self.bounding_box();
+                self.smoothness.set(value.max(0.0));
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        // All the changes must be taken at once, or the rest would only be reported on later calls
+        let changes = [self.changed.take(), self.a.changed(), self.b.changed()];
+        // The smooth transition may also modify the surroundings of the changes
+        let smoothness = self.smoothness.get();
+        changes.into_iter().flatten()
+            .reduce(|merged, changed_box| merge_bounding_boxes(&merged, &changed_box))
+            .map(|changed_box| expand_bounding_box(&changed_box, smoothness))
+    }
+}
@@ -0,0 +1,134 @@
+//! Generic combinators to build complex SDFs from simpler ones, without writing raw distance math.
+//!
+//! All combinators own their operands (which can be any [`SDFSurface`], including other combinators),
+//! report them as [`children`](SDFSurface::children), and expose their configuration as parameters.
+//! Cloning a combinator is cheap and the clone shares the parameters with the original.
+//!
+//! ```ignore
+//! use sdf_viewer::sdf::ops::{Boolean, Translate};
+//! let model = Boolean::difference(cube, Translate::new(sphere, [0.5, 0.0, 0.0].into())).with_smoothness(0.05);
+//! ```
+
+use cgmath::Vector3;
+
+use crate::sdf::SDFSample;
+
+pub mod boolean;
+pub mod modifiers;
+pub mod transform;
+
+pub use boolean::Boolean;
+pub use modifiers::{Onion, Round};
+pub use transform::{Mirror, Repeat, Rotate, Scale, Translate};
+
+/// Linearly interpolates the material properties of both samples (0 means `a`, 1 means `b`), using
+/// the given distance for the result.
+pub fn mix_samples(a: SDFSample, b: SDFSample, t: f32, distance: f32) -> SDFSample {
+    SDFSample {
+        distance,
+        color: a.color * (1.0 - t) + b.color * t,
+        metallic: a.metallic * (1.0 - t) + b.metallic * t,
+        roughness: a.roughness * (1.0 - t) + b.roughness * t,
+        occlusion: a.occlusion * (1.0 - t) + b.occlusion * t,
+    }
+}
+
+/// Grows the bounding box by the given amount in all directions.
+pub fn expand_bounding_box(bb: &[Vector3<f32>; 2], amount: f32) -> [Vector3<f32>; 2] {
+    let amount = Vector3::new(amount, amount, amount);
+    [bb[0] - amount, bb[1] + amount]
+}
+
+/// Returns the bounding box that contains the 8 corners of the given bounding box after transforming them.
+pub fn transform_bounding_box(bb: &[Vector3<f32>; 2], f: impl Fn(Vector3<f32>) -> Vector3<f32>) -> [Vector3<f32>; 2] {
+    let mut res = [Vector3::new(f32::MAX, f32::MAX, f32::MAX), Vector3::new(f32::MIN, f32::MIN, f32::MIN)];
+    for corner in 0..8 {
+        let p = f(Vector3::new(
+            if corner & 1 == 0 { bb[0].x } else { bb[1].x },
+            if corner & 2 == 0 { bb[0].y } else { bb[1].y },
+            if corner & 4 == 0 { bb[0].z } else { bb[1].z },
+        ));
+        res = [
+            Vector3::new(res[0].x.min(p.x), res[0].y.min(p.y), res[0].z.min(p.z)),
+            Vector3::new(res[1].x.max(p.x), res[1].y.max(p.y), res[1].z.max(p.z)),
+        ];
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Vector3, Zero};
+
+    use crate::sdf::{SDFParamValue, SDFSample, SDFSurface};
+    use crate::sdf::ops::*;
+
+    /// A minimal sphere to test the combinators.
+    #[derive(Clone)]
+    struct TestSphere(f32);
+
+    impl SDFSurface for TestSphere {
+        fn bounding_box(&self) -> [Vector3<f32>; 2] {
+            [Vector3::new(-self.0, -self.0, -self.0), Vector3::new(self.0, self.0, self.0)]
+        }
+
+        fn sample(&self, p: Vector3<f32>, _distance_only: bool) -> SDFSample {
+            SDFSample::new(p.magnitude() - self.0, Vector3::zero())
+        }
+    }
+
+    #[test]
+    fn test_boolean_distances() {
+        let p = Vector3::new(1.5, 0.0, 0.0);
+        let union = Boolean::union(TestSphere(1.0), TestSphere(2.0));
+        assert_eq!(union.sample(p, true).distance, -0.5);
+        let intersection = Boolean::intersection(TestSphere(1.0), TestSphere(2.0));
+        assert_eq!(intersection.sample(p, true).distance, 0.5);
+        let difference = Boolean::difference(TestSphere(2.0), TestSphere(1.0));
+        assert_eq!(difference.sample(p, true).distance, -0.5);
+        assert_eq!(difference.bounding_box(), TestSphere(2.0).bounding_box());
+        let sharp_union = Boolean::union(TestSphere(1.0), TestSphere(1.0));
+        let smooth_union = Boolean::union(TestSphere(1.0), TestSphere(1.0)).with_smoothness(0.4);
+        assert!(smooth_union.sample(p, true).distance < sharp_union.sample(p, true).distance);
+        let disjoint = Boolean::intersection(TestSphere(1.0), Translate::new(TestSphere(1.0), Vector3::new(4.0, 0.0, 0.0)));
+        assert_eq!(disjoint.bounding_box(), [Vector3::new(2.0, -1.0, -1.0), Vector3::new(2.0, 1.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_transform_bounding_boxes() {
+        let translated = Translate::new(TestSphere(1.0), Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(translated.bounding_box(), [Vector3::new(0.0, 1.0, 2.0), Vector3::new(2.0, 3.0, 4.0)]);
+        assert!(translated.sample(Vector3::new(1.0, 2.0, 3.0), true).distance < 0.0);
+        let scaled = Scale::new(TestSphere(1.0), 2.0);
+        assert_eq!(scaled.sample(Vector3::new(3.0, 0.0, 0.0), true).distance, 1.0);
+        assert_eq!(scaled.bounding_box()[1], Vector3::new(2.0, 2.0, 2.0));
+        let rotated = Rotate::new(Translate::new(TestSphere(0.5), Vector3::new(1.0, 0.0, 0.0)),
+                                  Vector3::new(0.0, 0.0, std::f32::consts::FRAC_PI_2));
+        assert!(rotated.sample(Vector3::new(0.0, 1.0, 0.0), true).distance < 0.0);
+        let bb = rotated.bounding_box();
+        assert!(bb[0].y > 0.0 && bb[1].y > 1.0);
+    }
+
+    #[test]
+    fn test_parameters_are_shared() {
+        let onion = Onion::new(TestSphere(1.0), 0.1);
+        let clone = onion.clone();
+        assert!(onion.changed().is_none());
+        clone.set_parameter(0, &SDFParamValue::Float(0.2)).unwrap();
+        assert_eq!(onion.sample(Vector3::zero(), true).distance, 0.8);
+        assert!(onion.changed().is_some());
+        assert!(onion.changed().is_none());
+        assert!(clone.set_parameter(0, &SDFParamValue::Boolean(true)).is_err());
+    }
+
+    #[test]
+    fn test_boolean_reports_all_changes() {
+        let (a, b) = (Onion::new(TestSphere(1.0), 0.1), Onion::new(TestSphere(1.0), 0.1));
+        let union = Boolean::union(a.clone(), Translate::new(b.clone(), Vector3::new(4.0, 0.0, 0.0)));
+        a.set_parameter(0, &SDFParamValue::Float(0.2)).unwrap();
+        b.set_parameter(0, &SDFParamValue::Float(0.2)).unwrap();
+        let changed = union.changed().unwrap();
+        assert!(changed[0].x <= -1.0 && changed[1].x >= 5.0, "{:?}", changed);
+        assert!(union.changed().is_none());
+    }
+}
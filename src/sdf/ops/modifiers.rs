@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use cgmath::Vector3;
+
+use crate::sdf::{SDFParam, SDFParamValue, SDFSample, SDFSurface};
+use crate::sdf::defaults::merge_bounding_boxes;
+use crate::sdf::ops::expand_bounding_box;
+use crate::sdf::shared::{ChangedBox, float_param, next_id, Shared, unknown_param_error};
+
+/// Turns the child SDF into a hollow shell of the given thickness around its surface.
+#[derive(Debug)]
+pub struct Onion<S> {
+    sdf: Arc<S>,
+    id: u32,
+    thickness: Shared<f32>,
+    changed: ChangedBox,
+}
+
+impl<S> Clone for Onion<S> {
+    fn clone(&self) -> Self {
+        Self { sdf: Arc::clone(&self.sdf), id: self.id, thickness: self.thickness.clone(), changed: self.changed.clone() }
+    }
+}
+
+impl<S> Onion<S> {
+    const ID_THICKNESS: u32 = 0;
+
+    pub fn new(sdf: S, thickness: f32) -> Self {
+        Self { sdf: Arc::new(sdf), id: next_id(), thickness: Shared::new(thickness.max(0.0)), changed: ChangedBox::default() }
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+}
+
+impl<S: SDFSurface + 'static> SDFSurface for Onion<S> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        expand_bounding_box(&self.sdf.bounding_box(), self.thickness.get())
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        let mut sample = self.sdf.sample(p, distance_only);
+        sample.distance = sample.distance.abs() - self.thickness.get();
+        sample
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let thickness = self.thickness.get();
+        let mut samples = self.sdf.sample_batch(points, distance_only);
+        samples.iter_mut().for_each(|sample| sample.distance = sample.distance.abs() - thickness);
+        samples
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        vec![Box::new(Arc::clone(&self.sdf))]
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        "Onion".to_string()
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![float_param(Self::ID_THICKNESS, "thickness", self.thickness.get(), 0.0..=1.0,
+                         "The distance from the surface of the child SDF to each side of the shell.")]
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_THICKNESS, SDFParamValue::Float(value)) => {
+                let old_box = self.bounding_box();
+                self.thickness.set(value.max(0.0));
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        if let Some(changed_box) = self.changed.take() {
+            return Some(changed_box);
+        }
+        let thickness = self.thickness.get();
+        self.sdf.changed().map(|changed_box| expand_bounding_box(&changed_box, thickness))
+    }
+}
+
+/// Rounds the edges of the child SDF by growing its surface by the given radius.
+#[derive(Debug)]
+pub struct Round<S> {
+    sdf: Arc<S>,
+    id: u32,
+    radius: Shared<f32>,
+    changed: ChangedBox,
+}
+
+impl<S> Clone for Round<S> {
+    fn clone(&self) -> Self {
+        Self { sdf: Arc::clone(&self.sdf), id: self.id, radius: self.radius.clone(), changed: self.changed.clone() }
+    }
+}
+
+impl<S> Round<S> {
+    const ID_RADIUS: u32 = 0;
+
+    pub fn new(sdf: S, radius: f32) -> Self {
+        Self { sdf: Arc::new(sdf), id: next_id(), radius: Shared::new(radius.max(0.0)), changed: ChangedBox::default() }
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+}
+
+impl<S: SDFSurface + 'static> SDFSurface for Round<S> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        expand_bounding_box(&self.sdf.bounding_box(), self.radius.get())
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        let mut sample = self.sdf.sample(p, distance_only);
+        sample.distance -= self.radius.get();
+        sample
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let radius = self.radius.get();
+        let mut samples = self.sdf.sample_batch(points, distance_only);
+        samples.iter_mut().for_each(|sample| sample.distance -= radius);
+        samples
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        vec![Box::new(Arc::clone(&self.sdf))]
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        "Round".to_string()
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![float_param(Self::ID_RADIUS, "radius", self.radius.get(), 0.0..=1.0,
+                         "The radius of the rounded edges (the surface grows by this amount).")]
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_RADIUS, SDFParamValue::Float(value)) => {
+                let old_box = self.bounding_box();
+                self.radius.set(value.max(0.0));
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        if let Some(changed_box) = self.changed.take() {
+            return Some(changed_box);
+        }
+        let radius = self.radius.get();
+        self.sdf.changed().map(|changed_box| expand_bounding_box(&changed_box, radius))
+    }
+}
@@ -0,0 +1,516 @@
+use std::sync::Arc;
+
+use cgmath::{ElementWise, Matrix, Matrix3, Rad, Vector3};
+
+use crate::sdf::{SDFParam, SDFParamKind, SDFParamValue, SDFSample, SDFSurface};
+use crate::sdf::defaults::merge_bounding_boxes;
+use crate::sdf::ops::transform_bounding_box;
+use crate::sdf::shared::{ChangedBox, float_param, next_id, Shared, unknown_param_error, vec3_param};
+
+/// Moves the child SDF by the given offset.
+#[derive(Debug)]
+pub struct Translate<S> {
+    sdf: Arc<S>,
+    id: u32,
+    offset: Shared<Vector3<f32>>,
+    changed: ChangedBox,
+}
+
+impl<S> Clone for Translate<S> {
+    fn clone(&self) -> Self {
+        Self { sdf: Arc::clone(&self.sdf), id: self.id, offset: self.offset.clone(), changed: self.changed.clone() }
+    }
+}
+
+impl<S> Translate<S> {
+    const ID_OFFSET: u32 = 0;
+
+    pub fn new(sdf: S, offset: Vector3<f32>) -> Self {
+        Self { sdf: Arc::new(sdf), id: next_id(), offset: Shared::new(offset), changed: ChangedBox::default() }
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+}
+
+impl<S: SDFSurface + 'static> SDFSurface for Translate<S> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let [min, max] = self.sdf.bounding_box();
+        let offset = self.offset.get();
+        [min + offset, max + offset]
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        self.sdf.sample(p - self.offset.get(), distance_only)
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let offset = self.offset.get();
+        let points = points.iter().map(|p| p - offset).collect::<Vec<_>>();
+        self.sdf.sample_batch(&points, distance_only)
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        vec![Box::new(Arc::clone(&self.sdf))]
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        "Translate".to_string()
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![vec3_param(Self::ID_OFFSET, "offset", self.offset.get(), -100.0..=100.0, 0.01,
+                        "The displacement applied to the child SDF.")]
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_OFFSET, SDFParamValue::Vec3(value)) => {
+                let old_box = self.bounding_box();
+                self.offset.set(*value);
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        if let Some(changed_box) = self.changed.take() {
+            return Some(changed_box);
+        }
+        let offset = self.offset.get();
+        self.sdf.changed().map(|[min, max]| [min + offset, max + offset])
+    }
+}
+
+/// Rotates the child SDF around the origin, using XYZ euler angles (the X rotation is applied first).
+#[derive(Debug)]
+pub struct Rotate<S> {
+    sdf: Arc<S>,
+    id: u32,
+    angles: Shared<Vector3<f32>>,
+    changed: ChangedBox,
+}
+
+impl<S> Clone for Rotate<S> {
+    fn clone(&self) -> Self {
+        Self { sdf: Arc::clone(&self.sdf), id: self.id, angles: self.angles.clone(), changed: self.changed.clone() }
+    }
+}
+
+impl<S> Rotate<S> {
+    /// The parameter IDs of the angles around the X, Y and Z axes (in this order).
+    const ID_ANGLES: [u32; 3] = [0, 1, 2];
+
+    /// Creates a new rotation, with the angles in radians.
+    pub fn new(sdf: S, angles: Vector3<f32>) -> Self {
+        Self { sdf: Arc::new(sdf), id: next_id(), angles: Shared::new(angles), changed: ChangedBox::default() }
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// The rotation matrix that transforms the child SDF to world space.
+    fn rotation(&self) -> Matrix3<f32> {
+        let angles = self.angles.get();
+        Matrix3::from_angle_z(Rad(angles.z)) * Matrix3::from_angle_y(Rad(angles.y)) * Matrix3::from_angle_x(Rad(angles.x))
+    }
+}
+
+impl<S: SDFSurface + 'static> SDFSurface for Rotate<S> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let rotation = self.rotation();
+        transform_bounding_box(&self.sdf.bounding_box(), |p| rotation * p)
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        // The inverse of a rotation is its transpose
+        self.sdf.sample(self.rotation().transpose() * p, distance_only)
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let inverse = self.rotation().transpose();
+        let points = points.iter().map(|p| inverse * p).collect::<Vec<_>>();
+        self.sdf.sample_batch(&points, distance_only)
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        vec![Box::new(Arc::clone(&self.sdf))]
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        "Rotate".to_string()
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        let angles = self.angles.get();
+        ["x", "y", "z"].iter().enumerate().map(|(axis, axis_name)| SDFParam {
+            id: Self::ID_ANGLES[axis],
+            name: format!("angle {axis_name}"),
+            kind: SDFParamKind::Angle { range: -std::f32::consts::PI..=std::f32::consts::PI, step: 0.01 },
+            value: SDFParamValue::Angle(angles[axis]),
+            description: format!("The rotation around the {} axis.", axis_name.to_uppercase()),
+        }).collect()
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (Self::ID_ANGLES.iter().position(|id| *id == param_id), param_value) {
+            (Some(axis), SDFParamValue::Angle(value)) => {
+                let old_box = self.bounding_box();
+                let mut angles = self.angles.get();
+                angles[axis] = *value;
+                self.angles.set(angles);
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        if let Some(changed_box) = self.changed.take() {
+            return Some(changed_box);
+        }
+        let rotation = self.rotation();
+        self.sdf.changed().map(|changed_box| transform_bounding_box(&changed_box, |p| rotation * p))
+    }
+}
+
+/// Uniformly scales the child SDF around the origin, keeping the distances exact.
+#[derive(Debug)]
+pub struct Scale<S> {
+    sdf: Arc<S>,
+    id: u32,
+    factor: Shared<f32>,
+    changed: ChangedBox,
+}
+
+impl<S> Clone for Scale<S> {
+    fn clone(&self) -> Self {
+        Self { sdf: Arc::clone(&self.sdf), id: self.id, factor: self.factor.clone(), changed: self.changed.clone() }
+    }
+}
+
+impl<S> Scale<S> {
+    const ID_FACTOR: u32 = 0;
+    const MIN_FACTOR: f32 = 0.01;
+
+    pub fn new(sdf: S, factor: f32) -> Self {
+        Self { sdf: Arc::new(sdf), id: next_id(), factor: Shared::new(factor.max(Self::MIN_FACTOR)), changed: ChangedBox::default() }
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+}
+
+impl<S: SDFSurface + 'static> SDFSurface for Scale<S> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let [min, max] = self.sdf.bounding_box();
+        let factor = self.factor.get();
+        [min * factor, max * factor]
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        let factor = self.factor.get();
+        let mut sample = self.sdf.sample(p / factor, distance_only);
+        sample.distance *= factor;
+        sample
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let factor = self.factor.get();
+        let points = points.iter().map(|p| p / factor).collect::<Vec<_>>();
+        let mut samples = self.sdf.sample_batch(&points, distance_only);
+        samples.iter_mut().for_each(|sample| sample.distance *= factor);
+        samples
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        vec![Box::new(Arc::clone(&self.sdf))]
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        "Scale".to_string()
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![float_param(Self::ID_FACTOR, "factor", self.factor.get(), Self::MIN_FACTOR..=10.0,
+                         "The scale factor applied to the child SDF.")]
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_FACTOR, SDFParamValue::Float(value)) => {
+                let old_box = self.bounding_box();
+                self.factor.set(value.max(Self::MIN_FACTOR));
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        if let Some(changed_box) = self.changed.take() {
+            return Some(changed_box);
+        }
+        let factor = self.factor.get();
+        self.sdf.changed().map(|[min, max]| [min * factor, max * factor])
+    }
+}
+
+/// Mirrors the positive side of the child SDF (along the selected axis) to the negative side.
+#[derive(Debug)]
+pub struct Mirror<S> {
+    sdf: Arc<S>,
+    id: u32,
+    axis: Shared<usize>,
+    changed: ChangedBox,
+}
+
+impl<S> Clone for Mirror<S> {
+    fn clone(&self) -> Self {
+        Self { sdf: Arc::clone(&self.sdf), id: self.id, axis: self.axis.clone(), changed: self.changed.clone() }
+    }
+}
+
+impl<S> Mirror<S> {
+    const ID_AXIS: u32 = 0;
+    const AXIS_NAMES: [&'static str; 3] = ["X", "Y", "Z"];
+
+    /// Creates a new mirror along the given axis (0 for X, 1 for Y and 2 for Z).
+    pub fn new(sdf: S, axis: usize) -> Self {
+        Self { sdf: Arc::new(sdf), id: next_id(), axis: Shared::new(axis.min(2)), changed: ChangedBox::default() }
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Mirrors the given bounding box along the axis.
+    fn mirror_bounding_box(bb: &[Vector3<f32>; 2], axis: usize) -> [Vector3<f32>; 2] {
+        let mut res = *bb;
+        let max = bb[1][axis].max(0.0);
+        res[0][axis] = -max;
+        res[1][axis] = max;
+        res
+    }
+}
+
+impl<S: SDFSurface + 'static> SDFSurface for Mirror<S> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        Self::mirror_bounding_box(&self.sdf.bounding_box(), self.axis.get())
+    }
+
+    fn sample(&self, mut p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        let axis = self.axis.get();
+        p[axis] = p[axis].abs();
+        self.sdf.sample(p, distance_only)
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let axis = self.axis.get();
+        let points = points.iter().map(|p| {
+            let mut p = *p;
+            p[axis] = p[axis].abs();
+            p
+        }).collect::<Vec<_>>();
+        self.sdf.sample_batch(&points, distance_only)
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        vec![Box::new(Arc::clone(&self.sdf))]
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        "Mirror".to_string()
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![SDFParam {
+            id: Self::ID_AXIS,
+            name: "axis".to_string(),
+            kind: SDFParamKind::String { choices: Self::AXIS_NAMES.iter().map(|s| s.to_string()).collect() },
+            value: SDFParamValue::String(Self::AXIS_NAMES[self.axis.get()].to_string()),
+            description: "The axis along which the child SDF is mirrored.".to_string(),
+        }]
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_AXIS, SDFParamValue::String(value)) if Self::AXIS_NAMES.contains(&value.as_str()) => {
+                let old_box = self.bounding_box();
+                self.axis.set(Self::AXIS_NAMES.iter().position(|name| name == value).unwrap());
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        if let Some(changed_box) = self.changed.take() {
+            return Some(changed_box);
+        }
+        let axis = self.axis.get();
+        self.sdf.changed().map(|changed_box| {
+            let mirrored = transform_bounding_box(&changed_box, |mut p| {
+                p[axis] = -p[axis];
+                p
+            });
+            merge_bounding_boxes(&changed_box, &mirrored)
+        })
+    }
+}
+
+/// Repeats the child SDF in a grid, optionally limiting the number of copies along each axis.
+/// The child should fit inside a cell of the grid (centered at the origin) for the distances to be correct.
+#[derive(Debug)]
+pub struct Repeat<S> {
+    sdf: Arc<S>,
+    id: u32,
+    spacing: Shared<Vector3<f32>>,
+    limit: Shared<Vector3<f32>>,
+    changed: ChangedBox,
+}
+
+impl<S> Clone for Repeat<S> {
+    fn clone(&self) -> Self {
+        Self {
+            sdf: Arc::clone(&self.sdf),
+            id: self.id,
+            spacing: self.spacing.clone(),
+            limit: self.limit.clone(),
+            changed: self.changed.clone(),
+        }
+    }
+}
+
+impl<S> Repeat<S> {
+    const ID_SPACING: u32 = 0;
+    const ID_LIMIT: u32 = 1;
+    const MAX_LIMIT: f32 = 100.0;
+
+    /// Creates a new grid repetition. A spacing of 0 disables the repetition along that axis, and
+    /// the limit is the number of extra copies to each side of the original along each axis.
+    pub fn new(sdf: S, spacing: Vector3<f32>, limit: Vector3<u32>) -> Self {
+        let limit = limit.map(|l| (l as f32).min(Self::MAX_LIMIT));
+        Self {
+            sdf: Arc::new(sdf),
+            id: next_id(),
+            spacing: Shared::new(spacing.map(|s| s.max(0.0))),
+            limit: Shared::new(limit),
+            changed: ChangedBox::default(),
+        }
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Moves the point to the closest copy of the child SDF.
+    fn repeat(p: Vector3<f32>, spacing: Vector3<f32>, limit: Vector3<f32>) -> Vector3<f32> {
+        let mut q = p;
+        for axis in 0..3 {
+            if spacing[axis] > 0.0 {
+                q[axis] -= spacing[axis] * (p[axis] / spacing[axis]).round().clamp(-limit[axis], limit[axis]);
+            }
+        }
+        q
+    }
+}
+
+impl<S: SDFSurface + 'static> SDFSurface for Repeat<S> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let [min, max] = self.sdf.bounding_box();
+        let extent = self.spacing.get().mul_element_wise(self.limit.get());
+        [min - extent, max + extent]
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        self.sdf.sample(Self::repeat(p, self.spacing.get(), self.limit.get()), distance_only)
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let (spacing, limit) = (self.spacing.get(), self.limit.get());
+        let points = points.iter().map(|p| Self::repeat(*p, spacing, limit)).collect::<Vec<_>>();
+        self.sdf.sample_batch(&points, distance_only)
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        vec![Box::new(Arc::clone(&self.sdf))]
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        "Repeat".to_string()
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![
+            vec3_param(Self::ID_SPACING, "spacing", self.spacing.get(), 0.0..=100.0, 0.01,
+                       "The distance between copies along each axis (0 disables the repetition along that axis)."),
+            vec3_param(Self::ID_LIMIT, "limit", self.limit.get(), 0.0..=Self::MAX_LIMIT, 1.0,
+                       "The number of extra copies to each side of the original along each axis."),
+        ]
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_SPACING, SDFParamValue::Vec3(value)) => {
+                let old_box = self.bounding_box();
+                self.spacing.set(value.map(|s| s.max(0.0)));
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                Ok(())
+            }
+            (Self::ID_LIMIT, SDFParamValue::Vec3(value)) => {
+                let old_box = self.bounding_box();
+                self.limit.set(value.map(|l| l.round().clamp(0.0, Self::MAX_LIMIT)));
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        if let Some(changed_box) = self.changed.take() {
+            return Some(changed_box);
+        }
+        // Any change to the child may affect all of its copies
+        self.sdf.changed().map(|_| self.bounding_box())
+    }
+}
@@ -0,0 +1,307 @@
+//! Tested parametric building blocks to start new SDFs from, instead of writing raw distance math.
+//!
+//! Each shape only defines its geometry (see [`Shape`] and [`Shape2D`]) and becomes a full
+//! [`SDFSurface`] when wrapped in a [`Primitive`], which adds a simple material, live-editable
+//! parameters and precise change tracking. They can be combined with [`crate::sdf::ops`] and
+//! exported through [`crate::sdf::ffi::set_root_sdf`].
+//!
+//! ```ignore
+//! use sdf_viewer::sdf::primitives::{Primitive, Torus};
+//! let torus = Primitive::new(Torus::new(1.0, 0.25)).with_color([0.8, 0.2, 0.2]).with_id(0);
+//! sdf_viewer::sdf::ffi::set_root_sdf(Box::new(torus));
+//! ```
+
+use cgmath::{Vector2, Vector3};
+
+use crate::sdf::{SDFParam, SDFParamKind, SDFParamValue, SDFSample, SDFSurface};
+use crate::sdf::defaults::merge_bounding_boxes;
+use crate::sdf::shared::{ChangedBox, float_param, next_id, Shared, unknown_param_error};
+
+pub mod profiles;
+pub mod solids;
+
+pub use profiles::{Circle, Extrude, Polygon, Rectangle, Revolve};
+pub use solids::{Capsule, Cone, Cuboid, Cylinder, Ellipsoid, HexPrism, RoundedCuboid, Slab, Sphere, Torus};
+
+/// The geometry of a 3D primitive, centered at the origin.
+pub trait Shape {
+    /// The user-facing name of the shape.
+    fn name(&self) -> String;
+
+    /// The (signed) distance from the given point to the surface, negative inside.
+    fn distance(&self, p: Vector3<f32>) -> f32;
+
+    /// The bounding box that contains the whole shape.
+    fn bounding_box(&self) -> [Vector3<f32>; 2];
+
+    /// The parameters that define the shape. Their IDs must be lower than [`Primitive::FIRST_MATERIAL_PARAM_ID`].
+    fn parameters(&self) -> Vec<SDFParam>;
+
+    /// Modifies a parameter returned by [`parameters`](#tymethod.parameters).
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String>;
+}
+
+/// The geometry of a 2D primitive on the XZ plane (or the radial-height plane for revolutions),
+/// centered at the origin. It needs [`Extrude`] or [`Revolve`] to become a 3D [`Shape`].
+pub trait Shape2D {
+    /// The user-facing name of the shape.
+    fn name(&self) -> String;
+
+    /// The (signed) distance from the given point to the outline, negative inside.
+    fn distance(&self, p: Vector2<f32>) -> f32;
+
+    /// The bounding rectangle that contains the whole shape.
+    fn bounding_box(&self) -> [Vector2<f32>; 2];
+
+    /// The parameters that define the shape.
+    fn parameters(&self) -> Vec<SDFParam>;
+
+    /// Modifies a parameter returned by [`parameters`](#tymethod.parameters).
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String>;
+}
+
+/// The uniform material applied to the whole surface of a [`Primitive`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    pub color: Vector3<f32>,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self { color: Vector3::new(0.8, 0.8, 0.8), metallic: 0.0, roughness: 0.5 }
+    }
+}
+
+/// Turns a [`Shape`] into an [`SDFSurface`] with a uniform [`Material`].
+/// Cloning it is cheap and the clone shares the parameters with the original.
+#[derive(Debug)]
+pub struct Primitive<S> {
+    shape: Shared<S>,
+    material: Shared<Material>,
+    id: u32,
+    changed: ChangedBox,
+}
+
+impl<S> Clone for Primitive<S> {
+    fn clone(&self) -> Self {
+        Self { shape: self.shape.clone(), material: self.material.clone(), id: self.id, changed: self.changed.clone() }
+    }
+}
+
+impl<S> Primitive<S> {
+    /// The parameter ID of the color, after which the other material parameters follow.
+    pub const FIRST_MATERIAL_PARAM_ID: u32 = 100;
+    const ID_COLOR: u32 = Self::FIRST_MATERIAL_PARAM_ID;
+    const ID_METALLIC: u32 = Self::FIRST_MATERIAL_PARAM_ID + 1;
+    const ID_ROUGHNESS: u32 = Self::FIRST_MATERIAL_PARAM_ID + 2;
+
+    pub fn new(shape: S) -> Self {
+        Self { shape: Shared::new(shape), material: Shared::new(Material::default()), id: next_id(), changed: ChangedBox::default() }
+    }
+
+    /// Sets the color of the whole surface.
+    pub fn with_color(self, color: impl Into<Vector3<f32>>) -> Self {
+        self.material.update(|material| material.color = color.into());
+        self
+    }
+
+    /// Sets the whole material of the surface.
+    pub fn with_material(self, material: Material) -> Self {
+        self.material.set(material);
+        self
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+}
+
+impl<S: Shape> From<S> for Primitive<S> {
+    fn from(shape: S) -> Self {
+        Self::new(shape)
+    }
+}
+
+impl<S: Shape> SDFSurface for Primitive<S> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        self.shape.with(|shape| shape.bounding_box())
+    }
+
+    fn sample(&self, p: Vector3<f32>, _distance_only: bool) -> SDFSample {
+        let distance = self.shape.with(|shape| shape.distance(p));
+        let material = self.material.get();
+        SDFSample { distance, color: material.color, metallic: material.metallic, roughness: material.roughness, occlusion: 0.0 }
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], _distance_only: bool) -> Vec<SDFSample> {
+        let material = self.material.get();
+        self.shape.with(|shape| points.iter().map(|p| SDFSample {
+            distance: shape.distance(*p),
+            color: material.color,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            occlusion: 0.0,
+        }).collect())
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        self.shape.with(|shape| shape.name())
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        let material = self.material.get();
+        let mut params = self.shape.with(|shape| shape.parameters());
+        params.push(SDFParam {
+            id: Self::ID_COLOR,
+            name: "color".to_string(),
+            kind: SDFParamKind::Color,
+            value: SDFParamValue::Color(material.color),
+            description: "The color of the surface.".to_string(),
+        });
+        params.push(float_param(Self::ID_METALLIC, "metallic", material.metallic, 0.0..=1.0,
+                                "How metallic the surface is."));
+        params.push(float_param(Self::ID_ROUGHNESS, "roughness", material.roughness, 0.0..=1.0,
+                                "How rough the surface is."));
+        params
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_COLOR, SDFParamValue::Color(value)) => self.material.update(|material| material.color = *value),
+            (Self::ID_METALLIC, SDFParamValue::Float(value)) => self.material.update(|material| material.metallic = value.clamp(0.0, 1.0)),
+            (Self::ID_ROUGHNESS, SDFParamValue::Float(value)) => self.material.update(|material| material.roughness = value.clamp(0.0, 1.0)),
+            (Self::FIRST_MATERIAL_PARAM_ID.., _) => return unknown_param_error(param_id, param_value),
+            _ => {
+                // The geometry changes inside both the previous and the new bounding boxes
+                let old_box = self.bounding_box();
+                self.shape.update(|shape| shape.set_parameter(param_id, param_value))?;
+                self.changed.add(merge_bounding_boxes(&old_box, &self.bounding_box()));
+                return Ok(());
+            }
+        }
+        // Material changes only affect the surface, which is inside the bounding box
+        self.changed.add(self.bounding_box());
+        Ok(())
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        self.changed.take()
+    }
+}
+
+/// The exact distance to an axis-aligned box centered at the origin (negative inside).
+fn box_distance(p: Vector3<f32>, half_size: Vector3<f32>) -> f32 {
+    let q = Vector3::new(p.x.abs() - half_size.x, p.y.abs() - half_size.y, p.z.abs() - half_size.z);
+    let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+    (outside.x * outside.x + outside.y * outside.y + outside.z * outside.z).sqrt() + q.x.max(q.y).max(q.z).min(0.0)
+}
+
+/// The exact distance to the intersection of a 2D distance field and a vertical slab of the given half height.
+fn slab_intersection_distance(distance_2d: f32, height_distance: f32) -> f32 {
+    let outside = Vector2::new(distance_2d.max(0.0), height_distance.max(0.0));
+    distance_2d.max(height_distance).min(0.0) + (outside.x * outside.x + outside.y * outside.y).sqrt()
+}
+
+/// Builds the parameter list of a shape from its float parameters `(id, name, value, description)`.
+fn size_params(params: &[(u32, &str, f32, &str)]) -> Vec<SDFParam> {
+    params.iter().map(|(id, name, value, description)| float_param(*id, name, *value, 0.0..=10.0, description)).collect()
+}
+
+/// Sets the given float field if the parameter matches any of the `(id, field)` pairs.
+fn set_size_param(fields: &mut [(u32, &mut f32)], param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+    match (fields.iter_mut().find(|(id, _)| *id == param_id), param_value) {
+        (Some((_, field)), SDFParamValue::Float(value)) => {
+            **field = value.max(0.0);
+            Ok(())
+        }
+        _ => unknown_param_error(param_id, param_value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2, Vector3, Zero};
+
+    use crate::sdf::{SDFParamValue, SDFSurface};
+    use crate::sdf::primitives::*;
+
+    /// Checks that the surface is inside the bounding box, by verifying that the distance at
+    /// many points of the faces of the bounding box is not negative.
+    fn assert_bounded(sdf: &dyn SDFSurface) {
+        let [min, max] = sdf.bounding_box();
+        let steps = 8;
+        for axis in 0..3 {
+            for face in [min[axis], max[axis]] {
+                for i in 0..=steps {
+                    for j in 0..=steps {
+                        let (u, v) = (i as f32 / steps as f32, j as f32 / steps as f32);
+                        let mut p = min + (max - min) * 0.5;
+                        p[axis] = face;
+                        p[(axis + 1) % 3] = min[(axis + 1) % 3] + (max[(axis + 1) % 3] - min[(axis + 1) % 3]) * u;
+                        p[(axis + 2) % 3] = min[(axis + 2) % 3] + (max[(axis + 2) % 3] - min[(axis + 2) % 3]) * v;
+                        let distance = sdf.sample(p, true).distance;
+                        assert!(distance >= -1e-4, "{} is inside at {:?} (distance {})", sdf.name(), p, distance);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_primitives_are_bounded() {
+        let square = [Vector2::new(-0.5, -0.5), Vector2::new(0.5, -0.5), Vector2::new(0.5, 0.5), Vector2::new(-0.5, 0.5)];
+        let primitives: Vec<Box<dyn SDFSurface>> = vec![
+            Box::new(Primitive::new(Cuboid::new(Vector3::new(1.0, 0.5, 0.25)))),
+            Box::new(Primitive::new(RoundedCuboid::new(Vector3::new(1.0, 0.5, 0.25), 0.1))),
+            Box::new(Primitive::new(Sphere::new(0.75))),
+            Box::new(Primitive::new(Ellipsoid::new(Vector3::new(1.0, 0.5, 0.25)))),
+            Box::new(Primitive::new(Torus::new(1.0, 0.25))),
+            Box::new(Primitive::new(Capsule::new(0.5, 0.25))),
+            Box::new(Primitive::new(Cylinder::new(0.5, 0.25))),
+            Box::new(Primitive::new(Cone::new(0.5, 0.5, 0.1))),
+            Box::new(Primitive::new(Slab::new(Vector3::new(1.0, 1.0, 0.0), 0.2, 0.1, 1.0))),
+            Box::new(Primitive::new(HexPrism::new(0.5, 0.25))),
+            Box::new(Primitive::new(Extrude::new(Circle::new(0.5), 0.25))),
+            Box::new(Primitive::new(Extrude::new(Polygon::new(square.to_vec()), 0.25))),
+            Box::new(Primitive::new(Revolve::new(Rectangle::new(Vector2::new(0.1, 0.3)), 0.5))),
+        ];
+        for primitive in &primitives {
+            assert_bounded(primitive.as_ref());
+        }
+    }
+
+    #[test]
+    fn test_exact_distances() {
+        let p = Vector3::new(2.0, 0.0, 0.0);
+        assert_eq!(Sphere::new(0.5).distance(p), 1.5);
+        assert_eq!(Cuboid::new(Vector3::new(1.0, 1.0, 1.0)).distance(p), 1.0);
+        assert_eq!(Cylinder::new(1.0, 0.5).distance(p), 1.5);
+        assert_eq!(Capsule::new(1.0, 0.5).distance(Vector3::new(0.0, 2.0, 0.0)), 0.5);
+        assert_eq!(Torus::new(1.0, 0.25).distance(Vector3::new(1.0, 0.0, 0.0)), -0.25);
+        assert!((Extrude::new(Rectangle::new(Vector2::new(1.0, 1.0)), 1.0).distance(p)
+            - Cuboid::new(Vector3::new(1.0, 1.0, 1.0)).distance(p)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_changed_boxes() {
+        let sphere = Primitive::new(Sphere::new(0.5));
+        assert!(sphere.changed().is_none());
+        sphere.set_parameter(0, &SDFParamValue::Float(1.0)).unwrap();
+        // The changed box contains both the old and the new surfaces
+        assert_eq!(sphere.changed(), Some([Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0)]));
+        assert!(sphere.changed().is_none());
+        sphere.set_parameter(Primitive::<Sphere>::FIRST_MATERIAL_PARAM_ID, &SDFParamValue::Color(Vector3::zero())).unwrap();
+        assert_eq!(sphere.sample(Vector3::zero(), false).color, Vector3::zero());
+        assert!(sphere.changed().is_some());
+        assert!(sphere.set_parameter(0, &SDFParamValue::Boolean(true)).is_err());
+        assert!(sphere.changed().is_none());
+    }
+}
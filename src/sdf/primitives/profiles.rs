@@ -0,0 +1,232 @@
+//! 2D shapes and the operations that turn them into 3D shapes.
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::sdf::{SDFParam, SDFParamValue};
+use crate::sdf::primitives::{set_size_param, Shape, Shape2D, size_params, slab_intersection_distance};
+use crate::sdf::shared::unknown_param_error;
+
+/// The offset added to the parameter IDs of the profile of an [`Extrude`] or [`Revolve`], to avoid
+/// conflicts with their own parameters.
+const PROFILE_PARAM_OFFSET: u32 = 10;
+
+/// Lists the parameters of the profile after the given ones, with their IDs offset.
+fn with_profile_params(mut params: Vec<SDFParam>, profile: &impl Shape2D) -> Vec<SDFParam> {
+    params.extend(profile.parameters().into_iter().map(|mut param| {
+        param.id += PROFILE_PARAM_OFFSET;
+        param.name = format!("{} {}", profile.name().to_lowercase(), param.name);
+        param
+    }));
+    params
+}
+
+/// A circle of the given radius.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Circle {
+    pub radius: f32,
+}
+
+impl Circle {
+    pub fn new(radius: f32) -> Self {
+        Self { radius }
+    }
+}
+
+impl Shape2D for Circle {
+    fn name(&self) -> String {
+        "Circle".to_string()
+    }
+
+    fn distance(&self, p: Vector2<f32>) -> f32 {
+        p.magnitude() - self.radius
+    }
+
+    fn bounding_box(&self) -> [Vector2<f32>; 2] {
+        [Vector2::new(-self.radius, -self.radius), Vector2::new(self.radius, self.radius)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        size_params(&[(0, "radius", self.radius, "The radius of the circle.")])
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        set_size_param(&mut [(0, &mut self.radius)], param_id, param_value)
+    }
+}
+
+/// An axis-aligned rectangle with the given half size along each axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rectangle {
+    pub half_size: Vector2<f32>,
+}
+
+impl Rectangle {
+    pub fn new(half_size: Vector2<f32>) -> Self {
+        Self { half_size }
+    }
+}
+
+impl Shape2D for Rectangle {
+    fn name(&self) -> String {
+        "Rectangle".to_string()
+    }
+
+    fn distance(&self, p: Vector2<f32>) -> f32 {
+        let q = Vector2::new(p.x.abs() - self.half_size.x, p.y.abs() - self.half_size.y);
+        Vector2::new(q.x.max(0.0), q.y.max(0.0)).magnitude() + q.x.max(q.y).min(0.0)
+    }
+
+    fn bounding_box(&self) -> [Vector2<f32>; 2] {
+        [-self.half_size, self.half_size]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        size_params(&[
+            (0, "half width", self.half_size.x, "Half the size of the rectangle along the first axis."),
+            (1, "half height", self.half_size.y, "Half the size of the rectangle along the second axis."),
+        ])
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        set_size_param(&mut [(0, &mut self.half_size.x), (1, &mut self.half_size.y)], param_id, param_value)
+    }
+}
+
+/// A closed polygon defined by its vertices (in any winding order), which has no parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    pub vertices: Vec<Vector2<f32>>,
+}
+
+impl Polygon {
+    pub fn new(vertices: Vec<Vector2<f32>>) -> Self {
+        Self { vertices }
+    }
+}
+
+impl Shape2D for Polygon {
+    fn name(&self) -> String {
+        "Polygon".to_string()
+    }
+
+    fn distance(&self, p: Vector2<f32>) -> f32 {
+        // Based on https://iquilezles.org/articles/distfunctions2d/
+        let v = &self.vertices;
+        if v.is_empty() {
+            return f32::MAX;
+        }
+        let mut dist2 = (p - v[0]).magnitude2();
+        let mut sign = 1.0;
+        let mut j = v.len() - 1;
+        for i in 0..v.len() {
+            let e = v[j] - v[i];
+            let w = p - v[i];
+            let e_len2 = e.magnitude2();
+            let t = if e_len2 > 0.0 { (w.dot(e) / e_len2).clamp(0.0, 1.0) } else { 0.0 };
+            dist2 = dist2.min((w - e * t).magnitude2());
+            // Winding number test to know whether the point is inside
+            let conditions = [p.y >= v[i].y, p.y < v[j].y, e.x * w.y > e.y * w.x];
+            if conditions.iter().all(|c| *c) || conditions.iter().all(|c| !*c) {
+                sign = -sign;
+            }
+            j = i;
+        }
+        sign * dist2.sqrt()
+    }
+
+    fn bounding_box(&self) -> [Vector2<f32>; 2] {
+        self.vertices.iter().fold(
+            [Vector2::new(f32::MAX, f32::MAX), Vector2::new(f32::MIN, f32::MIN)],
+            |[min, max], v| [Vector2::new(min.x.min(v.x), min.y.min(v.y)), Vector2::new(max.x.max(v.x), max.y.max(v.y))])
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![]
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        unknown_param_error(param_id, param_value)
+    }
+}
+
+/// Extrudes a 2D profile on the XZ plane along the Y axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extrude<P> {
+    pub profile: P,
+    pub half_height: f32,
+}
+
+impl<P> Extrude<P> {
+    pub fn new(profile: P, half_height: f32) -> Self {
+        Self { profile, half_height }
+    }
+}
+
+impl<P: Shape2D> Shape for Extrude<P> {
+    fn name(&self) -> String {
+        format!("Extruded {}", self.profile.name().to_lowercase())
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        slab_intersection_distance(self.profile.distance(Vector2::new(p.x, p.z)), p.y.abs() - self.half_height)
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let [min, max] = self.profile.bounding_box();
+        [Vector3::new(min.x, -self.half_height, min.y), Vector3::new(max.x, self.half_height, max.y)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        with_profile_params(size_params(&[(0, "half height", self.half_height, "Half the length of the extrusion.")]), &self.profile)
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        if param_id >= PROFILE_PARAM_OFFSET {
+            self.profile.set_parameter(param_id - PROFILE_PARAM_OFFSET, param_value)
+        } else {
+            set_size_param(&mut [(0, &mut self.half_height)], param_id, param_value)
+        }
+    }
+}
+
+/// Revolves a 2D profile around the Y axis. The first coordinate of the profile is the distance
+/// to the axis (moved by the offset) and the second one is the height.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Revolve<P> {
+    pub profile: P,
+    pub offset: f32,
+}
+
+impl<P> Revolve<P> {
+    pub fn new(profile: P, offset: f32) -> Self {
+        Self { profile, offset }
+    }
+}
+
+impl<P: Shape2D> Shape for Revolve<P> {
+    fn name(&self) -> String {
+        format!("Revolved {}", self.profile.name().to_lowercase())
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        self.profile.distance(Vector2::new(Vector2::new(p.x, p.z).magnitude() - self.offset, p.y))
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let [min, max] = self.profile.bounding_box();
+        let radius = (self.offset + min.x).abs().max((self.offset + max.x).abs());
+        [Vector3::new(-radius, min.y, -radius), Vector3::new(radius, max.y, radius)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        with_profile_params(size_params(&[(0, "offset", self.offset, "The distance from the axis to the center of the profile.")]), &self.profile)
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        if param_id >= PROFILE_PARAM_OFFSET {
+            self.profile.set_parameter(param_id - PROFILE_PARAM_OFFSET, param_value)
+        } else {
+            set_size_param(&mut [(0, &mut self.offset)], param_id, param_value)
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! 3D shapes, mostly based on https://iquilezles.org/articles/distfunctions/.
+//! Shapes with a main axis are aligned with the Y axis.
+
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+
+use crate::sdf::{SDFParam, SDFParamValue};
+use crate::sdf::primitives::{box_distance, set_size_param, Shape, size_params, slab_intersection_distance};
+use crate::sdf::shared::{float_param, unknown_param_error, vec3_param};
+
+/// A sphere of the given radius.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sphere {
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(radius: f32) -> Self {
+        Self { radius }
+    }
+}
+
+impl Shape for Sphere {
+    fn name(&self) -> String {
+        "Sphere".to_string()
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        p.magnitude() - self.radius
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let r = self.radius;
+        [Vector3::new(-r, -r, -r), Vector3::new(r, r, r)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        size_params(&[(0, "radius", self.radius, "The radius of the sphere.")])
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        set_size_param(&mut [(0, &mut self.radius)], param_id, param_value)
+    }
+}
+
+/// An axis-aligned box (cuboid) with the given half size along each axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cuboid {
+    pub half_size: Vector3<f32>,
+}
+
+impl Cuboid {
+    pub fn new(half_size: Vector3<f32>) -> Self {
+        Self { half_size }
+    }
+}
+
+impl Shape for Cuboid {
+    fn name(&self) -> String {
+        "Box".to_string()
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        box_distance(p, self.half_size)
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        [-self.half_size, self.half_size]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![vec3_param(0, "half size", self.half_size, 0.0..=10.0, 0.01, "The half size of the box along each axis.")]
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (0, SDFParamValue::Vec3(value)) => {
+                self.half_size = value.map(|c| c.max(0.0));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+}
+
+/// An axis-aligned box with rounded edges, keeping the given half size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundedCuboid {
+    pub half_size: Vector3<f32>,
+    pub radius: f32,
+}
+
+impl RoundedCuboid {
+    pub fn new(half_size: Vector3<f32>, radius: f32) -> Self {
+        Self { half_size, radius }
+    }
+
+    /// The radius can't be larger than the smallest half size.
+    fn effective_radius(&self) -> f32 {
+        self.radius.min(self.half_size.x).min(self.half_size.y).min(self.half_size.z)
+    }
+}
+
+impl Shape for RoundedCuboid {
+    fn name(&self) -> String {
+        "Rounded box".to_string()
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        let radius = self.effective_radius();
+        box_distance(p, self.half_size - Vector3::new(radius, radius, radius)) - radius
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        [-self.half_size, self.half_size]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![
+            vec3_param(0, "half size", self.half_size, 0.0..=10.0, 0.01, "The half size of the box along each axis."),
+            float_param(1, "radius", self.radius, 0.0..=10.0, "The radius of the rounded edges."),
+        ]
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (0, SDFParamValue::Vec3(value)) => {
+                self.half_size = value.map(|c| c.max(0.0));
+                Ok(())
+            }
+            _ => set_size_param(&mut [(1, &mut self.radius)], param_id, param_value),
+        }
+    }
+}
+
+/// An axis-aligned ellipsoid with the given radii (the distance is a good approximation, not exact).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ellipsoid {
+    pub radii: Vector3<f32>,
+}
+
+impl Ellipsoid {
+    pub fn new(radii: Vector3<f32>) -> Self {
+        Self { radii }
+    }
+}
+
+impl Shape for Ellipsoid {
+    fn name(&self) -> String {
+        "Ellipsoid".to_string()
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        let r = self.radii.map(|c| c.max(1e-6));
+        let k0 = Vector3::new(p.x / r.x, p.y / r.y, p.z / r.z).magnitude();
+        let k1 = Vector3::new(p.x / (r.x * r.x), p.y / (r.y * r.y), p.z / (r.z * r.z)).magnitude();
+        if k1.is_zero() { // At the center
+            -r.x.min(r.y).min(r.z)
+        } else {
+            k0 * (k0 - 1.0) / k1
+        }
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        [-self.radii, self.radii]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![vec3_param(0, "radii", self.radii, 0.0..=10.0, 0.01, "The radius of the ellipsoid along each axis.")]
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (0, SDFParamValue::Vec3(value)) => {
+                self.radii = value.map(|c| c.max(0.0));
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+}
+
+/// A torus around the Y axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Torus {
+    /// The distance from the center to the center of the tube.
+    pub major_radius: f32,
+    /// The radius of the tube.
+    pub minor_radius: f32,
+}
+
+impl Torus {
+    pub fn new(major_radius: f32, minor_radius: f32) -> Self {
+        Self { major_radius, minor_radius }
+    }
+}
+
+impl Shape for Torus {
+    fn name(&self) -> String {
+        "Torus".to_string()
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        Vector2::new(Vector2::new(p.x, p.z).magnitude() - self.major_radius, p.y).magnitude() - self.minor_radius
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let (xz, y) = (self.major_radius + self.minor_radius, self.minor_radius);
+        [Vector3::new(-xz, -y, -xz), Vector3::new(xz, y, xz)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        size_params(&[
+            (0, "major radius", self.major_radius, "The distance from the center to the center of the tube."),
+            (1, "minor radius", self.minor_radius, "The radius of the tube."),
+        ])
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        set_size_param(&mut [(0, &mut self.major_radius), (1, &mut self.minor_radius)], param_id, param_value)
+    }
+}
+
+/// A capsule (a cylinder with hemispherical caps) along the Y axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capsule {
+    /// Half the distance between the centers of both caps.
+    pub half_height: f32,
+    pub radius: f32,
+}
+
+impl Capsule {
+    pub fn new(half_height: f32, radius: f32) -> Self {
+        Self { half_height, radius }
+    }
+}
+
+impl Shape for Capsule {
+    fn name(&self) -> String {
+        "Capsule".to_string()
+    }
+
+    fn distance(&self, mut p: Vector3<f32>) -> f32 {
+        p.y -= p.y.clamp(-self.half_height, self.half_height);
+        p.magnitude() - self.radius
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let (xz, y) = (self.radius, self.half_height + self.radius);
+        [Vector3::new(-xz, -y, -xz), Vector3::new(xz, y, xz)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        size_params(&[
+            (0, "half height", self.half_height, "Half the distance between the centers of both caps."),
+            (1, "radius", self.radius, "The radius of the capsule."),
+        ])
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        set_size_param(&mut [(0, &mut self.half_height), (1, &mut self.radius)], param_id, param_value)
+    }
+}
+
+/// A capped cylinder along the Y axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cylinder {
+    pub half_height: f32,
+    pub radius: f32,
+}
+
+impl Cylinder {
+    pub fn new(half_height: f32, radius: f32) -> Self {
+        Self { half_height, radius }
+    }
+}
+
+impl Shape for Cylinder {
+    fn name(&self) -> String {
+        "Cylinder".to_string()
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        slab_intersection_distance(Vector2::new(p.x, p.z).magnitude() - self.radius, p.y.abs() - self.half_height)
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let (xz, y) = (self.radius, self.half_height);
+        [Vector3::new(-xz, -y, -xz), Vector3::new(xz, y, xz)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        size_params(&[
+            (0, "half height", self.half_height, "Half the height of the cylinder."),
+            (1, "radius", self.radius, "The radius of the cylinder."),
+        ])
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        set_size_param(&mut [(0, &mut self.half_height), (1, &mut self.radius)], param_id, param_value)
+    }
+}
+
+/// A capped cone along the Y axis, which becomes a pointy cone if the top radius is 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cone {
+    pub half_height: f32,
+    pub bottom_radius: f32,
+    pub top_radius: f32,
+}
+
+impl Cone {
+    pub fn new(half_height: f32, bottom_radius: f32, top_radius: f32) -> Self {
+        Self { half_height, bottom_radius, top_radius }
+    }
+}
+
+impl Shape for Cone {
+    fn name(&self) -> String {
+        "Cone".to_string()
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        let (h, r1, r2) = (self.half_height, self.bottom_radius, self.top_radius);
+        let q = Vector2::new(Vector2::new(p.x, p.z).magnitude(), p.y);
+        let k1 = Vector2::new(r2, h);
+        let k2 = Vector2::new(r2 - r1, 2.0 * h);
+        let ca = Vector2::new(q.x - q.x.min(if q.y < 0.0 { r1 } else { r2 }), q.y.abs() - h);
+        let k2_len2 = k2.magnitude2();
+        let t = if k2_len2 > 0.0 { ((k1 - q).dot(k2) / k2_len2).clamp(0.0, 1.0) } else { 0.0 };
+        let cb = q - k1 + k2 * t;
+        let sign = if cb.x < 0.0 && ca.y < 0.0 { -1.0 } else { 1.0 };
+        sign * ca.magnitude2().min(cb.magnitude2()).sqrt()
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let (xz, y) = (self.bottom_radius.max(self.top_radius), self.half_height);
+        [Vector3::new(-xz, -y, -xz), Vector3::new(xz, y, xz)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        size_params(&[
+            (0, "half height", self.half_height, "Half the height of the cone."),
+            (1, "bottom radius", self.bottom_radius, "The radius of the bottom cap."),
+            (2, "top radius", self.top_radius, "The radius of the top cap (0 for a pointy cone)."),
+        ])
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        set_size_param(&mut [(0, &mut self.half_height), (1, &mut self.bottom_radius), (2, &mut self.top_radius)],
+                       param_id, param_value)
+    }
+}
+
+/// The space between two parallel planes, clipped to a cube so that it is bounded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slab {
+    /// The normal of the planes (it does not need to be normalized).
+    pub normal: Vector3<f32>,
+    /// The signed distance from the origin to the middle of the slab, along the normal.
+    pub offset: f32,
+    pub thickness: f32,
+    /// The half size of the cube that clips the otherwise infinite slab.
+    pub extent: f32,
+}
+
+impl Slab {
+    pub fn new(normal: Vector3<f32>, offset: f32, thickness: f32, extent: f32) -> Self {
+        Self { normal, offset, thickness, extent }
+    }
+}
+
+impl Shape for Slab {
+    fn name(&self) -> String {
+        "Slab".to_string()
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        let normal = if self.normal.is_zero() { Vector3::unit_y() } else { self.normal.normalize() };
+        let slab = (p.dot(normal) - self.offset).abs() - self.thickness / 2.0;
+        // Intersection with the clipping cube (a lower bound of the distance outside of the cube)
+        slab.max(box_distance(p, Vector3::new(self.extent, self.extent, self.extent)))
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let e = self.extent;
+        [Vector3::new(-e, -e, -e), Vector3::new(e, e, e)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![
+            vec3_param(0, "normal", self.normal, -1.0..=1.0, 0.01, "The normal of the planes."),
+            float_param(1, "offset", self.offset, -10.0..=10.0, "The distance from the origin to the middle of the slab."),
+            float_param(2, "thickness", self.thickness, 0.0..=10.0, "The distance between both planes."),
+            float_param(3, "extent", self.extent, 0.0..=10.0, "The half size of the cube that clips the slab."),
+        ]
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (0, SDFParamValue::Vec3(value)) => {
+                self.normal = *value;
+                Ok(())
+            }
+            (1, SDFParamValue::Float(value)) => {
+                self.offset = *value;
+                Ok(())
+            }
+            _ => set_size_param(&mut [(2, &mut self.thickness), (3, &mut self.extent)], param_id, param_value),
+        }
+    }
+}
+
+/// A hexagonal prism along the Y axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HexPrism {
+    /// The distance from the axis to the center of each side (the apothem).
+    pub radius: f32,
+    pub half_height: f32,
+}
+
+impl HexPrism {
+    pub fn new(radius: f32, half_height: f32) -> Self {
+        Self { radius, half_height }
+    }
+}
+
+impl Shape for HexPrism {
+    fn name(&self) -> String {
+        "Hexagonal prism".to_string()
+    }
+
+    fn distance(&self, p: Vector3<f32>) -> f32 {
+        const K: Vector3<f32> = Vector3::new(-0.866_025_4, 0.5, 0.577_350_26);
+        let mut q = Vector2::new(p.x.abs(), p.z.abs());
+        q -= Vector2::new(K.x, K.y) * (2.0 * Vector2::new(K.x, K.y).dot(q).min(0.0));
+        let edge = K.z * self.radius;
+        let distance_2d = (q - Vector2::new(q.x.clamp(-edge, edge), self.radius)).magnitude() * (q.y - self.radius).signum();
+        slab_intersection_distance(distance_2d, p.y.abs() - self.half_height)
+    }
+
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        // The vertices are along the X axis, at the circumradius
+        let (x, y, z) = (self.radius * 2.0 * 0.577_350_26, self.half_height, self.radius);
+        [Vector3::new(-x, -y, -z), Vector3::new(x, y, z)]
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        size_params(&[
+            (0, "radius", self.radius, "The distance from the axis to the center of each side."),
+            (1, "half height", self.half_height, "Half the height of the prism."),
+        ])
+    }
+
+    fn set_parameter(&mut self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        set_size_param(&mut [(0, &mut self.radius), (1, &mut self.half_height)], param_id, param_value)
+    }
+}
@@ -0,0 +1,223 @@
+//! The client side of the remote SDF protocol (see [`crate::sdf::remote`]).
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::sdf::{SDFParam, SDFParamValue, SDFSample, SDFSurface};
+use crate::sdf::defaults::{glsl_default_impl, name_default_impl, normal_batch_from_samples};
+use crate::sdf::error::{SDFError, SDFErrorLog};
+use crate::sdf::remote::{Decoder, Encoder, REMOTE_PROTOCOL_VERSION};
+
+/// A SDF that is evaluated by a server of the remote SDF protocol, with a blocking request per call.
+///
+/// Failed requests are answered with safe defaults, as the [`SDFSurface`] API is infallible (other
+/// than [`set_parameter`](SDFSurface::set_parameter)), and reported through [`SDFSurface::take_errors`].
+#[derive(Debug, Clone)]
+pub struct RemoteSDF {
+    /// The base URL of the protocol, without the trailing slash (like `http://127.0.0.1:8080/sdf`).
+    base_url: String,
+    sdf_id: u32,
+    /// The failed requests, shared by the whole hierarchy.
+    errors: SDFErrorLog,
+}
+
+impl RemoteSDF {
+    /// Creates the client for the root SDF of the server at the given base URL, without any request.
+    /// See [`check_version`] to validate the server first.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self { base_url: base_url.into().trim_end_matches('/').to_string(), sdf_id: 0, errors: SDFErrorLog::default() }
+    }
+
+    /// The URL of the endpoint of the protocol that reports its version, to be used with [`check_version`].
+    pub fn version_url(&self) -> String {
+        format!("{}/version", self.base_url)
+    }
+
+    fn fetch(&self, method: &str, endpoint: &str, body: Vec<u8>) -> Result<ehttp::Response, String> {
+        let url = format!("{}/{}/{}", self.base_url, self.sdf_id, endpoint);
+        let request = if method == "POST" { ehttp::Request::post(url, body) } else { ehttp::Request::get(url) };
+        fetch_blocking(&request)
+    }
+
+    /// Requests the given endpoint, returning the body of successful responses and logging errors.
+    fn request(&self, method: &str, endpoint: &str, body: Vec<u8>) -> Option<Vec<u8>> {
+        match self.fetch(method, endpoint, body) {
+            Ok(resp) if resp.ok => Some(resp.bytes),
+            Ok(resp) => {
+                tracing::error!("Remote SDF {} failed for ID {} with status {}: {}",
+                    endpoint, self.sdf_id, resp.status, String::from_utf8_lossy(&resp.bytes));
+                self.report(endpoint, format!("status {}: {}", resp.status, String::from_utf8_lossy(&resp.bytes)));
+                None
+            }
+            Err(err) => {
+                tracing::error!("Remote SDF {} failed for ID {}: {}", endpoint, self.sdf_id, err);
+                self.report(endpoint, err);
+                None
+            }
+        }
+    }
+
+    /// Records the error of a request to the given endpoint, to be reported to the user.
+    fn report(&self, endpoint: &str, message: String) {
+        let endpoint = endpoint.split('?').next().unwrap_or_default();
+        self.errors.push(SDFError::new(message).with_export(endpoint).with_sdf_id(self.sdf_id).with_url(&self.base_url));
+    }
+
+    /// Requests and decodes the given endpoint, logging errors.
+    fn request_decode<T>(&self, method: &str, endpoint: &str, body: Vec<u8>,
+                         decode: impl FnOnce(&mut Decoder) -> Result<T, String>) -> Option<T> {
+        let bytes = self.request(method, endpoint, body)?;
+        decode(&mut Decoder::new(&bytes))
+            .map_err(|err| {
+                tracing::error!("Remote SDF {} returned invalid data for ID {}: {}", endpoint, self.sdf_id, err);
+                self.report(endpoint, format!("invalid data: {err}"));
+            })
+            .ok()
+    }
+}
+
+/// Validates the response of the version endpoint of a remote SDF server (see [`RemoteSDF::version_url`]).
+pub fn check_version(resp: &ehttp::Response) -> anyhow::Result<()> {
+    if !resp.ok {
+        return Err(anyhow::anyhow!("Not a remote SDF server (status {} {})", resp.status, resp.status_text));
+    }
+    let version = Decoder::new(&resp.bytes).u32().map_err(|err| anyhow::anyhow!("Not a remote SDF server: {}", err))?;
+    if version != REMOTE_PROTOCOL_VERSION {
+        return Err(anyhow::anyhow!("Incompatible remote SDF server: it uses version {} of the protocol, but this viewer uses version {}",
+            version, REMOTE_PROTOCOL_VERSION));
+    }
+    Ok(())
+}
+
+/// Performs the request synchronously, as the [`SDFSurface`] API is synchronous.
+#[cfg(not(target_arch = "wasm32"))]
+fn fetch_blocking(request: &ehttp::Request) -> Result<ehttp::Response, String> {
+    ehttp::fetch_blocking(request)
+}
+
+/// Performs the request synchronously, as the [`SDFSurface`] API is synchronous.
+///
+/// Browsers only support this through the deprecated synchronous mode of `XMLHttpRequest`, which
+/// also needs a user-defined charset to read binary responses.
+#[cfg(target_arch = "wasm32")]
+fn fetch_blocking(request: &ehttp::Request) -> Result<ehttp::Response, String> {
+    let js_err = |err: wasm_bindgen::JsValue| format!("{err:?}");
+    let xhr = web_sys::XmlHttpRequest::new().map_err(js_err)?;
+    xhr.open_with_async(&request.method, &request.url, false).map_err(js_err)?;
+    xhr.override_mime_type("text/plain; charset=x-user-defined").map_err(js_err)?;
+    for (key, value) in &request.headers.headers {
+        xhr.set_request_header(key, value).map_err(js_err)?;
+    }
+    if request.body.is_empty() {
+        xhr.send().map_err(js_err)?;
+    } else {
+        xhr.send_with_opt_u8_array(Some(&request.body)).map_err(js_err)?;
+    }
+    let status = xhr.status().map_err(js_err)?;
+    // The user-defined charset maps each byte to a character whose lower 8 bits are that byte.
+    let bytes = xhr.response_text().map_err(js_err)?.unwrap_or_default().chars().map(|c| c as u32 as u8).collect();
+    Ok(ehttp::Response {
+        url: request.url.clone(),
+        ok: (200..300).contains(&status),
+        status,
+        status_text: xhr.status_text().unwrap_or_default(),
+        headers: ehttp::Headers::default(),
+        bytes,
+    })
+}
+
+impl SDFSurface for RemoteSDF {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        self.request_decode("GET", "bounding_box", vec![], |dec| dec.bounding_box())
+            .unwrap_or([Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0)])
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        self.sample_batch(&[p], distance_only).pop().unwrap() // Always one sample per point
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        let mut enc = Encoder::default();
+        for p in points {
+            enc.vec3(*p);
+        }
+        let endpoint = if distance_only { "sample_batch?distance_only=1" } else { "sample_batch" };
+        let mut samples = self.request_decode("POST", endpoint, enc.finish(),
+                                              |dec| (0..points.len()).map(|_| dec.sample()).collect::<Result<Vec<_>, _>>())
+            .unwrap_or_default();
+        if samples.len() != points.len() { // Far away (empty) samples on errors
+            samples.resize_with(points.len(), || SDFSample::new(f32::MAX, Vector3::new(0.0, 0.0, 0.0)));
+        }
+        samples
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        self.request_decode("GET", "children", vec![], |dec| (0..dec.u32()?).map(|_| dec.u32()).collect::<Result<Vec<_>, _>>())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|sdf_id| Box::new(Self { sdf_id, ..self.clone() }) as Box<dyn SDFSurface>)
+            .collect()
+    }
+
+    fn id(&self) -> u32 {
+        self.sdf_id
+    }
+
+    fn name(&self) -> String {
+        self.request("GET", "name", vec![])
+            .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+            .unwrap_or_else(|| name_default_impl(self))
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        self.request_decode("GET", "parameters", vec![], |dec| (0..dec.u32()?).map(|_| dec.param()).collect::<Result<Vec<_>, _>>())
+            .unwrap_or_default()
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        let mut enc = Encoder::default();
+        enc.param_value(param_value);
+        let resp = self.fetch("POST", &format!("set_parameter/{param_id}"), enc.finish())?;
+        if resp.ok {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&resp.bytes).to_string())
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        let bytes = self.request("GET", "changed", vec![])?;
+        if bytes.is_empty() {
+            return None;
+        }
+        Decoder::new(&bytes).bounding_box()
+            .map_err(|err| {
+                tracing::error!("Remote SDF changed returned invalid data for ID {}: {}", self.sdf_id, err);
+                self.report("changed", format!("invalid data: {err}"));
+            })
+            .ok()
+    }
+
+    fn glsl(&self) -> String {
+        self.request("GET", "glsl", vec![])
+            .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+            .unwrap_or_else(|| glsl_default_impl(self))
+    }
+
+    fn take_errors(&self) -> Vec<SDFError> {
+        self.errors.take()
+    }
+
+    fn normal(&self, p: Vector3<f32>, eps: Option<f32>) -> Vector3<f32> {
+        // Same as the default implementation, but with a single request
+        let eps = eps.unwrap_or(0.001);
+        let offsets = [Vector3::new(1., -1., -1.), Vector3::new(-1., 1., -1.), Vector3::new(-1., -1., 1.), Vector3::new(1., 1., 1.)];
+        let points: Vec<_> = offsets.iter().map(|o| p + o * eps).collect();
+        let samples = self.sample_batch(&points, true);
+        offsets.iter().zip(samples).map(|(o, s)| o * s.distance).fold(Vector3::new(0., 0., 0.), |a, b| a + b).normalize()
+    }
+
+    fn normal_batch(&self, points: &[Vector3<f32>], eps: Option<f32>) -> Vec<Vector3<f32>> {
+        normal_batch_from_samples(self, points, eps) // Same as the normals above, but with a single request
+    }
+}
+
@@ -0,0 +1,451 @@
+//! Exposes a SDF over HTTP, so that an expensive SDF can be evaluated by one process (or machine)
+//! while another one renders it, which also works for viewers that can't load the SDF themselves
+//! (like the web and Android apps for native libraries).
+//!
+//! The server side is [`handle_request`], which is independent of the HTTP server (see
+//! `server --remote-sdf`), and the client side is [`client::RemoteSDF`].
+//!
+//! # Protocol (version 1)
+//!
+//! All requests are relative to a base URL (like `http://127.0.0.1:8080/sdf`) and most of them
+//! refer to a SDF of the hierarchy by its ID, where `0` always means the root SDF (as in the
+//! WebAssembly API, see [`crate::sdf::wasm`]):
+//!
+//! | Request                                           | Body            | Response                                |
+//! |---------------------------------------------------|-----------------|-----------------------------------------|
+//! | `GET {base}/version`                              |                 | u32: [`REMOTE_PROTOCOL_VERSION`]        |
+//! | `GET {base}/{id}/bounding_box`                    |                 | 2 vec3: min and max                     |
+//! | `POST {base}/{id}/sample_batch?distance_only=1`   | N vec3: points  | N samples                               |
+//! | `GET {base}/{id}/children`                        |                 | list of u32: IDs of the children        |
+//! | `GET {base}/{id}/name`                            |                 | UTF-8 text                              |
+//! | `GET {base}/{id}/parameters`                      |                 | list of parameters                      |
+//! | `POST {base}/{id}/set_parameter/{param_id}`       | parameter value | empty, or UTF-8 error text (status 422) |
+//! | `GET {base}/{id}/changed`                         |                 | empty if unchanged, or 2 vec3           |
+//! | `GET {base}/{id}/glsl`                            |                 | UTF-8 text                              |
+//!
+//! The `distance_only` query parameter is optional (`0` by default). Unknown IDs or endpoints
+//! respond with status 404, and malformed bodies with status 400.
+//!
+//! Bodies use a compact binary encoding of little-endian values: u32, i32 and f32 take 4 bytes,
+//! a bool is a u32 (0 or 1), a vec3 is 3 f32, a string is its length in bytes (u32) followed by its
+//! UTF-8 bytes and a list is its length (u32) followed by its items. A sample is 7 f32: distance,
+//! color (vec3), metallic, roughness and occlusion.
+//!
+//! A parameter is its ID (u32), name (string), kind, value and description (string). Both kinds and
+//! values are the index of the variant (u32, in declaration order of [`SDFParamKind`] and
+//! [`SDFParamValue`], like the WebAssembly API) followed by its fields:
+//! - Kinds: `Boolean`; `Int`: min, max and step (i32); `Float`, `Vec3` and `Angle`: min, max and
+//!   step (f32); `String`: choices (list of strings); `Color`.
+//! - Values: `Boolean` (bool); `Int` (i32); `Float` and `Angle` (f32); `String` (string);
+//!   `Vec3` and `Color` (vec3).
+
+use cgmath::Vector3;
+
+use crate::sdf::{SDFParam, SDFParamKind, SDFParamValue, SDFSample, SDFSurface};
+
+#[cfg(feature = "ehttp")]
+pub mod client;
+
+/// The version of the protocol, which must be increased whenever a change would make old clients
+/// and new servers (or vice versa) misinterpret each other.
+pub const REMOTE_PROTOCOL_VERSION: u32 = 1;
+
+/// The response to a request of the protocol, before being converted to an HTTP response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteResponse {
+    /// The HTTP status code.
+    pub status: u16,
+    /// The binary body (see the module documentation for the encoding).
+    pub body: Vec<u8>,
+}
+
+impl RemoteResponse {
+    fn ok(body: Vec<u8>) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self { status, body: message.into().into_bytes() }
+    }
+}
+
+/// Answers a request of the protocol for the given root SDF. The path is relative to the base URL
+/// (like `0/bounding_box`) and the query is the part of the URL after the `?` (if any).
+///
+/// It does not check the HTTP method, so that it is as easy as possible to use from any server.
+pub fn handle_request(root: &dyn SDFSurface, path: &str, query: &str, body: &[u8]) -> RemoteResponse {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    if segments == ["version"] {
+        let mut enc = Encoder::default();
+        enc.u32(REMOTE_PROTOCOL_VERSION);
+        return RemoteResponse::ok(enc.finish());
+    }
+    let sdf_id = match segments.first().and_then(|id| id.parse::<u32>().ok()) {
+        Some(id) => id,
+        None => return RemoteResponse::error(404, format!("Unknown endpoint: {path}")),
+    };
+    let distance_only = query.split('&').any(|pair| pair == "distance_only=1" || pair == "distance_only=true");
+    with_sdf(root, sdf_id, |sdf| {
+        let mut enc = Encoder::default();
+        match segments[1..] {
+            ["bounding_box"] => enc.bounding_box(&sdf.bounding_box()),
+            ["sample_batch"] => {
+                let mut dec = Decoder::new(body);
+                let mut points = Vec::with_capacity(body.len() / 12);
+                while !dec.is_empty() {
+                    match dec.vec3() {
+                        Ok(p) => points.push(p),
+                        Err(err) => return RemoteResponse::error(400, err),
+                    }
+                }
+                for sample in sdf.sample_batch(&points, distance_only) {
+                    enc.sample(&sample);
+                }
+            }
+            ["children"] => {
+                let children = sdf.children();
+                enc.u32(children.len() as u32);
+                for child in children {
+                    enc.u32(child.id());
+                }
+            }
+            ["name"] => return RemoteResponse::ok(sdf.name().into_bytes()),
+            ["parameters"] => {
+                let params = sdf.parameters();
+                enc.u32(params.len() as u32);
+                for param in &params {
+                    enc.param(param);
+                }
+            }
+            ["set_parameter", param_id] => {
+                let param_id = match param_id.parse::<u32>() {
+                    Ok(param_id) => param_id,
+                    Err(err) => return RemoteResponse::error(400, format!("Invalid parameter ID: {err}")),
+                };
+                let value = match Decoder::new(body).param_value() {
+                    Ok(value) => value,
+                    Err(err) => return RemoteResponse::error(400, err),
+                };
+                if let Err(err) = sdf.set_parameter(param_id, &value) {
+                    return RemoteResponse::error(422, err);
+                }
+            }
+            ["changed"] => if let Some(changed) = sdf.changed() {
+                enc.bounding_box(&changed);
+            },
+            ["glsl"] => return RemoteResponse::ok(sdf.glsl().into_bytes()),
+            _ => return RemoteResponse::error(404, format!("Unknown endpoint: {path}")),
+        }
+        RemoteResponse::ok(enc.finish())
+    }).unwrap_or_else(|| RemoteResponse::error(404, format!("Unknown SDF ID: {sdf_id}")))
+}
+
+/// Runs the given function on the SDF of the hierarchy with the given ID (0 is always the root).
+fn with_sdf<R>(root: &dyn SDFSurface, sdf_id: u32, f: impl FnOnce(&dyn SDFSurface) -> R) -> Option<R> {
+    if sdf_id == 0 || root.id() == sdf_id {
+        return Some(f(root));
+    }
+    let mut to_visit = root.children();
+    while let Some(sdf) = to_visit.pop() {
+        if sdf.id() == sdf_id {
+            return Some(f(sdf.as_ref()));
+        }
+        to_visit.extend(sdf.children());
+    }
+    None
+}
+
+/// Writes values with the binary encoding of the protocol.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u32(value as u32);
+    }
+
+    pub fn vec3(&mut self, value: Vector3<f32>) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn bounding_box(&mut self, value: &[Vector3<f32>; 2]) {
+        self.vec3(value[0]);
+        self.vec3(value[1]);
+    }
+
+    pub fn sample(&mut self, value: &SDFSample) {
+        self.f32(value.distance);
+        self.vec3(value.color);
+        self.f32(value.metallic);
+        self.f32(value.roughness);
+        self.f32(value.occlusion);
+    }
+
+    pub fn param_kind(&mut self, value: &SDFParamKind) {
+        match value {
+            SDFParamKind::Boolean => self.u32(0),
+            SDFParamKind::Int { range, step } => {
+                self.u32(1);
+                self.i32(*range.start());
+                self.i32(*range.end());
+                self.i32(*step);
+            }
+            SDFParamKind::Float { range, step } => {
+                self.u32(2);
+                self.f32(*range.start());
+                self.f32(*range.end());
+                self.f32(*step);
+            }
+            SDFParamKind::String { choices } => {
+                self.u32(3);
+                self.u32(choices.len() as u32);
+                for choice in choices {
+                    self.string(choice);
+                }
+            }
+            SDFParamKind::Vec3 { range, step } => {
+                self.u32(4);
+                self.f32(*range.start());
+                self.f32(*range.end());
+                self.f32(*step);
+            }
+            SDFParamKind::Color => self.u32(5),
+            SDFParamKind::Angle { range, step } => {
+                self.u32(6);
+                self.f32(*range.start());
+                self.f32(*range.end());
+                self.f32(*step);
+            }
+        }
+    }
+
+    pub fn param_value(&mut self, value: &SDFParamValue) {
+        match value {
+            SDFParamValue::Boolean(v) => {
+                self.u32(0);
+                self.bool(*v);
+            }
+            SDFParamValue::Int(v) => {
+                self.u32(1);
+                self.i32(*v);
+            }
+            SDFParamValue::Float(v) => {
+                self.u32(2);
+                self.f32(*v);
+            }
+            SDFParamValue::String(v) => {
+                self.u32(3);
+                self.string(v);
+            }
+            SDFParamValue::Vec3(v) => {
+                self.u32(4);
+                self.vec3(*v);
+            }
+            SDFParamValue::Color(v) => {
+                self.u32(5);
+                self.vec3(*v);
+            }
+            SDFParamValue::Angle(v) => {
+                self.u32(6);
+                self.f32(*v);
+            }
+        }
+    }
+
+    pub fn param(&mut self, value: &SDFParam) {
+        self.u32(value.id);
+        self.string(&value.name);
+        self.param_kind(&value.kind);
+        self.param_value(&value.value);
+        self.string(&value.description);
+    }
+}
+
+/// Reads values with the binary encoding of the protocol, failing if the data is too short or invalid.
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    /// Whether all the data has been read.
+    pub fn is_empty(&self) -> bool {
+        self.offset >= self.bytes.len()
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let bytes = self.bytes.get(self.offset..self.offset + N)
+            .ok_or_else(|| format!("Unexpected end of data at offset {} (length {})", self.offset, self.bytes.len()))?;
+        self.offset += N;
+        Ok(bytes.try_into().unwrap()) // Safe because of the exact slice length
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u32()? != 0)
+    }
+
+    pub fn vec3(&mut self) -> Result<Vector3<f32>, String> {
+        Ok(Vector3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    pub fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes.get(self.offset..self.offset.saturating_add(len))
+            .ok_or_else(|| format!("String of length {} does not fit at offset {}", len, self.offset))?;
+        self.offset += len;
+        String::from_utf8(bytes.to_vec()).map_err(|err| format!("Invalid string: {err}"))
+    }
+
+    pub fn bounding_box(&mut self) -> Result<[Vector3<f32>; 2], String> {
+        Ok([self.vec3()?, self.vec3()?])
+    }
+
+    pub fn sample(&mut self) -> Result<SDFSample, String> {
+        Ok(SDFSample {
+            distance: self.f32()?,
+            color: self.vec3()?,
+            metallic: self.f32()?,
+            roughness: self.f32()?,
+            occlusion: self.f32()?,
+        })
+    }
+
+    pub fn param_kind(&mut self) -> Result<SDFParamKind, String> {
+        Ok(match self.u32()? {
+            0 => SDFParamKind::Boolean,
+            1 => SDFParamKind::Int { range: self.i32()?..=self.i32()?, step: self.i32()? },
+            2 => SDFParamKind::Float { range: self.f32()?..=self.f32()?, step: self.f32()? },
+            3 => {
+                let len = self.u32()?;
+                SDFParamKind::String { choices: (0..len).map(|_| self.string()).collect::<Result<_, _>>()? }
+            }
+            4 => SDFParamKind::Vec3 { range: self.f32()?..=self.f32()?, step: self.f32()? },
+            5 => SDFParamKind::Color,
+            6 => SDFParamKind::Angle { range: self.f32()?..=self.f32()?, step: self.f32()? },
+            kind => return Err(format!("Unknown parameter kind: {kind}")),
+        })
+    }
+
+    pub fn param_value(&mut self) -> Result<SDFParamValue, String> {
+        Ok(match self.u32()? {
+            0 => SDFParamValue::Boolean(self.bool()?),
+            1 => SDFParamValue::Int(self.i32()?),
+            2 => SDFParamValue::Float(self.f32()?),
+            3 => SDFParamValue::String(self.string()?),
+            4 => SDFParamValue::Vec3(self.vec3()?),
+            5 => SDFParamValue::Color(self.vec3()?),
+            6 => SDFParamValue::Angle(self.f32()?),
+            value => return Err(format!("Unknown parameter value type: {value}")),
+        })
+    }
+
+    pub fn param(&mut self) -> Result<SDFParam, String> {
+        Ok(SDFParam {
+            id: self.u32()?,
+            name: self.string()?,
+            kind: self.param_kind()?,
+            value: self.param_value()?,
+            description: self.string()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::sdf::{SDFParamValue, SDFSurface};
+    use crate::sdf::ops::Translate;
+    use crate::sdf::primitives::{Primitive, Sphere};
+    use crate::sdf::remote::{Decoder, Encoder, handle_request, REMOTE_PROTOCOL_VERSION};
+
+    #[test]
+    fn test_handle_request() {
+        let sphere = Primitive::new(Sphere::new(1.0));
+        let sphere_id = sphere.id();
+        let root = Translate::new(sphere, Vector3::new(2.0, 0.0, 0.0)).with_id(0);
+
+        let version = handle_request(&root, "version", "", &[]);
+        assert_eq!(Decoder::new(&version.body).u32(), Ok(REMOTE_PROTOCOL_VERSION));
+
+        let children = handle_request(&root, "0/children", "", &[]);
+        let mut dec = Decoder::new(&children.body);
+        assert_eq!(dec.u32(), Ok(1));
+        assert_eq!(dec.u32(), Ok(sphere_id));
+
+        let mut points = Encoder::default();
+        points.vec3(Vector3::new(2.0, 0.0, 0.0));
+        points.vec3(Vector3::new(4.0, 0.0, 0.0));
+        let samples = handle_request(&root, "0/sample_batch", "distance_only=1", &points.finish());
+        let mut dec = Decoder::new(&samples.body);
+        assert_eq!(dec.sample().map(|s| s.distance), Ok(-1.0));
+        assert_eq!(dec.sample().map(|s| s.distance), Ok(1.0));
+        assert!(dec.is_empty());
+
+        let params = handle_request(&root, &format!("{sphere_id}/parameters"), "", &[]);
+        let mut dec = Decoder::new(&params.body);
+        let params = (0..dec.u32().unwrap()).map(|_| dec.param()).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(params.len(), root.children()[0].parameters().len());
+        for param in &params { // Every kind and value must survive the roundtrip
+            let mut enc = Encoder::default();
+            enc.param(param);
+            let decoded = Decoder::new(&enc.finish()).param().unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{param:?}"));
+        }
+
+        let mut value = Encoder::default();
+        value.param_value(&SDFParamValue::Float(2.0));
+        let set = handle_request(&root, &format!("{sphere_id}/set_parameter/{}", params[0].id), "", &value.finish());
+        assert_eq!(set.status, 200);
+        assert_eq!(handle_request(&root, "0/changed", "", &[]).body.len(), 24);
+        assert_eq!(handle_request(&root, "0/changed", "", &[]).body.len(), 0);
+
+        let mut value = Encoder::default();
+        value.param_value(&SDFParamValue::Boolean(true));
+        assert_eq!(handle_request(&root, "0/set_parameter/12345", "", &value.finish()).status, 422);
+        assert_eq!(handle_request(&root, "12345/name", "", &[]).status, 404);
+        assert_eq!(handle_request(&root, "0/sample_batch", "", &[1, 2]).status, 400);
+    }
+}
@@ -0,0 +1,108 @@
+//! Helpers to implement SDFs with parameters that can be modified through any of their (cheap) clones,
+//! as required by [`SDFSurface::children`](crate::sdf::SDFSurface::children).
+
+use std::ops::RangeInclusive;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use cgmath::Vector3;
+
+use crate::sdf::{SDFParam, SDFParamKind, SDFParamValue};
+use crate::sdf::defaults::merge_bounding_boxes;
+
+/// The first ID that is automatically assigned by [`next_id`]. Lower IDs are left for manually
+/// assigned IDs (like the root SDF, which must always be 0).
+const FIRST_AUTOMATIC_ID: u32 = 1 << 16;
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(FIRST_AUTOMATIC_ID);
+
+/// Returns a new unique ID for an SDF of the hierarchy.
+pub fn next_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A value shared between all clones, so that modifying it through any of them affects the others.
+/// It is thread-safe, so SDFs built with it are `Send + Sync` if their children are.
+#[derive(Debug, Default)]
+pub struct Shared<T>(Arc<RwLock<T>>);
+
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(RwLock::new(value)))
+    }
+
+    /// Replaces the current value.
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap() = value;
+    }
+
+    /// Runs the given function with a reference to the current value, without cloning it.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.0.read().unwrap())
+    }
+
+    /// Runs the given function with a mutable reference to the current value.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.0.write().unwrap())
+    }
+}
+
+impl<T: Clone> Shared<T> {
+    /// Returns a copy of the current value.
+    pub fn get(&self) -> T {
+        self.0.read().unwrap().clone()
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+/// Accumulates the regions of space that changed since the last [`take`](#method.take), shared
+/// between all clones. This is the building block for [`SDFSurface::changed`](crate::sdf::SDFSurface::changed).
+#[derive(Debug, Clone, Default)]
+pub struct ChangedBox(Shared<Option<[Vector3<f32>; 2]>>);
+
+impl ChangedBox {
+    /// Notifies of a change inside the given bounding box.
+    pub fn add(&self, bbox: [Vector3<f32>; 2]) {
+        self.0.update(|changed| *changed = Some(match changed {
+            Some(prev_box) => merge_bounding_boxes(prev_box, &bbox),
+            None => bbox,
+        }));
+    }
+
+    /// Returns the region that changed since the last call (if any), resetting it.
+    pub fn take(&self) -> Option<[Vector3<f32>; 2]> {
+        self.0.update(|changed| changed.take())
+    }
+}
+
+/// Builds the metadata of a float parameter.
+pub(crate) fn float_param(id: u32, name: &str, value: f32, range: RangeInclusive<f32>, description: &str) -> SDFParam {
+    SDFParam {
+        id,
+        name: name.to_string(),
+        kind: SDFParamKind::Float { range, step: 0.01 },
+        value: SDFParamValue::Float(value),
+        description: description.to_string(),
+    }
+}
+
+/// Builds the metadata of a vector parameter.
+pub(crate) fn vec3_param(id: u32, name: &str, value: Vector3<f32>, range: RangeInclusive<f32>, step: f32, description: &str) -> SDFParam {
+    SDFParam {
+        id,
+        name: name.to_string(),
+        kind: SDFParamKind::Vec3 { range, step },
+        value: SDFParamValue::Vec3(value),
+        description: description.to_string(),
+    }
+}
+
+/// The error returned when setting a parameter that does not exist or has the wrong kind.
+pub(crate) fn unknown_param_error(param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+    Err(format!("Unknown parameter {param_id} with value {param_value:?}"))
+}
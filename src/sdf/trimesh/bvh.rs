@@ -0,0 +1,215 @@
+//! A bounding volume hierarchy over the triangles of a mesh, to find the closest triangle to a point
+//! and to approximate the winding number of the mesh around a point, as in
+//! [Fast Winding Numbers for Soups and Clouds](https://www.dgp.toronto.edu/projects/fast-winding-numbers/).
+
+use cgmath::{InnerSpace, Vector3};
+
+/// The maximum number of triangles of a leaf.
+const LEAF_SIZE: usize = 4;
+
+/// How far (relative to its size) a node must be to approximate its winding number as a whole.
+const WINDING_NUMBER_ACCURACY: f32 = 2.0;
+
+/// The part of a triangle that is closest to a point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Face,
+    /// The edge from the given corner to the next one.
+    Edge(usize),
+    Vertex(usize),
+}
+
+/// The closest point of a triangle to a point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Closest {
+    pub triangle: usize,
+    pub point: Vector3<f32>,
+    /// The barycentric coordinates of the point (the weight of each corner).
+    pub weights: [f32; 3],
+    pub feature: Feature,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    bbox: [Vector3<f32>; 2],
+    /// Leaves: the first index of `order`, inner nodes: the index of the first child (the second one follows it).
+    first: usize,
+    /// The number of triangles of leaves, or 0 for inner nodes.
+    count: usize,
+    /// The sum of the normals of the triangles scaled by their areas.
+    normal: Vector3<f32>,
+    /// The center of the triangles weighted by their areas.
+    center: Vector3<f32>,
+    /// The distance from the center to the farthest corner of the bounding box.
+    radius: f32,
+}
+
+/// See the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// The indices of the triangles, sorted so that each leaf has a contiguous range.
+    order: Vec<usize>,
+}
+
+impl Bvh {
+    /// Builds the hierarchy for the given (non-empty) triangles.
+    pub fn new(triangles: &[[Vector3<f32>; 3]]) -> Self {
+        let centroids: Vec<Vector3<f32>> = triangles.iter().map(|[a, b, c]| (a + b + c) / 3.0).collect();
+        let mut bvh = Self { nodes: vec![], order: (0..triangles.len()).collect() };
+        bvh.nodes.push(bvh.node(triangles, 0, triangles.len()));
+        bvh.split(0, triangles, &centroids);
+        bvh
+    }
+
+    /// Builds a leaf with the given range of triangles.
+    fn node(&self, triangles: &[[Vector3<f32>; 3]], start: usize, end: usize) -> Node {
+        let mut bbox = [Vector3::new(f32::MAX, f32::MAX, f32::MAX), Vector3::new(f32::MIN, f32::MIN, f32::MIN)];
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+        let mut center = Vector3::new(0.0, 0.0, 0.0);
+        let mut area = 0.0;
+        for &index in &self.order[start..end] {
+            let [a, b, c] = triangles[index];
+            for corner in [a, b, c] {
+                bbox = [bbox[0].zip(corner, f32::min), bbox[1].zip(corner, f32::max)];
+            }
+            let scaled_normal = (b - a).cross(c - a) / 2.0;
+            let triangle_area = scaled_normal.magnitude();
+            normal += scaled_normal;
+            center += (a + b + c) / 3.0 * triangle_area;
+            area += triangle_area;
+        }
+        let center = if area > 0.0 { center / area } else { (bbox[0] + bbox[1]) / 2.0 };
+        let radius = (bbox[0] - center).zip(bbox[1] - center, |min, max| min.abs().max(max.abs())).magnitude();
+        Node { bbox, first: start, count: end - start, normal, center, radius }
+    }
+
+    /// Splits the leaf at the median of the longest axis, recursively.
+    fn split(&mut self, node_index: usize, triangles: &[[Vector3<f32>; 3]], centroids: &[Vector3<f32>]) {
+        let (start, count, bbox) = (self.nodes[node_index].first, self.nodes[node_index].count, self.nodes[node_index].bbox);
+        if count <= LEAF_SIZE {
+            return;
+        }
+        let size = bbox[1] - bbox[0];
+        let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+        let (end, mid) = (start + count, start + count / 2);
+        self.order[start..end].select_nth_unstable_by(count / 2, |a, b| centroids[*a][axis].total_cmp(&centroids[*b][axis]));
+        let first_child = self.nodes.len();
+        let (left, right) = (self.node(triangles, start, mid), self.node(triangles, mid, end));
+        self.nodes.extend([left, right]);
+        self.nodes[node_index].first = first_child;
+        self.nodes[node_index].count = 0;
+        self.split(first_child, triangles, centroids);
+        self.split(first_child + 1, triangles, centroids);
+    }
+
+    /// Finds the closest point of all the triangles to the given point.
+    pub fn closest(&self, p: Vector3<f32>, triangles: &[[Vector3<f32>; 3]]) -> Closest {
+        let mut best = Closest { triangle: 0, point: triangles[0][0], weights: [1.0, 0.0, 0.0], feature: Feature::Vertex(0) };
+        let mut best_distance2 = f32::MAX;
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if box_distance2(&node.bbox, p) >= best_distance2 {
+                continue;
+            }
+            if node.count > 0 {
+                for &triangle in &self.order[node.first..node.first + node.count] {
+                    let closest = closest_point_triangle(p, triangles[triangle], triangle);
+                    let distance2 = (closest.point - p).magnitude2();
+                    if distance2 < best_distance2 {
+                        best_distance2 = distance2;
+                        best = closest;
+                    }
+                }
+            } else {
+                // Visit the nearest child first (the last one pushed), to prune more nodes
+                let (left, right) = (node.first, node.first + 1);
+                if box_distance2(&self.nodes[left].bbox, p) < box_distance2(&self.nodes[right].bbox, p) {
+                    stack.extend([right, left]);
+                } else {
+                    stack.extend([left, right]);
+                }
+            }
+        }
+        best
+    }
+
+    /// The number of times that the triangles wind around the given point: about 1 inside a closed
+    /// mesh (or -1 if its triangles are flipped) and 0 outside, even with small holes.
+    pub fn winding_number(&self, p: Vector3<f32>, triangles: &[[Vector3<f32>; 3]]) -> f32 {
+        self.solid_angle(0, p, triangles) / (4.0 * std::f32::consts::PI)
+    }
+
+    fn solid_angle(&self, node_index: usize, p: Vector3<f32>, triangles: &[[Vector3<f32>; 3]]) -> f32 {
+        let node = &self.nodes[node_index];
+        let to_center = node.center - p;
+        let distance = to_center.magnitude();
+        if node.count > 0 {
+            self.order[node.first..node.first + node.count].iter()
+                .map(|triangle| triangle_solid_angle(p, triangles[*triangle])).sum()
+        } else if distance > WINDING_NUMBER_ACCURACY * node.radius {
+            // Far away, the triangles look like a single patch
+            to_center.dot(node.normal) / (distance * distance * distance)
+        } else {
+            self.solid_angle(node.first, p, triangles) + self.solid_angle(node.first + 1, p, triangles)
+        }
+    }
+}
+
+/// The squared distance from the point to the box (0 inside).
+fn box_distance2(bbox: &[Vector3<f32>; 2], p: Vector3<f32>) -> f32 {
+    (p.zip(bbox[0], f32::max).zip(bbox[1], f32::min) - p).magnitude2()
+}
+
+/// The signed solid angle of the triangle seen from the point (see Van Oosterom and Strackee, 1983).
+fn triangle_solid_angle(p: Vector3<f32>, [a, b, c]: [Vector3<f32>; 3]) -> f32 {
+    let (a, b, c) = (a - p, b - p, c - p);
+    let (la, lb, lc) = (a.magnitude(), b.magnitude(), c.magnitude());
+    let numerator = a.dot(b.cross(c));
+    let denominator = la * lb * lc + a.dot(b) * lc + a.dot(c) * lb + b.dot(c) * la;
+    2.0 * numerator.atan2(denominator)
+}
+
+/// The closest point of the triangle to the given point, as in Real-Time Collision Detection (Ericson, 2005).
+fn closest_point_triangle(p: Vector3<f32>, [a, b, c]: [Vector3<f32>; 3], triangle: usize) -> Closest {
+    let at = |weights: [f32; 3], feature: Feature| Closest {
+        triangle,
+        point: a * weights[0] + b * weights[1] + c * weights[2],
+        weights,
+        feature,
+    };
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return at([1.0, 0.0, 0.0], Feature::Vertex(0));
+    }
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return at([0.0, 1.0, 0.0], Feature::Vertex(1));
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return at([1.0 - v, v, 0.0], Feature::Edge(0));
+    }
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return at([0.0, 0.0, 1.0], Feature::Vertex(2));
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return at([1.0 - w, 0.0, w], Feature::Edge(2));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return at([0.0, 1.0 - w, w], Feature::Edge(1));
+    }
+    let denominator = 1.0 / (va + vb + vc);
+    let (v, w) = (vb * denominator, vc * denominator);
+    at([1.0 - v - w, v, w], Feature::Face)
+}
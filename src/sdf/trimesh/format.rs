@@ -0,0 +1,268 @@
+//! Parsers of the supported triangle mesh formats:
+//!
+//! - Wavefront OBJ: vertices (`v x y z`, with optional `r g b` colors) and polygonal faces (`f a b c ...`,
+//!   ignoring texture coordinates and normals). Everything else (groups, materials...) is ignored.
+//! - STL, both binary and ASCII. Vertices are repeated for each triangle, so they are welded by position.
+//! - PLY, in any encoding: the `x`, `y`, `z` and optional `red`, `green`, `blue` properties of the
+//!   `vertex` elements, and the `vertex_indices` (or `vertex_index`) lists of the `face` elements.
+//!
+//! Polygons are split into triangle fans.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context};
+use cgmath::Vector3;
+
+use crate::sdf::trimesh::TriMesh;
+
+/// The size of the header of binary STL files (before the triangles).
+const STL_HEADER_SIZE: usize = 84;
+
+/// The size of each triangle of binary STL files (a normal, 3 vertices and an attribute).
+const STL_TRIANGLE_SIZE: usize = 50;
+
+/// The maximum number of triangles, to fail early on corrupt files.
+const MAX_TRIANGLES: usize = 1 << 28;
+
+/// The supported formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MeshFormat {
+    Obj,
+    Stl,
+    Ply,
+}
+
+/// Whether the given file looks like a triangle mesh, based on its contents or its path (if any).
+pub fn is_mesh(bytes: &[u8], path: Option<&str>) -> bool {
+    detect_format(bytes, path).is_some()
+}
+
+/// Parses the triangle mesh in any of the supported formats (see the [module docs](self)).
+pub fn parse_mesh(bytes: &[u8], path: Option<&str>) -> anyhow::Result<TriMesh> {
+    let mesh = match detect_format(bytes, path) {
+        Some(MeshFormat::Obj) => parse_obj(bytes).context("invalid OBJ mesh")?,
+        Some(MeshFormat::Stl) => parse_stl(bytes).context("invalid STL mesh")?,
+        Some(MeshFormat::Ply) => parse_ply(bytes).context("invalid PLY mesh")?,
+        None => bail!("unknown mesh format"),
+    };
+    if mesh.triangles.is_empty() {
+        bail!("the mesh has no triangles");
+    }
+    Ok(mesh)
+}
+
+fn detect_format(bytes: &[u8], path: Option<&str>) -> Option<MeshFormat> {
+    if bytes.starts_with(b"ply\n") || bytes.starts_with(b"ply\r\n") {
+        return Some(MeshFormat::Ply);
+    }
+    if is_binary_stl(bytes) || (bytes.starts_with(b"solid") && find(bytes, b"facet").is_some()) {
+        return Some(MeshFormat::Stl);
+    }
+    // The path may be a URL, with a query or fragment after the extension
+    let path = path.map(|path| path.split(['?', '#']).next().unwrap_or(path).to_lowercase());
+    match path.as_deref().and_then(|path| path.rsplit_once('.')).map(|(_, extension)| extension) {
+        Some("obj") => return Some(MeshFormat::Obj),
+        Some("stl") => return Some(MeshFormat::Stl),
+        Some("ply") => return Some(MeshFormat::Ply),
+        _ => {}
+    }
+    // OBJ has no magic, so look for vertices in the first lines of text (e.g. for the standard input)
+    let start = &bytes[..bytes.len().min(4096)];
+    let looks_like_obj = !start.contains(&0) && start.split(|byte| *byte == b'\n')
+        .map(|line| line.trim_ascii_start())
+        .filter(|line| !line.is_empty() && !line.starts_with(b"#"))
+        .take(16)
+        .any(|line| line.starts_with(b"v "));
+    looks_like_obj.then_some(MeshFormat::Obj)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn is_binary_stl(bytes: &[u8]) -> bool {
+    bytes.len() >= STL_HEADER_SIZE && {
+        let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+        count > 0 && bytes.len() == STL_HEADER_SIZE + count * STL_TRIANGLE_SIZE
+    }
+}
+
+/// Resolves a (1-based or negative relative) OBJ index.
+fn obj_index(token: &str, vertex_count: usize) -> anyhow::Result<u32> {
+    let index_text = token.split('/').next().unwrap_or_default();
+    let index: i64 = index_text.parse().with_context(|| format!("invalid index {index_text:?}"))?;
+    let resolved = if index < 0 { vertex_count as i64 + index } else { index - 1 };
+    if resolved < 0 || resolved >= vertex_count as i64 {
+        bail!("index {index} out of range (there are {vertex_count} vertices)");
+    }
+    Ok(resolved as u32)
+}
+
+fn parse_obj(bytes: &[u8]) -> anyhow::Result<TriMesh> {
+    let text = std::str::from_utf8(bytes).context("not UTF-8 text")?;
+    let mut mesh = TriMesh::default();
+    let mut colors = vec![];
+    for (line_index, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let result = match tokens.next() {
+            Some("v") => (|| {
+                let numbers = tokens.map(|token| token.parse::<f32>()).collect::<Result<Vec<_>, _>>()?;
+                match numbers[..] {
+                    [x, y, z] | [x, y, z, _] => mesh.positions.push(Vector3::new(x, y, z)),
+                    [x, y, z, r, g, b] => {
+                        mesh.positions.push(Vector3::new(x, y, z));
+                        colors.resize(mesh.positions.len() - 1, Vector3::new(1.0, 1.0, 1.0));
+                        colors.push(Vector3::new(r, g, b));
+                    }
+                    _ => bail!("expected 3 coordinates and optionally 3 color components"),
+                }
+                Ok(())
+            })(),
+            Some("f") => (|| {
+                let indices = tokens.map(|token| obj_index(token, mesh.positions.len())).collect::<anyhow::Result<Vec<_>>>()?;
+                add_polygon(&mut mesh, &indices)
+            })(),
+            _ => Ok(()),
+        };
+        result.with_context(|| format!("at line {}", line_index + 1))?;
+    }
+    if !colors.is_empty() {
+        colors.resize(mesh.positions.len(), Vector3::new(1.0, 1.0, 1.0));
+        mesh.colors = Some(colors);
+    }
+    Ok(mesh)
+}
+
+fn add_polygon(mesh: &mut TriMesh, indices: &[u32]) -> anyhow::Result<()> {
+    if indices.len() < 3 {
+        bail!("faces need at least 3 vertices");
+    }
+    if mesh.triangles.len() + indices.len() - 2 > MAX_TRIANGLES {
+        bail!("too many triangles");
+    }
+    mesh.triangles.extend((1..indices.len() - 1).map(|i| [indices[0], indices[i], indices[i + 1]]));
+    Ok(())
+}
+
+/// Adds a triangle of separate vertices, reusing the vertices at the same position.
+fn add_stl_triangle(mesh: &mut TriMesh, welded: &mut HashMap<[u32; 3], u32>, corners: [Vector3<f32>; 3]) {
+    let triangle = corners.map(|corner| {
+        *welded.entry([corner.x.to_bits(), corner.y.to_bits(), corner.z.to_bits()]).or_insert_with(|| {
+            mesh.positions.push(corner);
+            mesh.positions.len() as u32 - 1
+        })
+    });
+    mesh.triangles.push(triangle);
+}
+
+fn parse_stl(bytes: &[u8]) -> anyhow::Result<TriMesh> {
+    let mut mesh = TriMesh::default();
+    let mut welded = HashMap::new();
+    if is_binary_stl(bytes) {
+        for triangle in bytes[STL_HEADER_SIZE..].chunks_exact(STL_TRIANGLE_SIZE) {
+            let float = |offset: usize| f32::from_le_bytes(triangle[offset..offset + 4].try_into().unwrap());
+            // Skip the normal (12 bytes), which is recomputed from the vertices
+            let corner = |index: usize| Vector3::new(float(12 + index * 12), float(16 + index * 12), float(20 + index * 12));
+            add_stl_triangle(&mut mesh, &mut welded, [corner(0), corner(1), corner(2)]);
+        }
+        return Ok(mesh);
+    }
+    let text = std::str::from_utf8(bytes).context("not a binary STL, and not UTF-8 text")?;
+    let mut corners = Vec::with_capacity(3);
+    for (line_index, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("vertex") => {
+                let numbers = tokens.map(|token| token.parse::<f32>()).collect::<Result<Vec<_>, _>>()
+                    .with_context(|| format!("at line {}", line_index + 1))?;
+                let [x, y, z] = numbers[..] else {
+                    bail!("expected 3 coordinates at line {}", line_index + 1);
+                };
+                corners.push(Vector3::new(x, y, z));
+            }
+            Some("endloop") => {
+                add_polygon_corners(&mut mesh, &mut welded, &corners).with_context(|| format!("at line {}", line_index + 1))?;
+                corners.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(mesh)
+}
+
+fn add_polygon_corners(mesh: &mut TriMesh, welded: &mut HashMap<[u32; 3], u32>, corners: &[Vector3<f32>]) -> anyhow::Result<()> {
+    if corners.len() < 3 {
+        bail!("facets need at least 3 vertices");
+    }
+    for i in 1..corners.len() - 1 {
+        add_stl_triangle(mesh, welded, [corners[0], corners[i], corners[i + 1]]);
+    }
+    Ok(())
+}
+
+fn parse_ply(bytes: &[u8]) -> anyhow::Result<TriMesh> {
+    use ply_rs::parser::Parser;
+    use ply_rs::ply::{DefaultElement, Property};
+
+    fn scalar(property: Option<&Property>) -> Option<f64> {
+        Some(match property? {
+            Property::Char(value) => *value as f64,
+            Property::UChar(value) => *value as f64,
+            Property::Short(value) => *value as f64,
+            Property::UShort(value) => *value as f64,
+            Property::Int(value) => *value as f64,
+            Property::UInt(value) => *value as f64,
+            Property::Float(value) => *value as f64,
+            Property::Double(value) => *value,
+            _ => return None,
+        })
+    }
+
+    fn list(property: Option<&Property>) -> Option<Vec<i64>> {
+        Some(match property? {
+            Property::ListChar(values) => values.iter().map(|value| *value as i64).collect(),
+            Property::ListUChar(values) => values.iter().map(|value| *value as i64).collect(),
+            Property::ListShort(values) => values.iter().map(|value| *value as i64).collect(),
+            Property::ListUShort(values) => values.iter().map(|value| *value as i64).collect(),
+            Property::ListInt(values) => values.iter().map(|value| *value as i64).collect(),
+            Property::ListUInt(values) => values.iter().map(|value| *value as i64).collect(),
+            _ => return None,
+        })
+    }
+
+    let ply = Parser::<DefaultElement>::new().read_ply(&mut std::io::Cursor::new(bytes))?;
+    let vertices = ply.payload.get("vertex").ok_or_else(|| anyhow!("no vertex elements"))?;
+    let mut mesh = TriMesh::default();
+    let mut colors = vec![];
+    for vertex in vertices {
+        let coord = |name: &str| scalar(vertex.get(name)).map(|value| value as f32)
+            .ok_or_else(|| anyhow!("vertices need a scalar {name:?} property"));
+        mesh.positions.push(Vector3::new(coord("x")?, coord("y")?, coord("z")?));
+        // Integer colors are bytes, but floats are already normalized
+        let color = |name: &str| match vertex.get(name) {
+            Some(Property::Float(value)) => Some(*value),
+            Some(Property::Double(value)) => Some(*value as f32),
+            other => scalar(other).map(|value| value as f32 / 255.0),
+        };
+        if let (Some(r), Some(g), Some(b)) = (color("red"), color("green"), color("blue")) {
+            colors.push(Vector3::new(r, g, b));
+        }
+    }
+    if !colors.is_empty() {
+        if colors.len() != mesh.positions.len() {
+            bail!("only some vertices have colors");
+        }
+        mesh.colors = Some(colors);
+    }
+    for face in ply.payload.get("face").map(Vec::as_slice).unwrap_or_default() {
+        let indices = list(face.get("vertex_indices").or_else(|| face.get("vertex_index")))
+            .ok_or_else(|| anyhow!("faces need a vertex_indices list property"))?;
+        let indices = indices.into_iter().map(|index| u32::try_from(index).ok()
+            .filter(|index| (*index as usize) < mesh.positions.len())
+            .ok_or_else(|| anyhow!("vertex index {index} out of range")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        add_polygon(&mut mesh, &indices)?;
+    }
+    Ok(mesh)
+}
+
@@ -0,0 +1,282 @@
+//! SDFs of triangle meshes, loaded from OBJ, STL or PLY files (see [`format`]).
+//!
+//! The distance is to the closest triangle, found with a [`bvh::Bvh`]. The sign is computed from
+//! the angle-weighted pseudo-normal of the closest feature, which is fast but requires a closed
+//! mesh, or from the (approximate) winding number, which is slower but also works with holes and
+//! self-intersections. Colors are interpolated from the vertex colors, if the file has them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use crate::sdf::{SDFParam, SDFParamKind, SDFParamValue, SDFSample, SDFSurface};
+use crate::sdf::shared::{ChangedBox, next_id, Shared, unknown_param_error};
+use crate::sdf::trimesh::bvh::{Bvh, Feature};
+
+pub mod bvh;
+pub mod format;
+
+/// The color of meshes without vertex colors.
+const DEFAULT_COLOR: Vector3<f32> = Vector3::new(0.8, 0.8, 0.8);
+
+/// The margin around the mesh in its bounding box, relative to its largest side.
+const BOUNDING_BOX_MARGIN: f32 = 0.05;
+
+/// An indexed triangle mesh, as read from a file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TriMesh {
+    pub positions: Vec<Vector3<f32>>,
+    /// The RGB color of each vertex, in the [0, 1] range.
+    pub colors: Option<Vec<Vector3<f32>>>,
+    /// The indices of the vertices of each triangle, counter-clockwise when seen from the outside.
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/// The mesh with everything precomputed for sampling.
+#[derive(Debug)]
+struct TriMeshData {
+    mesh: TriMesh,
+    /// The positions of the corners of each triangle (as used by the [`Bvh`]).
+    corners: Vec<[Vector3<f32>; 3]>,
+    /// The vertices of each triangle, merging the vertices at the same position (e.g. duplicated
+    /// for different texture coordinates), so that the pseudo-normals are shared across seams.
+    welded: Vec<[u32; 3]>,
+    face_normals: Vec<Vector3<f32>>,
+    /// Indexed by the welded vertices.
+    vertex_normals: Vec<Vector3<f32>>,
+    /// The normal of the edge from each corner of each triangle to the next one.
+    edge_normals: Vec<[Vector3<f32>; 3]>,
+    bvh: Bvh,
+    bounding_box: [Vector3<f32>; 2],
+}
+
+impl TriMeshData {
+    fn new(mut mesh: TriMesh) -> Self {
+        // Degenerate triangles have no normal and nothing to contribute to the distance
+        mesh.triangles.retain(|triangle| {
+            let [a, b, c] = triangle.map(|index| mesh.positions[index as usize]);
+            (b - a).cross(c - a).magnitude2() > 0.0
+        });
+        let corners: Vec<[Vector3<f32>; 3]> = mesh.triangles.iter()
+            .map(|triangle| triangle.map(|index| mesh.positions[index as usize])).collect();
+
+        let mut welded_indices = HashMap::new();
+        let welded_vertices: Vec<u32> = mesh.positions.iter().map(|position| {
+            let next = welded_indices.len() as u32;
+            *welded_indices.entry([position.x.to_bits(), position.y.to_bits(), position.z.to_bits()]).or_insert(next)
+        }).collect();
+        let welded: Vec<[u32; 3]> = mesh.triangles.iter()
+            .map(|triangle| triangle.map(|index| welded_vertices[index as usize])).collect();
+
+        let face_normals: Vec<Vector3<f32>> = corners.iter().map(|[a, b, c]| (b - a).cross(c - a).normalize()).collect();
+        let mut vertex_normals = vec![Vector3::zero(); welded_indices.len()];
+        let mut edge_sums = HashMap::new();
+        for ((triangle, corners), normal) in welded.iter().zip(&corners).zip(&face_normals) {
+            for corner in 0..3 {
+                let (next, previous) = ((corner + 1) % 3, (corner + 2) % 3);
+                let angle = (corners[next] - corners[corner]).angle(corners[previous] - corners[corner]).0;
+                vertex_normals[triangle[corner] as usize] += normal * angle;
+                let edge = (triangle[corner].min(triangle[next]), triangle[corner].max(triangle[next]));
+                *edge_sums.entry(edge).or_insert_with(Vector3::zero) += *normal;
+            }
+        }
+        let edge_normals = welded.iter().map(|triangle| [0, 1, 2].map(|corner| {
+            let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+            edge_sums[&(a.min(b), a.max(b))]
+        })).collect();
+
+        let bvh = Bvh::new(&corners);
+        let (min, max) = corners.iter().flatten().fold(
+            (Vector3::new(f32::MAX, f32::MAX, f32::MAX), Vector3::new(f32::MIN, f32::MIN, f32::MIN)),
+            |(min, max), corner| (min.zip(*corner, f32::min), max.zip(*corner, f32::max)));
+        let size = max - min;
+        let margin = size.x.max(size.y).max(size.z) * BOUNDING_BOX_MARGIN + f32::EPSILON;
+        let margin = Vector3::new(margin, margin, margin);
+        Self {
+            mesh,
+            corners,
+            welded,
+            face_normals,
+            vertex_normals,
+            edge_normals,
+            bvh,
+            bounding_box: [min - margin, max + margin],
+        }
+    }
+}
+
+/// An SDF that measures the distance to a [`TriMesh`].
+#[derive(Debug)]
+pub struct TriMeshSDF {
+    data: Arc<TriMeshData>,
+    id: u32,
+    /// The index of the method in [`Self::SIGN_NAMES`].
+    sign: Shared<usize>,
+    changed: ChangedBox,
+}
+
+impl Clone for TriMeshSDF {
+    fn clone(&self) -> Self {
+        Self {
+            data: Arc::clone(&self.data),
+            id: self.id,
+            sign: self.sign.clone(),
+            changed: self.changed.clone(),
+        }
+    }
+}
+
+impl TriMeshSDF {
+    const ID_SIGN: u32 = 0;
+    const SIGN_NAMES: [&'static str; 2] = ["pseudo-normals", "winding number"];
+    const SIGN_WINDING_NUMBER: usize = 1;
+
+    /// Creates the SDF, which requires at least one non-degenerate triangle.
+    pub fn new(mesh: TriMesh) -> anyhow::Result<Self> {
+        let data = TriMeshData::new(mesh);
+        if data.corners.is_empty() {
+            anyhow::bail!("the mesh has no (non-degenerate) triangles");
+        }
+        Ok(Self { data: Arc::new(data), id: next_id(), sign: Shared::new(0), changed: ChangedBox::default() })
+    }
+
+    /// Parses the mesh in any of the supported formats (see [`format`]), using the path (if any) to
+    /// detect formats without a signature.
+    pub fn parse(bytes: &[u8], path: Option<&str>) -> anyhow::Result<Self> {
+        format::parse_mesh(bytes, path).and_then(Self::new)
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+}
+
+impl SDFSurface for TriMeshSDF {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        self.data.bounding_box
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        let data = &*self.data;
+        let closest = data.bvh.closest(p, &data.corners);
+        let offset = p - closest.point;
+        let inside = if self.sign.get() == Self::SIGN_WINDING_NUMBER {
+            data.bvh.winding_number(p, &data.corners).abs() > 0.5
+        } else {
+            let normal = match closest.feature {
+                Feature::Face => data.face_normals[closest.triangle],
+                Feature::Edge(corner) => data.edge_normals[closest.triangle][corner],
+                Feature::Vertex(corner) => data.vertex_normals[data.welded[closest.triangle][corner] as usize],
+            };
+            offset.dot(normal) < 0.0
+        };
+        let distance = if inside { -offset.magnitude() } else { offset.magnitude() };
+        let color = match &data.mesh.colors {
+            Some(colors) if !distance_only => {
+                let triangle = data.mesh.triangles[closest.triangle];
+                (0..3).map(|corner| colors[triangle[corner] as usize] * closest.weights[corner]).sum()
+            }
+            _ => DEFAULT_COLOR,
+        };
+        SDFSample::new(distance, color)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        format!("Mesh ({} triangles)", self.data.corners.len())
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        vec![SDFParam {
+            id: Self::ID_SIGN,
+            name: "sign".to_string(),
+            kind: SDFParamKind::String { choices: Self::SIGN_NAMES.iter().map(|s| s.to_string()).collect() },
+            value: SDFParamValue::String(Self::SIGN_NAMES[self.sign.get()].to_string()),
+            description: "How to tell the inside from the outside: the pseudo-normals are faster, but the \
+            winding number also works for meshes with holes or self-intersections.".to_string(),
+        }]
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_SIGN, SDFParamValue::String(value)) if Self::SIGN_NAMES.contains(&value.as_str()) => {
+                self.sign.set(Self::SIGN_NAMES.iter().position(|name| name == value).unwrap());
+                self.changed.add(self.bounding_box());
+                Ok(())
+            }
+            _ => unknown_param_error(param_id, param_value),
+        }
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        self.changed.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::sdf::{SDFParamValue, SDFSurface};
+    use crate::sdf::trimesh::format::is_mesh;
+    use crate::sdf::trimesh::TriMeshSDF;
+
+    /// A unit cube centered at the origin, with quads and a red top.
+    const CUBE_OBJ: &str = "# Cube\nv -0.5 -0.5 -0.5\nv 0.5 -0.5 -0.5\nv 0.5 0.5 -0.5\nv -0.5 0.5 -0.5\n\
+        v -0.5 -0.5 0.5 1 0 0\nv 0.5 -0.5 0.5 1 0 0\nv 0.5 0.5 0.5 1 0 0\nv -0.5 0.5 0.5 1 0 0\n\
+        f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\n";
+
+    #[test]
+    fn test_obj_cube() {
+        assert!(is_mesh(CUBE_OBJ.as_bytes(), None)); // Like the standard input
+        let sdf = TriMeshSDF::parse(CUBE_OBJ.as_bytes(), Some("cube.obj")).unwrap();
+        assert_eq!(sdf.name(), "Mesh (12 triangles)");
+        for sign in ["pseudo-normals", "winding number"] {
+            sdf.set_parameter(0, &SDFParamValue::String(sign.to_string())).unwrap();
+            assert!((sdf.sample(Vector3::new(0.0, 0.0, 0.0), true).distance + 0.5).abs() < 1e-5, "{sign}");
+            assert!((sdf.sample(Vector3::new(0.3, 0.4, 0.1), true).distance + 0.1).abs() < 1e-5, "{sign}");
+            assert!((sdf.sample(Vector3::new(1.5, 0.0, 0.0), true).distance - 1.0).abs() < 1e-5, "{sign}");
+            // Closest to an edge and to a corner
+            assert!((sdf.sample(Vector3::new(1.5, 1.5, 0.0), true).distance - 2f32.sqrt()).abs() < 1e-5, "{sign}");
+            assert!((sdf.sample(Vector3::new(1.5, 1.5, 1.5), true).distance - 3f32.sqrt()).abs() < 1e-5, "{sign}");
+        }
+        assert!(sdf.changed().is_some());
+        assert_eq!(sdf.sample(Vector3::new(0.0, 0.0, 0.9), false).color, Vector3::new(1.0, 0.0, 0.0));
+        assert!(sdf.set_parameter(0, &SDFParamValue::String("magic".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_stl_formats() {
+        // A tetrahedron, in binary and ASCII
+        let corners = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        let faces = [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+        let mut binary = vec![0u8; 80];
+        binary.extend(4u32.to_le_bytes());
+        let mut ascii = "solid tetrahedron\n".to_string();
+        for face in faces {
+            binary.extend([0u8; 12]);
+            ascii += "facet normal 0 0 0\nouter loop\n";
+            for vertex in face {
+                corners[vertex].iter().for_each(|coord: &f32| binary.extend(coord.to_le_bytes()));
+                ascii += &format!("vertex {} {} {}\n", corners[vertex][0], corners[vertex][1], corners[vertex][2]);
+            }
+            binary.extend([0u8; 2]);
+            ascii += "endloop\nendfacet\n";
+        }
+        ascii += "endsolid tetrahedron\n";
+        for bytes in [binary, ascii.into_bytes()] {
+            assert!(is_mesh(&bytes, Some("model")));
+            let sdf = TriMeshSDF::parse(&bytes, None).unwrap();
+            assert!(sdf.sample(Vector3::new(0.1, 0.1, 0.1), true).distance < 0.0);
+            assert!((sdf.sample(Vector3::new(-1.0, 0.1, 0.1), true).distance - 1.0).abs() < 1e-5);
+        }
+        assert!(!is_mesh(b"\0asm\x01\0\0\0", Some("sdf.wasm")));
+        assert!(!is_mesh(b"\0asm\x01\0\0\0", None));
+    }
+}
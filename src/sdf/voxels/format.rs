@@ -0,0 +1,277 @@
+//! Parsers of the supported volume formats: [NRRD](https://teem.sourceforge.net/nrrd/format.html)
+//! and [MetaImage](https://itk.org/Wiki/ITK/MetaIO/Documentation) (the headered raw volumes of ITK).
+//! Both may store the data after the header or in a separate (detached) file.
+
+use std::io::Read;
+
+use cgmath::Vector3;
+
+use crate::sdf::voxels::VoxelGrid;
+
+/// The maximum number of voxels of a grid, to avoid running out of memory with bad headers (4 GiB of `f32`).
+const MAX_VOXELS: usize = 1 << 30;
+
+/// Reads the data of a detached header, given the file name written in the header.
+pub type ReadDetached<'a> = &'a dyn Fn(&str) -> anyhow::Result<Vec<u8>>;
+
+/// Whether the bytes look like any of the supported formats.
+pub fn is_voxel_grid(bytes: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(&bytes[..bytes.len().min(64)]).trim_start().to_string();
+    start.starts_with("NRRD000") || start.starts_with("ObjectType") || start.starts_with("NDims")
+}
+
+/// Parses a grid in any of the supported formats.
+pub fn parse_voxel_grid(bytes: &[u8], read_detached: ReadDetached) -> anyhow::Result<VoxelGrid> {
+    if String::from_utf8_lossy(&bytes[..bytes.len().min(8)]).starts_with("NRRD000") {
+        parse_nrrd(bytes, read_detached)
+    } else {
+        parse_metaimage(bytes, read_detached)
+    }
+}
+
+/// The type of each value of the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ElementType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl ElementType {
+    fn size(&self) -> usize {
+        match self {
+            ElementType::U8 | ElementType::I8 => 1,
+            ElementType::U16 | ElementType::I16 => 2,
+            ElementType::U32 | ElementType::I32 | ElementType::F32 => 4,
+            ElementType::F64 => 8,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8], big_endian: bool) -> f32 {
+        macro_rules! from_bytes {
+            ($t:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                (if big_endian { <$t>::from_be_bytes(bytes) } else { <$t>::from_le_bytes(bytes) }) as f32
+            }};
+        }
+        match self {
+            ElementType::U8 => bytes[0] as f32,
+            ElementType::I8 => bytes[0] as i8 as f32,
+            ElementType::U16 => from_bytes!(u16),
+            ElementType::I16 => from_bytes!(i16),
+            ElementType::U32 => from_bytes!(u32),
+            ElementType::I32 => from_bytes!(i32),
+            ElementType::F32 => from_bytes!(f32),
+            ElementType::F64 => from_bytes!(f64),
+        }
+    }
+}
+
+/// How the data is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Raw,
+    /// Numbers separated by whitespace.
+    Text,
+    Gzip,
+    Zlib,
+}
+
+/// The (already located) data of a grid and how to read it.
+struct RawData {
+    bytes: Vec<u8>,
+    element_type: ElementType,
+    encoding: Encoding,
+    big_endian: bool,
+}
+
+impl RawData {
+    /// Decodes the values, checking that there are enough of them.
+    fn values(self, count: usize) -> anyhow::Result<Vec<f32>> {
+        let expected_bytes = count * self.element_type.size();
+        let bytes = match self.encoding {
+            Encoding::Raw | Encoding::Text => self.bytes,
+            Encoding::Gzip => read_decompressed(flate2::read::MultiGzDecoder::new(self.bytes.as_slice()), expected_bytes)?,
+            Encoding::Zlib => read_decompressed(flate2::read::ZlibDecoder::new(self.bytes.as_slice()), expected_bytes)?,
+        };
+        if self.encoding == Encoding::Text {
+            let values = String::from_utf8_lossy(&bytes).split_whitespace().take(count)
+                .map(|value| value.parse::<f32>().map_err(|err| anyhow::anyhow!("Invalid value {value:?} in the data: {err}")))
+                .collect::<anyhow::Result<Vec<f32>>>()?;
+            anyhow::ensure!(values.len() == count, "Expected {} values in the data, found {}", count, values.len());
+            return Ok(values);
+        }
+        anyhow::ensure!(bytes.len() >= expected_bytes, "Expected {} bytes of data, found {}", expected_bytes, bytes.len());
+        // The data is at the end of the file (or it is the whole file), skipping anything before it
+        let bytes = &bytes[bytes.len() - expected_bytes..];
+        Ok(bytes.chunks_exact(self.element_type.size()).map(|value| self.element_type.decode(value, self.big_endian)).collect())
+    }
+}
+
+/// Decompresses up to the expected size (the rest of the data would be ignored anyway).
+fn read_decompressed(reader: impl Read, expected_bytes: usize) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.take(expected_bytes as u64).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Parses a list of exactly 3 numbers separated by whitespace (or commas).
+fn parse_vector<T: std::str::FromStr>(value: &str, field: &str) -> anyhow::Result<[T; 3]> {
+    let numbers = value.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|number| !number.is_empty())
+        .map(|number| number.parse::<T>().map_err(|_| anyhow::anyhow!("Invalid number {number:?} in the field {field:?}")))
+        .collect::<anyhow::Result<Vec<T>>>()?;
+    numbers.try_into().map_err(|numbers: Vec<T>| anyhow::anyhow!(
+        "Expected 3 numbers in the field {field:?} (only 3D volumes are supported), found {}", numbers.len()))
+}
+
+/// Builds the grid, validating its size.
+fn build_grid(dims: [usize; 3], spacing: [f32; 3], origin: [f32; 3], data: RawData) -> anyhow::Result<VoxelGrid> {
+    let count = dims.iter().try_fold(1usize, |count, dim| count.checked_mul(*dim))
+        .filter(|count| *count > 0 && *count <= MAX_VOXELS)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported size of the volume: {dims:?} voxels"))?;
+    anyhow::ensure!(spacing.iter().all(|spacing| *spacing != 0.0 && spacing.is_finite()), "Invalid spacing of the voxels: {spacing:?}");
+    let mut values = data.values(count)?;
+    let (mut spacing, mut origin) = (Vector3::from(spacing), Vector3::from(origin));
+    // Axes that point backwards are mirrored, so that the grid always grows along the positive axes
+    for axis in 0..3 {
+        if spacing[axis] < 0.0 {
+            origin[axis] += (dims[axis] - 1) as f32 * spacing[axis];
+            spacing[axis] = -spacing[axis];
+            mirror_axis(&mut values, dims, axis);
+        }
+    }
+    Ok(VoxelGrid::new(dims, spacing, origin, values))
+}
+
+/// Reverses the order of the values along the given axis (with X changing fastest and then Y).
+fn mirror_axis(values: &mut [f32], dims: [usize; 3], axis: usize) {
+    let stride = dims[..axis].iter().product::<usize>();
+    let len = dims[axis];
+    for block in values.chunks_exact_mut(stride * len) {
+        for index in 0..len / 2 {
+            for offset in 0..stride {
+                block.swap(index * stride + offset, (len - 1 - index) * stride + offset);
+            }
+        }
+    }
+}
+
+/// Splits the header (as `key: value` fields, lowercase keys) from the data that follows it.
+fn split_header<'a>(bytes: &'a [u8], separator: &str, is_last_field: impl Fn(&str) -> bool) -> (Vec<(String, String)>, &'a [u8]) {
+    let mut fields = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let line_end = bytes[offset..].iter().position(|b| *b == b'\n').map(|i| offset + i + 1).unwrap_or(bytes.len());
+        let line = String::from_utf8_lossy(&bytes[offset..line_end]);
+        let line = line.trim();
+        offset = line_end;
+        if line.is_empty() && !fields.is_empty() {
+            break; // The empty line that ends the header of NRRD
+        }
+        if let Some((key, value)) = line.split_once(separator) {
+            let key = key.trim().to_lowercase();
+            let is_last = is_last_field(&key);
+            fields.push((key, value.trim().to_string()));
+            if is_last {
+                break;
+            }
+        }
+    }
+    (fields, &bytes[offset..])
+}
+
+fn parse_nrrd(bytes: &[u8], read_detached: ReadDetached) -> anyhow::Result<VoxelGrid> {
+    let (fields, attached) = split_header(bytes, ":", |_| false);
+    let field = |key: &str| fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+    let required = |key: &str| field(key).ok_or_else(|| anyhow::anyhow!("Missing the field {key:?} in the NRRD header"));
+    anyhow::ensure!(required("dimension")? == "3", "Only 3D NRRD volumes are supported");
+    let element_type = match required("type")? {
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => ElementType::U8,
+        "signed char" | "int8" | "int8_t" => ElementType::I8,
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => ElementType::U16,
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => ElementType::I16,
+        "uint" | "unsigned int" | "uint32" | "uint32_t" => ElementType::U32,
+        "int" | "signed int" | "int32" | "int32_t" => ElementType::I32,
+        "float" => ElementType::F32,
+        "double" => ElementType::F64,
+        other => anyhow::bail!("Unsupported NRRD type {other:?}"),
+    };
+    let encoding = match required("encoding")? {
+        "raw" => Encoding::Raw,
+        "ascii" | "text" | "txt" => Encoding::Text,
+        "gzip" | "gz" => Encoding::Gzip,
+        other => anyhow::bail!("Unsupported NRRD encoding {other:?}"),
+    };
+    let dims = parse_vector::<usize>(required("sizes")?, "sizes")?;
+    let spacing = if let Some(spacings) = field("spacings") {
+        parse_vector::<f32>(spacings, "spacings")?
+    } else if let Some(directions) = field("space directions") {
+        // The grid is always axis-aligned, but its axes may point backwards (mirrored by build_grid)
+        let axes = directions.split(')').filter(|axis| axis.contains('('))
+            .map(|axis| parse_vector::<f32>(axis.trim().trim_start_matches('('), "space directions"))
+            .collect::<anyhow::Result<Vec<[f32; 3]>>>()?;
+        let axes: [[f32; 3]; 3] = axes.try_into().map_err(|_| anyhow::anyhow!("Expected 3 axes in the field \"space directions\""))?;
+        let mut spacing = [0.0; 3];
+        for (i, axis) in axes.iter().enumerate() {
+            anyhow::ensure!((0..3).all(|j| j == i || axis[j] == 0.0),
+                "Only axis-aligned volumes are supported, found the axis {axis:?} in the field \"space directions\"");
+            spacing[i] = axis[i];
+        }
+        spacing
+    } else {
+        [1.0; 3]
+    };
+    let origin = match field("space origin") {
+        Some(origin) => parse_vector::<f32>(origin.trim_start_matches('(').trim_end_matches(')'), "space origin")?,
+        None => [0.0; 3],
+    };
+    let bytes = match field("data file").or_else(|| field("datafile")) {
+        Some(file) => read_detached(file)?,
+        None => attached.to_vec(),
+    };
+    let big_endian = field("endian") == Some("big");
+    build_grid(dims, spacing, origin, RawData { bytes, element_type, encoding, big_endian })
+}
+
+fn parse_metaimage(bytes: &[u8], read_detached: ReadDetached) -> anyhow::Result<VoxelGrid> {
+    let (fields, attached) = split_header(bytes, "=", |key| key == "elementdatafile");
+    let field = |key: &str| fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+    let required = |key: &str| field(key).ok_or_else(|| anyhow::anyhow!("Missing the field {key:?} in the MetaImage header"));
+    anyhow::ensure!(required("ndims")? == "3", "Only 3D MetaImage volumes are supported");
+    anyhow::ensure!(field("elementnumberofchannels").is_none_or(|channels| channels == "1"), "Only MetaImage volumes with one channel are supported");
+    let element_type = match required("elementtype")? {
+        "MET_UCHAR" => ElementType::U8,
+        "MET_CHAR" => ElementType::I8,
+        "MET_USHORT" => ElementType::U16,
+        "MET_SHORT" => ElementType::I16,
+        "MET_UINT" => ElementType::U32,
+        "MET_INT" => ElementType::I32,
+        "MET_FLOAT" => ElementType::F32,
+        "MET_DOUBLE" => ElementType::F64,
+        other => anyhow::bail!("Unsupported MetaImage element type {other:?}"),
+    };
+    let is_true = |key: &str| field(key).is_some_and(|value| value.eq_ignore_ascii_case("true"));
+    let encoding = if is_true("compresseddata") { Encoding::Zlib } else { Encoding::Raw };
+    anyhow::ensure!(field("binarydata").is_none_or(|_| is_true("binarydata")), "Only binary MetaImage data is supported");
+    let dims = parse_vector::<usize>(required("dimsize")?, "DimSize")?;
+    let spacing = match field("elementspacing").or_else(|| field("elementsize")) {
+        Some(spacing) => parse_vector::<f32>(spacing, "ElementSpacing")?,
+        None => [1.0; 3],
+    };
+    let origin = match field("offset").or_else(|| field("origin")).or_else(|| field("position")) {
+        Some(origin) => parse_vector::<f32>(origin, "Offset")?,
+        None => [0.0; 3],
+    };
+    let bytes = match required("elementdatafile")? {
+        "LOCAL" | "local" | "Local" => attached.to_vec(),
+        file => read_detached(file)?,
+    };
+    let big_endian = is_true("binarydatabyteordermsb") || is_true("elementbyteordermsb");
+    build_grid(dims, spacing, origin, RawData { bytes, element_type, encoding, big_endian })
+}
@@ -0,0 +1,273 @@
+//! SDFs backed by a dense voxel grid, like CT scans or precomputed distance grids, loaded from
+//! NRRD (`.nrrd`, `.nhdr`) or MetaImage (`.mha`, `.mhd`) volumes (see [`format`]).
+//!
+//! The grid is sampled with trilinear interpolation and the surface is where the values cross the
+//! `iso_offset` parameter. The values increase towards the outside for distance grids, but scans are
+//! usually denser inside, so the `inside_is_above` parameter flips the sign of the distance.
+
+use std::sync::{Arc, RwLock};
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::sdf::{SDFParam, SDFParamKind, SDFParamValue, SDFSample, SDFSurface};
+use crate::sdf::shared::{ChangedBox, float_param, next_id, Shared, unknown_param_error};
+
+pub mod format;
+
+/// The configuration of the grids loaded from now on (see [`CliVoxelGrid::apply`]).
+static OPTIONS: RwLock<VoxelGridOptions> = RwLock::new(VoxelGridOptions { iso_offset: 0.0, inside_is_above: false });
+
+/// The initial values of the parameters of the loaded grids, which can't be configured per file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoxelGridOptions {
+    pub iso_offset: f32,
+    pub inside_is_above: bool,
+}
+
+/// Options for the voxel grids, shared by all commands (as grids are loaded from any SDF input).
+#[derive(clap::Parser, Debug, Clone, PartialEq)]
+pub struct CliVoxelGrid {
+    /// The initial value of the surface of voxel grids (NRRD or MetaImage volumes), which can be
+    /// modified later as a parameter.
+    #[clap(long, global = true, default_value = "0", allow_hyphen_values = true)]
+    pub voxels_iso_offset: f32,
+    /// Whether the inside of voxel grids has values above the iso offset, like the density of CT
+    /// scans. By default, values below it are inside, like distance grids.
+    #[clap(long, global = true)]
+    pub voxels_inside_is_above: bool,
+}
+
+impl CliVoxelGrid {
+    /// Configures the options for all the following loads.
+    pub fn apply(&self) {
+        *OPTIONS.write().unwrap() = VoxelGridOptions { iso_offset: self.voxels_iso_offset, inside_is_above: self.voxels_inside_is_above };
+    }
+}
+
+/// The values of a dense grid, placed at `origin + index * spacing`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelGrid {
+    dims: [usize; 3],
+    spacing: Vector3<f32>,
+    origin: Vector3<f32>,
+    /// The values, with X changing fastest and then Y.
+    values: Vec<f32>,
+    /// The minimum and maximum value, for the range of the parameters.
+    value_range: [f32; 2],
+}
+
+impl VoxelGrid {
+    /// Creates the grid, which must have one value per voxel.
+    pub fn new(dims: [usize; 3], spacing: Vector3<f32>, origin: Vector3<f32>, values: Vec<f32>) -> Self {
+        assert_eq!(values.len(), dims[0] * dims[1] * dims[2], "one value per voxel is required");
+        let value_range = values.iter().filter(|value| value.is_finite())
+            .fold([f32::MAX, f32::MIN], |[min, max], value| [min.min(*value), max.max(*value)]);
+        Self { dims, spacing, origin, values, value_range }
+    }
+
+    /// The box between the centers of the first and last voxels.
+    pub fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let size = Vector3::new((self.dims[0] - 1) as f32, (self.dims[1] - 1) as f32, (self.dims[2] - 1) as f32);
+        [self.origin, self.origin + size.zip(self.spacing, |size, spacing| size * spacing)]
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[x + self.dims[0] * (y + self.dims[1] * z)]
+    }
+
+    /// Interpolates the value at the given point, which is clamped to the grid.
+    pub fn sample(&self, p: Vector3<f32>) -> f32 {
+        let mut index = [0usize; 3];
+        let mut t = [0f32; 3];
+        for axis in 0..3 {
+            let max_index = self.dims[axis] - 1;
+            let coord = ((p[axis] - self.origin[axis]) / self.spacing[axis]).clamp(0.0, max_index as f32);
+            index[axis] = (coord.floor() as usize).min(max_index.saturating_sub(1));
+            t[axis] = coord - index[axis] as f32;
+        }
+        let next = |axis: usize| (index[axis] + 1).min(self.dims[axis] - 1);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let (x0, y0, z0, x1, y1, z1) = (index[0], index[1], index[2], next(0), next(1), next(2));
+        let plane = |z: usize| lerp(
+            lerp(self.value(x0, y0, z), self.value(x1, y0, z), t[0]),
+            lerp(self.value(x0, y1, z), self.value(x1, y1, z), t[0]), t[1]);
+        lerp(plane(z0), plane(z1), t[2])
+    }
+}
+
+/// An SDF that samples a [`VoxelGrid`].
+#[derive(Debug)]
+pub struct VoxelGridSDF {
+    grid: Arc<VoxelGrid>,
+    id: u32,
+    iso_offset: Shared<f32>,
+    inside_is_above: Shared<bool>,
+    changed: ChangedBox,
+}
+
+impl Clone for VoxelGridSDF {
+    fn clone(&self) -> Self {
+        Self {
+            grid: Arc::clone(&self.grid),
+            id: self.id,
+            iso_offset: self.iso_offset.clone(),
+            inside_is_above: self.inside_is_above.clone(),
+            changed: self.changed.clone(),
+        }
+    }
+}
+
+impl VoxelGridSDF {
+    const ID_ISO_OFFSET: u32 = 0;
+    const ID_INSIDE_IS_ABOVE: u32 = 1;
+
+    /// Creates the SDF with the configured initial parameters (see [`CliVoxelGrid`]).
+    pub fn new(grid: VoxelGrid) -> Self {
+        let options = *OPTIONS.read().unwrap();
+        Self {
+            grid: Arc::new(grid),
+            id: next_id(),
+            iso_offset: Shared::new(options.iso_offset),
+            inside_is_above: Shared::new(options.inside_is_above),
+            changed: ChangedBox::default(),
+        }
+    }
+
+    /// Parses the grid in any of the supported formats (see [`format`]).
+    pub fn parse(bytes: &[u8], read_detached: format::ReadDetached) -> anyhow::Result<Self> {
+        format::parse_voxel_grid(bytes, read_detached).map(Self::new)
+    }
+
+    /// Overrides the automatically assigned ID (e.g. to use this as the root SDF, which must have ID 0).
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+}
+
+impl SDFSurface for VoxelGridSDF {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        self.grid.bounding_box()
+    }
+
+    fn sample(&self, p: Vector3<f32>, _distance_only: bool) -> SDFSample {
+        let value = self.grid.sample(p) - self.iso_offset.get();
+        let distance = if self.inside_is_above.get() { -value } else { value };
+        // Outside of the grid, keep growing with the distance to it
+        let [min, max] = self.grid.bounding_box();
+        let outside = p.zip(min, f32::max).zip(max, f32::min) - p;
+        SDFSample::new(distance + outside.magnitude(), Vector3::new(0.8, 0.8, 0.8))
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> String {
+        format!("Voxels {}x{}x{}", self.grid.dims[0], self.grid.dims[1], self.grid.dims[2])
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        let [min, max] = self.grid.value_range;
+        let iso_offset = self.iso_offset.get();
+        vec![
+            float_param(Self::ID_ISO_OFFSET, "iso_offset", iso_offset, min.min(iso_offset)..=max.max(iso_offset),
+                        "The value of the grid at the surface."),
+            SDFParam {
+                id: Self::ID_INSIDE_IS_ABOVE,
+                name: "inside_is_above".to_string(),
+                kind: SDFParamKind::Boolean,
+                value: SDFParamValue::Boolean(self.inside_is_above.get()),
+                description: "Whether values above the iso offset are inside the surface (like the density of scans) \
+                instead of outside (like distance grids).".to_string(),
+            },
+        ]
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        match (param_id, param_value) {
+            (Self::ID_ISO_OFFSET, SDFParamValue::Float(value)) => self.iso_offset.set(*value),
+            (Self::ID_INSIDE_IS_ABOVE, SDFParamValue::Boolean(value)) => self.inside_is_above.set(*value),
+            _ => return unknown_param_error(param_id, param_value),
+        }
+        self.changed.add(self.bounding_box());
+        Ok(())
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        self.changed.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use cgmath::Vector3;
+
+    use crate::sdf::SDFSurface;
+    use crate::sdf::voxels::VoxelGridSDF;
+    use crate::sdf::voxels::format::is_voxel_grid;
+
+    /// A 2x2x2 grid whose values are the X coordinate minus 0.5.
+    const VALUES: [f32; 8] = [-0.5, 0.5, -0.5, 0.5, -0.5, 0.5, -0.5, 0.5];
+
+    fn no_detached(file: &str) -> anyhow::Result<Vec<u8>> {
+        anyhow::bail!("unexpected detached file {file:?}")
+    }
+
+    #[test]
+    fn test_nrrd_trilinear() {
+        let mut nrrd = b"NRRD0004\n# A comment\ntype: float\ndimension: 3\nsizes: 2 2 2\nspacings: 2 1 1\n\
+            space origin: (1,0,0)\nendian: big\nencoding: raw\n\n".to_vec();
+        VALUES.iter().for_each(|value| nrrd.extend(value.to_be_bytes()));
+        assert!(is_voxel_grid(&nrrd));
+        let sdf = VoxelGridSDF::parse(&nrrd, &no_detached).unwrap();
+        assert_eq!(sdf.bounding_box(), [Vector3::new(1.0, 0.0, 0.0), Vector3::new(3.0, 1.0, 1.0)]);
+        assert_eq!(sdf.sample(Vector3::new(2.0, 0.5, 0.5), true).distance, 0.0);
+        assert_eq!(sdf.sample(Vector3::new(2.5, 0.2, 0.7), true).distance, 0.25);
+        assert_eq!(sdf.sample(Vector3::new(4.0, 0.5, 0.5), true).distance, 1.5); // Outside of the grid
+        sdf.set_parameter(0, &crate::sdf::SDFParamValue::Float(0.25)).unwrap();
+        sdf.set_parameter(1, &crate::sdf::SDFParamValue::Boolean(true)).unwrap();
+        assert_eq!(sdf.sample(Vector3::new(2.5, 0.2, 0.7), true).distance, 0.0);
+        assert!(sdf.changed().is_some());
+    }
+
+    #[test]
+    fn test_nrrd_space_directions() {
+        let header = |directions: &str| format!("NRRD0004\ntype: float\ndimension: 3\nsizes: 2 2 2\n\
+            space directions: {directions}\nspace origin: (1,0,0)\nendian: little\nencoding: raw\n\n");
+        let parse = |directions: &str| {
+            let mut nrrd = header(directions).into_bytes();
+            VALUES.iter().for_each(|value| nrrd.extend(value.to_le_bytes()));
+            VoxelGridSDF::parse(&nrrd, &no_detached)
+        };
+        // The X axis points backwards, so the values decrease along X
+        let sdf = parse("(-2,0,0) (0,1,0) (0,0,1)").unwrap();
+        assert_eq!(sdf.bounding_box(), [Vector3::new(-1.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0)]);
+        assert_eq!(sdf.sample(Vector3::new(-1.0, 0.5, 0.5), true).distance, 0.5);
+        assert_eq!(sdf.sample(Vector3::new(1.0, 0.5, 0.5), true).distance, -0.5);
+        assert!(parse("(1,1,0) (0,1,0) (0,0,1)").is_err());
+    }
+
+    #[test]
+    fn test_metaimage_formats() {
+        let mut raw = vec![];
+        VALUES.iter().for_each(|value| raw.extend(value.to_le_bytes()));
+        let header = "ObjectType = Image\nNDims = 3\nDimSize = 2 2 2\nElementSpacing = 1 1 1\nElementType = MET_FLOAT\n";
+        let mha = [format!("{header}ElementDataFile = LOCAL\n").as_bytes(), &raw].concat();
+        let sdf = VoxelGridSDF::parse(&mha, &no_detached).unwrap();
+        assert_eq!(sdf.sample(Vector3::new(0.75, 0.0, 1.0), true).distance, 0.25);
+        // Detached and compressed
+        let mut compressed = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        compressed.write_all(&raw).unwrap();
+        let compressed = compressed.finish().unwrap();
+        let mhd = format!("{header}CompressedData = True\nElementDataFile = volume.zraw\n");
+        let sdf = VoxelGridSDF::parse(mhd.as_bytes(), &|file| {
+            assert_eq!(file, "volume.zraw");
+            Ok(compressed.clone())
+        }).unwrap();
+        assert_eq!(sdf.sample(Vector3::new(0.75, 0.0, 1.0), true).distance, 0.25);
+        assert!(VoxelGridSDF::parse(format!("{header}ElementDataFile = LOCAL\n").as_bytes(), &no_detached).is_err());
+    }
+}
@@ -0,0 +1,894 @@
+//! The implementations of the WebAssembly API (see [`crate::sdf::wasm`]) that can run the modules,
+//! selected at build time with cargo features and at runtime with `--wasm-backend`.
+//!
+//! Backends only call the exports of their instances and access their memory (see
+//! [`ModuleInstance`]), while the rest of the host is shared by all of them (see [`WasmSDF`]).
+
+use std::collections::HashSet;
+use std::mem::{align_of, size_of, size_of_val};
+use std::sync::{Arc, RwLock};
+
+use cgmath::{Vector3, Zero};
+
+use crate::sdf::defaults::{children_default_impl, glsl_default_impl, name_default_impl, normal_batch_default_impl, normal_batch_from_samples, normal_default_impl, parameters_default_impl, set_parameter_default_impl};
+use crate::sdf::error::{SDFError, SDFErrorLog};
+#[cfg(feature = "wasmi_backend")]
+use crate::sdf::wasm::interpreter;
+use crate::sdf::wasm::guest::{bounding_box_from_memory, check_abi_version, check_capabilities, check_param_value_supported, children_from_memory, changed_from_memory, GuestMemory, parameters_from_memory, read_vector3, sample_from_memory, samples_from_memory, set_parameter_result_from_memory, set_parameter_result_size, versioned_signatures, WasmType, BOUNDING_BOX_SIZE, CHANGED_SIZE};
+use crate::sdf::wasm::limits::{self, ExecutionLimits, LimitExceeded};
+#[cfg(feature = "wasmer_backend")]
+use crate::sdf::wasm::native;
+#[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+use crate::sdf::wasm::pool;
+use crate::sdf::wasm::pool::InstancePool;
+use crate::sdf::wasm::util::{reinterpret_i32_as_u32, reinterpret_u32_as_i32};
+use crate::sdf::wasm::wasi::CliWasiEnv;
+use crate::sdf::{abi, SDFMetadata, SDFParam, SDFParamValue, SDFSample, SDFSurface};
+
+#[cfg(not(any(feature = "wasmer_backend", feature = "wasmi_backend")))]
+compile_error!("The wasminterpreters feature needs at least one backend: enable the wasmer_backend or wasmi_backend features.");
+
+/// The backend selected for the modules loaded from now on, if not the default one.
+static BACKEND: RwLock<Option<WasmBackend>> = RwLock::new(None);
+
+/// A WebAssembly runtime that can run the modules.
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WasmBackend {
+    /// Compiles the modules to native code (or lets the browser run them), with full WASI support.
+    #[cfg(feature = "wasmer_backend")]
+    Wasmer,
+    /// Interprets the modules, which is portable but much slower, with basic WASI support.
+    #[cfg(feature = "wasmi_backend")]
+    Wasmi,
+}
+
+impl WasmBackend {
+    /// All the backends of this build, the default one first.
+    pub const ALL: &'static [Self] = &[
+        #[cfg(feature = "wasmer_backend")]
+        Self::Wasmer,
+        #[cfg(feature = "wasmi_backend")]
+        Self::Wasmi,
+    ];
+
+    /// The backend configured for this process.
+    pub fn current() -> Self {
+        BACKEND.read().unwrap().unwrap_or(Self::ALL[0])
+    }
+
+    /// Loads the given bytes as a WebAssembly module with this backend.
+    pub async fn load(self, wasm_bytes: &[u8], wasi: &CliWasiEnv) -> anyhow::Result<Box<dyn SDFSurface + Send + Sync>> {
+        match self {
+            #[cfg(feature = "wasmer_backend")]
+            Self::Wasmer => native::load_sdf_wasm_send_sync(wasm_bytes, wasi).await,
+            #[cfg(feature = "wasmi_backend")]
+            Self::Wasmi => interpreter::load_sdf_wasm_send_sync(wasm_bytes, wasi),
+        }
+    }
+}
+
+/// Options for the backend that runs WebAssembly SDFs, shared by all commands.
+#[derive(clap::Parser, Debug, Clone, PartialEq, Eq)]
+pub struct CliWasmBackend {
+    /// The runtime for WebAssembly SDFs. Defaults to wasmer if this build includes it, as the wasmi
+    /// interpreter is much slower (but available on more platforms).
+    #[clap(long, global = true, arg_enum)]
+    pub wasm_backend: Option<WasmBackend>,
+}
+
+impl CliWasmBackend {
+    /// Configures the backend for all the following loads.
+    pub fn apply(&self) {
+        *BACKEND.write().unwrap() = self.wasm_backend;
+    }
+}
+
+/// The exports of the modules that are used by the host (see [`crate::sdf::wasm`]).
+pub(crate) const EXPORTS: [&str; 28] = ["bounding_box", "bounding_box_free", "sample", "sample_free", "sample_batch",
+    "sample_batch_free", "sample_batch_points", "children", "children_free", "name", "name_free", "parameters",
+    "parameters_free", "set_parameter", "set_parameter_free", "changed", "changed_free", "glsl", "glsl_free",
+    "normal", "normal_free", "alloc", "dealloc", "malloc", "free", "init", "sdf_abi_version", "capabilities"];
+
+/// A value passed to or returned by the exports of the modules (the API only uses these types).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum WasmValue {
+    I32(i32),
+    F32(f32),
+}
+
+/// A call into a module that failed.
+#[derive(Debug)]
+pub(crate) struct Trap {
+    /// The error to report, with the export and the backtrace (if known).
+    pub error: SDFError,
+    /// Whether the call ran out of fuel, instead of failing by itself.
+    pub out_of_fuel: bool,
+}
+
+impl From<SDFError> for Trap {
+    fn from(error: SDFError) -> Self {
+        Self { error, out_of_fuel: false }
+    }
+}
+
+/// An instance of a module, with its own memory, that runs one call at a time. This is all that a
+/// backend implements, as the rest of the host is shared by all of them (see [`WasmSDF`]).
+pub(crate) trait ModuleInstance: GuestMemory + Send + 'static {
+    /// The types of the parameters and results of the given export (see [`EXPORTS`]), if available.
+    fn signature(&self, export: &str) -> Option<(Vec<WasmType>, Vec<WasmType>)>;
+
+    /// Calls the given export (see [`EXPORTS`]), with a budget of instructions if metered.
+    fn call(&mut self, export: &str, args: &[WasmValue], fuel: Option<u64>) -> Result<Vec<WasmValue>, Trap>;
+
+    /// Writes to the guest memory, which must have been reserved with [`WasmSDF::guest_alloc`]
+    /// (or be otherwise owned by the module for this purpose).
+    fn write_memory(&mut self, mem_pointer: u32, to_write: &[u8]);
+}
+
+/// The functions exported by the module to manage the memory of the buffers written by the host
+/// (see [`crate::sdf::wasm`]), by the names of their exports.
+#[derive(Debug, Clone, Copy)]
+enum GuestAllocator {
+    /// `alloc(size, align) -> ptr` and `dealloc(ptr, size, align)`, as exported by [`crate::sdf::ffi`].
+    AllocDealloc,
+    /// `malloc(size) -> ptr` and `free(ptr)`, as exported by TinyGo or Emscripten binaries.
+    MallocFree,
+}
+
+impl GuestAllocator {
+    /// Finds the allocator exported by the module, if any.
+    fn find(instance: &impl ModuleInstance) -> Option<Self> {
+        use WasmType::I32;
+        let has = |name: &str, params: &[WasmType], results: &[WasmType]| {
+            let (actual_params, actual_results) = instance.signature(name)?;
+            if actual_params != params || actual_results != results {
+                tracing::warn!("Ignoring `{}` export with unexpected signature {:?} -> {:?}", name, actual_params, actual_results);
+                return None;
+            }
+            Some(())
+        };
+        if has("alloc", &[I32; 2], &[I32]).and(has("dealloc", &[I32; 3], &[])).is_some() {
+            Some(Self::AllocDealloc)
+        } else if has("malloc", &[I32], &[I32]).and(has("free", &[I32], &[])).is_some() {
+            Some(Self::MallocFree)
+        } else {
+            tracing::info!("The SDF module does not export an allocator, so string parameters can't be set");
+            None
+        }
+    }
+}
+
+/// Reads the version of the API and the capabilities of the module (see [`crate::sdf::abi`]),
+/// failing with a clear error if the module can't be used by this host.
+fn negotiate_abi(instance: &mut impl ModuleInstance, limits: &ExecutionLimits) -> anyhow::Result<(u32, Option<u32>)> {
+    let mut call_u32 = |name: &str| -> anyhow::Result<Option<u32>> {
+        if instance.signature(name).is_none() {
+            return Ok(None);
+        }
+        match instance.call(name, &[], limits.fuel_for_init()).map_err(|trap| trap.error)?.as_slice() {
+            [WasmValue::I32(value)] => Ok(Some(reinterpret_i32_as_u32(*value))),
+            other => anyhow::bail!("Incompatible SDF module: `{}` must return a single i32, but it returned {:?}", name, other),
+        }
+    };
+
+    let abi_version = call_u32("sdf_abi_version")?.unwrap_or(abi::SDF_ABI_LEGACY_VERSION);
+    check_abi_version(abi_version)?;
+
+    // Modules that don't declare their capabilities are probed for the optional exports instead
+    let capabilities = call_u32("capabilities")?;
+    if let Some(capabilities) = capabilities {
+        check_capabilities(capabilities, |name| instance.signature(name).is_some())?;
+    }
+
+    // Check the signatures that depend on the version, to fail early instead of reading garbage
+    for (name, params, results) in versioned_signatures(abi_version) {
+        if let Some((actual_params, actual_results)) = instance.signature(name) {
+            if actual_params != params || actual_results != results {
+                anyhow::bail!("Incompatible SDF module: `{}` has signature {:?} -> {:?}, but ABI version {} expects {:?} -> {:?}",
+                    name, actual_params, actual_results, abi_version, params, results);
+            }
+        }
+    }
+
+    tracing::info!("Negotiated SDF ABI version {} with capabilities {:?}", abi_version, capabilities);
+    Ok((abi_version, capabilities))
+}
+
+/// A WebAssembly SDF run by any backend, which implements the host side of the API on top of the
+/// calls and memory accesses of its instances (see [`ModuleInstance`]).
+#[derive(Debug)]
+pub(crate) struct WasmSDF<I> {
+    sdf_id: u32,
+    /// The instances of the module, all of them with the same parameters (see [`InstancePool`]).
+    pool: Arc<InstancePool<I>>,
+    /// The exports used by the host that are available, which are the same for all the instances.
+    exports: Arc<HashSet<&'static str>>,
+    /// The allocator used for all the buffers written by the host, if exported by the module.
+    allocator: Option<GuestAllocator>,
+    metadata: Option<SDFMetadata>,
+    /// The negotiated version of the API, which defines the memory layout of some values.
+    abi_version: u32,
+    /// The limits of each call into the module (see [`limits`]).
+    limits: ExecutionLimits,
+    /// The traps of the calls, to report them to the user. Shared by the whole hierarchy, as all of
+    /// it stops working when any call exceeds a limit.
+    errors: SDFErrorLog,
+}
+
+impl<I> Clone for WasmSDF<I> { // Note: cloning is cheap and shares the instances
+    fn clone(&self) -> Self {
+        Self {
+            sdf_id: self.sdf_id,
+            pool: self.pool.clone(),
+            exports: self.exports.clone(),
+            allocator: self.allocator,
+            metadata: self.metadata.clone(),
+            abi_version: self.abi_version,
+            limits: self.limits,
+            errors: self.errors.clone(),
+        }
+    }
+}
+
+impl<I: ModuleInstance> WasmSDF<I> {
+    /// Makes sure that the host can talk to the given instances of a module (the first one being the
+    /// primary instance, see [`InstancePool`]) and initializes them.
+    pub(crate) fn new(mut instances: Vec<I>, metadata: Option<SDFMetadata>, limits: ExecutionLimits) -> anyhow::Result<Self> {
+        let primary = instances.first_mut().ok_or_else(|| anyhow::anyhow!("no instances of the SDF module"))?;
+        let (abi_version, capabilities) = negotiate_abi(primary, &limits)?;
+        let has_capability = |capability: u32| capabilities.map(|c| c & capability != 0).unwrap_or(true);
+        let mut exports = EXPORTS.into_iter().filter(|name| primary.signature(name).is_some()).collect::<HashSet<_>>();
+        if !has_capability(abi::CAPABILITY_SAMPLE_BATCH) {
+            exports.retain(|name| !["sample_batch", "sample_batch_free", "sample_batch_points"].contains(name));
+        }
+        if !has_capability(abi::CAPABILITY_GLSL) {
+            exports.retain(|name| !["glsl", "glsl_free"].contains(name));
+        }
+        for required in ["bounding_box", "sample"] {
+            if !exports.contains(required) {
+                anyhow::bail!("Incompatible SDF module: it does not export `{}`", required);
+            }
+        }
+        let allocator = GuestAllocator::find(primary);
+
+        // Call init() to initialize each instance (optional).
+        if exports.contains("init") {
+            for instance in &mut instances {
+                let fuel = limits.fuel_for_init();
+                if let Err(trap) = instance.call("init", &[], fuel) {
+                    if let Some(limit) = fuel.filter(|_| trap.out_of_fuel) {
+                        return Err(SDFError { message: LimitExceeded::Fuel { limit }.to_string(), ..trap.error }.into());
+                    }
+                    tracing::error!("Calling init() failed: {}", trap.error);
+                }
+            }
+        }
+
+        Ok(Self {
+            sdf_id: 0, // This must always be the ID of the root SDF (as specified by the docs)
+            pool: Arc::new(InstancePool::new(instances)),
+            exports: Arc::new(exports),
+            allocator,
+            metadata,
+            abi_version,
+            limits,
+            errors: SDFErrorLog::default(),
+        })
+    }
+
+    /// Runs a call into the given export of the module within the execution limits (see [`limits`])
+    /// for the given units of work. It fails without calling into the module if the SDF already
+    /// faulted. Traps are recorded to be reported to the user, marking the SDF as faulted if the
+    /// call exceeded a limit.
+    fn call(&self, instance: &mut I, export: &str, work: usize, args: &[WasmValue]) -> anyhow::Result<Vec<WasmValue>> {
+        if let Some(fault) = self.errors.fault() {
+            return Err(anyhow::anyhow!("the SDF is faulted: {}", fault));
+        }
+        if !self.exports.contains(export) {
+            anyhow::bail!("the SDF module does not export `{}`", export);
+        }
+        let fuel = self.limits.fuel_for(work);
+        limits::take_memory_grow_refused(); // Forget about previous calls
+        instance.call(export, args, fuel).map_err(|trap| {
+            let mut error = trap.error.with_sdf_id(self.sdf_id);
+            match self.limit_exceeded(trap.out_of_fuel, fuel) {
+                Some(exceeded) => {
+                    tracing::error!("Wasm SDF with ID {} faulted: {} (trap: {})", self.sdf_id, exceeded, error.message);
+                    error.message = exceeded.to_string();
+                    self.errors.set_fault(error.clone());
+                }
+                None => self.errors.push(error.clone()),
+            }
+            anyhow::Error::from(error)
+        })
+    }
+
+    /// Finds out whether the last (failed) call exceeded a limit.
+    fn limit_exceeded(&self, out_of_fuel: bool, fuel: Option<u64>) -> Option<LimitExceeded> {
+        if let Some(limit) = fuel.filter(|_| out_of_fuel) {
+            return Some(LimitExceeded::Fuel { limit });
+        }
+        // The module traps (or aborts) after failing to grow its memory, so blame the limit if it refused
+        let limit_bytes = self.limits.max_memory_bytes?;
+        limits::take_memory_grow_refused().then_some(LimitExceeded::Memory { limit_bytes })
+    }
+
+    /// Calls an export that returns a pointer to its result, logging any error as failing to get `what`.
+    /// Returns the pointer and the raw result, to be passed to the matching `_free` export.
+    fn call_pointer(&self, instance: &mut I, export: &str, work: usize, args: &[WasmValue], what: &str) -> Option<(u32, Vec<WasmValue>)> {
+        let result = self.call(instance, export, work, args).unwrap_or_else(|err| {
+            tracing::error!("Failed to get {} of wasm SDF with ID {}: {}", what, self.sdf_id, err);
+            vec![]
+        });
+        match result.as_slice() {
+            [WasmValue::I32(pointer)] => Some((reinterpret_i32_as_u32(*pointer), result)),
+            [] => None, // Errors already logged
+            other => {
+                tracing::error!("Expected i32 output for {}(), got {:?}", export, other);
+                None
+            }
+        }
+    }
+
+    /// Frees the memory of a result (see [`call_pointer`](#method.call_pointer)), if the module
+    /// exports the given function.
+    fn free(&self, instance: &mut I, export: &str, result: &[WasmValue]) {
+        if self.exports.contains(export) {
+            let _ = self.call(instance, export, 1, result); // Errors already recorded
+        }
+    }
+
+    /// Reads the memory referenced by the pointer and length at the given pointer.
+    fn read_pointer_length_at(instance: &I, mem_pointer: u32) -> Vec<u8> {
+        instance.read_pointer_length(&instance.read_bytes(mem_pointer as u64, 2 * size_of::<u32>()))
+    }
+
+    /// Reserves a buffer in the guest memory using the allocator exported by the module, if any.
+    fn guest_alloc(&self, instance: &mut I, size: usize, align: usize) -> Option<u32> {
+        let (export, args) = match self.allocator? {
+            GuestAllocator::AllocDealloc => ("alloc", vec![
+                WasmValue::I32(reinterpret_u32_as_i32(size as u32)),
+                WasmValue::I32(reinterpret_u32_as_i32(align as u32)),
+            ]),
+            // malloc is aligned for any primitive type
+            GuestAllocator::MallocFree => ("malloc", vec![WasmValue::I32(reinterpret_u32_as_i32(size as u32))]),
+        };
+        match self.call_pointer(instance, export, 1, &args, "memory") {
+            Some((0, _)) => {
+                tracing::error!("Failed to allocate {} bytes in wasm SDF with ID {}: out of memory", size, self.sdf_id);
+                self.errors.push(SDFError::new(format!("Failed to allocate {} bytes: out of memory", size))
+                    .with_export(export).with_sdf_id(self.sdf_id));
+                None
+            }
+            result => result.map(|(mem_pointer, _)| mem_pointer),
+        }
+    }
+
+    /// Releases a buffer previously reserved with [`guest_alloc`](#method.guest_alloc).
+    fn guest_dealloc(&self, instance: &mut I, mem_pointer: u32, size: usize, align: usize) {
+        let (export, args) = match self.allocator {
+            Some(GuestAllocator::AllocDealloc) => ("dealloc", vec![
+                WasmValue::I32(reinterpret_u32_as_i32(mem_pointer)),
+                WasmValue::I32(reinterpret_u32_as_i32(size as u32)),
+                WasmValue::I32(reinterpret_u32_as_i32(align as u32)),
+            ]),
+            Some(GuestAllocator::MallocFree) => ("free", vec![WasmValue::I32(reinterpret_u32_as_i32(mem_pointer))]),
+            None => return,
+        };
+        if let Err(err) = self.call(instance, export, 1, &args) {
+            tracing::error!("Failed to free {} bytes in wasm SDF with ID {}: {}", size, self.sdf_id, err);
+        }
+    }
+
+    /// Samples a single point with the given instance.
+    fn sample_with(&self, instance: &mut I, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        let args = [
+            WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id)),
+            WasmValue::F32(p.x),
+            WasmValue::F32(p.y),
+            WasmValue::F32(p.z),
+            WasmValue::I32(i32::from(distance_only)),
+        ];
+        let (mem_pointer, result) = match self.call_pointer(instance, "sample", 1, &args, "sample") {
+            Some(pointer_result) => pointer_result,
+            None => return SDFSample::new(1.0, Vector3::zero()), // Errors already logged
+        };
+        let mem_bytes = instance.read_bytes(mem_pointer as u64, size_of::<SDFSample>());
+        self.free(instance, "sample_free", &result); // Free the memory, now that we copied it
+        sample_from_memory(&mem_bytes)
+    }
+
+    /// Samples all the points with the given instance, in a single call if supported by the module.
+    fn sample_batch_with(&self, instance: &mut I, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        if !self.exports.contains("sample_batch") ||
+            (self.allocator.is_none() && !self.exports.contains("sample_batch_points")) {
+            return points.iter().map(|p| self.sample_with(instance, *p, distance_only)).collect();
+        }
+        if points.is_empty() {
+            return vec![];
+        }
+        let failed = || (0..points.len()).map(|_| SDFSample::new(1.0, Vector3::zero())).collect(); // Errors already logged
+        // Get a buffer from the module (preferably from its allocator) and write the flattened points to it
+        let points_len_bytes = size_of_val(points);
+        let mut points_buffer = self.allocator
+            .and_then(|_| self.guest_alloc(instance, points_len_bytes, align_of::<f32>()))
+            .map(|mem_pointer| (mem_pointer, true));
+        if points_buffer.is_none() && self.exports.contains("sample_batch_points") {
+            points_buffer = self.call_pointer(instance, "sample_batch_points", 1, &[WasmValue::I32(reinterpret_u32_as_i32(points_len_bytes as u32))],
+                                              "sample batch buffer").map(|(mem_pointer, _)| (mem_pointer, false));
+        }
+        let (points_pointer, allocated) = match points_buffer {
+            Some(points_buffer) => points_buffer,
+            // The allocator failed (errors already logged), so try sampling the points one by one
+            None => return points.iter().map(|p| self.sample_with(instance, *p, distance_only)).collect(),
+        };
+        let points_bytes = points.iter()
+            .flat_map(|p| [p.x, p.y, p.z])
+            .flat_map(|f| f.to_le_bytes())
+            .collect::<Vec<_>>();
+        instance.write_memory(points_pointer, &points_bytes);
+        // Sample all points at once
+        let args = [
+            WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id)),
+            WasmValue::I32(reinterpret_u32_as_i32(points_pointer)),
+            WasmValue::I32(reinterpret_u32_as_i32(points_len_bytes as u32)),
+            WasmValue::I32(i32::from(distance_only)),
+        ];
+        let pointer_result = self.call_pointer(instance, "sample_batch", points.len(), &args, "sample batch");
+        if allocated {
+            self.guest_dealloc(instance, points_pointer, points_len_bytes, align_of::<f32>());
+        }
+        let (mem_pointer, result) = match pointer_result {
+            Some(pointer_result) => pointer_result,
+            None => return failed(),
+        };
+        let mem_bytes = Self::read_pointer_length_at(instance, mem_pointer);
+        self.free(instance, "sample_batch_free", &result); // Free the memory, now that we copied it
+        samples_from_memory(&mem_bytes, points.len())
+    }
+
+    /// Samples all the points with any instance of the pool.
+    fn sample_batch_any(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        self.sample_batch_with(&mut self.pool.acquire(), points, distance_only)
+    }
+
+    /// Sets the parameter with the given instance.
+    fn set_parameter_with(&self, instance: &mut I, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        if !self.exports.contains("set_parameter") {
+            return set_parameter_default_impl(self, param_id, param_value);
+        }
+        check_param_value_supported(param_value, self.abi_version)?;
+        let mut string_to_free = None;
+        // The SDFParamValueC is flattened into the enum index and the (maximum) payload size, as i32 values.
+        let f32_bits = |value: f32| i32::from_le_bytes(value.to_le_bytes()); // f32 bits to i32
+        let (enum_type, payload) = match param_value {
+            SDFParamValue::Boolean(value) => (0, [i32::from(*value), 0, 0]),
+            SDFParamValue::Int(value) => (1, [*value, 0, 0]),
+            SDFParamValue::Float(value) => (2, [f32_bits(*value), 0, 0]),
+            SDFParamValue::String(value) => {
+                // The module only borrows the string during the call, so we free it afterwards
+                if self.allocator.is_none() {
+                    return Err(format!("Can't set string parameter {param_id}: the SDF module does not export an allocator (alloc/dealloc or malloc/free)"));
+                }
+                let string_pointer = self.guest_alloc(instance, value.len(), align_of::<u8>()).ok_or_else(|| format!(
+                    "Can't set string parameter {param_id}: the SDF module failed to allocate {} bytes", value.len()))?;
+                instance.write_memory(string_pointer, value.as_bytes());
+                string_to_free = Some((string_pointer, value.len()));
+                (3, [reinterpret_u32_as_i32(string_pointer), reinterpret_u32_as_i32(value.len() as u32), 0])
+            }
+            SDFParamValue::Vec3(value) => (4, [f32_bits(value.x), f32_bits(value.y), f32_bits(value.z)]),
+            SDFParamValue::Color(value) => (5, [f32_bits(value.x), f32_bits(value.y), f32_bits(value.z)]),
+            SDFParamValue::Angle(value) => (6, [f32_bits(*value), 0, 0]),
+        };
+        let payload_words = abi::param_value_payload_words(self.abi_version);
+        let mut args = vec![
+            WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id)),
+            WasmValue::I32(reinterpret_u32_as_i32(param_id)),
+            WasmValue::I32(enum_type),
+        ];
+        args.extend(payload[..payload_words].iter().map(|word| WasmValue::I32(*word)));
+        let pointer_result = self.call_pointer(instance, "set_parameter", 1, &args, "set parameter result");
+        if let Some((string_pointer, string_len)) = string_to_free {
+            self.guest_dealloc(instance, string_pointer, string_len, align_of::<u8>());
+        }
+        let (mem_pointer, result) = match pointer_result {
+            Some(pointer_result) => pointer_result,
+            None => return set_parameter_default_impl(self, param_id, param_value), // Errors already logged
+        };
+        let mem_bytes = instance.read_bytes(mem_pointer as u64, set_parameter_result_size(instance.pointer_size()));
+        let res = set_parameter_result_from_memory(&*instance, &mem_bytes);
+        self.free(instance, "set_parameter_free", &result); // Free the memory, now that we copied it
+        res
+    }
+}
+
+impl<I: ModuleInstance> SDFSurface for WasmSDF<I> {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let mut instance = self.pool.primary();
+        let (mem_pointer, result) = match self.call_pointer(&mut instance, "bounding_box", 1, &[WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id))], "bounding box") {
+            Some(pointer_result) => pointer_result,
+            None => return [Vector3::<f32>::zero(), Vector3::<f32>::new(1.0, 1.0, 1.0)], // Errors already logged
+        };
+        let mem_bytes = instance.read_bytes(mem_pointer as u64, BOUNDING_BOX_SIZE);
+        self.free(&mut instance, "bounding_box_free", &result); // Free the memory, now that we copied it
+        bounding_box_from_memory(&mem_bytes)
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        self.sample_with(&mut self.pool.acquire(), p, distance_only)
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        // Split large batches between all the instances, sampling them in parallel
+        #[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+        if self.pool.size() > 1 && points.len() >= 2 * pool::MIN_POINTS_PER_INSTANCE {
+            use rayon::prelude::*;
+            let chunk_len = points.len().div_ceil(self.pool.size()).max(pool::MIN_POINTS_PER_INSTANCE);
+            return points.par_chunks(chunk_len)
+                .flat_map_iter(|chunk| self.sample_batch_any(chunk, distance_only))
+                .collect();
+        }
+        self.sample_batch_any(points, distance_only)
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        if !self.exports.contains("children") {
+            return children_default_impl(self);
+        }
+        let mut instance = self.pool.primary();
+        let (mem_pointer, result) = match self.call_pointer(&mut instance, "children", 1, &[WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id))], "children") {
+            Some(pointer_result) => pointer_result,
+            None => return children_default_impl(self), // Errors already logged
+        };
+        let mem_bytes = Self::read_pointer_length_at(&instance, mem_pointer);
+        self.free(&mut instance, "children_free", &result); // Free the memory, now that we copied it
+        children_from_memory(&mem_bytes).into_iter()
+            .filter_map(|child_sdf_id| {
+                if child_sdf_id == self.sdf_id {
+                    tracing::error!("Children of wasm SDF with ID {} include itself! Skipping, but this should be fixed.", self.sdf_id);
+                    return None;
+                }
+                Some(Box::new(Self {
+                    sdf_id: child_sdf_id,
+                    ..self.clone() // Cloning is cheap and shares the memory of children parameters
+                }) as Box<dyn SDFSurface>)
+            }).collect()
+    }
+
+    fn id(&self) -> u32 {
+        self.sdf_id // Already known!
+    }
+
+    fn name(&self) -> String {
+        if !self.exports.contains("name") {
+            return name_default_impl(self);
+        }
+        let mut instance = self.pool.primary();
+        let (mem_pointer, result) = match self.call_pointer(&mut instance, "name", 1, &[WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id))], "name") {
+            Some(pointer_result) => pointer_result,
+            None => return name_default_impl(self), // Errors already logged
+        };
+        let mem_bytes = Self::read_pointer_length_at(&instance, mem_pointer);
+        self.free(&mut instance, "name_free", &result); // Free the memory, now that we copied it
+        String::from_utf8_lossy(mem_bytes.as_slice()).to_string()
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        if !self.exports.contains("parameters") {
+            return parameters_default_impl(self);
+        }
+        let mut instance = self.pool.primary();
+        let (mem_pointer, result) = match self.call_pointer(&mut instance, "parameters", 1, &[WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id))], "parameters") {
+            Some(pointer_result) => pointer_result,
+            None => return parameters_default_impl(self), // Errors already logged
+        };
+        let mem_bytes = Self::read_pointer_length_at(&instance, mem_pointer);
+        let res = parameters_from_memory(&*instance, &mem_bytes, self.abi_version);
+        self.free(&mut instance, "parameters_free", &result); // Free the memory, now that we copied it
+        res
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        // The primary instance decides whether the change is valid, and the others replay it to stay
+        // in sync (keeping the primary locked, so that concurrent changes are replayed in order)
+        let mut instance = self.pool.primary();
+        self.set_parameter_with(&mut instance, param_id, param_value)?;
+        self.pool.for_each_secondary(|index, instance| {
+            if let Err(err) = self.set_parameter_with(instance, param_id, param_value) {
+                tracing::error!("Instance {} of wasm SDF with ID {} rejected parameter {} accepted by the primary instance: {}",
+                    index, self.sdf_id, param_id, err);
+            }
+        });
+        Ok(())
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        if !self.exports.contains("changed") {
+            return None;
+        }
+        let mut instance = self.pool.primary();
+        let (mem_pointer, result) = self.call_pointer(&mut instance, "changed", 1, &[WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id))], "changed")?;
+        let mem_bytes = instance.read_bytes(mem_pointer as u64, CHANGED_SIZE);
+        let res = changed_from_memory(&mem_bytes);
+        self.free(&mut instance, "changed_free", &result); // Free the memory, now that we copied it
+        res
+    }
+
+    fn glsl(&self) -> String {
+        if !self.exports.contains("glsl") {
+            return glsl_default_impl(self);
+        }
+        let mut instance = self.pool.primary();
+        let (mem_pointer, result) = match self.call_pointer(&mut instance, "glsl", 1, &[WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id))], "GLSL code") {
+            Some(pointer_result) => pointer_result,
+            None => return glsl_default_impl(self), // Errors already logged
+        };
+        let mem_bytes = Self::read_pointer_length_at(&instance, mem_pointer);
+        self.free(&mut instance, "glsl_free", &result); // Free the memory, now that we copied it
+        String::from_utf8_lossy(mem_bytes.as_slice()).to_string()
+    }
+
+    fn metadata(&self) -> Option<SDFMetadata> {
+        self.metadata.clone() // Shared by the whole hierarchy, as it comes from the same module
+    }
+
+    fn fault(&self) -> Option<SDFError> {
+        self.errors.fault()
+    }
+
+    fn take_errors(&self) -> Vec<SDFError> {
+        self.errors.take() // Shared by the whole hierarchy
+    }
+
+    fn normal(&self, p: Vector3<f32>, eps: Option<f32>) -> Vector3<f32> {
+        if !self.exports.contains("normal") {
+            return normal_default_impl(self, p, eps);
+        }
+        let args = [
+            WasmValue::I32(reinterpret_u32_as_i32(self.sdf_id)),
+            WasmValue::F32(p.x),
+            WasmValue::F32(p.y),
+            WasmValue::F32(p.z),
+            WasmValue::F32(eps.unwrap_or(-1.0)),
+        ];
+        let mut instance = self.pool.acquire();
+        let (mem_pointer, result) = match self.call_pointer(&mut instance, "normal", 1, &args, "normal") {
+            Some(pointer_result) => pointer_result,
+            None => return Vector3::new(0.0, 0.0, 0.0), // Errors already logged
+        };
+        let mem_bytes = instance.read_bytes(mem_pointer as u64, 3 * size_of::<f32>());
+        self.free(&mut instance, "normal_free", &result); // Free the memory, now that we copied it
+        read_vector3(&mem_bytes, 0)
+    }
+
+    fn normal_batch(&self, points: &[Vector3<f32>], eps: Option<f32>) -> Vec<Vector3<f32>> {
+        // Modules without custom normals get them from a single batch of samples
+        if !self.exports.contains("normal") {
+            return normal_batch_from_samples(self, points, eps);
+        }
+        normal_batch_default_impl(self, points, eps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use cgmath::Vector3;
+
+    use crate::sdf::{SDFParamValue, SDFSample, SDFSurface};
+    use crate::sdf::wasm::backend::{ModuleInstance, WasmBackend, WasmSDF};
+    use crate::sdf::wasm::console;
+    #[cfg(feature = "wasmer_backend")]
+    use crate::sdf::wasm::fixtures::block_on;
+    use crate::sdf::wasm::fixtures::{load_with_each_backend, wasm, SPHERE_WAT};
+    #[cfg(feature = "wasmi_backend")]
+    use crate::sdf::wasm::interpreter;
+    #[cfg(feature = "wasmer_backend")]
+    use crate::sdf::wasm::native;
+    #[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+    use crate::sdf::wasm::pool;
+    use crate::sdf::wasm::wasi::CliWasiEnv;
+
+    /// A sphere whose radius is its only parameter (version 2 of the API, without batched sampling).
+    const RADIUS_SPHERE_WAT: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $radius (mut f32) (f32.const 1))
+        (func (export "sdf_abi_version") (result i32) (i32.const 2))
+        (func (export "capabilities") (result i32) (i32.const 0))
+        (func (export "bounding_box") (param i32) (result i32) (i32.const 0))
+        (func (export "sample") (param i32 f32 f32 f32 i32) (result i32)
+            (f32.store (i32.const 64) (f32.sub (f32.sqrt (f32.add (f32.add
+                (f32.mul (local.get 1) (local.get 1)) (f32.mul (local.get 2) (local.get 2)))
+                (f32.mul (local.get 3) (local.get 3)))) (global.get $radius)))
+            (i32.const 64))
+        (func (export "set_parameter") (param i32 i32 i32 i32 i32 i32) (result i32)
+            (global.set $radius (f32.reinterpret_i32 (local.get 3)))
+            (i32.store (i32.const 128) (i32.const 0))
+            (i32.const 128)))"#;
+
+    /// A sphere implementing version 2 of the API with batched sampling and an allocator. Its
+    /// parameters are the radius (a float) and a label (a string), which is printed to stdout
+    /// through WASI whenever it changes.
+    const PARAM_SPHERE_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (global $radius (mut f32) (f32.const 1))
+        (global $label_len (mut i32) (i32.const 0))
+        (global $heap (mut i32) (i32.const 40000))
+        (data (i32.const 0) "\00\00\00\c0\00\00\00\c0\00\00\00\c0\00\00\00\40\00\00\00\40\00\00\00\40")
+        (data (i32.const 2048) "radius")
+        (data (i32.const 2056) "label")
+        (data (i32.const 2200) "unknown parameter")
+        (data (i32.const 2300) "\n")
+        (func (export "sdf_abi_version") (result i32) (i32.const 2))
+        (func (export "capabilities") (result i32) (i32.const 5))
+        (func (export "alloc") (param $size i32) (param $align i32) (result i32) (local $pointer i32)
+            (local.set $pointer (global.get $heap))
+            (global.set $heap (i32.add (local.get $pointer) (local.get $size)))
+            (local.get $pointer))
+        (func (export "dealloc") (param $pointer i32) (param $size i32) (param $align i32)
+            (if (i32.eq (i32.add (local.get $pointer) (local.get $size)) (global.get $heap))
+                (then (global.set $heap (local.get $pointer)))))
+        (func (export "bounding_box") (param i32) (result i32) (i32.const 0))
+        (func $sample_into (param $out i32) (param $x f32) (param $y f32) (param $z f32)
+            (f32.store (local.get $out) (f32.sub (f32.sqrt (f32.add (f32.add
+                (f32.mul (local.get $x) (local.get $x)) (f32.mul (local.get $y) (local.get $y)))
+                (f32.mul (local.get $z) (local.get $z)))) (global.get $radius)))
+            (f32.store offset=4 (local.get $out) (f32.const 1))
+            (f32.store offset=8 (local.get $out) (local.get $y))
+            (f32.store offset=12 (local.get $out) (f32.const 0))
+            (f32.store offset=16 (local.get $out) (f32.const 0.5))
+            (f32.store offset=20 (local.get $out) (f32.const 0.25))
+            (f32.store offset=24 (local.get $out) (f32.const 1)))
+        (func (export "sample") (param i32 f32 f32 f32 i32) (result i32)
+            (call $sample_into (i32.const 64) (local.get 1) (local.get 2) (local.get 3))
+            (i32.const 64))
+        (func (export "sample_batch") (param i32) (param $points i32) (param $len i32) (param i32) (result i32)
+            (local $i i32) (local $point i32)
+            (block $done (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (i32.div_u (local.get $len) (i32.const 12))))
+                (local.set $point (i32.add (local.get $points) (i32.mul (local.get $i) (i32.const 12))))
+                (call $sample_into (i32.add (i32.const 4096) (i32.mul (local.get $i) (i32.const 28)))
+                    (f32.load (local.get $point)) (f32.load offset=4 (local.get $point)) (f32.load offset=8 (local.get $point)))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+            (i32.store (i32.const 128) (i32.const 4096))
+            (i32.store (i32.const 132) (i32.mul (local.get $i) (i32.const 28)))
+            (i32.const 128))
+        (func (export "sample_batch_free") (param i32))
+        (func (export "sample_batch_points") (param i32) (result i32) (i32.const 32768))
+        (func (export "parameters") (param i32) (result i32)
+            (i32.store (i32.const 512) (i32.const 0))
+            (i32.store (i32.const 516) (i32.const 2048))
+            (i32.store (i32.const 520) (i32.const 6))
+            (i32.store (i32.const 524) (i32.const 2))
+            (f32.store (i32.const 528) (f32.const 0.5))
+            (f32.store (i32.const 532) (f32.const 2))
+            (f32.store (i32.const 536) (f32.const 0.5))
+            (i32.store (i32.const 540) (i32.const 2))
+            (f32.store (i32.const 544) (global.get $radius))
+            (i32.store (i32.const 564) (i32.const 1))
+            (i32.store (i32.const 568) (i32.const 2056))
+            (i32.store (i32.const 572) (i32.const 5))
+            (i32.store (i32.const 576) (i32.const 3))
+            (i32.store (i32.const 592) (i32.const 3))
+            (i32.store (i32.const 596) (i32.const 1024))
+            (i32.store (i32.const 600) (global.get $label_len))
+            (i32.store (i32.const 256) (i32.const 512))
+            (i32.store (i32.const 260) (i32.const 104))
+            (i32.const 256))
+        (func (export "set_parameter") (param i32) (param $param i32) (param $kind i32) (param $w0 i32) (param $w1 i32) (param i32) (result i32)
+            (i32.store (i32.const 320) (i32.const 0))
+            (if (i32.and (i32.eq (local.get $param) (i32.const 0)) (i32.eq (local.get $kind) (i32.const 2))) (then
+                (global.set $radius (f32.reinterpret_i32 (local.get $w0)))
+                (return (i32.const 320))))
+            (if (i32.and (i32.eq (local.get $param) (i32.const 1)) (i32.eq (local.get $kind) (i32.const 3))) (then
+                (memory.copy (i32.const 1024) (local.get $w0) (local.get $w1))
+                (global.set $label_len (local.get $w1))
+                (i32.store (i32.const 336) (i32.const 1024))
+                (i32.store (i32.const 340) (local.get $w1))
+                (i32.store (i32.const 344) (i32.const 2300))
+                (i32.store (i32.const 348) (i32.const 1))
+                (drop (call $fd_write (i32.const 1) (i32.const 336) (i32.const 2) (i32.const 352)))
+                (return (i32.const 320))))
+            (i32.store (i32.const 320) (i32.const 1))
+            (i32.store (i32.const 324) (i32.const 2200))
+            (i32.store (i32.const 328) (i32.const 17))
+            (i32.const 320)))"#;
+
+    #[test]
+    fn test_backends_agree() {
+        let points = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0), Vector3::new(0.5, -0.5, 0.5)];
+        let results = load_with_each_backend(SPHERE_WAT).into_iter().map(|(backend, sdf)| {
+            let samples = points.iter().map(|p| sdf.sample(*p, false)).map(|s| (s.distance, s.color)).collect::<Vec<_>>();
+            assert!(sdf.take_errors().is_empty(), "{:?} recorded errors", backend);
+            let trapped = sdf.sample(Vector3::new(20.0, 0.0, 0.0), true);
+            assert_eq!(sdf.take_errors().len(), 1, "{:?} did not record the trap", backend);
+            (sdf.bounding_box(), samples, trapped.distance)
+        }).collect::<Vec<_>>();
+        let (bounding_box, samples, trapped_distance) = &results[0];
+        assert_eq!(*bounding_box, [Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0)]);
+        assert_eq!(samples[0].0, -1.0);
+        assert_eq!(samples[1], (1.0, Vector3::new(1.0, 0.0, 0.0)));
+        assert_eq!(*trapped_distance, 1.0); // Placeholder value
+        for (backend, result) in WasmBackend::ALL.iter().zip(&results).skip(1) {
+            assert_eq!(result, &results[0], "{:?} differs from {:?}", backend, WasmBackend::ALL[0]);
+        }
+    }
+
+    #[test]
+    fn test_backends_agree_on_batches_parameters_and_output() {
+        let points = (0..200).map(|i| Vector3::new(i as f32 * 0.01, 1.0 - i as f32 * 0.01, 0.5)).collect::<Vec<_>>();
+        let results = load_with_each_backend(PARAM_SPHERE_WAT).into_iter().map(|(backend, sdf)| {
+            let label = format!("label set through {:?}", backend);
+            assert_eq!(sdf.set_parameter(0, &SDFParamValue::Float(1.5)), Ok(()));
+            assert_eq!(sdf.set_parameter(1, &SDFParamValue::String(label.clone())), Ok(()));
+            assert_eq!(sdf.set_parameter(2, &SDFParamValue::Boolean(true)), Err("unknown parameter".to_string()));
+            let fields = |s: &SDFSample| (s.distance, s.color, s.metallic, s.roughness, s.occlusion);
+            let batch = sdf.sample_batch(&points, false).iter().map(fields).collect::<Vec<_>>();
+            let single = points.iter().map(|p| fields(&sdf.sample(*p, false))).collect::<Vec<_>>();
+            assert_eq!(batch, single, "{:?} batched and single samples differ", backend);
+            let parameters = sdf.parameters();
+            assert!(matches!(&parameters[1].value, SDFParamValue::String(value) if *value == label),
+                    "{:?} did not store the label: {:?}", backend, parameters);
+            assert!(sdf.take_errors().is_empty(), "{:?} recorded errors", backend);
+
+            // The output is captured asynchronously by some backends
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !console::lines().iter().any(|line| line.source == "sdf" && line.text == label) {
+                assert!(Instant::now() < deadline, "{:?} did not print the label to the console", backend);
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            (sdf.bounding_box(), batch, format!("{:?}", parameters[0]), parameters[1].name.clone())
+        }).collect::<Vec<_>>();
+        let (bounding_box, batch, _, _) = &results[0];
+        assert_eq!(*bounding_box, [Vector3::new(-2.0, -2.0, -2.0), Vector3::new(2.0, 2.0, 2.0)]);
+        assert_eq!(batch[0], (1.25f32.sqrt() - 1.5, Vector3::new(1.0, 1.0, 0.0), 0.5, 0.25, 1.0));
+        for (backend, result) in WasmBackend::ALL.iter().zip(&results).skip(1) {
+            assert_eq!(result, &results[0], "{:?} differs from {:?}", backend, WasmBackend::ALL[0]);
+        }
+    }
+
+    /// Checks that a parameter set through the SDF reaches each of its instances.
+    fn assert_parameters_reach_every_instance<I: ModuleInstance>(sdf: &WasmSDF<I>) {
+        assert_eq!(sdf.set_parameter(0, &SDFParamValue::Float(2.0)), Ok(()));
+        let p = Vector3::new(3.0, 0.0, 0.0);
+        let mut distances = vec![sdf.sample_with(&mut sdf.pool.primary(), p, true).distance];
+        sdf.pool.for_each_secondary(|_, instance| distances.push(sdf.sample_with(instance, p, true).distance));
+        assert_eq!(distances, vec![1.0; sdf.pool.size()]);
+        assert!(sdf.take_errors().is_empty());
+    }
+
+    /// Checks that a batch split between the instances of the SDF matches sampling each point serially.
+    #[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+    fn assert_parallel_batches_match_serial_samples<I: ModuleInstance>(sdf: &WasmSDF<I>) {
+        assert_eq!(sdf.set_parameter(0, &SDFParamValue::Float(0.5)), Ok(()));
+        let points = (0..4 * pool::MIN_POINTS_PER_INSTANCE)
+            .map(|i| Vector3::new(i as f32 * 0.001, 1.0, -0.5)).collect::<Vec<_>>();
+        let parallel = sdf.sample_batch(&points, true).iter().map(|s| s.distance).collect::<Vec<_>>();
+        let mut primary = sdf.pool.primary();
+        let serial = points.iter().map(|p| sdf.sample_with(&mut primary, *p, true).distance).collect::<Vec<_>>();
+        assert_eq!(parallel, serial);
+    }
+
+    #[test]
+    #[cfg(feature = "wasmer_backend")]
+    fn test_wasmer_instances_stay_in_sync() {
+        let sdf = block_on(native::load_sdf_wasm(&wasm(RADIUS_SPHERE_WAT), &CliWasiEnv::default(), 3)).unwrap();
+        assert_eq!(sdf.pool.size(), 3);
+        assert_parameters_reach_every_instance(&sdf);
+        #[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+        assert_parallel_batches_match_serial_samples(&sdf);
+    }
+
+    #[test]
+    #[cfg(feature = "wasmi_backend")]
+    fn test_wasmi_instances_stay_in_sync() {
+        let sdf = interpreter::load_sdf_wasm(&wasm(RADIUS_SPHERE_WAT), &CliWasiEnv::default(), 3).unwrap();
+        assert_eq!(sdf.pool.size(), 3);
+        assert_parameters_reach_every_instance(&sdf);
+        #[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+        assert_parallel_batches_match_serial_samples(&sdf);
+    }
+}
@@ -0,0 +1,271 @@
+//! An on-disk cache of compiled WebAssembly modules, so that reloading a module that was already
+//! compiled (like reopening a model or switching between a few versions) is nearly instant.
+//!
+//! Modules are stored with wasmer's serialization, keyed by a hash of the WebAssembly bytes and the
+//! engine that compiled them, and the least recently used ones are evicted to limit the disk usage.
+//! Deserializing is only safe for files written by this cache, so the cache directory must not be
+//! writable by untrusted users.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use wasmer::{Module, Store};
+
+/// The extension of the files of the cache, to avoid touching any other file in the directory.
+const CACHE_FILE_EXTENSION: &str = "wasmu";
+
+/// The default maximum size of the cache (see [`CliModuleCache::module_cache_max_mib`]).
+const DEFAULT_MAX_SIZE_MIB: u64 = 512;
+
+/// The configuration of the cache shared by all the loaders (see [`CliModuleCache::apply`]).
+static SETTINGS: RwLock<Settings> = RwLock::new(Settings { enabled: true, max_size_bytes: DEFAULT_MAX_SIZE_MIB * 1024 * 1024 });
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    enabled: bool,
+    max_size_bytes: u64,
+}
+
+/// Options for the cache of compiled WebAssembly modules, shared by all commands.
+#[derive(clap::Parser, Debug, Clone, PartialEq, Eq)]
+pub struct CliModuleCache {
+    /// Disable the on-disk cache of compiled WebAssembly modules, compiling them from scratch on every load.
+    #[clap(long, global = true)]
+    pub no_module_cache: bool,
+    /// Remove all the compiled WebAssembly modules from the on-disk cache before starting.
+    #[clap(long, global = true)]
+    pub clear_module_cache: bool,
+    /// The maximum size (in MiB) of the on-disk cache of compiled WebAssembly modules. The least
+    /// recently used modules are removed when it is exceeded.
+    #[clap(long, global = true, default_value = "512")]
+    pub module_cache_max_mib: u64,
+}
+
+impl CliModuleCache {
+    /// Configures the cache for all the following loads.
+    pub fn apply(&self) {
+        *SETTINGS.write().unwrap() = Settings {
+            enabled: !self.no_module_cache,
+            max_size_bytes: self.module_cache_max_mib.saturating_mul(1024 * 1024),
+        };
+        if self.clear_module_cache {
+            match ModuleCache::default_dir() {
+                Some(dir) => match clear(&dir) {
+                    Ok(removed) => tracing::info!("Removed {} compiled modules from the cache at {:?}", removed, dir),
+                    Err(err) => tracing::error!("Failed to clear the module cache at {:?}: {}", dir, err),
+                },
+                None => tracing::warn!("No cache directory available on this platform, nothing to clear"),
+            }
+        }
+    }
+}
+
+/// The on-disk cache of compiled modules.
+#[derive(Debug, Clone)]
+pub struct ModuleCache {
+    dir: PathBuf,
+    max_size_bytes: u64,
+}
+
+impl ModuleCache {
+    /// The cache configured for this process, if enabled and available on this platform.
+    pub fn global() -> Option<Self> {
+        let settings = *SETTINGS.read().unwrap();
+        if !settings.enabled {
+            return None;
+        }
+        Some(Self { dir: Self::default_dir()?, max_size_bytes: settings.max_size_bytes })
+    }
+
+    /// The directory of the cache for the current user, if the platform has one.
+    pub fn default_dir() -> Option<PathBuf> {
+        Some(dirs::cache_dir()?.join(env!("CARGO_PKG_NAME")).join("modules"))
+    }
+
+    /// The key of the given module: a hash of its bytes and of everything that affects the
+    /// compiled code (the version of wasmer, its configuration, the target platform and the given
+    /// compilation key, like the middlewares and tunables set up by the host).
+    fn key(store: &Store, wasm_bytes: &[u8], compilation_key: &str) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(wasm_bytes);
+        hasher.update(compilation_key.as_bytes());
+        hasher.update(wasmer::VERSION.as_bytes());
+        hasher.update(store.engine().deterministic_id().as_bytes());
+        hasher.update(std::env::consts::ARCH.as_bytes());
+        hasher.update(std::env::consts::OS.as_bytes());
+        hasher.finalize().to_hex().to_string()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension(CACHE_FILE_EXTENSION)
+    }
+
+    /// Loads the compiled module from the cache, removing the entry if it can't be used.
+    fn load(&self, store: &Store, key: &str) -> Option<Module> {
+        let path = self.path(key);
+        let bytes = fs::read(&path).ok()?;
+        // SAFETY: the file was written by Self::save for this exact engine (see Self::key)
+        match unsafe { Module::deserialize(store, bytes) } {
+            Ok(module) => {
+                // Mark it as recently used, for eviction
+                if let Err(err) = fs::File::options().write(true).open(&path).and_then(|f| f.set_modified(SystemTime::now())) {
+                    tracing::warn!("Failed to update the access time of cached module {:?}: {}", path, err);
+                }
+                Some(module)
+            }
+            Err(err) => {
+                tracing::warn!("Removing invalid cached module {:?}: {}", path, err);
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    /// Saves the compiled module to the cache and evicts old entries if needed.
+    fn save(&self, key: &str, module: &Module) -> anyhow::Result<()> {
+        let bytes = module.serialize()?;
+        fs::create_dir_all(&self.dir)?;
+        // Write to a temporary file first, so that other processes never read a partial module
+        let tmp_path = self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+        fs::write(&tmp_path, &bytes)?;
+        fs::rename(&tmp_path, self.path(key))?;
+        evict(&self.dir, self.max_size_bytes)?;
+        Ok(())
+    }
+}
+
+/// Compiles the given module, or loads it from the cache if it was already compiled with the same
+/// compilation key. Errors of the cache itself are only logged, as the module can always be compiled.
+pub fn compile_module(store: &Store, wasm_bytes: &[u8], compilation_key: &str) -> anyhow::Result<Module> {
+    let cache = match ModuleCache::global() {
+        Some(cache) => cache,
+        None => return Ok(Module::new(store, wasm_bytes)?),
+    };
+    let key = ModuleCache::key(store, wasm_bytes, compilation_key);
+    if let Some(module) = cache.load(store, &key) {
+        tracing::info!(key=key, "Loaded compiled module from the cache");
+        return Ok(module);
+    }
+    let module = Module::new(store, wasm_bytes)?;
+    match cache.save(&key, &module) {
+        Ok(()) => tracing::info!(key=key, "Saved compiled module to the cache"),
+        Err(err) => tracing::warn!(key=key, "Failed to save compiled module to the cache: {}", err),
+    }
+    Ok(module)
+}
+
+/// Lists the files of the cache in the given directory, with their size and last use.
+fn cache_files(dir: &Path) -> std::io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == CACHE_FILE_EXTENSION) {
+            let metadata = fs::metadata(&path)?;
+            files.push((path, metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)));
+        }
+    }
+    Ok(files)
+}
+
+/// Removes the least recently used files of the cache until it fits in the given size.
+fn evict(dir: &Path, max_size_bytes: u64) -> std::io::Result<()> {
+    let mut files = cache_files(dir)?;
+    let mut total_size: u64 = files.iter().map(|(_, size, _)| size).sum();
+    files.sort_by_key(|(_, _, modified)| *modified);
+    for (path, size, _) in files {
+        if total_size <= max_size_bytes {
+            break;
+        }
+        tracing::info!("Evicting cached module {:?} ({} bytes)", path, size);
+        fs::remove_file(&path)?;
+        total_size -= size;
+    }
+    Ok(())
+}
+
+/// Removes all the files of the cache, returning how many were removed.
+fn clear(dir: &Path) -> std::io::Result<usize> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let files = cache_files(dir)?;
+    for (path, _, _) in &files {
+        fs::remove_file(path)?;
+    }
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    use cgmath::Vector3;
+    use wasmer::{Module, Store};
+
+    use crate::sdf::SDFSurface;
+    use crate::sdf::wasm::backend::WasmSDF;
+    use crate::sdf::wasm::cache::{cache_files, clear, evict, ModuleCache};
+    use crate::sdf::wasm::fixtures::{block_on, wasm, SPHERE_WAT};
+    use crate::sdf::wasm::limits::ExecutionLimits;
+    use crate::sdf::wasm::native;
+    use crate::sdf::wasm::wasi::CliWasiEnv;
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let dir = std::env::temp_dir().join(format!("sdf-viewer-test-module-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        for (i, name) in ["old", "newest", "new"].iter().enumerate() {
+            let path = dir.join(name).with_extension("wasmu");
+            fs::write(&path, [0u8; 100]).unwrap();
+            let age = match i { 0 => 30, 1 => 10, _ => 20 };
+            fs::File::options().write(true).open(&path).unwrap().set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        fs::write(dir.join("unrelated.txt"), [0u8; 1000]).unwrap();
+
+        evict(&dir, 250).unwrap();
+        let mut remaining: Vec<_> = cache_files(&dir).unwrap().into_iter()
+            .map(|(path, _, _)| path.file_stem().unwrap().to_string_lossy().to_string()).collect();
+        remaining.sort();
+        assert_eq!(remaining, vec!["new", "newest"]);
+
+        assert_eq!(clear(&dir).unwrap(), 2);
+        assert!(dir.join("unrelated.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_compiled_module() {
+        let dir = std::env::temp_dir().join(format!("sdf-viewer-test-module-cache-reload-{}", std::process::id()));
+        let cache = ModuleCache { dir: dir.clone(), max_size_bytes: u64::MAX };
+        let store = Store::default();
+        let wasm_bytes = wasm(SPHERE_WAT);
+        let key = ModuleCache::key(&store, &wasm_bytes, "metered=false");
+        assert!(cache.load(&store, &key).is_none());
+        cache.save(&key, &Module::new(&store, &wasm_bytes).unwrap()).unwrap();
+
+        // The module compiled for other limits is not reused
+        let other_key = ModuleCache::key(&store, &wasm_bytes, "metered=true");
+        assert_ne!(other_key, key);
+        assert!(cache.load(&store, &other_key).is_none());
+
+        // The module deserialized from the disk works like the compiled one
+        let module = cache.load(&store, &key).expect("the compiled module should be cached");
+        let instance = block_on(native::instantiate(store, &module, "sdf", &CliWasiEnv::default())).unwrap();
+        let sdf = WasmSDF::new(vec![instance], None, ExecutionLimits::UNLIMITED).unwrap();
+        assert_eq!(sdf.sample(Vector3::new(2.0, 0.0, 0.0), false).distance, 1.0);
+
+        // Corrupted entries are removed, so that the module is compiled again
+        let store = Store::default();
+        let serialized = fs::read(cache.path(&key)).unwrap();
+        for corrupted in [&serialized[..serialized.len() / 2], b"not a compiled module"] {
+            fs::write(cache.path(&key), corrupted).unwrap();
+            assert!(cache.load(&store, &key).is_none());
+            assert!(!cache.path(&key).exists());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+//! A ring buffer with the output of the SDF modules (their WASI stdout and stderr) and of our own
+//! logs, so that it can be displayed to the user (like in the console of the app).
+
+use std::collections::VecDeque;
+#[cfg(all(feature = "wasmer_backend", not(target_arch = "wasm32")))]
+use std::io::{BufRead, BufReader};
+use std::sync::Mutex;
+
+#[cfg(feature = "wasmer_backend")]
+use wasmer_wasix::virtual_fs::VirtualFile;
+#[cfg(all(feature = "wasmer_backend", not(target_arch = "wasm32")))]
+use wasmer_wasix::virtual_fs::Pipe;
+#[cfg(all(feature = "wasmer_backend", target_arch = "wasm32"))]
+use wasmer_wasix::virtual_fs::{DualWriteFile, NullFile};
+
+/// The maximum number of lines kept in the console, dropping the oldest ones.
+const MAX_LINES: usize = 10_000;
+
+static CONSOLE: Mutex<Console> = Mutex::new(Console { lines: VecDeque::new(), generation: 0, forward_to_stderr: false });
+
+struct Console {
+    lines: VecDeque<ConsoleLine>,
+    /// Increased on every change, so that readers only copy the lines when needed.
+    generation: u64,
+    /// Whether the output of the modules is also written to our stderr.
+    forward_to_stderr: bool,
+}
+
+/// Where a line of the console comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleStream {
+    /// The standard output of a module.
+    Stdout,
+    /// The standard error of a module.
+    Stderr,
+    /// Our own logs, with their level.
+    Log(tracing::Level),
+}
+
+/// A line of the console.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsoleLine {
+    /// The name of the module (or the target of the log) that wrote the line, displayed as its prefix.
+    pub source: String,
+    pub stream: ConsoleStream,
+    pub text: String,
+}
+
+/// Appends a line to the console.
+pub fn push(line: ConsoleLine) {
+    let mut console = CONSOLE.lock().unwrap();
+    if console.forward_to_stderr && !matches!(line.stream, ConsoleStream::Log(_)) {
+        eprintln!("[{}] {}", line.source, line.text);
+    }
+    if console.lines.len() >= MAX_LINES {
+        console.lines.pop_front();
+    }
+    console.lines.push_back(line);
+    console.generation += 1;
+}
+
+/// A counter that changes whenever the lines of the console change.
+pub fn generation() -> u64 {
+    CONSOLE.lock().unwrap().generation
+}
+
+/// A copy of all the lines of the console, oldest first.
+pub fn lines() -> Vec<ConsoleLine> {
+    CONSOLE.lock().unwrap().lines.iter().cloned().collect()
+}
+
+/// Removes all the lines of the console.
+pub fn clear() {
+    let mut console = CONSOLE.lock().unwrap();
+    console.lines.clear();
+    console.generation += 1;
+}
+
+/// Also writes the output of the modules to our stderr (like for the CLI, where there is no console).
+pub fn set_forward_to_stderr(forward: bool) {
+    CONSOLE.lock().unwrap().forward_to_stderr = forward;
+}
+
+/// Creates the stdout and stderr files for a WASI module, whose lines are appended to the console
+/// with the given module name as their source until the module is dropped.
+///
+/// On native, each file is a pipe read by its own thread. Threads can't be spawned on the web, so the
+/// writes of the module are appended right away instead (see [`ConsoleWriter`]).
+#[cfg(feature = "wasmer_backend")]
+pub fn capture_wasi_output(module_name: &str) -> (Box<dyn VirtualFile + Send + Sync>, Box<dyn VirtualFile + Send + Sync>) {
+    #[cfg(not(target_arch = "wasm32"))]
+    let capture = |stream: ConsoleStream| -> Box<dyn VirtualFile + Send + Sync> {
+        let (module_side, reader_side) = Pipe::channel();
+        let source = module_name.to_string();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(reader_side);
+            let mut line = vec![];
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) => break, // The module was dropped
+                    Ok(_) => push(ConsoleLine {
+                        source: source.clone(),
+                        stream,
+                        text: String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string(),
+                    }),
+                    Err(err) => {
+                        tracing::warn!("Stopped reading the {:?} of module {}: {}", stream, source, err);
+                        break;
+                    }
+                }
+            }
+        });
+        Box::new(module_side)
+    };
+    #[cfg(target_arch = "wasm32")]
+    let capture = |stream: ConsoleStream| -> Box<dyn VirtualFile + Send + Sync> {
+        let mut writer = ConsoleWriter::new(module_name, stream);
+        Box::new(DualWriteFile::new(Box::<NullFile>::default(), move |bytes| writer.write(bytes)))
+    };
+    (capture(ConsoleStream::Stdout), capture(ConsoleStream::Stderr))
+}
+
+/// Appends the bytes written to a stream of a module to the console, line by line (for hosts that
+/// implement the WASI output themselves, instead of using [`capture_wasi_output`]).
+#[derive(Debug)]
+pub struct ConsoleWriter {
+    source: String,
+    stream: ConsoleStream,
+    /// The last line, until it is completed or the writer is dropped.
+    partial_line: Vec<u8>,
+}
+
+impl ConsoleWriter {
+    pub fn new(module_name: &str, stream: ConsoleStream) -> Self {
+        Self { source: module_name.to_string(), stream, partial_line: vec![] }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.partial_line.extend_from_slice(bytes);
+        while let Some(end) = self.partial_line.iter().position(|b| *b == b'\n') {
+            let line = self.partial_line.drain(..=end).collect::<Vec<_>>();
+            self.push_line(&line);
+        }
+    }
+
+    fn push_line(&self, line: &[u8]) {
+        push(ConsoleLine {
+            source: self.source.clone(),
+            stream: self.stream,
+            text: String::from_utf8_lossy(line).trim_end_matches(['\r', '\n']).to_string(),
+        });
+    }
+}
+
+impl Drop for ConsoleWriter {
+    fn drop(&mut self) {
+        if !self.partial_line.is_empty() {
+            self.push_line(&self.partial_line);
+        }
+    }
+}
+
+/// A layer for the global tracing subscriber that appends our own logs to the console.
+#[cfg(feature = "tracing-subscriber")]
+pub struct ConsoleLayer;
+
+#[cfg(feature = "tracing-subscriber")]
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for ConsoleLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        /// Formats the message followed by the other fields, like the default formatter.
+        #[derive(Default)]
+        struct Visitor {
+            message: String,
+            fields: String,
+        }
+        impl tracing::field::Visit for Visitor {
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                use std::fmt::Write;
+                if field.name() == "message" {
+                    let _ = write!(self.message, "{value:?}");
+                } else {
+                    let _ = write!(self.fields, " {}={:?}", field.name(), value);
+                }
+            }
+        }
+        let mut visitor = Visitor::default();
+        event.record(&mut visitor);
+        push(ConsoleLine {
+            source: event.metadata().target().to_string(),
+            stream: ConsoleStream::Log(*event.metadata().level()),
+            text: visitor.message + &visitor.fields,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sdf::wasm::console::{ConsoleStream, ConsoleWriter, lines};
+
+    /// The lines of the console written by the given source, as the console is shared by all tests.
+    fn lines_of(source: &str) -> Vec<(ConsoleStream, String)> {
+        lines().into_iter().filter(|line| line.source == source).map(|line| (line.stream, line.text)).collect()
+    }
+
+    #[test]
+    fn test_console_writer_lines() {
+        let mut stdout = ConsoleWriter::new("test_console_writer", ConsoleStream::Stdout);
+        let mut stderr = ConsoleWriter::new("test_console_writer", ConsoleStream::Stderr);
+        stdout.write(b"first\r\nsec");
+        stderr.write(b"error\n");
+        stdout.write(b"ond\nunfinished");
+        assert_eq!(lines_of("test_console_writer"), vec![
+            (ConsoleStream::Stdout, "first".to_string()),
+            (ConsoleStream::Stderr, "error".to_string()),
+            (ConsoleStream::Stdout, "second".to_string()),
+        ]);
+        drop(stdout); // Flushes the last line
+        assert_eq!(lines_of("test_console_writer").last(), Some(&(ConsoleStream::Stdout, "unfinished".to_string())));
+    }
+
+    #[cfg(feature = "wasmer_backend")]
+    #[test]
+    fn test_capture_wasi_output() {
+        use tokio::io::AsyncWriteExt;
+        let (mut stdout, mut stderr) = crate::sdf::wasm::console::capture_wasi_output("test_capture");
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+            stdout.write_all(b"hello\nworld\n").await.unwrap();
+            stderr.write_all(b"oops\n").await.unwrap();
+        });
+        drop((stdout, stderr)); // Like when the module is dropped
+        let expected = [(ConsoleStream::Stdout, "hello"), (ConsoleStream::Stdout, "world"), (ConsoleStream::Stderr, "oops")];
+        // The lines are read by other threads on native
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while lines_of("test_capture").len() < expected.len() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let mut captured = lines_of("test_capture");
+        captured.sort_by_key(|(stream, _)| *stream == ConsoleStream::Stderr); // The streams are read concurrently
+        assert_eq!(captured, expected.map(|(stream, text)| (stream, text.to_string())));
+    }
+}
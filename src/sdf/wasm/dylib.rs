@@ -0,0 +1,494 @@
+//! Loads a native shared library (`.so`, `.dylib` or `.dll`) that exports the same API as the
+//! WebAssembly modules, like a `cdylib` crate that uses [`export_sdf!`](crate::export_sdf).
+//! It runs at full native speed and skips compiling the WebAssembly module, which is useful while
+//! developing heavy SDFs, but the library is fully trusted: it runs inside the viewer process, without
+//! any sandboxing.
+//!
+//! The reference implementation ([`crate::sdf::ffi`]) keeps the SDFs in thread-local storage, so all
+//! the calls to a library are performed by a single thread that owns it. Each call is a round trip
+//! to that thread (a few microseconds), which dominates the cost of simple samples: prefer
+//! [`SDFSurface::sample_batch`], which samples all the points in a single round trip (even if the
+//! library does not export `sample_batch`).
+
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::{Arc, mpsc, Mutex};
+
+use cgmath::{Vector3, Zero};
+use libloading::Library;
+
+use crate::sdf::defaults::{children_default_impl, glsl_default_impl, name_default_impl, normal_batch_from_samples, normal_default_impl, parameters_default_impl, sample_batch_default_impl, set_parameter_default_impl};
+use crate::sdf::wasm::guest::{bounding_box_from_memory, changed_from_memory, check_abi_version, check_capabilities, check_param_value_supported, children_from_memory, GuestMemory, parameters_from_memory, pointer_length_size, read_vector3, sample_from_memory, samples_from_memory, set_parameter_result_from_memory, set_parameter_result_size, BOUNDING_BOX_SIZE, CHANGED_SIZE};
+use crate::sdf::{abi, SDFParam, SDFParamValue, SDFSample, SDFSurface};
+
+/// The file extensions of native shared libraries.
+pub const NATIVE_LIB_EXTENSIONS: [&str; 3] = ["so", "dylib", "dll"];
+
+/// Returns the local path of the native shared library referenced by the given path or URL, if it is one.
+pub fn native_lib_path(path_or_url: &str) -> Option<&str> {
+    let path = path_or_url.strip_prefix("file://").unwrap_or(path_or_url);
+    let extension = Path::new(path).extension()?.to_str()?;
+    if path.contains("://") || !NATIVE_LIB_EXTENSIONS.contains(&extension) {
+        return None;
+    }
+    Some(path)
+}
+
+/// Loads the native shared library at the given path, which is then queried to satisfy the SDF trait.
+pub fn load_sdf_native_lib(path: &str) -> anyhow::Result<Box<dyn SDFSurface + Send + Sync>> {
+    let path = path.to_string();
+    let (jobs_sender, jobs_receiver) = mpsc::channel::<Job>();
+    let (loaded_sender, loaded_receiver) = mpsc::sync_channel(1);
+    std::thread::Builder::new().name("sdf-native-lib".to_string()).spawn(move || {
+        let lib = match NativeLib::load(&path) {
+            Ok(lib) => lib,
+            Err(err) => {
+                let _ = loaded_sender.send(Err(err));
+                return;
+            }
+        };
+        let _ = loaded_sender.send(Ok(lib.abi_version));
+        for job in jobs_receiver { // Until all the SDFs of this library are dropped
+            job(&lib);
+        }
+        // Never unload the library, as it may have registered thread-local destructors that run after this
+        std::mem::forget(lib);
+    })?;
+    let abi_version = loaded_receiver.recv()??;
+    Ok(Box::new(NativeLibSDF {
+        sdf_id: 0, // This must always be the ID of the root SDF (as specified by the docs)
+        thread: Arc::new(NativeLibThread { jobs: Mutex::new(jobs_sender) }),
+        abi_version,
+    }))
+}
+
+type FnInit = unsafe extern "C" fn();
+type FnU32 = unsafe extern "C" fn() -> u32;
+type FnFree = unsafe extern "C" fn(*mut u8);
+type FnById = unsafe extern "C" fn(u32) -> *mut u8;
+type FnSample = unsafe extern "C" fn(u32, Vector3<f32>, bool) -> *mut u8;
+type FnSampleBatch = unsafe extern "C" fn(u32, *const Vector3<f32>, usize, bool) -> *mut u8;
+type FnSetParameter = unsafe extern "C" fn(u32, u32, SDFParamValueArg) -> *mut u8;
+type FnNormal = unsafe extern "C" fn(u32, Vector3<f32>, f32) -> *mut u8;
+
+/// The `SDFParamValueC` passed by value to `set_parameter`: the enum index and the largest payload.
+#[repr(C)]
+struct SDFParamValueArg {
+    enum_type: u32,
+    payload: SDFParamValuePayload,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union SDFParamValuePayload {
+    words: [u32; 3],
+    pointer_length: [usize; 2],
+}
+
+/// The loaded library and its exports, which must only be used from the thread that loaded it.
+struct NativeLib {
+    abi_version: u32,
+    f_bounding_box: FnById,
+    f_bounding_box_free: Option<FnFree>,
+    f_sample: FnSample,
+    f_sample_free: Option<FnFree>,
+    f_sample_batch: Option<FnSampleBatch>,
+    f_sample_batch_free: Option<FnFree>,
+    f_children: Option<FnById>,
+    f_children_free: Option<FnFree>,
+    f_name: Option<FnById>,
+    f_name_free: Option<FnFree>,
+    f_parameters: Option<FnById>,
+    f_parameters_free: Option<FnFree>,
+    f_set_parameter: Option<FnSetParameter>,
+    f_set_parameter_free: Option<FnFree>,
+    f_changed: Option<FnById>,
+    f_changed_free: Option<FnFree>,
+    f_glsl: Option<FnById>,
+    f_glsl_free: Option<FnFree>,
+    f_normal: Option<FnNormal>,
+    f_normal_free: Option<FnFree>,
+    /// Keeps the exports above loaded.
+    _library: Library,
+}
+
+impl NativeLib {
+    fn load(path: &str) -> anyhow::Result<Self> {
+        // SAFETY: Loading the library runs its initialization code, which is trusted like the rest of it.
+        let library = unsafe { Library::new(path) }?;
+        let has_export = |name: &str| unsafe { export::<FnInit>(&library, name) }.is_some();
+
+        // Make sure that we can talk to the library before using it.
+        let abi_version = unsafe { export::<FnU32>(&library, "sdf_abi_version").map(|f| f()) }
+            .unwrap_or(abi::SDF_ABI_LEGACY_VERSION);
+        check_abi_version(abi_version)?;
+        let capabilities = unsafe { export::<FnU32>(&library, "capabilities").map(|f| f()) };
+        if let Some(capabilities) = capabilities {
+            check_capabilities(capabilities, has_export)?;
+        }
+        let has_capability = |capability: u32| capabilities.map(|c| c & capability != 0).unwrap_or(true);
+        tracing::info!("Negotiated SDF ABI version {} with capabilities {:?} for native library {}", abi_version, capabilities, path);
+
+        for required in ["bounding_box", "sample"] {
+            if !has_export(required) {
+                anyhow::bail!("Incompatible SDF module: the native library does not export `{}`", required);
+            }
+        }
+
+        // SAFETY: The exports have the types specified by the API.
+        unsafe {
+            // Call init() to initialize the library (optional).
+            if let Some(init) = export::<FnInit>(&library, "init") {
+                init();
+            }
+
+            Ok(Self {
+                abi_version,
+                f_bounding_box: export(&library, "bounding_box").unwrap(),
+                f_bounding_box_free: export(&library, "bounding_box_free"),
+                f_sample: export(&library, "sample").unwrap(),
+                f_sample_free: export(&library, "sample_free"),
+                f_sample_batch: export(&library, "sample_batch").filter(|_| has_capability(abi::CAPABILITY_SAMPLE_BATCH)),
+                f_sample_batch_free: export(&library, "sample_batch_free").filter(|_| has_capability(abi::CAPABILITY_SAMPLE_BATCH)),
+                f_children: export(&library, "children"),
+                f_children_free: export(&library, "children_free"),
+                f_name: export(&library, "name"),
+                f_name_free: export(&library, "name_free"),
+                f_parameters: export(&library, "parameters"),
+                f_parameters_free: export(&library, "parameters_free"),
+                f_set_parameter: export(&library, "set_parameter"),
+                f_set_parameter_free: export(&library, "set_parameter_free"),
+                f_changed: export(&library, "changed"),
+                f_changed_free: export(&library, "changed_free"),
+                f_glsl: export(&library, "glsl").filter(|_| has_capability(abi::CAPABILITY_GLSL)),
+                f_glsl_free: export(&library, "glsl_free").filter(|_| has_capability(abi::CAPABILITY_GLSL)),
+                f_normal: export(&library, "normal"),
+                f_normal_free: export(&library, "normal_free"),
+                _library: library,
+            })
+        }
+    }
+
+    /// Samples a single point with the `sample` export.
+    fn sample(&self, sdf_id: u32, p: Vector3<f32>, distance_only: bool) -> Option<SDFSample> {
+        let pointer = unsafe { (self.f_sample)(sdf_id, p, distance_only) };
+        self.returned("sample", pointer, self.f_sample_free,
+                      |p| sample_from_memory(&ProcessMemory.read_bytes(p, size_of::<SDFSample>())))
+    }
+
+    /// Decodes the value pointed to by the return value of an export and frees it afterwards.
+    fn returned<R>(&self, name: &str, pointer: *mut u8, free: Option<FnFree>, decode: impl FnOnce(u64) -> R) -> Option<R> {
+        if pointer.is_null() {
+            tracing::error!("`{}` of native SDF library returned a null pointer", name);
+            return None;
+        }
+        let res = decode(pointer as u64);
+        if let Some(free) = free {
+            unsafe { free(pointer) }; // Free the memory, now that we copied it
+        }
+        Some(res)
+    }
+}
+
+/// Gets the function exported by the library with the given name.
+///
+/// SAFETY: The type must match the signature of the export.
+unsafe fn export<T: Copy>(library: &Library, name: &str) -> Option<T> {
+    library.get::<T>(name.as_bytes()).ok().map(|symbol| *symbol)
+}
+
+/// The memory of the viewer process, which is shared with the library.
+struct ProcessMemory;
+
+impl GuestMemory for ProcessMemory {
+    fn pointer_size(&self) -> usize {
+        size_of::<usize>()
+    }
+
+    fn read_bytes(&self, pointer: u64, length: usize) -> Vec<u8> {
+        if length == 0 {
+            return vec![];
+        }
+        if pointer == 0 {
+            debug_assert!(false, "Null pointer access with length {length}");
+            tracing::error!("Null pointer access with length {} in native SDF library", length);
+            return vec![0u8; length];
+        }
+        // SAFETY: The library is trusted to return valid pointers and lengths, as specified by the API.
+        unsafe { std::slice::from_raw_parts(pointer as usize as *const u8, length) }.to_vec()
+    }
+}
+
+type Job = Box<dyn FnOnce(&NativeLib) + Send>;
+
+/// Sends the calls to the thread that owns the library, which stops when this is dropped.
+#[derive(Debug)]
+struct NativeLibThread {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl NativeLibThread {
+    /// Runs the given function on the thread that owns the library and waits for its result.
+    fn call<R: Send + 'static>(&self, f: impl FnOnce(&NativeLib) -> R + Send + 'static) -> Option<R> {
+        let (result_sender, result_receiver) = mpsc::sync_channel(1);
+        let job: Job = Box::new(move |lib| {
+            let _ = result_sender.send(f(lib));
+        });
+        if self.jobs.lock().unwrap().send(job).is_err() {
+            tracing::error!("The thread of the native SDF library has stopped");
+            return None;
+        }
+        result_receiver.recv().ok()
+    }
+}
+
+#[derive(Debug, Clone)] // Note: cloning is cheap, as the library is shared
+pub struct NativeLibSDF {
+    sdf_id: u32,
+    thread: Arc<NativeLibThread>,
+    /// The negotiated version of the API, which defines the memory layout of some values.
+    abi_version: u32,
+}
+
+impl SDFSurface for NativeLibSDF {
+    fn bounding_box(&self) -> [Vector3<f32>; 2] {
+        let sdf_id = self.sdf_id;
+        self.thread.call(move |lib| {
+            let pointer = unsafe { (lib.f_bounding_box)(sdf_id) };
+            lib.returned("bounding_box", pointer, lib.f_bounding_box_free,
+                         |p| bounding_box_from_memory(&ProcessMemory.read_bytes(p, BOUNDING_BOX_SIZE)))
+        }).flatten().unwrap_or([Vector3::zero(), Vector3::new(1.0, 1.0, 1.0)])
+    }
+
+    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+        let sdf_id = self.sdf_id;
+        self.thread.call(move |lib| lib.sample(sdf_id, p, distance_only))
+            .flatten().unwrap_or_else(|| SDFSample::new(1.0, Vector3::zero()))
+    }
+
+    fn sample_batch(&self, points: &[Vector3<f32>], distance_only: bool) -> Vec<SDFSample> {
+        if points.is_empty() {
+            return vec![];
+        }
+        let sdf_id = self.sdf_id;
+        let points_vec = points.to_vec();
+        let res = self.thread.call(move |lib| {
+            let f_sample_batch = match lib.f_sample_batch {
+                Some(f_sample_batch) => f_sample_batch,
+                // Still a single round trip to the thread of the library
+                None => return points_vec.iter().map(|p| lib.sample(sdf_id, *p, distance_only)).collect(),
+            };
+            // The library only borrows the points during the call
+            let points_len_bytes = points_vec.len() * size_of::<Vector3<f32>>();
+            let pointer = unsafe { f_sample_batch(sdf_id, points_vec.as_ptr(), points_len_bytes, distance_only) };
+            lib.returned("sample_batch", pointer, lib.f_sample_batch_free, |p| {
+                let pointer_length = ProcessMemory.read_bytes(p, pointer_length_size(ProcessMemory.pointer_size()));
+                samples_from_memory(&ProcessMemory.read_pointer_length(&pointer_length), points_vec.len())
+            })
+        }).flatten();
+        res.unwrap_or_else(|| sample_batch_default_impl(self, points, distance_only))
+    }
+
+    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
+        let sdf_id = self.sdf_id;
+        let children_ids = self.thread.call(move |lib| {
+            let pointer = unsafe { (lib.f_children?)(sdf_id) };
+            lib.returned("children", pointer, lib.f_children_free, |p| {
+                let pointer_length = ProcessMemory.read_bytes(p, pointer_length_size(ProcessMemory.pointer_size()));
+                children_from_memory(&ProcessMemory.read_pointer_length(&pointer_length))
+            })
+        }).flatten();
+        let children_ids = match children_ids {
+            Some(children_ids) => children_ids,
+            None => return children_default_impl(self),
+        };
+        children_ids.into_iter()
+            .filter_map(|child_sdf_id| {
+                if child_sdf_id == self.sdf_id {
+                    tracing::error!("Children of native SDF with ID {} include itself! Skipping, but this should be fixed.", self.sdf_id);
+                    return None;
+                }
+                Some(Box::new(Self { sdf_id: child_sdf_id, ..self.clone() }) as Box<dyn SDFSurface>)
+            }).collect()
+    }
+
+    fn id(&self) -> u32 {
+        self.sdf_id // Already known!
+    }
+
+    fn name(&self) -> String {
+        let sdf_id = self.sdf_id;
+        self.thread.call(move |lib| {
+            let pointer = unsafe { (lib.f_name?)(sdf_id) };
+            lib.returned("name", pointer, lib.f_name_free,
+                         |p| ProcessMemory.read_string(&ProcessMemory.read_bytes(p, pointer_length_size(ProcessMemory.pointer_size()))))
+        }).flatten().unwrap_or_else(|| name_default_impl(self))
+    }
+
+    fn parameters(&self) -> Vec<SDFParam> {
+        let sdf_id = self.sdf_id;
+        let abi_version = self.abi_version;
+        self.thread.call(move |lib| {
+            let pointer = unsafe { (lib.f_parameters?)(sdf_id) };
+            lib.returned("parameters", pointer, lib.f_parameters_free, |p| {
+                let pointer_length = ProcessMemory.read_bytes(p, pointer_length_size(ProcessMemory.pointer_size()));
+                parameters_from_memory(&ProcessMemory, &ProcessMemory.read_pointer_length(&pointer_length), abi_version)
+            })
+        }).flatten().unwrap_or_else(|| parameters_default_impl(self))
+    }
+
+    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+        check_param_value_supported(param_value, self.abi_version)?;
+        let sdf_id = self.sdf_id;
+        let param_value_clone = param_value.clone();
+        self.thread.call(move |lib| {
+            let f_set_parameter = lib.f_set_parameter?;
+            let mut payload = SDFParamValuePayload { pointer_length: [0; 2] };
+            let enum_type = match &param_value_clone {
+                SDFParamValue::Boolean(value) => {
+                    payload.words = [u32::from(*value), 0, 0];
+                    0
+                }
+                SDFParamValue::Int(value) => {
+                    payload.words = [u32::from_le_bytes(value.to_le_bytes()), 0, 0];
+                    1
+                }
+                SDFParamValue::Float(value) => {
+                    payload.words = [value.to_bits(), 0, 0];
+                    2
+                }
+                SDFParamValue::String(value) => {
+                    // The library only borrows the string during the call
+                    payload.pointer_length = [value.as_ptr() as usize, value.len()];
+                    3
+                }
+                SDFParamValue::Vec3(value) => {
+                    payload.words = [value.x.to_bits(), value.y.to_bits(), value.z.to_bits()];
+                    4
+                }
+                SDFParamValue::Color(value) => {
+                    payload.words = [value.x.to_bits(), value.y.to_bits(), value.z.to_bits()];
+                    5
+                }
+                SDFParamValue::Angle(value) => {
+                    payload.words = [value.to_bits(), 0, 0];
+                    6
+                }
+            };
+            let pointer = unsafe { f_set_parameter(sdf_id, param_id, SDFParamValueArg { enum_type, payload }) };
+            lib.returned("set_parameter", pointer, lib.f_set_parameter_free, |p| {
+                let mem_bytes = ProcessMemory.read_bytes(p, set_parameter_result_size(ProcessMemory.pointer_size()));
+                set_parameter_result_from_memory(&ProcessMemory, &mem_bytes)
+            })
+        }).flatten().unwrap_or_else(|| set_parameter_default_impl(self, param_id, param_value))
+    }
+
+    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
+        let sdf_id = self.sdf_id;
+        self.thread.call(move |lib| {
+            let pointer = unsafe { (lib.f_changed?)(sdf_id) };
+            lib.returned("changed", pointer, lib.f_changed_free,
+                         |p| changed_from_memory(&ProcessMemory.read_bytes(p, CHANGED_SIZE)))
+        }).flatten().flatten()
+    }
+
+    fn glsl(&self) -> String {
+        let sdf_id = self.sdf_id;
+        self.thread.call(move |lib| {
+            let pointer = unsafe { (lib.f_glsl?)(sdf_id) };
+            lib.returned("glsl", pointer, lib.f_glsl_free,
+                         |p| ProcessMemory.read_string(&ProcessMemory.read_bytes(p, pointer_length_size(ProcessMemory.pointer_size()))))
+        }).flatten().unwrap_or_else(|| glsl_default_impl(self))
+    }
+
+    fn normal(&self, p: Vector3<f32>, eps: Option<f32>) -> Vector3<f32> {
+        let sdf_id = self.sdf_id;
+        self.thread.call(move |lib| {
+            let pointer = unsafe { (lib.f_normal?)(sdf_id, p, eps.unwrap_or(-1.0)) };
+            lib.returned("normal", pointer, lib.f_normal_free,
+                         |p| read_vector3(&ProcessMemory.read_bytes(p, 3 * size_of::<f32>()), 0))
+        }).flatten().unwrap_or_else(|| normal_default_impl(self, p, eps))
+    }
+
+    fn normal_batch(&self, points: &[Vector3<f32>], eps: Option<f32>) -> Vec<Vector3<f32>> {
+        let sdf_id = self.sdf_id;
+        let points_vec = points.to_vec();
+        let res = self.thread.call(move |lib| {
+            let f_normal = lib.f_normal?;
+            // Still a single round trip to the thread of the library
+            points_vec.iter().map(|p| {
+                let pointer = unsafe { f_normal(sdf_id, *p, eps.unwrap_or(-1.0)) };
+                lib.returned("normal", pointer, lib.f_normal_free,
+                             |p| read_vector3(&ProcessMemory.read_bytes(p, 3 * size_of::<f32>()), 0))
+            }).collect::<Option<Vec<_>>>()
+        }).flatten();
+        res.unwrap_or_else(|| normal_batch_from_samples(self, points, eps))
+    }
+}
+
+#[cfg(all(test, feature = "sdfdemo"))]
+mod tests {
+    use std::process::Command;
+
+    use cgmath::Vector3;
+
+    use crate::sdf::demo::SDFDemo;
+    use crate::sdf::wasm::dylib::{load_sdf_native_lib, native_lib_path};
+    use crate::sdf::{SDFParamValue, SDFSample, SDFSurface};
+
+    /// Builds the demo SDF as a native library, exporting only its API like a `cdylib` crate would.
+    fn build_demo_lib() -> String {
+        let target_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/target/dylib-tests");
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--lib", "--no-default-features", "--features", "sdfdemoffi", "--target-dir", target_dir])
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .status().unwrap();
+        assert!(status.success(), "Failed to build the demo SDF as a native library");
+        format!("{}/debug/{}sdf_viewer{}", target_dir, std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX)
+    }
+
+    fn fields(s: &SDFSample) -> (f32, Vector3<f32>, f32, f32, f32) {
+        (s.distance, s.color, s.metallic, s.roughness, s.occlusion)
+    }
+
+    #[test]
+    fn test_native_lib_path() {
+        assert_eq!(native_lib_path("file:///a/b.so"), Some("/a/b.so"));
+        assert_eq!(native_lib_path("b.dll"), Some("b.dll"));
+        assert_eq!(native_lib_path("http://a/b.so"), None);
+        assert_eq!(native_lib_path("b.wasm"), None);
+    }
+
+    #[test]
+    #[ignore = "builds the demo SDF with a nested cargo build, run it with --ignored"]
+    fn test_native_lib_matches_demo() {
+        let lib = load_sdf_native_lib(&build_demo_lib()).unwrap();
+        let demo = SDFDemo::default();
+        assert_eq!(lib.bounding_box(), demo.bounding_box());
+        assert_eq!(lib.name(), demo.name());
+        let points = (0..100).map(|i| Vector3::new(i as f32 * 0.02 - 1.0, 0.2, -0.3)).collect::<Vec<_>>();
+        let demo_samples = points.iter().map(|p| fields(&demo.sample(*p, false))).collect::<Vec<_>>();
+        assert_eq!(lib.sample_batch(&points, false).iter().map(fields).collect::<Vec<_>>(), demo_samples);
+        assert_eq!(points.iter().map(|p| fields(&lib.sample(*p, false))).collect::<Vec<_>>(), demo_samples);
+        let children = |sdf: &dyn SDFSurface| format!("{:?}", sdf.children().iter()
+            .map(|child| (child.id(), child.name(), child.parameters())).collect::<Vec<_>>());
+        assert_eq!(children(&*lib), children(&demo));
+
+        // Parameters of every kind reach the library, which owns its own copy of the demo
+        assert_eq!(format!("{:?}", lib.parameters()), format!("{:?}", demo.parameters()));
+        for param in demo.parameters() {
+            let value = match &param.value {
+                SDFParamValue::Boolean(value) => SDFParamValue::Boolean(!value),
+                SDFParamValue::Float(value) => SDFParamValue::Float(value * 0.5),
+                other => other.clone(),
+            };
+            assert_eq!(lib.set_parameter(param.id, &value), demo.set_parameter(param.id, &value));
+        }
+        assert!(lib.set_parameter(999, &SDFParamValue::Int(1)).is_err());
+        assert_eq!(format!("{:?}", lib.parameters()), format!("{:?}", demo.parameters()));
+        assert_eq!(lib.changed(), demo.changed());
+        assert_eq!(lib.glsl(), demo.glsl());
+        assert_eq!(lib.normal(points[0], None), demo.normal(points[0], None));
+        assert_eq!(fields(&lib.sample(points[0], false)), fields(&demo.sample(points[0], false)));
+    }
+}
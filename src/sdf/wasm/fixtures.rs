@@ -0,0 +1,56 @@
+//! WebAssembly modules and helpers shared by the tests that load modules, which usually run them
+//! on every backend of the build to make sure that all of them implement the API in the same way.
+
+use std::future::Future;
+use std::sync::OnceLock;
+
+use crate::sdf::SDFSurface;
+use crate::sdf::wasm::backend::WasmBackend;
+use crate::sdf::wasm::wasi::CliWasiEnv;
+
+/// A unit sphere implementing the original version of the API, which traps when sampled at x > 10.
+/// Tests add the exports they need with [`with_exports`].
+pub(crate) const SPHERE_WAT: &str = r#"(module
+    (memory (export "memory") 1)
+    (data (i32.const 0) "\00\00\80\bf\00\00\80\bf\00\00\80\bf\00\00\80\3f\00\00\80\3f\00\00\80\3f")
+    (func (export "bounding_box") (param i32) (result i32) (i32.const 0))
+    (func (export "sample") (param i32 f32 f32 f32 i32) (result i32)
+        (if (f32.gt (local.get 1) (f32.const 10)) (then unreachable))
+        (f32.store (i32.const 64) (f32.sub (f32.sqrt (f32.add (f32.add
+            (f32.mul (local.get 1) (local.get 1)) (f32.mul (local.get 2) (local.get 2)))
+            (f32.mul (local.get 3) (local.get 3)))) (f32.const 1)))
+        (f32.store (i32.const 68) (f32.const 1))
+        (i32.const 64)))"#;
+
+/// Adds the given fields (like exports or custom sections) to a module in the text format.
+pub(crate) fn with_exports(wat: &str, fields: &str) -> String {
+    wat.replacen("(module", &format!("(module {fields}"), 1)
+}
+
+/// Compiles a module in the text format, failing the test if it is not valid.
+pub(crate) fn wasm(wat: &str) -> Vec<u8> {
+    wat::parse_str(wat).unwrap_or_else(|err| panic!("invalid test module: {err}"))
+}
+
+/// Runs a future (like loading a module) to completion. All the tests share the same runtime, as
+/// some backends keep using it after loading (like for capturing the output).
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap()).block_on(future)
+}
+
+/// Loads a module with the given backend and WASI environment.
+pub(crate) fn load(backend: WasmBackend, wasm_bytes: &[u8], wasi: &CliWasiEnv) -> anyhow::Result<Box<dyn SDFSurface + Send + Sync>> {
+    block_on(backend.load(wasm_bytes, wasi))
+}
+
+/// Loads a module in the text format with every backend (and the default WASI environment),
+/// failing the test if any of them can't load it.
+pub(crate) fn load_with_each_backend(wat: &str) -> Vec<(WasmBackend, Box<dyn SDFSurface + Send + Sync>)> {
+    let wasm_bytes = wasm(wat);
+    WasmBackend::ALL.iter().map(|backend| {
+        let sdf = load(*backend, &wasm_bytes, &CliWasiEnv::default())
+            .unwrap_or_else(|err| panic!("{:?} failed to load the module: {:#}", backend, err));
+        (*backend, sdf)
+    }).collect()
+}
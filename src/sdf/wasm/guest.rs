@@ -0,0 +1,457 @@
+//! Logic shared by the hosts of the API (see [`crate::sdf::wasm`]), no matter if the guest is a
+//! WebAssembly module ([`super::native`]) or a native library ([`super::dylib`]): checking the
+//! version and capabilities, and decoding the values returned by each function.
+//!
+//! All values are little-endian and laid out as `#[repr(C)]` structures, so the only difference
+//! between guests is the size of their pointers and lengths (`usize`).
+
+use std::mem::size_of;
+use std::ops::RangeInclusive;
+
+use cgmath::Vector3;
+
+use crate::sdf::{abi, SDFParam, SDFParamKind, SDFParamValue, SDFSample};
+
+/// Read access to the memory of the guest.
+pub(crate) trait GuestMemory {
+    /// The size in bytes of the pointers and lengths of the guest (4 for wasm32).
+    fn pointer_size(&self) -> usize;
+
+    /// Copies the given number of bytes starting at the given address.
+    fn read_bytes(&self, pointer: u64, length: usize) -> Vec<u8>;
+
+    /// Copies the memory referenced by the pointer and length (in bytes) at the start of the given bytes.
+    fn read_pointer_length(&self, mem_bytes: &[u8]) -> Vec<u8> {
+        let pointer_size = self.pointer_size();
+        let pointer = read_usize(mem_bytes, 0, pointer_size);
+        let length_bytes = read_usize(mem_bytes, pointer_size, pointer_size);
+        self.read_bytes(pointer, length_bytes as usize)
+    }
+
+    /// Same as [`read_pointer_length`](#method.read_pointer_length), but decodes the memory as a string.
+    fn read_string(&self, mem_bytes: &[u8]) -> String {
+        String::from_utf8_lossy(self.read_pointer_length(mem_bytes).as_slice()).to_string()
+    }
+}
+
+/// Fails with a clear error if the guest uses a version of the API that this host can't talk to.
+pub(crate) fn check_abi_version(abi_version: u32) -> anyhow::Result<()> {
+    if abi_version > abi::SDF_ABI_VERSION {
+        anyhow::bail!("Incompatible SDF module: it uses ABI version {}, but this viewer only supports versions {} to {}. Please update the viewer.",
+            abi_version, abi::SDF_ABI_MIN_SUPPORTED_VERSION, abi::SDF_ABI_VERSION);
+    } else if abi_version < abi::SDF_ABI_MIN_SUPPORTED_VERSION {
+        anyhow::bail!("Incompatible SDF module: it uses ABI version {}, but this viewer only supports versions {} to {}. Please rebuild the module with a newer sdf-viewer.",
+            abi_version, abi::SDF_ABI_MIN_SUPPORTED_VERSION, abi::SDF_ABI_VERSION);
+    }
+    Ok(())
+}
+
+/// The types of the values passed to and returned by the exports of WebAssembly modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WasmType {
+    I32,
+    F32,
+    /// The types not used by the API, which are only reported in errors.
+    I64,
+    F64,
+    Other,
+}
+
+/// The signatures (parameters and results) of the exports that depend on the given version of the
+/// API, which WebAssembly hosts check to fail early instead of reading garbage.
+pub(crate) fn versioned_signatures(abi_version: u32) -> [(&'static str, Vec<WasmType>, Vec<WasmType>); 5] {
+    use WasmType::{F32, I32};
+    let payload_words = abi::param_value_payload_words(abi_version);
+    [
+        ("bounding_box", vec![I32], vec![I32]),
+        ("sample", vec![I32, F32, F32, F32, I32], vec![I32]),
+        ("sample_batch", vec![I32; 4], vec![I32]),
+        ("sample_batch_points", vec![I32], vec![I32]),
+        ("set_parameter", vec![I32; 3 + payload_words], vec![I32]),
+    ]
+}
+
+/// Fails with a clear error if the guest declares a capability without exporting all of its functions.
+pub(crate) fn check_capabilities(capabilities: u32, has_export: impl Fn(&str) -> bool) -> anyhow::Result<()> {
+    let mut known_capabilities = 0;
+    for (capability, capability_name, required_exports) in abi::CAPABILITIES {
+        known_capabilities |= capability;
+        if capabilities & capability == 0 {
+            continue;
+        }
+        if let Some(missing) = required_exports.iter().find(|name| !has_export(name)) {
+            anyhow::bail!("Incompatible SDF module: it declares the {} capability, but it does not export `{}`", capability_name, missing);
+        }
+    }
+    if capabilities & !known_capabilities != 0 {
+        tracing::warn!("Ignoring unknown capabilities of the SDF module: {:#b}", capabilities & !known_capabilities);
+    }
+    Ok(())
+}
+
+pub(crate) fn read_u32(mem_bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(mem_bytes[offset..offset + size_of::<u32>()].try_into().unwrap())
+}
+
+pub(crate) fn read_i32(mem_bytes: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(mem_bytes[offset..offset + size_of::<i32>()].try_into().unwrap())
+}
+
+pub(crate) fn read_f32(mem_bytes: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes(mem_bytes[offset..offset + size_of::<f32>()].try_into().unwrap())
+}
+
+/// Reads a pointer or length of the given size (4 or 8 bytes).
+pub(crate) fn read_usize(mem_bytes: &[u8], offset: usize, pointer_size: usize) -> u64 {
+    let mut bytes = [0u8; size_of::<u64>()];
+    bytes[..pointer_size].copy_from_slice(&mem_bytes[offset..offset + pointer_size]);
+    u64::from_le_bytes(bytes)
+}
+
+pub(crate) fn read_vector3(mem_bytes: &[u8], offset: usize) -> Vector3<f32> {
+    Vector3::new(
+        read_f32(mem_bytes, offset),
+        read_f32(mem_bytes, offset + size_of::<f32>()),
+        read_f32(mem_bytes, offset + 2 * size_of::<f32>()),
+    )
+}
+
+fn align_up(offset: usize, align: usize) -> usize {
+    offset.next_multiple_of(align)
+}
+
+/// The offset of the payload of the enums of the API, which is aligned to hold a pointer.
+fn enum_payload_offset(pointer_size: usize) -> usize {
+    align_up(size_of::<u32>(), pointer_size)
+}
+
+/// The size of the `PointerLength` structure used for strings and other arrays.
+pub(crate) fn pointer_length_size(pointer_size: usize) -> usize {
+    2 * pointer_size
+}
+
+/// The size of the flattened bounding box returned by `bounding_box`.
+pub(crate) const BOUNDING_BOX_SIZE: usize = size_of::<[Vector3<f32>; 2]>();
+
+/// Parses the flattened bounding box stored in the given memory.
+pub(crate) fn bounding_box_from_memory(mem_bytes: &[u8]) -> [Vector3<f32>; 2] {
+    [read_vector3(mem_bytes, 0), read_vector3(mem_bytes, 3 * size_of::<f32>())]
+}
+
+/// Parses the flattened [`SDFSample`] stored in the given memory.
+pub(crate) fn sample_from_memory(mem_bytes: &[u8]) -> SDFSample {
+    SDFSample {
+        distance: read_f32(mem_bytes, 0),
+        color: read_vector3(mem_bytes, size_of::<f32>()),
+        metallic: read_f32(mem_bytes, 4 * size_of::<f32>()),
+        roughness: read_f32(mem_bytes, 5 * size_of::<f32>()),
+        occlusion: read_f32(mem_bytes, 6 * size_of::<f32>()),
+    }
+}
+
+/// Parses the array of samples (already read from the `PointerLength` returned by `sample_batch`),
+/// making sure that there is one sample for each of the requested points.
+pub(crate) fn samples_from_memory(mem_bytes: &[u8], expected_len: usize) -> Vec<SDFSample> {
+    let mut res = mem_bytes.chunks_exact(size_of::<SDFSample>())
+        .map(sample_from_memory)
+        .collect::<Vec<_>>();
+    if res.len() != expected_len {
+        debug_assert!(false, "Expected {} samples, got {}", expected_len, res.len());
+        tracing::error!("Expected {} samples from SDF, got {}", expected_len, res.len());
+        res.resize_with(expected_len, || SDFSample::new(1.0, Vector3::new(0.0, 0.0, 0.0)));
+    }
+    res
+}
+
+/// Parses the array of IDs (already read from the `PointerLength` returned by `children`).
+pub(crate) fn children_from_memory(mem_bytes: &[u8]) -> Vec<u32> {
+    mem_bytes.chunks_exact(size_of::<u32>())
+        .map(|ch| u32::from_le_bytes(ch.try_into().unwrap()))
+        .collect()
+}
+
+/// The size of the `Result<(), PointerLength<u8>>` returned by `set_parameter`.
+pub(crate) fn set_parameter_result_size(pointer_size: usize) -> usize {
+    enum_payload_offset(pointer_size) + pointer_length_size(pointer_size)
+}
+
+/// Parses the `Result<(), PointerLength<u8>>` returned by `set_parameter`.
+pub(crate) fn set_parameter_result_from_memory(memory: &impl GuestMemory, mem_bytes: &[u8]) -> Result<(), String> {
+    let enum_result_kind = read_u32(mem_bytes, 0);
+    match enum_result_kind {
+        0 => Ok(()),
+        1 => Err(memory.read_string(&mem_bytes[enum_payload_offset(memory.pointer_size())..])),
+        _ => {
+            debug_assert!(false, "Unknown SDF set parameter result kind enum type {enum_result_kind}");
+            tracing::error!("Unknown SDF set parameter result kind enum type {}", enum_result_kind); // TODO: less logging in case of multiple errors
+            Err(String::from("Unknown SDF set parameter result kind enum type"))
+        }
+    }
+}
+
+/// The size of the `Option<[Vector3<f32>; 2]>` returned by `changed`.
+pub(crate) const CHANGED_SIZE: usize = size_of::<u32>() + BOUNDING_BOX_SIZE;
+
+/// Parses the `Option<[Vector3<f32>; 2]>` returned by `changed`.
+pub(crate) fn changed_from_memory(mem_bytes: &[u8]) -> Option<[Vector3<f32>; 2]> {
+    let enum_result_kind = read_u32(mem_bytes, 0);
+    match enum_result_kind {
+        0 => None,
+        1 => Some(bounding_box_from_memory(&mem_bytes[size_of::<u32>()..])),
+        _ => {
+            debug_assert!(false, "Unknown SDF changed result kind enum type {enum_result_kind}");
+            tracing::error!("Unknown SDF changed result kind enum type {}", enum_result_kind); // TODO: less logging in case of multiple errors
+            None
+        }
+    }
+}
+
+/// The offsets of the fields of `SDFParamC`, which depend on the size of pointers and the version of the API.
+struct ParamLayout {
+    name: usize,
+    kind: usize,
+    value: usize,
+    description: usize,
+    size: usize,
+    payload_offset: usize,
+}
+
+impl ParamLayout {
+    fn new(pointer_size: usize, abi_version: u32) -> Self {
+        let payload_offset = enum_payload_offset(pointer_size);
+        // The largest variants are ranges with steps, vectors and PointerLengths
+        let kind_payload_size = align_up((3 * size_of::<f32>()).max(pointer_length_size(pointer_size)), pointer_size);
+        let value_payload_words = abi::param_value_payload_words(abi_version);
+        let value_payload_size = align_up((value_payload_words * size_of::<u32>()).max(pointer_length_size(pointer_size)), pointer_size);
+        let name = align_up(size_of::<u32>() /* param ID */, pointer_size);
+        let kind = name + pointer_length_size(pointer_size);
+        let value = kind + payload_offset + kind_payload_size;
+        let description = value + payload_offset + value_payload_size;
+        let size = align_up(description + pointer_length_size(pointer_size), pointer_size);
+        Self { name, kind, value, description, size, payload_offset }
+    }
+}
+
+/// Parses the array of `SDFParamC` (already read from the `PointerLength` returned by `parameters`),
+/// reading the strings they reference from the guest memory.
+pub(crate) fn parameters_from_memory(memory: &impl GuestMemory, mem_bytes: &[u8], abi_version: u32) -> Vec<SDFParam> {
+    let layout = ParamLayout::new(memory.pointer_size(), abi_version);
+    let read_range_step = |mem: &[u8]| (
+        RangeInclusive::new(read_f32(mem, 0), read_f32(mem, size_of::<f32>())), read_f32(mem, 2 * size_of::<f32>()));
+    mem_bytes.chunks_exact(layout.size)
+        .filter_map(|sdf_param_mem| {
+            /* param ID */
+            let param_id = read_u32(sdf_param_mem, 0);
+            /* name pointer */
+            let name = memory.read_string(&sdf_param_mem[layout.name..]);
+            /* SDFParamKindC */
+            let sdf_param_kind_enum_type = read_u32(sdf_param_mem, layout.kind); /* enum index = u32 */
+            let kind_payload = &sdf_param_mem[layout.kind + layout.payload_offset..];
+            let sdf_param_kind = match sdf_param_kind_enum_type {
+                0 => SDFParamKind::Boolean,
+                1 => SDFParamKind::Int {
+                    range: RangeInclusive::new(read_i32(kind_payload, 0), read_i32(kind_payload, size_of::<i32>())),
+                    step: read_i32(kind_payload, 2 * size_of::<i32>()),
+                },
+                2 => {
+                    let (range, step) = read_range_step(kind_payload);
+                    SDFParamKind::Float { range, step }
+                }
+                3 => {
+                    let choices_mem_bytes = memory.read_pointer_length(kind_payload);
+                    let choices = choices_mem_bytes.chunks_exact(pointer_length_size(memory.pointer_size()))
+                        .map(|choice_mem_bytes| memory.read_string(choice_mem_bytes))
+                        .collect();
+                    SDFParamKind::String { choices }
+                }
+                4 => {
+                    let (range, step) = read_range_step(kind_payload);
+                    SDFParamKind::Vec3 { range, step }
+                }
+                5 => SDFParamKind::Color,
+                6 => {
+                    let (range, step) = read_range_step(kind_payload);
+                    SDFParamKind::Angle { range, step }
+                }
+                _ => {
+                    debug_assert!(false, "Unknown SDF param kind enum type {sdf_param_kind_enum_type}");
+                    tracing::error!("Unknown SDF param kind enum type {}", sdf_param_kind_enum_type); // TODO: less logging in case of multiple errors
+                    return None;
+                }
+            };
+            /* SDFParamValueC */
+            let sdf_param_value_enum_type = read_u32(sdf_param_mem, layout.value); /* enum index = u32 */
+            debug_assert_eq!(sdf_param_kind_enum_type, sdf_param_value_enum_type, "SDF param kind enum type {sdf_param_kind_enum_type} != SDF param value enum type {sdf_param_value_enum_type}");
+            let value_payload = &sdf_param_mem[layout.value + layout.payload_offset..];
+            let sdf_param_value = match sdf_param_value_enum_type {
+                0 => SDFParamValue::Boolean(value_payload[0] != 0) /* bool = u8 */,
+                1 => SDFParamValue::Int(read_i32(value_payload, 0)),
+                2 => SDFParamValue::Float(read_f32(value_payload, 0)),
+                3 => SDFParamValue::String(memory.read_string(value_payload)),
+                4 => SDFParamValue::Vec3(read_vector3(value_payload, 0)),
+                5 => SDFParamValue::Color(read_vector3(value_payload, 0)),
+                6 => SDFParamValue::Angle(read_f32(value_payload, 0)),
+                _ => {
+                    debug_assert!(false, "Unknown SDF param value enum type {sdf_param_value_enum_type}");
+                    tracing::error!("Unknown SDF param value enum type {}", sdf_param_value_enum_type); // TODO: less logging in case of multiple errors
+                    return None;
+                }
+            };
+            /* description */
+            let description = memory.read_string(&sdf_param_mem[layout.description..]);
+
+            Some(SDFParam { id: param_id, name, kind: sdf_param_kind, value: sdf_param_value, description })
+        })
+        .collect()
+}
+
+/// Fails if the kind of the given parameter value was added after the given version of the API.
+pub(crate) fn check_param_value_supported(param_value: &SDFParamValue, abi_version: u32) -> Result<(), String> {
+    match param_value {
+        // Vec3, Color and Angle were added in version 2
+        SDFParamValue::Vec3(_) | SDFParamValue::Color(_) | SDFParamValue::Angle(_) if abi_version < 2 =>
+            Err(format!("Parameter value {param_value:?} is not supported by the ABI version {abi_version} of the SDF module")),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::vec3;
+
+    use super::*;
+
+    #[test]
+    fn test_param_layout() {
+        // wasm32 (see the flattened SDFParamC of the WebAssembly API)
+        assert_eq!(ParamLayout::new(4, 1).size, 4 + 8 + (4 + 12) + (4 + 8) + 8);
+        assert_eq!(ParamLayout::new(4, 2).size, 4 + 8 + (4 + 12) + (4 + 12) + 8);
+        // 64-bit native libraries, where enums are aligned to hold pointers
+        let layout = ParamLayout::new(8, 2);
+        assert_eq!((layout.name, layout.kind, layout.value, layout.description, layout.size), (8, 24, 48, 72, 88));
+        assert_eq!(set_parameter_result_size(4), 12);
+        assert_eq!(set_parameter_result_size(8), 24);
+    }
+
+    #[test]
+    fn test_param_value_payload_is_versioned() {
+        let set_parameter_arity = |abi_version| versioned_signatures(abi_version).into_iter()
+            .find(|(name, _, _)| *name == "set_parameter").unwrap().1.len();
+        assert_eq!(set_parameter_arity(1), 5);
+        assert_eq!(set_parameter_arity(2), 6);
+        // The new kinds can't be encoded for legacy modules, which only have room for 2 words
+        assert!(check_param_value_supported(&SDFParamValue::Vec3(vec3(1.0, 2.0, 3.0)), 1).is_err());
+        assert!(check_param_value_supported(&SDFParamValue::Color(vec3(1.0, 2.0, 3.0)), 1).is_err());
+        assert!(check_param_value_supported(&SDFParamValue::Angle(1.0), 1).is_err());
+        assert!(check_param_value_supported(&SDFParamValue::Float(1.0), 1).is_ok());
+        assert!(check_param_value_supported(&SDFParamValue::Vec3(vec3(1.0, 2.0, 3.0)), 2).is_ok());
+    }
+
+    /// Decodes the values encoded by the reference implementation of the API, like a host would.
+    mod ffi {
+        use std::cell::Cell;
+
+        use cgmath::vec3;
+
+        use crate::sdf::ffi::{parameters, parameters_free, set_parameter, set_parameter_free, set_root_sdf, SDFParamValueC};
+        use crate::sdf::primitives::Primitive;
+        use crate::sdf::primitives::solids::Sphere;
+        use crate::sdf::SDFSurface;
+
+        use super::super::*;
+
+        /// Reads the memory of this process, to decode the values returned by the exports of [`crate::sdf::ffi`].
+        struct HostMemory;
+
+        impl GuestMemory for HostMemory {
+            fn pointer_size(&self) -> usize {
+                size_of::<usize>()
+            }
+
+            fn read_bytes(&self, pointer: u64, length: usize) -> Vec<u8> {
+                if length == 0 {
+                    return vec![];
+                }
+                // SAFETY: The pointers come from the values returned by the exports, which are still alive.
+                unsafe { std::slice::from_raw_parts(pointer as usize as *const u8, length) }.to_vec()
+            }
+        }
+
+        /// A sphere with one parameter of each kind that needs the larger payload of version 2 of the API.
+        struct Knobs {
+            offset: Cell<Vector3<f32>>,
+            color: Cell<Vector3<f32>>,
+            angle: Cell<f32>,
+        }
+
+        impl SDFSurface for Knobs {
+            fn bounding_box(&self) -> [Vector3<f32>; 2] {
+                Primitive::new(Sphere::new(1.0)).bounding_box()
+            }
+
+            fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
+                Primitive::new(Sphere::new(1.0)).sample(p, distance_only)
+            }
+
+            fn parameters(&self) -> Vec<SDFParam> {
+                let param = |id: u32, kind: SDFParamKind, value: SDFParamValue| SDFParam {
+                    id, name: format!("knob {id}"), kind, value, description: String::new(),
+                };
+                vec![
+                    param(0, SDFParamKind::Vec3 { range: -2.0..=2.0, step: 0.1 }, SDFParamValue::Vec3(self.offset.get())),
+                    param(1, SDFParamKind::Color, SDFParamValue::Color(self.color.get())),
+                    param(2, SDFParamKind::Angle { range: -3.0..=3.0, step: 0.01 }, SDFParamValue::Angle(self.angle.get())),
+                ]
+            }
+
+            fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
+                match (param_id, param_value) {
+                    (0, SDFParamValue::Vec3(value)) => self.offset.set(*value),
+                    (1, SDFParamValue::Color(value)) => self.color.set(*value),
+                    (2, SDFParamValue::Angle(value)) => self.angle.set(*value),
+                    _ => return Err(format!("unknown parameter {param_id}")),
+                }
+                Ok(())
+            }
+        }
+
+        /// Calls the `parameters` export and decodes its result like a host of the current version would.
+        fn exported_parameters() -> Vec<SDFParam> {
+            let result = parameters(0);
+            let pointer_length = HostMemory.read_bytes(&*result as *const _ as u64, pointer_length_size(HostMemory.pointer_size()));
+            let params = parameters_from_memory(&HostMemory, &HostMemory.read_pointer_length(&pointer_length), abi::SDF_ABI_VERSION);
+            parameters_free(result);
+            params
+        }
+
+        #[test]
+        fn test_vec3_color_angle_parameters_round_trip() {
+            set_root_sdf(Box::new(Knobs {
+                offset: Cell::new(vec3(0.0, 0.0, 0.0)),
+                color: Cell::new(vec3(1.0, 1.0, 1.0)),
+                angle: Cell::new(0.0),
+            }));
+            let params = exported_parameters();
+            assert_eq!(params.len(), 3);
+            assert_eq!(params[0].name, "knob 0");
+            assert!(matches!(&params[0].kind, SDFParamKind::Vec3 { range, step } if *range == (-2.0..=2.0) && *step == 0.1), "{:?}", params[0]);
+            assert!(matches!(params[1].kind, SDFParamKind::Color), "{:?}", params[1]);
+            assert!(matches!(&params[2].kind, SDFParamKind::Angle { range, step } if *range == (-3.0..=3.0) && *step == 0.01), "{:?}", params[2]);
+
+            for (param_id, value) in [(0, SDFParamValueC::Vec3(vec3(1.0, -2.0, 0.5))),
+                                      (1, SDFParamValueC::Color(vec3(0.25, 0.5, 0.75))),
+                                      (2, SDFParamValueC::Angle(1.5))] {
+                let result = set_parameter(0, param_id, value);
+                let result_bytes = HostMemory.read_bytes(&*result as *const _ as u64, set_parameter_result_size(HostMemory.pointer_size()));
+                assert_eq!(set_parameter_result_from_memory(&HostMemory, &result_bytes), Ok(()));
+                set_parameter_free(result);
+            }
+            let values = exported_parameters().into_iter().map(|param| format!("{:?}", param.value)).collect::<Vec<_>>();
+            assert_eq!(values, [
+                format!("{:?}", SDFParamValue::Vec3(vec3(1.0, -2.0, 0.5))),
+                format!("{:?}", SDFParamValue::Color(vec3(0.25, 0.5, 0.75))),
+                format!("{:?}", SDFParamValue::Angle(1.5)),
+            ]);
+        }
+    }
+}
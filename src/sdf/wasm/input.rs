@@ -0,0 +1,260 @@
+//! Decodes the inputs accepted as WebAssembly SDFs into the bytes of a module, detecting their
+//! format from their contents (not from their names):
+//!
+//! - WebAssembly binaries, as is.
+//! - WebAssembly text format (`.wat`), compiled in-process. Useful for small hand-written SDFs.
+//! - gzip or zstd compressed inputs, which are decompressed and decoded again.
+//! - Zip bundles with a module, an optional manifest (see [`BUNDLE_MANIFEST`]) and assets for WASI.
+//!
+//! [`data_url_bytes`] also decodes `data:` URLs, so that a whole model can be shared as a link.
+
+use std::borrow::Cow;
+#[cfg(feature = "input_formats")]
+use std::io::Read;
+
+use crate::sdf::wasm::wasi::CliWasiEnv;
+
+/// The first bytes of every WebAssembly binary.
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+/// The name of the manifest of zip bundles, with `key=value` lines (and `#` comments):
+///
+/// - `wasm`: the path of the module in the bundle, which may also be compressed or text.
+///   Defaults to the only `.wasm` or `.wat` file in the bundle.
+/// - `arg`: an argument for the module (repeat it for more), before the ones given by the user.
+/// - `env`: an environment variable for the module, as `KEY=VALUE` (repeat it for more), which the
+///   ones given by the user override.
+/// - `assets`: the directory of the bundle that the module can read at `/assets`. Defaults to `assets/`.
+pub const BUNDLE_MANIFEST: &str = "sdf-manifest.txt";
+
+/// The maximum size of a decompressed input, to survive decompression bombs.
+#[cfg(feature = "input_formats")]
+const MAX_DECODED_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// The maximum number of nested formats, like a gzipped module inside a zip bundle.
+#[cfg(feature = "input_formats")]
+const MAX_NESTING: usize = 4;
+
+/// Decodes the given input into the bytes of a WebAssembly module, returning the environment for it
+/// (which bundles extend with their own arguments and assets). Unknown formats are returned as is,
+/// so that the backend reports what is wrong with them.
+pub fn decode_sdf_input<'a>(bytes: &'a [u8], wasi: &CliWasiEnv) -> anyhow::Result<(Cow<'a, [u8]>, CliWasiEnv)> {
+    let mut wasi = wasi.clone();
+    let wasm_bytes = decode(Cow::Borrowed(bytes), &mut wasi, 0)?;
+    Ok((wasm_bytes, wasi))
+}
+
+#[cfg_attr(not(feature = "input_formats"), allow(unused_variables))]
+fn decode<'a>(bytes: Cow<'a, [u8]>, wasi: &mut CliWasiEnv, depth: usize) -> anyhow::Result<Cow<'a, [u8]>> {
+    if bytes.starts_with(WASM_MAGIC) {
+        return Ok(bytes);
+    }
+    #[cfg(feature = "input_formats")]
+    {
+        const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+        const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+        const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+        if depth >= MAX_NESTING {
+            anyhow::bail!("Too many nested formats in the SDF input (more than {})", MAX_NESTING);
+        }
+        let decoded = if bytes.starts_with(GZIP_MAGIC) {
+            tracing::info!("Decompressing gzip SDF input ({} bytes)", bytes.len());
+            read_limited(flate2::read::GzDecoder::new(bytes.as_ref()))?
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            tracing::info!("Decompressing zstd SDF input ({} bytes)", bytes.len());
+            read_limited(ruzstd::decoding::StreamingDecoder::new(bytes.as_ref())?)?
+        } else if bytes.starts_with(ZIP_MAGIC) {
+            read_bundle(&bytes, wasi)?
+        } else if looks_like_wat(&bytes) {
+            tracing::info!("Compiling WebAssembly text SDF input ({} bytes)", bytes.len());
+            return Ok(Cow::Owned(wat::parse_bytes(&bytes)?.into_owned()));
+        } else {
+            return Ok(bytes);
+        };
+        return decode(Cow::Owned(decoded), wasi, depth + 1);
+    }
+    #[allow(unreachable_code)] // Without other formats
+    Ok(bytes)
+}
+
+/// Reads the whole decompressed stream, failing if it is too large.
+#[cfg(feature = "input_formats")]
+fn read_limited(reader: impl Read) -> anyhow::Result<Vec<u8>> {
+    let mut decoded = vec![];
+    reader.take(MAX_DECODED_SIZE + 1).read_to_end(&mut decoded)?;
+    if decoded.len() as u64 > MAX_DECODED_SIZE {
+        anyhow::bail!("The decompressed SDF input is larger than {} MiB", MAX_DECODED_SIZE / 1024 / 1024);
+    }
+    Ok(decoded)
+}
+
+/// Whether the bytes look like the text format, which starts with a module or a comment.
+#[cfg(feature = "input_formats")]
+fn looks_like_wat(bytes: &[u8]) -> bool {
+    std::str::from_utf8(bytes).map(|text| text.trim_start().starts_with(['(', ';'])).unwrap_or(false)
+}
+
+/// Reads the module of a zip bundle, adding its arguments, environment variables and assets to the
+/// environment (see [`BUNDLE_MANIFEST`]).
+#[cfg(feature = "input_formats")]
+fn read_bundle(bytes: &[u8], wasi: &mut CliWasiEnv) -> anyhow::Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+    let read_file = |archive: &mut zip::ZipArchive<_>, name: &str| -> anyhow::Result<Vec<u8>> {
+        let file = archive.by_name(name).map_err(|err| anyhow::anyhow!("Can't read {:?} from the SDF bundle: {}", name, err))?;
+        read_limited(file)
+    };
+
+    let (mut wasm, mut args, mut envs, mut assets) = (None, vec![], vec![], "assets/".to_string());
+    match read_file(&mut archive, BUNDLE_MANIFEST) {
+        Ok(manifest) => for (number, line) in String::from_utf8_lossy(&manifest).lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("{}:{}: expected key=value, got {:?}", BUNDLE_MANIFEST, number + 1, line))?;
+            match key.trim() {
+                "wasm" => wasm = Some(value.to_string()),
+                "arg" => args.push(value.to_string()),
+                "env" => envs.push(value.split_once('=').map(|(k, v)| (k.to_string(), v.to_string()))
+                    .ok_or_else(|| anyhow::anyhow!("{}:{}: expected env=KEY=VALUE, got {:?}", BUNDLE_MANIFEST, number + 1, line))?),
+                "assets" => assets = match value.trim_end_matches('/') {
+                    "" => String::new(), // The whole bundle
+                    dir => format!("{dir}/"),
+                },
+                other => tracing::warn!("{}:{}: ignoring unknown key {:?}", BUNDLE_MANIFEST, number + 1, other),
+            }
+        },
+        Err(_) => tracing::info!("The SDF bundle has no {}, using the defaults", BUNDLE_MANIFEST),
+    }
+    let wasm = match wasm {
+        Some(wasm) => wasm,
+        None => {
+            // Compressed modules (like sdf.wasm.gz) also count
+            let mut modules = archive.file_names()
+                .filter(|name| name.rsplit('/').next().unwrap_or_default().split('.').skip(1).any(|ext| ext == "wasm" || ext == "wat"))
+                .collect::<Vec<_>>();
+            match modules.len() {
+                1 => modules.remove(0).to_string(),
+                0 => anyhow::bail!("The SDF bundle has no .wasm or .wat module"),
+                _ => anyhow::bail!("The SDF bundle has many modules ({:?}), choose one with wasm=... in its {}", modules, BUNDLE_MANIFEST),
+            }
+        }
+    };
+    tracing::info!("Loading module {:?} from the SDF bundle", wasm);
+    let wasm_bytes = read_file(&mut archive, &wasm)?;
+
+    // The manifest goes first, so that the user can extend (or override) it
+    wasi.wasi_args.splice(0..0, args);
+    wasi.wasi_envs.splice(0..0, envs);
+    let asset_names = archive.file_names()
+        .filter(|name| name.starts_with(&assets) && !name.ends_with('/'))
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    for name in asset_names {
+        if archive.by_name(&name)?.enclosed_name().is_none() {
+            tracing::warn!("Skipping SDF bundle asset with unsafe path {:?}", name);
+            continue;
+        }
+        let asset = read_file(&mut archive, &name)?;
+        wasi.wasi_bundled_assets.push((name[assets.len()..].to_string(), asset));
+    }
+    if !wasi.wasi_bundled_assets.is_empty() {
+        tracing::info!("The SDF bundle provides {} assets", wasi.wasi_bundled_assets.len());
+    }
+    Ok(wasm_bytes)
+}
+
+/// Decodes the payload of a `data:` URL (`data:[<media type>][;base64],<data>`), or returns None if
+/// it is not one. The media type is ignored, as the format is detected from the contents.
+pub fn data_url_bytes(url: &str) -> Option<anyhow::Result<Vec<u8>>> {
+    let rest = url.strip_prefix("data:")?;
+    Some((|| {
+        let (header, data) = rest.split_once(',').ok_or_else(|| anyhow::anyhow!("Invalid data URL: missing ','"))?;
+        let data = percent_decode(data)?;
+        if header.split(';').any(|param| param.eq_ignore_ascii_case("base64")) {
+            #[cfg(feature = "input_formats")]
+            {
+                use base64::Engine;
+                // Links may have been copied with line breaks or spaces
+                let data = data.into_iter().filter(|b| !b.is_ascii_whitespace()).collect::<Vec<_>>();
+                Ok(base64::engine::general_purpose::STANDARD.decode(data)?)
+            }
+            #[cfg(not(feature = "input_formats"))]
+            anyhow::bail!("Base64 data URLs are not supported by this build (enable the input_formats feature)")
+        } else {
+            Ok(data)
+        }
+    })())
+}
+
+/// Decodes the `%XX` escapes of an URL.
+fn percent_decode(data: &str) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut iter = data.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next().unwrap_or_default(), iter.next().unwrap_or_default()];
+            let hex = std::str::from_utf8(&hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| anyhow::anyhow!("Invalid data URL: bad escape sequence"))?;
+            bytes.push(hex);
+        } else {
+            bytes.push(b);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(all(test, feature = "input_formats"))]
+mod tests {
+    use std::io::Write;
+
+    use crate::sdf::wasm::input::{data_url_bytes, decode_sdf_input, BUNDLE_MANIFEST};
+    use crate::sdf::wasm::wasi::CliWasiEnv;
+
+    const WAT: &str = "(module (func (export \"init\")))";
+
+    fn decode(bytes: &[u8]) -> (Vec<u8>, CliWasiEnv) {
+        let (wasm_bytes, wasi) = decode_sdf_input(bytes, &CliWasiEnv::default()).unwrap();
+        (wasm_bytes.into_owned(), wasi)
+    }
+
+    #[test]
+    fn test_decode_formats() {
+        let wasm = wat::parse_str(WAT).unwrap();
+        assert_eq!(decode(&wasm).0, wasm);
+        assert_eq!(decode(WAT.as_bytes()).0, wasm);
+
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(WAT.as_bytes()).unwrap();
+        assert_eq!(decode(&gzip.finish().unwrap()).0, wasm);
+
+        let zstd = ruzstd::encoding::compress_to_vec(wasm.as_slice(), ruzstd::encoding::CompressionLevel::Fastest);
+        assert_eq!(decode(&zstd).0, wasm);
+
+        let url = format!("data:application/wasm;base64,{}", base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &wasm));
+        assert_eq!(data_url_bytes(&url).unwrap().unwrap(), wasm);
+        assert_eq!(data_url_bytes("data:,(module%20)").unwrap().unwrap(), b"(module )");
+        assert!(data_url_bytes("sdf.wasm").is_none());
+    }
+
+    #[test]
+    fn test_decode_bundle() {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file(BUNDLE_MANIFEST, options).unwrap();
+        zip.write_all(b"# A bundle\nwasm=model/sdf.wat\narg=--quality\nenv=SCALE=2\nassets=data\n").unwrap();
+        zip.start_file("model/sdf.wat", options).unwrap();
+        zip.write_all(WAT.as_bytes()).unwrap();
+        zip.start_file("data/lut/table.bin", options).unwrap();
+        zip.write_all(&[1, 2, 3]).unwrap();
+        let bundle = zip.finish().unwrap().into_inner();
+
+        let wasi = CliWasiEnv { wasi_args: vec!["--user".to_string()], ..CliWasiEnv::default() };
+        let (wasm_bytes, wasi) = decode_sdf_input(&bundle, &wasi).unwrap();
+        assert_eq!(wasm_bytes.as_ref(), wat::parse_str(WAT).unwrap().as_slice());
+        assert_eq!(wasi.wasi_args, vec!["--quality", "--user"]);
+        assert_eq!(wasi.wasi_envs, vec![("SCALE".to_string(), "2".to_string())]);
+        assert_eq!(wasi.wasi_bundled_assets, vec![("lut/table.bin".to_string(), vec![1, 2, 3])]);
+    }
+}
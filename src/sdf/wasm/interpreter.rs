@@ -0,0 +1,472 @@
+//! A portable WebAssembly interpreter (wasmi) that implements the same API as [`super::native`], for
+//! targets where wasmer is not available or too large (like `native_no_wasmer` builds). It is much
+//! slower, as it interprets the modules instead of compiling them, but it runs anywhere Rust does.
+//!
+//! Only the basic WASI functions are provided (arguments, environment variables, clocks, random
+//! numbers and writing to stdout/stderr), and the rest fail with `ENOSYS`, so modules can't read
+//! the assets directory with this backend.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use wasmi::core::{TrapCode, ValType};
+use wasmi::{Caller, Config, Engine, ExternType, Func, Linker, Memory, Module, ResourceLimiter, Store, StoreLimits, StoreLimitsBuilder, Val};
+
+use crate::sdf::error::SDFError;
+use crate::sdf::wasm::backend::{ModuleInstance, Trap, WasmSDF, WasmValue, EXPORTS};
+use crate::sdf::wasm::console::{ConsoleStream, ConsoleWriter};
+use crate::sdf::wasm::guest::{read_u32, GuestMemory, WasmType};
+use crate::sdf::wasm::limits::{self, ExecutionLimits};
+use crate::sdf::wasm::pool;
+use crate::sdf::wasm::util::{reinterpret_i32_as_u32, reinterpret_u32_as_i32};
+use crate::sdf::wasm::wasi::CliWasiEnv;
+use crate::sdf::{SDFMetadata, SDFSurface};
+
+/// The namespaces of the WASI functions provided to the modules.
+const WASI_MODULES: [&str; 2] = ["wasi_snapshot_preview1", "wasi_unstable"];
+
+/// The WASI functions implemented by this host (the rest fail with `ENOSYS`).
+const WASI_FUNCTIONS: [&str; 8] = ["args_get", "args_sizes_get", "environ_get", "environ_sizes_get",
+    "fd_write", "proc_exit", "random_get", "clock_time_get"];
+
+/// The WASI error codes returned by this host.
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_FAULT: i32 = 21;
+const ERRNO_INVAL: i32 = 28;
+const ERRNO_NOSYS: i32 = 52;
+
+/// Loads the given bytes as a WebAssembly module that is then interpreted to satisfy the SDF trait.
+pub fn load_sdf_wasm_send_sync(wasm_bytes: &[u8], wasi: &CliWasiEnv) -> anyhow::Result<Box<dyn SDFSurface + Send + Sync>> {
+    Ok(Box::new(load_sdf_wasm(wasm_bytes, wasi, pool::pool_size())?))
+}
+
+/// Loads the given bytes as a WebAssembly module, with the given number of instances (see [`pool`]).
+pub(crate) fn load_sdf_wasm(wasm_bytes: &[u8], wasi: &CliWasiEnv, pool_size: usize) -> anyhow::Result<WasmSDF<WasmiInstance>> {
+    #[cfg(feature = "execution_limits")]
+    let execution_limits = ExecutionLimits::current();
+    #[cfg(not(feature = "execution_limits"))]
+    let execution_limits = ExecutionLimits::UNLIMITED;
+    let mut config = Config::default();
+    config.consume_fuel(execution_limits.fuel_per_work.is_some());
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, wasm_bytes)?;
+
+    // Read the optional metadata embedded by export_sdf!
+    let metadata = custom_section(wasm_bytes, SDFMetadata::CUSTOM_SECTION).and_then(SDFMetadata::parse);
+    if let Some(metadata) = &metadata {
+        tracing::info!("Loading SDF exported by {} v{}", metadata.crate_name, metadata.crate_version);
+    }
+    let module_name = metadata.as_ref().map(|metadata| metadata.crate_name.as_str()).unwrap_or("sdf");
+    #[cfg(not(target_arch = "wasm32"))]
+    let has_assets = wasi.wasi_assets_dir.is_some() || !wasi.wasi_bundled_assets.is_empty();
+    #[cfg(target_arch = "wasm32")]
+    let has_assets = !wasi.wasi_assets.is_empty() || !wasi.wasi_bundled_assets.is_empty();
+    if has_assets {
+        tracing::warn!("The wasmi backend does not provide the WASI assets to SDF modules, use the wasmer backend instead");
+    }
+
+    // Instantiate the module as many times as configured, sharing the translated code (see `pool`)
+    let linker = new_linker(&engine, &module)?;
+    let mut instances = Vec::with_capacity(pool_size);
+    for index in 0..pool_size {
+        // The output of the secondary instances is told apart by its source in the console
+        let name = if index == 0 { module_name.to_string() } else { format!("{}#{}", module_name, index) };
+        instances.push(instantiate(&engine, &linker, &module, &name, wasi, &execution_limits)?);
+    }
+    if pool_size > 1 {
+        tracing::info!("Created {} instances of the SDF module for parallel sampling", pool_size);
+    }
+
+    WasmSDF::new(instances, metadata, execution_limits)
+}
+
+/// Finds the contents of the custom section with the given name, as wasmi does not keep them.
+fn custom_section<'a>(wasm_bytes: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    fn read_leb128(bytes: &[u8], offset: &mut usize) -> Option<usize> {
+        let mut value = 0usize;
+        for shift in (0..35).step_by(7) {
+            let byte = *bytes.get(*offset)?;
+            *offset += 1;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+    let mut offset = 8; // Magic number and version
+    while offset < wasm_bytes.len() {
+        let id = wasm_bytes[offset];
+        offset += 1;
+        let size = read_leb128(wasm_bytes, &mut offset)?;
+        let section = wasm_bytes.get(offset..offset.checked_add(size)?)?;
+        offset += size;
+        if id == 0 {
+            let mut name_offset = 0;
+            let name_len = read_leb128(section, &mut name_offset)?;
+            if section.get(name_offset..name_offset.checked_add(name_len)?)? == name.as_bytes() {
+                return Some(&section[name_offset + name_len..]);
+            }
+        }
+    }
+    None
+}
+
+/// The limits of a store, which also record when the memory limit refuses to grow a memory (see
+/// [`limits::take_memory_grow_refused`]).
+#[derive(Debug)]
+struct RecordingLimits(StoreLimits);
+
+impl ResourceLimiter for RecordingLimits {
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> Result<bool, wasmi::errors::MemoryError> {
+        let allowed = self.0.memory_growing(current, desired, maximum)?;
+        if !allowed {
+            limits::record_memory_grow_refused();
+        }
+        Ok(allowed)
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> Result<bool, wasmi::errors::TableError> {
+        self.0.table_growing(current, desired, maximum)
+    }
+
+    fn instances(&self) -> usize {
+        self.0.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.0.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.0.memories()
+    }
+}
+
+/// The data of the store of each instance: its limits and the state of its WASI functions.
+#[derive(Debug)]
+struct HostState {
+    limits: RecordingLimits,
+    /// The arguments, starting with the name of the module.
+    args: Vec<String>,
+    /// The environment variables, as KEY=VALUE.
+    envs: Vec<String>,
+    stdout: ConsoleWriter,
+    stderr: ConsoleWriter,
+    /// The state of the generator of `random_get`, which is not cryptographically secure (modules
+    /// only need it for things like the seeds of hash maps).
+    random_state: u64,
+}
+
+/// Creates the linker that provides the WASI functions imported by the module (see the module docs).
+/// Other imports are not provided, so the instantiation fails with a descriptive error.
+fn new_linker(engine: &Engine, module: &Module) -> anyhow::Result<Linker<HostState>> {
+    let mut linker = Linker::<HostState>::new(engine);
+    for wasi_module in WASI_MODULES {
+        linker.func_wrap(wasi_module, "args_sizes_get", |mut caller: Caller<'_, HostState>, count_ptr: i32, size_ptr: i32| {
+            with_memory(&mut caller, |memory, state| strings_sizes_get(memory, &state.args, count_ptr, size_ptr))
+        })?;
+        linker.func_wrap(wasi_module, "args_get", |mut caller: Caller<'_, HostState>, ptrs: i32, buf: i32| {
+            with_memory(&mut caller, |memory, state| strings_get(memory, &state.args, ptrs, buf))
+        })?;
+        linker.func_wrap(wasi_module, "environ_sizes_get", |mut caller: Caller<'_, HostState>, count_ptr: i32, size_ptr: i32| {
+            with_memory(&mut caller, |memory, state| strings_sizes_get(memory, &state.envs, count_ptr, size_ptr))
+        })?;
+        linker.func_wrap(wasi_module, "environ_get", |mut caller: Caller<'_, HostState>, ptrs: i32, buf: i32| {
+            with_memory(&mut caller, |memory, state| strings_get(memory, &state.envs, ptrs, buf))
+        })?;
+        linker.func_wrap(wasi_module, "fd_write", |mut caller: Caller<'_, HostState>, fd: i32, iovs: i32, iovs_len: i32, written_ptr: i32| {
+            with_memory(&mut caller, |memory, state| {
+                let writer = match fd {
+                    1 => &mut state.stdout,
+                    2 => &mut state.stderr,
+                    _ => return ERRNO_BADF, // There are no files
+                };
+                // Check all the buffers before writing any of them
+                let mut buffers = vec![];
+                let mut written = 0u32;
+                for i in 0..reinterpret_i32_as_u32(iovs_len) as usize {
+                    let iov_pointer = i.checked_mul(8).and_then(|offset| offset.checked_add(reinterpret_i32_as_u32(iovs) as usize));
+                    let iov = match iov_pointer.and_then(|iov_pointer| guest_slice(memory, iov_pointer, 8)) {
+                        Some(iov) => iov,
+                        None => return ERRNO_FAULT,
+                    };
+                    let (buf, buf_len) = (read_u32(iov, 0), read_u32(iov, 4));
+                    if guest_slice(memory, buf as usize, buf_len as usize).is_none() {
+                        return ERRNO_FAULT;
+                    }
+                    // The buffers may overlap, so their total length is only bounded by the module
+                    written = match written.checked_add(buf_len) {
+                        Some(written) => written,
+                        None => return ERRNO_INVAL,
+                    };
+                    buffers.push((buf as usize, buf_len as usize));
+                }
+                for (buf, buf_len) in buffers {
+                    writer.write(&memory[buf..buf + buf_len]);
+                }
+                write_guest(memory, written_ptr, &written.to_le_bytes())
+            })
+        })?;
+        linker.func_wrap(wasi_module, "proc_exit", |code: i32| -> Result<(), wasmi::Error> {
+            Err(wasmi::Error::i32_exit(code))
+        })?;
+        linker.func_wrap(wasi_module, "random_get", |mut caller: Caller<'_, HostState>, buf: i32, buf_len: i32| {
+            with_memory(&mut caller, |memory, state| {
+                if guest_slice(memory, reinterpret_i32_as_u32(buf) as usize, reinterpret_i32_as_u32(buf_len) as usize).is_none() {
+                    return ERRNO_FAULT; // Before generating the bytes, as the length comes from the module
+                }
+                let bytes = (0..reinterpret_i32_as_u32(buf_len)).map(|_| {
+                    // xorshift64*
+                    state.random_state ^= state.random_state >> 12;
+                    state.random_state ^= state.random_state << 25;
+                    state.random_state ^= state.random_state >> 27;
+                    (state.random_state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+                }).collect::<Vec<_>>();
+                write_guest(memory, buf, &bytes)
+            })
+        })?;
+        linker.func_wrap(wasi_module, "clock_time_get", |mut caller: Caller<'_, HostState>, clock_id: i32, _precision: i64, time_ptr: i32| {
+            // The realtime clock is also used as the monotonic clock, which is good enough for timing
+            let now = match clock_id {
+                0 | 1 => now_nanos(),
+                _ => None,
+            };
+            match now {
+                Some(now) => with_memory(&mut caller, |memory, _| write_guest(memory, time_ptr, &now.to_le_bytes())),
+                None => ERRNO_INVAL,
+            }
+        })?;
+    }
+
+    // The rest of the WASI functions imported by the module are stubs
+    for import in module.imports() {
+        if !WASI_MODULES.contains(&import.module()) || WASI_FUNCTIONS.contains(&import.name()) {
+            continue;
+        }
+        if let ExternType::Func(ty) = import.ty() {
+            let name = import.name().to_string();
+            let returns_errno = ty.results() == [ValType::I32];
+            linker.func_new(import.module(), import.name(), ty.clone(), move |_caller, _params, results| {
+                if !returns_errno {
+                    return Err(wasmi::Error::new(format!("the WASI function `{name}` is not supported by the wasmi backend")));
+                }
+                tracing::debug!("The SDF module called the unsupported WASI function `{}`", name);
+                results[0] = Val::I32(ERRNO_NOSYS);
+                Ok(())
+            })?;
+        }
+    }
+    Ok(linker)
+}
+
+/// Runs the given WASI function with the memory of the calling module, failing if it has none.
+fn with_memory(caller: &mut Caller<'_, HostState>, f: impl FnOnce(&mut [u8], &mut HostState) -> i32) -> i32 {
+    match caller.get_export("memory").and_then(|export| export.into_memory()) {
+        Some(memory) => {
+            let (memory, state) = memory.data_and_store_mut(caller);
+            f(memory, state)
+        }
+        None => ERRNO_FAULT,
+    }
+}
+
+/// The given range of the memory of the module, if it is in bounds.
+fn guest_slice(memory: &[u8], pointer: usize, length: usize) -> Option<&[u8]> {
+    memory.get(pointer..pointer.checked_add(length)?)
+}
+
+/// Writes to the memory of the module from a WASI function, returning its error code.
+fn write_guest(memory: &mut [u8], pointer: i32, bytes: &[u8]) -> i32 {
+    let pointer = reinterpret_i32_as_u32(pointer) as usize;
+    match pointer.checked_add(bytes.len()).and_then(|end| memory.get_mut(pointer..end)) {
+        Some(target) => {
+            target.copy_from_slice(bytes);
+            ERRNO_SUCCESS
+        }
+        None => ERRNO_FAULT,
+    }
+}
+
+/// Implements `args_sizes_get` and `environ_sizes_get`.
+fn strings_sizes_get(memory: &mut [u8], strings: &[String], count_ptr: i32, size_ptr: i32) -> i32 {
+    let size = strings.iter().map(|s| s.len() + 1).sum::<usize>() as u32; // NUL-terminated
+    match write_guest(memory, count_ptr, &(strings.len() as u32).to_le_bytes()) {
+        ERRNO_SUCCESS => write_guest(memory, size_ptr, &size.to_le_bytes()),
+        errno => errno,
+    }
+}
+
+/// Implements `args_get` and `environ_get`.
+fn strings_get(memory: &mut [u8], strings: &[String], ptrs: i32, buf: i32) -> i32 {
+    let mut offset = reinterpret_i32_as_u32(buf);
+    for (i, string) in strings.iter().enumerate() {
+        let ptr = reinterpret_i32_as_u32(ptrs).wrapping_add(4 * i as u32);
+        let mut bytes = string.as_bytes().to_vec();
+        bytes.push(0);
+        match write_guest(memory, reinterpret_u32_as_i32(ptr), &offset.to_le_bytes()) {
+            ERRNO_SUCCESS => {}
+            errno => return errno,
+        }
+        match write_guest(memory, reinterpret_u32_as_i32(offset), &bytes) {
+            ERRNO_SUCCESS => {}
+            errno => return errno,
+        }
+        offset = offset.wrapping_add(bytes.len() as u32);
+    }
+    ERRNO_SUCCESS
+}
+
+/// The current time in nanoseconds since the UNIX epoch, if available on this platform.
+fn now_nanos() -> Option<u64> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok().map(|time| time.as_nanos() as u64)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        None // std has no clock on web
+    }
+}
+
+/// Creates a new instance of the module, which is initialized by [`WasmSDF::new`].
+fn instantiate(engine: &Engine, linker: &Linker<HostState>, module: &Module, name: &str, wasi: &CliWasiEnv,
+               execution_limits: &ExecutionLimits) -> anyhow::Result<WasmiInstance> {
+    let mut store_limits = StoreLimitsBuilder::new();
+    if let Some(max_memory_bytes) = execution_limits.max_memory_bytes {
+        store_limits = store_limits.memory_size(usize::try_from(max_memory_bytes).unwrap_or(usize::MAX));
+    }
+    let state = HostState {
+        limits: RecordingLimits(store_limits.build()),
+        args: std::iter::once(name.to_string()).chain(wasi.wasi_args.iter().cloned()).collect(),
+        envs: wasi.wasi_envs.iter().map(|(key, value)| format!("{key}={value}")).collect(),
+        stdout: ConsoleWriter::new(name, ConsoleStream::Stdout),
+        stderr: ConsoleWriter::new(name, ConsoleStream::Stderr),
+        random_state: 0x9e37_79b9_7f4a_7c15 ^ now_nanos().unwrap_or_default() | 1, // Never zero
+    };
+    let mut store = Store::new(engine, state);
+    store.limiter(|state| &mut state.limits);
+    if let Some(fuel) = execution_limits.fuel_for_init() {
+        store.set_fuel(fuel).map_err(|err| anyhow::anyhow!("{}", err))?; // For the start function
+    }
+    let instance = linker.instantiate(&mut store, module)
+        .map_err(|err| anyhow::anyhow!("Failed to instantiate the SDF module (note that the wasmi backend only provides basic WASI functions): {}", err))?
+        .start(&mut store)?;
+
+    // Cache the exports of the module.
+    let memory = instance.get_memory(&store, "memory")
+        .ok_or_else(|| anyhow::anyhow!("Incompatible SDF module: it does not export its `memory`"))?;
+    let funcs = EXPORTS.iter()
+        .filter_map(|name| Some((*name, instance.get_func(&store, name)?)))
+        .collect();
+    Ok(WasmiInstance { store, memory, funcs })
+}
+
+/// An instance of the module, with its own store (and memory), that runs one call at a time.
+#[derive(Debug)]
+pub(crate) struct WasmiInstance {
+    store: Store<HostState>,
+    memory: Memory,
+    /// The exports used by the host (see [`EXPORTS`]) that are available.
+    funcs: HashMap<&'static str, Func>,
+}
+
+impl GuestMemory for WasmiInstance {
+    fn pointer_size(&self) -> usize {
+        size_of::<u32>() // wasm32
+    }
+
+    fn read_bytes(&self, pointer: u64, length: usize) -> Vec<u8> {
+        match usize::try_from(pointer).ok().and_then(|pointer| guest_slice(self.memory.data(&self.store), pointer, length)) {
+            Some(bytes) => bytes.to_vec(),
+            None => {
+                debug_assert!(false, "Out of bounds memory access at index {pointer}, length {length}");
+                tracing::error!("Out of bounds memory access at index {}, length {}", pointer, length);
+                vec![0u8; length]
+            }
+        }
+    }
+}
+
+impl ModuleInstance for WasmiInstance {
+    fn signature(&self, export: &str) -> Option<(Vec<WasmType>, Vec<WasmType>)> {
+        let ty = self.funcs.get(export)?.ty(&self.store);
+        Some((ty.params().iter().map(wasm_type).collect(), ty.results().iter().map(wasm_type).collect()))
+    }
+
+    fn call(&mut self, export: &str, args: &[WasmValue], fuel: Option<u64>) -> Result<Vec<WasmValue>, Trap> {
+        let func = *self.funcs.get(export)
+            .ok_or_else(|| SDFError::new(format!("the SDF module does not export `{export}`")).with_export(export))?;
+        if let Some(fuel) = fuel {
+            self.store.set_fuel(fuel).map_err(|err| SDFError::new(err.to_string()).with_export(export))?;
+        }
+        let args = args.iter().map(|arg| match *arg {
+            WasmValue::I32(value) => Val::I32(value),
+            WasmValue::F32(value) => Val::F32(value.into()),
+        }).collect::<Vec<_>>();
+        let mut results = func.ty(&self.store).results().iter().map(|ty| Val::default(*ty)).collect::<Vec<_>>();
+        match func.call(&mut self.store, &args, &mut results) {
+            Ok(()) => results.iter().map(|result| match result {
+                Val::I32(value) => Ok(WasmValue::I32(*value)),
+                Val::F32(value) => Ok(WasmValue::F32(value.to_float())),
+                other => Err(SDFError::new(format!("unsupported result {other:?}")).with_export(export).into()),
+            }).collect(),
+            Err(err) => Err(Trap {
+                error: SDFError::new(err.to_string()).with_export(export),
+                out_of_fuel: err.as_trap_code() == Some(TrapCode::OutOfFuel),
+            }),
+        }
+    }
+
+    fn write_memory(&mut self, mem_pointer: u32, to_write: &[u8]) {
+        if let Err(err) = self.memory.write(&mut self.store, mem_pointer as usize, to_write) {
+            tracing::error!("Out of bounds memory access at index {}, length {}: {:?}", mem_pointer, to_write.len(), err);
+        }
+    }
+}
+
+/// The type of a value in the shared host, for checking the signatures of the exports.
+fn wasm_type(ty: &ValType) -> WasmType {
+    match ty {
+        ValType::I32 => WasmType::I32,
+        ValType::F32 => WasmType::F32,
+        ValType::I64 => WasmType::I64,
+        ValType::F64 => WasmType::F64,
+        _ => WasmType::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::sdf::SDFSurface;
+    use crate::sdf::wasm::fixtures::wasm;
+    use crate::sdf::wasm::interpreter::load_sdf_wasm;
+    use crate::sdf::wasm::wasi::CliWasiEnv;
+
+    /// A SDF whose samples are the error code of writing 65537 times its first 64 KiB to stdout
+    /// with a single `fd_write`, which adds up to more than 4 GiB.
+    const OVERFLOWING_WRITE_WAT: &str = r#"(module
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 10)
+        (func (export "bounding_box") (param i32) (result i32) (i32.const 0))
+        (func (export "sample") (param i32 f32 f32 f32 i32) (result i32) (local $i i32)
+            (loop $fill
+                (i32.store (i32.add (i32.const 65536) (i32.mul (local.get $i) (i32.const 8))) (i32.const 0))
+                (i32.store (i32.add (i32.const 65540) (i32.mul (local.get $i) (i32.const 8))) (i32.const 65536))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br_if $fill (i32.le_u (local.get $i) (i32.const 65536))))
+            (f32.store (i32.const 64) (f32.convert_i32_u
+                (call $fd_write (i32.const 1) (i32.const 65536) (i32.const 65537) (i32.const 128))))
+            (i32.const 64)))"#;
+
+    #[test]
+    fn test_fd_write_overflow_is_rejected() {
+        let wasm_bytes = wasm(OVERFLOWING_WRITE_WAT);
+        let sdf = load_sdf_wasm(&wasm_bytes, &CliWasiEnv::default(), 1).unwrap();
+        assert_eq!(sdf.sample(Vector3::new(0.0, 0.0, 0.0), true).distance, super::ERRNO_INVAL as f32);
+        assert!(sdf.take_errors().is_empty());
+    }
+}
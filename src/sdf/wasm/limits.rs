@@ -0,0 +1,333 @@
+//! Execution limits for untrusted WebAssembly SDFs, so that a bad module (like one with an infinite
+//! loop or unbounded memory growth) can't freeze or crash the viewer.
+//!
+//! Each call into the module gets an instruction budget (fuel) proportional to the amount of work
+//! requested (like the number of points of a batch), so that every point gets the same budget
+//! whether it is sampled alone or in a batch, and the memory of the module can't grow past a
+//! maximum size. When a limit is hit, the call fails (see [`LimitExceeded`]) and the SDF is marked
+//! as faulted (see [`SDFSurface::fault`](crate::sdf::SDFSurface::fault)): it stops calling into the
+//! module, as its state may be inconsistent, and only returns placeholder values.
+//!
+//! The limits are only enforced with the `execution_limits` feature: by wasmer on native platforms
+//! (as the browser runs the module on web), and by the wasmi interpreter everywhere.
+
+use std::cell::Cell;
+use std::fmt::{Display, Formatter};
+use std::sync::RwLock;
+
+/// The configuration of the limits shared by all the loaders (see `CliExecutionLimits::apply`).
+static LIMITS: RwLock<ExecutionLimits> = RwLock::new(ExecutionLimits::DEFAULT);
+
+/// The units of work (see [`ExecutionLimits::fuel_for`]) allowed for initializing a module, which
+/// may need to precompute expensive data.
+const INIT_WORK: u64 = 1000;
+
+thread_local! {
+    /// Whether a memory of a module failed to grow because of the memory limit, since this was last
+    /// checked. Calls run on the calling thread, so this belongs to the current call.
+    static MEMORY_GROW_REFUSED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Records that the memory limit refused to grow a memory of the module being called.
+pub fn record_memory_grow_refused() {
+    MEMORY_GROW_REFUSED.with(|refused| refused.set(true));
+}
+
+/// Whether the memory limit refused to grow a memory since the last time that this was called
+/// (which should be before each call into a module).
+pub fn take_memory_grow_refused() -> bool {
+    MEMORY_GROW_REFUSED.with(|refused| refused.replace(false))
+}
+
+/// The limits applied to the modules loaded from now on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionLimits {
+    /// The number of instructions that a call may run per unit of work (a sampled point, or the
+    /// whole call for other functions), if limited.
+    pub fuel_per_work: Option<u64>,
+    /// The maximum size of the memory of the module, in bytes, if limited.
+    pub max_memory_bytes: Option<u64>,
+}
+
+impl ExecutionLimits {
+    const DEFAULT: Self = Self { fuel_per_work: Some(10_000_000), max_memory_bytes: Some(1024 * 1024 * 1024) };
+
+    /// No limits at all, like on web (where they can't be enforced).
+    pub const UNLIMITED: Self = Self { fuel_per_work: None, max_memory_bytes: None };
+
+    /// The limits configured for this process.
+    pub fn current() -> Self {
+        *LIMITS.read().unwrap()
+    }
+
+    /// The instruction budget for a call that performs the given units of work.
+    pub fn fuel_for(&self, work: usize) -> Option<u64> {
+        self.fuel_per_work.map(|fuel| fuel.saturating_mul(work.max(1) as u64))
+    }
+
+    /// The instruction budget for initializing a module, which is not capped like other calls as it
+    /// only happens while loading.
+    pub fn fuel_for_init(&self) -> Option<u64> {
+        self.fuel_per_work.map(|fuel| fuel.saturating_mul(INIT_WORK))
+    }
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Options for the execution limits of WebAssembly SDFs, shared by all commands.
+#[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+#[derive(clap::Parser, Debug, Clone, PartialEq, Eq)]
+pub struct CliExecutionLimits {
+    /// The maximum number of WebAssembly instructions that a SDF may run per sampled point (or per
+    /// call for other queries) before it is stopped and marked as faulted. 0 disables the limit.
+    #[clap(long, global = true, default_value = "10000000")]
+    pub wasm_fuel_per_sample: u64,
+    /// The maximum memory (in MiB) that a WebAssembly SDF may use. 0 disables the limit.
+    #[clap(long, global = true, default_value = "1024")]
+    pub wasm_max_memory_mib: u64,
+}
+
+#[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+impl CliExecutionLimits {
+    /// Configures the limits for all the following loads.
+    pub fn apply(&self) {
+        *LIMITS.write().unwrap() = ExecutionLimits {
+            fuel_per_work: Some(self.wasm_fuel_per_sample).filter(|fuel| *fuel > 0),
+            max_memory_bytes: Some(self.wasm_max_memory_mib.saturating_mul(1024 * 1024)).filter(|bytes| *bytes > 0),
+        };
+    }
+}
+
+/// The distinct error of a call that was stopped because it exceeded a limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// The call ran more instructions than allowed (probably an infinite loop).
+    Fuel { limit: u64 },
+    /// The module tried to use more memory than allowed.
+    Memory { limit_bytes: u64 },
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Fuel { limit } => write!(f, "execution limit exceeded: ran more than {limit} instructions"),
+            LimitExceeded::Memory { limit_bytes } => write!(f, "memory limit exceeded: tried to use more than {} MiB", limit_bytes / 1024 / 1024),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+#[cfg(all(feature = "execution_limits", feature = "wasmer_backend", not(target_arch = "wasm32")))]
+pub use native::*;
+
+#[cfg(all(feature = "execution_limits", feature = "wasmer_backend", not(target_arch = "wasm32")))]
+mod native {
+    use std::ptr::NonNull;
+    use std::sync::Arc;
+
+    use wasmer::sys::{BaseTunables, CompilerConfig, Cranelift, NativeEngineExt, Tunables};
+    use wasmer::vm::{LinearMemory, VMMemory, VMMemoryDefinition, VMTable, VMTableDefinition};
+    use wasmer::wasmparser::Operator;
+    use wasmer::{Engine, Instance, MemoryError, MemoryStyle, MemoryType, Pages, Store, TableStyle, TableType, Target};
+    use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+    use wasmer_middlewares::Metering;
+
+    use super::{record_memory_grow_refused, ExecutionLimits};
+
+    /// Creates a store whose modules are metered and whose memories are limited as configured.
+    /// The fuel of each call must be set with [`set_fuel`], as modules start with an unlimited budget.
+    pub fn new_store(limits: &ExecutionLimits) -> Store {
+        let mut compiler = Cranelift::default();
+        if limits.fuel_per_work.is_some() {
+            compiler.push_middleware(Arc::new(Metering::new(u64::MAX, |_: &Operator| -> u64 { 1 })));
+        }
+        let mut engine: Engine = compiler.into();
+        if let Some(max_memory_bytes) = limits.max_memory_bytes {
+            let pages = Pages((max_memory_bytes / wasmer::WASM_PAGE_SIZE as u64).clamp(1, wasmer::WASM_MAX_PAGES as u64) as u32);
+            engine.set_tunables(LimitingTunables { limit: pages, base: BaseTunables::for_target(&Target::default()) });
+        }
+        Store::new(engine)
+    }
+
+    /// Sets the instruction budget for the next call into the module.
+    pub fn set_fuel(store: &mut Store, instance: &Instance, fuel: u64) {
+        set_remaining_points(store, instance, fuel);
+    }
+
+    /// Whether the last call into the module ran out of instructions.
+    pub fn fuel_exhausted(store: &mut Store, instance: &Instance) -> bool {
+        matches!(get_remaining_points(store, instance), MeteringPoints::Exhausted)
+    }
+
+    /// Caps the maximum size of all the memories of the module (based on the example of wasmer).
+    struct LimitingTunables<T: Tunables> {
+        limit: Pages,
+        base: T,
+    }
+
+    impl<T: Tunables> LimitingTunables<T> {
+        /// Memories without a maximum size get the limit as their maximum size.
+        fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+            let mut adjusted = *requested;
+            if requested.maximum.is_none_or(|maximum| maximum > self.limit) {
+                adjusted.maximum = Some(self.limit);
+            }
+            adjusted
+        }
+
+        fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+            if ty.minimum > self.limit {
+                return Err(MemoryError::Generic(format!(
+                    "the minimum memory size ({} pages) exceeds the memory limit ({} pages)", ty.minimum.0, self.limit.0)));
+            }
+            Ok(())
+        }
+    }
+
+    impl<T: Tunables> Tunables for LimitingTunables<T> {
+        fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+            self.base.memory_style(&self.adjust_memory(memory))
+        }
+
+        fn table_style(&self, table: &TableType) -> TableStyle {
+            self.base.table_style(table)
+        }
+
+        fn create_host_memory(&self, ty: &MemoryType, style: &MemoryStyle) -> Result<VMMemory, MemoryError> {
+            let adjusted = self.adjust_memory(ty);
+            self.validate_memory(&adjusted)?;
+            let memory = self.base.create_host_memory(&adjusted, style)?;
+            Ok(VMMemory::from(Box::new(RecordingMemory(memory)) as Box<dyn LinearMemory + 'static>))
+        }
+
+        unsafe fn create_vm_memory(&self, ty: &MemoryType, style: &MemoryStyle,
+                                   vm_definition_location: NonNull<VMMemoryDefinition>) -> Result<VMMemory, MemoryError> {
+            let adjusted = self.adjust_memory(ty);
+            self.validate_memory(&adjusted)?;
+            let memory = self.base.create_vm_memory(&adjusted, style, vm_definition_location)?;
+            Ok(VMMemory::from(Box::new(RecordingMemory(memory)) as Box<dyn LinearMemory + 'static>))
+        }
+
+        fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+            self.base.create_host_table(ty, style)
+        }
+
+        unsafe fn create_vm_table(&self, ty: &TableType, style: &TableStyle,
+                                  vm_definition_location: NonNull<VMTableDefinition>) -> Result<VMTable, String> {
+            self.base.create_vm_table(ty, style, vm_definition_location)
+        }
+    }
+
+    /// A memory whose failures to grow (past the limit, as its maximum size is the limit) are
+    /// recorded (see [`super::take_memory_grow_refused`]).
+    #[derive(Debug)]
+    struct RecordingMemory(VMMemory);
+
+    impl LinearMemory for RecordingMemory {
+        fn ty(&self) -> MemoryType {
+            self.0.ty()
+        }
+
+        fn size(&self) -> Pages {
+            self.0.size()
+        }
+
+        fn style(&self) -> MemoryStyle {
+            self.0.style()
+        }
+
+        fn grow(&mut self, delta: Pages) -> Result<Pages, MemoryError> {
+            self.0.grow(delta).inspect_err(|_| record_memory_grow_refused())
+        }
+
+        fn grow_at_least(&mut self, min_size: u64) -> Result<(), MemoryError> {
+            self.0.grow_at_least(min_size).inspect_err(|_| record_memory_grow_refused())
+        }
+
+        fn reset(&mut self) -> Result<(), MemoryError> {
+            self.0.reset()
+        }
+
+        fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+            self.0.vmmemory()
+        }
+
+        fn try_clone(&self) -> Result<Box<dyn LinearMemory + 'static>, MemoryError> {
+            self.0.try_clone()
+        }
+
+        fn copy(&mut self) -> Result<Box<dyn LinearMemory + 'static>, MemoryError> {
+            Ok(Box::new(RecordingMemory(VMMemory::from(self.0.copy()?))))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "execution_limits", not(target_arch = "wasm32")))]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::sdf::wasm::backend::WasmBackend;
+    use crate::sdf::wasm::fixtures::load_with_each_backend;
+    use crate::sdf::wasm::limits::ExecutionLimits;
+
+    /// A SDF that loops forever when sampled at x > 0, tries to grow its memory past 1 GiB (from a
+    /// single page) when sampled at y > 0, and traps for other reasons when sampled at z > 0.
+    const BAD_SDF_WAT: &str = r#"(module
+        (memory (export "memory") 1)
+        (func (export "bounding_box") (param i32) (result i32) (i32.const 0))
+        (func (export "sample") (param i32 f32 f32 f32 i32) (result i32)
+            (if (f32.gt (local.get 1) (f32.const 0)) (then (loop $forever (br $forever))))
+            (if (f32.gt (local.get 2) (f32.const 0)) (then
+                (if (i32.eq (memory.grow (i32.const 20000)) (i32.const -1)) (then unreachable))))
+            (if (f32.gt (local.get 3) (f32.const 0)) (then unreachable))
+            (i32.const 64)))"#;
+
+    /// Samples a newly loaded bad SDF at the given point with each backend, returning the message of
+    /// its fault (if it faulted).
+    fn fault_after_sampling(p: Vector3<f32>) -> Vec<(WasmBackend, Option<String>)> {
+        load_with_each_backend(BAD_SDF_WAT).into_iter().map(|(backend, sdf)| {
+            assert!(sdf.fault().is_none(), "{:?} faulted before sampling", backend);
+            let sample = sdf.sample(p, true);
+            assert_eq!(sample.distance, 1.0, "{:?} did not return a placeholder", backend); // Placeholder value
+            (backend, sdf.fault().map(|fault| fault.message))
+        }).collect()
+    }
+
+    #[test]
+    fn test_infinite_loop_is_stopped() {
+        for (backend, fault) in fault_after_sampling(Vector3::new(1.0, 0.0, 0.0)) {
+            let fault = fault.unwrap_or_else(|| panic!("{:?} did not fault", backend));
+            assert!(fault.starts_with("execution limit exceeded"), "{:?}: {}", backend, fault);
+        }
+    }
+
+    #[test]
+    fn test_memory_grow_past_limit() {
+        // A single grow of many pages, starting far below the limit
+        for (backend, fault) in fault_after_sampling(Vector3::new(0.0, 1.0, 0.0)) {
+            let fault = fault.unwrap_or_else(|| panic!("{:?} did not fault", backend));
+            assert!(fault.starts_with("memory limit exceeded"), "{:?}: {}", backend, fault);
+        }
+        // Other traps are reported as errors, without faulting
+        for (backend, fault) in fault_after_sampling(Vector3::new(0.0, 0.0, 1.0)) {
+            assert_eq!(fault, None, "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn test_batches_get_the_fuel_of_single_samples() {
+        let limits = ExecutionLimits::default();
+        let per_sample = limits.fuel_for(1).unwrap();
+        assert_eq!(Some(per_sample), limits.fuel_per_work);
+        // Like a slice of the mesher and a chunk of the instance pool
+        for points in [2, 257 * 257, 16 * 1024] {
+            assert_eq!(limits.fuel_for(points), Some(per_sample * points as u64), "{} points", points);
+        }
+        assert_eq!(ExecutionLimits::UNLIMITED.fuel_for(256), None);
+    }
+}
@@ -7,28 +7,202 @@ use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::metadata::short_version_info_is_ours;
 
+use crate::sdf::remote::client::{check_version, RemoteSDF};
+use crate::sdf::error::SDFError;
 use crate::sdf::SDFSurface;
-use crate::sdf::wasm::native;
-use crate::sdf::demo::SDFDemo;
+#[cfg(feature = "voxel_grids")]
+use crate::sdf::voxels::{self, VoxelGridSDF};
+#[cfg(feature = "mesh_inputs")]
+use crate::sdf::trimesh::{self, TriMeshSDF};
+#[cfg(all(feature = "dylib", not(target_arch = "wasm32")))]
+use crate::sdf::wasm::dylib;
+use crate::sdf::wasm::backend::WasmBackend;
+use crate::sdf::wasm::input;
+use crate::sdf::wasm::wasi::CliWasiEnv;
+
+/// The result of a load, sent through the update channels: the errors are reported to the user,
+/// who keeps the last good SDF.
+pub type SDFLoadResult = Result<Box<dyn SDFSurface + Send + Sync>, SDFError>;
+
+/// The path that reads the SDF from the standard input (only once, see [`load_sdf_from_path_or_url`]).
+#[cfg(not(target_arch = "wasm32"))]
+pub const STDIN_INPUT: &str = "-";
 
 /// See [`load_sdf_wasm`] for more information.
-pub async fn load_sdf_wasm_send_sync(wasm_bytes: &[u8]) -> anyhow::Result<Box<dyn SDFSurface + Send + Sync>> {
-    native::load_sdf_wasm_send_sync(wasm_bytes).await
+pub async fn load_sdf_wasm_send_sync(bytes: &[u8], wasi: &CliWasiEnv) -> anyhow::Result<Box<dyn SDFSurface + Send + Sync>> {
+    let (wasm_bytes, wasi) = input::decode_sdf_input(bytes, wasi)?;
+    WasmBackend::current().load(&wasm_bytes, &wasi).await
+}
+
+/// Loads the given bytes as any of the supported SDF formats: a WebAssembly module (see
+/// [`load_sdf_wasm_send_sync`]), a voxel grid (see [`crate::sdf::voxels`]) or a triangle mesh (see
+/// [`crate::sdf::trimesh`]). `path` is where the bytes came from, used to find the data files of voxel
+/// grids with detached headers and to recognize meshes by their extension. Without a path (like for
+/// [`STDIN_INPUT`]), the format is only detected from the contents.
+#[cfg_attr(not(any(feature = "voxel_grids", feature = "mesh_inputs")), allow(unused_variables))]
+pub async fn load_sdf_input_send_sync(bytes: &[u8], wasi: &CliWasiEnv, path: Option<&str>) -> anyhow::Result<Box<dyn SDFSurface + Send + Sync>> {
+    #[cfg(feature = "voxel_grids")]
+    if voxels::format::is_voxel_grid(bytes) {
+        let sdf = VoxelGridSDF::parse(bytes, &|file| read_detached_file(path, file))?;
+        return Ok(Box::new(sdf.with_id(0)));
+    }
+    #[cfg(feature = "mesh_inputs")]
+    if trimesh::format::is_mesh(bytes, path) {
+        let sdf = TriMeshSDF::parse(bytes, path)?;
+        return Ok(Box::new(sdf.with_id(0)));
+    }
+    load_sdf_wasm_send_sync(bytes, wasi).await
+}
+
+/// Reads a file referenced by the header at the given path, relative to it. Only local headers are
+/// supported, as the files can't be guessed for other sources (or without a path).
+#[cfg(feature = "voxel_grids")]
+fn read_detached_file(header_path: Option<&str>, file: &str) -> anyhow::Result<Vec<u8>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(header_path) = header_path {
+        let header_path = std::path::Path::new(header_path.strip_prefix("file://").unwrap_or(header_path));
+        if header_path.exists() {
+            let path = header_path.parent().unwrap_or(std::path::Path::new("")).join(file);
+            return std::fs::read(&path).map_err(|err| anyhow!("Failed to read the data file {:?}: {}", path, err));
+        }
+    }
+    let header = header_path.map(|path| format!(" {path:?}")).unwrap_or_default();
+    Err(anyhow!("The data file {:?} of the voxel grid header{} can only be read next to a local header, \
+                  embed the data in the header instead (like a .nrrd or .mha file)", file, header))
 }
 
 /// Loads the given bytes as a WebAssembly module that is then queried to satisfy the SDF trait.
+/// They may also be in any of the other formats of [`input`], like text or compressed modules.
 ///
-/// It uses the default WebAssembly interpreter for the platform.
+/// It uses the configured WebAssembly backend (see [`WasmBackend`]), and the given environment for
+/// modules that use WASI.
 #[allow(dead_code)] // Not used in the current implementation because Send + Sync is needed for the WebAssembly engine.
-pub async fn load_sdf_wasm(wasm_bytes: &[u8]) -> anyhow::Result<Box<dyn SDFSurface>> {
-    native::load_sdf_wasm(wasm_bytes).await
+pub async fn load_sdf_wasm(wasm_bytes: &[u8], wasi: &CliWasiEnv) -> anyhow::Result<Box<dyn SDFSurface>> {
+    load_sdf_wasm_send_sync(wasm_bytes, wasi).await.map(|sdf| sdf as Box<dyn SDFSurface>)
 }
 
 /// Abstraction over [`load_sdf_wasm`] that allows it to load from a file path or HTTP server URL,
 /// providing automatic updates for the latter (if the server supports it).
-pub fn load_sdf_from_path_or_url(sender_of_updates: Sender<Receiver<Box<dyn SDFSurface + Send + Sync>>>, watch_url: String) {
+///
+/// [`STDIN_INPUT`] and `data:` URLs are read directly (sending a single update), and paths to native
+/// shared libraries are loaded with [`load_sdf_from_native_lib`] instead.
+pub fn load_sdf_from_path_or_url(sender_of_updates: Sender<Receiver<SDFLoadResult>>, watch_url: String, wasi: CliWasiEnv) {
+    #[cfg(not(target_arch = "wasm32"))]
+    if watch_url == STDIN_INPUT {
+        return load_sdf_from_bytes(sender_of_updates, read_stdin_once, "the standard input".to_string(), wasi);
+    }
+    if let Some(bytes) = input::data_url_bytes(&watch_url) {
+        // The whole URL is too long to be displayed
+        let short_url = watch_url.chars().take(48).collect::<String>() + "...";
+        return load_sdf_from_bytes(sender_of_updates, move || bytes, format!("data URL {short_url:?}"), wasi);
+    }
+    #[cfg(all(feature = "dylib", not(target_arch = "wasm32")))]
+    if let Some(path) = dylib::native_lib_path(&watch_url) {
+        return load_sdf_from_native_lib(sender_of_updates, path.to_string());
+    }
     ehttp::fetch(Request::get(watch_url.clone()), move |data| {
-        handle_sdf_data_response(data, watch_url, sender_of_updates)
+        handle_sdf_data_response(data, watch_url, wasi, sender_of_updates)
+    });
+}
+
+/// Loads the SDF from a native shared library (see [`crate::sdf::wasm`]) in a new thread.
+/// It only sends one update, as the library is not watched for changes.
+#[cfg(all(feature = "dylib", not(target_arch = "wasm32")))]
+pub fn load_sdf_from_native_lib(sender_of_updates: Sender<Receiver<SDFLoadResult>>, path: String) {
+    spawn_async(async move {
+        let (sender_single_update, receiver_single_update) = mpsc::channel(1);
+        if sender_of_updates.send(receiver_single_update).await.is_err() {
+            tracing::warn!("The listener ignored our update notification, won't send more notifications");
+            return;
+        }
+        let res = dylib::load_sdf_native_lib(&path).map_err(|err| {
+            tracing::error!("Failed to load SDF from native library {:?}: {:?}", path, err);
+            SDFError::from(err).with_url(path)
+        });
+        if sender_single_update.send(res).await.is_err() {
+            tracing::warn!("The listener ignored our update notification (2)");
+        }
+    }, true)
+}
+
+/// Loads the SDF from bytes that don't come from a watched path or URL (see [`STDIN_INPUT`] and
+/// `data:` URLs), which are read in a new thread. It only sends one update. The description is only
+/// used to report errors, so the format of the bytes is detected from their contents.
+fn load_sdf_from_bytes(sender_of_updates: Sender<Receiver<SDFLoadResult>>, read: impl FnOnce() -> anyhow::Result<Vec<u8>> + Send + 'static,
+                       description: String, wasi: CliWasiEnv) {
+    spawn_async(async move {
+        let (sender_single_update, receiver_single_update) = mpsc::channel(1);
+        if sender_of_updates.send(receiver_single_update).await.is_err() {
+            tracing::warn!("The listener ignored our update notification, won't send more notifications");
+            return;
+        }
+        let res = match read() {
+            Ok(bytes) => load_sdf_input_send_sync(&bytes, &wasi, None).await,
+            Err(err) => Err(err),
+        };
+        let res = res.map_err(|err| {
+            tracing::error!("Failed to load SDF from {}: {:?}", description, err);
+            SDFError::from(err).with_url(description)
+        });
+        if sender_single_update.send(res).await.is_err() {
+            tracing::warn!("The listener ignored our update notification (2)");
+        }
+    }, true)
+}
+
+/// Reads the whole standard input the first time, returning the same bytes for later loads (like
+/// when the mesher reloads the SDF of the app).
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stdin_once() -> anyhow::Result<Vec<u8>> {
+    use std::io::{IsTerminal, Read};
+    use std::sync::Mutex;
+
+    static STDIN: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+    read_once(&STDIN, || {
+        let mut stdin = std::io::stdin().lock();
+        if stdin.is_terminal() {
+            anyhow::bail!("Expected a SDF piped to the standard input, like `cat sdf.wasm | sdf-viewer ... -`");
+        }
+        let mut bytes = vec![];
+        stdin.read_to_end(&mut bytes)?;
+        tracing::info!("Read {} bytes of SDF from the standard input", bytes.len());
+        Ok(bytes)
+    })
+}
+
+/// Reads the bytes the first time, storing them in the given cache for later calls. Only the first
+/// call reads, the others wait for it. Failed reads are not cached.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_once(cache: &std::sync::Mutex<Option<Vec<u8>>>, read: impl FnOnce() -> anyhow::Result<Vec<u8>>) -> anyhow::Result<Vec<u8>> {
+    let mut cache = cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(bytes) = &*cache {
+        return Ok(bytes.clone());
+    }
+    Ok(cache.insert(read()?).clone())
+}
+
+/// Connects to a server of the remote SDF protocol (see [`crate::sdf::remote`]) at the given base URL.
+/// It only sends one update, as the server reports its own changes through the protocol.
+pub fn load_sdf_from_remote(sender_of_updates: Sender<Receiver<SDFLoadResult>>, base_url: String) {
+    let sdf = RemoteSDF::new(base_url.clone());
+    ehttp::fetch(Request::get(sdf.version_url()), move |data| {
+        spawn_async(async move {
+            let (sender_single_update, receiver_single_update) = mpsc::channel(1);
+            if sender_of_updates.send(receiver_single_update).await.is_err() {
+                tracing::warn!("The listener ignored our update notification, won't send more notifications");
+                return;
+            }
+            let res = match data.map_err(|err| anyhow!(err)).and_then(|resp| check_version(&resp)) {
+                Ok(()) => Ok(Box::new(sdf) as Box<dyn SDFSurface + Send + Sync>),
+                Err(err) => {
+                    tracing::error!("Failed to connect to remote SDF at {:?}: {:?}", base_url, err);
+                    Err(SDFError::from(err).with_url(base_url))
+                }
+            };
+            if sender_single_update.send(res).await.is_err() {
+                tracing::warn!("The listener ignored our update notification (2)");
+            }
+        }, true)
     });
 }
 
@@ -55,8 +229,8 @@ pub fn spawn_async(fut: impl Future<Output=()> + Send + 'static, new_runtime: bo
 
 /// This is a helper function to load a SDF from a WebAssembly binary. It initially tries to load the
 /// HTTP response as a WebAssembly binary, but falls back to loading it as a local file if that fails.
-fn handle_sdf_data_response(data: ehttp::Result<ehttp::Response>, watch_url_closure: String,
-                            sender_of_updates: Sender<Receiver<Box<dyn SDFSurface + Send + Sync>>>) {
+fn handle_sdf_data_response(data: ehttp::Result<ehttp::Response>, watch_url_closure: String, wasi: CliWasiEnv,
+                            sender_of_updates: Sender<Receiver<SDFLoadResult>>) {
     // First, try to request the file as an URL on any platform (with some fallbacks).
     let fut = async move {
         let (sender_single_update, receiver_single_update) = mpsc::channel(1);
@@ -82,8 +256,9 @@ fn handle_sdf_data_response(data: ehttp::Result<ehttp::Response>, watch_url_clos
                     tracing::info!("Server supports watching for file changes, enabling continuous updates.");
                     // Queue a ?watch request to the server, which will wait for source updates, recompile and return the new WASM file!
                     let watch_url_closure_clone = watch_url_closure.clone();
+                    let wasi_clone = wasi.clone();
                     ehttp::fetch(Request::get(watch_url_closure.clone() + "?watch"), move |data| {
-                        handle_sdf_data_response(data, watch_url_closure_clone, sender_of_updates)
+                        handle_sdf_data_response(data, watch_url_closure_clone, wasi_clone, sender_of_updates)
                     });
                 } else {
                     // Otherwise, give up on continuous updates by dropping the sender_of_updates!
@@ -91,51 +266,70 @@ fn handle_sdf_data_response(data: ehttp::Result<ehttp::Response>, watch_url_clos
                     drop(sender_of_updates); // This is not needed, but states what we want
                 }
                 // TODO: Avoid this blocking code...
-                load_sdf_wasm_send_sync(resp.bytes.as_slice()).await
+                load_sdf_input_send_sync(resp.bytes.as_slice(), &wasi, Some(&watch_url_closure)).await
             }
             Err(err_str) => Err(anyhow::anyhow!(err_str)),
         };
-        let channel_send_err = match res {
-            Ok(sdf) => { // If successful, load it now
-                sender_single_update.send(sdf).await.map_err(|_| anyhow!("can't send SDF update"))
-            }
+        let res = match res {
+            Ok(sdf) => Ok(sdf), // If successful, load it now
             Err(err) => { // If not, try to load it as a local file on native platforms.
                 #[cfg(target_arch = "wasm32")]
                 {
                     tracing::error!("Failed to load SDF from URL: {:?}", err);
-                    sender_single_update.try_send(unsafe {
-                        // FIXME: Extremely unsafe code (forcing SDFDemo Send+Sync), but only used for this error path
-                        std::mem::transmute::<Box<dyn SDFSurface>, Box<dyn SDFSurface + Send + Sync>>(Box::new(SDFDemo::default()) as Box<dyn SDFSurface>)
-                    }).map_err(|_| anyhow!("can't send SDF update"))
+                    Err(SDFError::from(err).with_url(watch_url_closure))
                 }
                 #[cfg(not(target_arch = "wasm32"))]
                 {
                     // TODO: Avoid this blocking code...
-                    let res = match std::fs::read(watch_url_closure) {
+                    let res = match std::fs::read(&watch_url_closure) {
                         Ok(bytes) => {
                             // TODO: Avoid this blocking code...
-                            load_sdf_wasm_send_sync(bytes.as_slice()).await
+                            load_sdf_input_send_sync(bytes.as_slice(), &wasi, Some(&watch_url_closure)).await
                         }
                         Err(err) => Err(anyhow::Error::from(err)),
                     };
-                    match res {
-                        Ok(sdf) => {
-                            sender_single_update.send(sdf).await.map_err(|_| anyhow!("can't send SDF update"))
-                        }
-                        Err(err2) => {
-                            tracing::error!("Failed to load SDF from URL ({:?}) or file ({:?})", err, err2);
-                            sender_single_update.try_send(unsafe {
-                                // FIXME: Extremely unsafe code (forcing SDFDemo Send+Sync), but only used for this error path
-                                std::mem::transmute::<Box<dyn SDFSurface>, Box<dyn SDFSurface + Send + Sync>>(Box::<SDFDemo>::default() as Box<dyn SDFSurface>)
-                            }).map_err(|_| anyhow!("can't send SDF update"))
-                        }
-                    }
+                    res.map_err(|err2| {
+                        tracing::error!("Failed to load SDF from URL ({:?}) or file ({:?})", err, err2);
+                        // Report the error of the file if it exists, as the URL was only the first guess
+                        let err = if std::path::Path::new(&watch_url_closure).exists() { err2 } else { err };
+                        SDFError::from(err).with_url(watch_url_closure)
+                    })
                 }
             }
         };
+        let channel_send_err = sender_single_update.send(res).await;
         if channel_send_err.is_err() {
             tracing::warn!("The listener ignored our update notification (2)");
         }
     };
     spawn_async(fut, true)
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::sdf::wasm::load::read_once;
+
+    #[test]
+    fn test_read_once_reuses_the_bytes() {
+        let cache = Mutex::new(None);
+        let mut reads = 0;
+        let mut read = || {
+            reads += 1;
+            Ok(vec![1, 2, 3])
+        };
+        assert_eq!(read_once(&cache, &mut read).unwrap(), [1, 2, 3]);
+        assert_eq!(read_once(&cache, &mut read).unwrap(), [1, 2, 3]); // Like a reload
+        assert_eq!(read_once(&cache, || panic!("read again")).unwrap(), [1, 2, 3]);
+        assert_eq!(reads, 1);
+    }
+
+    #[test]
+    fn test_read_once_retries_failed_reads() {
+        let cache = Mutex::new(None);
+        assert!(read_once(&cache, || Err(anyhow::anyhow!("not ready"))).is_err());
+        assert_eq!(read_once(&cache, || Ok(vec![4])).unwrap(), [4]);
+        assert_eq!(read_once(&cache, || Ok(vec![5])).unwrap(), [4]);
+    }
+}
@@ -36,8 +36,178 @@
 //! The WASM module may also optionally export the init() method with no arguments and no return value,
 //! which will be called once before any other method.
 //!
+//! ## Batched sampling
+//!
+//! Sampling one point per call has a high overhead, so the module may optionally export
+//! `sample_batch(sdf_id, points_ptr, points_len_bytes, distance_only)`, returning a pointer to
+//! (pointer to the flattened SDFSample array + length in bytes), along with `sample_batch_free`.
+//! The input points are flattened Vector3s written by the host to the buffer returned by
+//! `sample_batch_points(len_bytes)`, which is owned by the module and reused between calls.
+//! Both exports are required to enable batching, otherwise `sample` is called for each point.
+//!
+//! ## Memory allocation
+//!
+//! Buffers written by the host (like the values of string parameters, or the points of batched
+//! sampling) are allocated in the module's memory through its `alloc(size, align) -> ptr` and
+//! `dealloc(ptr, size, align)` exports, or `malloc(size) -> ptr` and `free(ptr)` (as exported by
+//! TinyGo or Emscripten binaries) if those are not available. The module only borrows these buffers
+//! during the call that receives them, and the host frees them afterwards.
+//! Without an allocator, string parameters can't be set and batched sampling falls back to the
+//! buffer returned by `sample_batch_points`.
+//!
+//! ## Versioning
+//!
+//! The module should export `sdf_abi_version()`, returning the version of the memory layout and
+//! signatures it implements (see [`crate::sdf::abi`]), and `capabilities()`, returning a bitfield
+//! of the optional features it implements. Modules without `sdf_abi_version` are assumed to use
+//! the original layout (version 1), and modules without `capabilities` are probed for optional exports.
+//! Loading fails with an error describing the mismatch if the version is not supported, if a declared
+//! capability lacks its exports or if the signature of an export does not match the version.
+//! [`crate::sdf::ffi`] exports both automatically.
+//!
+//! ## Metadata
+//!
+//! The module may contain a custom section named `sdf_viewer_metadata` with `key=value` lines
+//! describing the crate that exported it (`name` and `version`), which the viewer displays.
+//! [`export_sdf!`](crate::export_sdf) embeds it automatically.
+//!
+//! ## Execution limits
+//!
+//! On native platforms, each call may only run a limited number of instructions (proportional to the
+//! number of points for batched sampling) and the memory of the module may only grow up to a limit.
+//! Calls that exceed a limit fail and mark the SDF as faulted, so that it is no longer called.
+//! See [`limits`] for more information.
+//!
+//! ## Parallel sampling
+//!
+//! On native platforms, the module is instantiated once per core (by default), and large batches of
+//! points are split between the instances to sample them in parallel. Every parameter change is
+//! applied to all the instances, so the module must not keep any other state that affects sampling
+//! (like caches that depend on previous calls). See [`pool`] for more information.
+//!
+//! ## WASI
+//!
+//! Modules that import WASI functions may print to their stdout and stderr (like for debugging),
+//! which is captured line by line and displayed in the console of the app, prefixed by the name of
+//! the crate that exported them (see [`console`]).
+//!
+//! They may also read the arguments, environment variables and read-only assets directory (at
+//! `/assets`) configured by the user, like for loading data at `init()` (see [`wasi`]).
+//!
+//! ## Backends
+//!
+//! Modules run on wasmer by default (compiled to native code, or by the browser on web), or on the
+//! portable wasmi interpreter (see [`backend`]), selected with `--wasm-backend` or by building
+//! without the `wasmer_backend` feature. Both implement the same API, but the interpreter is much
+//! slower and only provides basic WASI functions (no assets directory).
+//!
+//! ## Input formats
+//!
+//! Besides WebAssembly binaries, the loader accepts the WebAssembly text format, gzip or zstd
+//! compressed modules, `data:` URLs (like `data:application/wasm;base64,...`) and zip bundles of a
+//! module, a manifest and assets for WASI, detecting the format from the contents (see [`input`]).
+//!
+//! ## Native libraries
+//!
+//! On native platforms, the same API may be implemented by a shared library (`.so`, `.dylib` or `.dll`),
+//! like a `cdylib` crate using [`export_sdf!`](crate::export_sdf), which is loaded instead of a
+//! WebAssembly module if the path has one of those extensions. Pointers and lengths are then as large
+//! as in the host, which only changes the layout of some values. It runs at full speed, but it is
+//! not sandboxed, so only load trusted libraries.
+//!
 
 
 pub(crate) mod load;
+pub(crate) mod backend;
+pub(crate) mod input;
+#[cfg(feature = "wasmer_backend")]
 mod native;
+#[cfg(feature = "wasmi_backend")]
+mod interpreter;
+#[cfg(all(feature = "dylib", not(target_arch = "wasm32")))]
+pub(crate) mod dylib;
+#[cfg(all(feature = "module_cache", not(target_arch = "wasm32")))]
+pub(crate) mod cache;
+pub(crate) mod limits;
+pub(crate) mod pool;
+pub(crate) mod console;
+pub(crate) mod wasi;
+mod guest;
 mod util;
+#[cfg(test)]
+pub(crate) mod fixtures;
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::sdf::SDFParamValue;
+    use crate::sdf::wasm::fixtures::load_with_each_backend;
+
+    /// A legacy module whose distance is the X coordinate and whose color counts the buffers that
+    /// the host allocated (and the ones still alive). Its allocator refuses buffers over 1000 bytes.
+    const ALLOCATOR_WAT: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $allocated (mut i32) (i32.const 0))
+        (global $alive (mut i32) (i32.const 0))
+        (func (export "alloc") (param $size i32) (param i32) (result i32)
+            (if (i32.gt_u (local.get $size) (i32.const 1000)) (then (return (i32.const 0))))
+            (global.set $allocated (i32.add (global.get $allocated) (i32.const 1)))
+            (global.set $alive (i32.add (global.get $alive) (i32.const 1)))
+            (i32.const 8192))
+        (func (export "dealloc") (param i32 i32 i32)
+            (global.set $alive (i32.sub (global.get $alive) (i32.const 1))))
+        (func (export "bounding_box") (param i32) (result i32) (i32.const 0))
+        (func $sample_into (param $out i32) (param $x f32)
+            (f32.store (local.get $out) (local.get $x))
+            (f32.store offset=4 (local.get $out) (f32.convert_i32_s (global.get $allocated)))
+            (f32.store offset=8 (local.get $out) (f32.convert_i32_s (global.get $alive))))
+        (func (export "sample") (param i32 f32 f32 f32 i32) (result i32)
+            (call $sample_into (i32.const 64) (local.get 1))
+            (i32.const 64))
+        (func (export "sample_batch") (param i32) (param $points i32) (param $len i32) (param i32) (result i32)
+            (local $i i32)
+            (block $done (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (i32.div_u (local.get $len) (i32.const 12))))
+                (call $sample_into (i32.add (i32.const 16384) (i32.mul (local.get $i) (i32.const 28)))
+                    (f32.load (i32.add (local.get $points) (i32.mul (local.get $i) (i32.const 12)))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+            (i32.store (i32.const 128) (i32.const 16384))
+            (i32.store (i32.const 132) (i32.mul (local.get $i) (i32.const 28)))
+            (i32.const 128))
+        (func (export "sample_batch_free") (param i32))
+        (func (export "sample_batch_points") (param i32) (result i32) (i32.const 32768))
+        (func (export "set_parameter") (param i32 i32 i32 i32 i32) (result i32)
+            (i32.store (i32.const 320) (i32.const 0))
+            (i32.const 320)))"#;
+
+    #[test]
+    fn test_host_buffers_use_the_allocator() {
+        let small_batch = (0..10).map(|i| Vector3::new(i as f32, 0.0, 0.0)).collect::<Vec<_>>();
+        let large_batch = (0..100).map(|i| Vector3::new(i as f32, 0.0, 0.0)).collect::<Vec<_>>();
+        for (backend, sdf) in load_with_each_backend(ALLOCATOR_WAT) {
+            assert_eq!(sdf.set_parameter(0, &SDFParamValue::String("text".to_string())), Ok(()));
+            let distances = sdf.sample_batch(&small_batch, true).iter().map(|s| s.distance).collect::<Vec<_>>();
+            assert_eq!(distances, small_batch.iter().map(|p| p.x).collect::<Vec<_>>(), "{:?}", backend);
+            assert!(sdf.take_errors().is_empty(), "{:?} recorded errors", backend);
+            // The buffers of the string and the batch were allocated and freed (by the same instance)
+            assert_eq!(sdf.sample(Vector3::new(0.0, 0.0, 0.0), false).color, Vector3::new(2.0, 0.0, 0.0), "{:?}", backend);
+
+            // A refused allocation falls back to the buffer of the module, reporting the failure
+            let distances = sdf.sample_batch(&large_batch, true).iter().map(|s| s.distance).collect::<Vec<_>>();
+            assert_eq!(distances, large_batch.iter().map(|p| p.x).collect::<Vec<_>>(), "{:?}", backend);
+            let errors = sdf.take_errors();
+            assert_eq!(errors.len(), 1, "{:?} recorded {:?}", backend, errors);
+            assert_eq!(errors[0].export.as_deref(), Some("alloc"));
+        }
+
+        // Without dealloc, the module has no usable allocator
+        for (backend, sdf) in load_with_each_backend(&ALLOCATOR_WAT.replace("\"dealloc\"", "\"unused\"")) {
+            let err = sdf.set_parameter(0, &SDFParamValue::String("text".to_string())).unwrap_err();
+            assert!(err.contains("does not export an allocator"), "{:?}: {}", backend, err);
+            assert_eq!(sdf.sample_batch(&large_batch, true).len(), large_batch.len());
+            assert!(sdf.take_errors().is_empty(), "{:?} recorded errors", backend);
+        }
+    }
+}
@@ -2,536 +2,220 @@
 //! It also supports WASI!
 //! It may not support target platforms added in the future.
 
-use std::fmt::Debug;
+use std::collections::HashMap;
 use std::mem::size_of;
-use std::ops::RangeInclusive;
-use std::sync::{Arc, Mutex};
 
-use cgmath::{Vector3, Zero};
-use wasmer::AsStoreRef;
-use wasmer::{AsStoreMut, FunctionEnv};
-use wasmer::{Function, Imports, Instance, Memory, Module, Store, Value};
-use wasmer_wasix::{generate_import_object_from_env, get_wasi_version, WasiEnv};
-use crate::sdf::defaults::{children_default_impl, name_default_impl, parameters_default_impl, set_parameter_default_impl};
-use crate::sdf::wasm::util::reinterpret_i32_as_u32;
-use crate::sdf::wasm::util::reinterpret_u32_as_i32;
-use crate::sdf::{SDFParam, SDFParamKind, SDFParamValue, SDFSample, SDFSurface};
+use wasmer::{Function, Imports, Instance, Memory, MemoryView, RuntimeError, Store, Type, Value};
+use wasmer_wasix::{generate_import_object_from_env, get_wasi_version, WasiEnv, WasiFunctionEnv};
+use crate::sdf::error::SDFError;
+use crate::sdf::wasm::backend::{ModuleInstance, Trap, WasmSDF, WasmValue, EXPORTS};
+#[cfg(all(feature = "module_cache", not(target_arch = "wasm32")))]
+use crate::sdf::wasm::cache;
+use crate::sdf::wasm::console;
+use crate::sdf::wasm::pool;
+use crate::sdf::wasm::wasi::CliWasiEnv;
+#[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+use crate::sdf::wasm::limits;
+use crate::sdf::wasm::limits::ExecutionLimits;
+use crate::sdf::wasm::guest::{GuestMemory, WasmType};
+use crate::sdf::{SDFMetadata, SDFSurface};
 
 #[cfg(all(not(feature = "web"), target_arch = "wasm32"))]
 compile_error!("On wasm32 targets, you need to enable the web feature (and disable any native* features).");
 
 /// Loads the given bytes as a WebAssembly module that is then queried to satisfy the SDF trait.
-pub async fn load_sdf_wasm(wasm_bytes: &[u8]) -> anyhow::Result<Box<dyn SDFSurface>> {
-    unsafe { std::mem::transmute::<anyhow::Result<Box<dyn SDFSurface + Send + Sync>>, anyhow::Result<Box<dyn SDFSurface>>>(load_sdf_wasm_send_sync(wasm_bytes).await) }
+pub async fn load_sdf_wasm_send_sync(wasm_bytes: &[u8], wasi: &CliWasiEnv) -> anyhow::Result<Box<dyn SDFSurface + Send + Sync>> {
+    Ok(Box::new(load_sdf_wasm(wasm_bytes, wasi, pool::pool_size()).await?))
 }
 
-/// Loads the given bytes as a WebAssembly module that is then queried to satisfy the SDF trait.
-pub async fn load_sdf_wasm_send_sync(wasm_bytes: &[u8]) -> anyhow::Result<Box<dyn SDFSurface + Send + Sync>> {
+/// Loads the given bytes as a WebAssembly module, with the given number of instances (see [`pool`]).
+pub(crate) async fn load_sdf_wasm(wasm_bytes: &[u8], wasi: &CliWasiEnv, pool_size: usize) -> anyhow::Result<WasmSDF<WasmerInstance>> {
     // TODO: Test other compilers provided by the wasmer crate
 
-    let mut store = Store::default();
+    #[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+    let execution_limits = ExecutionLimits::current();
+    #[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+    let store = limits::new_store(&execution_limits);
+    #[cfg(not(all(feature = "execution_limits", not(target_arch = "wasm32"))))]
+    let execution_limits = ExecutionLimits::UNLIMITED; // They can't be enforced (e.g. the browser runs the module on web)
+    #[cfg(not(all(feature = "execution_limits", not(target_arch = "wasm32"))))]
+    let store = Store::default();
     #[cfg(target_arch = "wasm32")]
     {
         // HACK: Basic validation because the wasm32 wasmer crate doesn't do it for us.
-        Module::validate(&store, wasm_bytes)?;
+        wasmer::Module::validate(&store, wasm_bytes)?;
+    }
+    #[cfg(all(feature = "module_cache", not(target_arch = "wasm32")))]
+    let module = cache::compile_module(&store, wasm_bytes, &compilation_key(&execution_limits))?;
+    #[cfg(not(all(feature = "module_cache", not(target_arch = "wasm32"))))]
+    let module = wasmer::Module::new(&store, wasm_bytes)?;
+
+    // Read the optional metadata embedded by export_sdf!
+    let metadata = module.custom_sections(SDFMetadata::CUSTOM_SECTION).next()
+        .and_then(|section| SDFMetadata::parse(&section));
+    if let Some(metadata) = &metadata {
+        tracing::info!("Loading SDF exported by {} v{}", metadata.crate_name, metadata.crate_version);
     }
-    let module = Module::new(&store, wasm_bytes)?;
+    let module_name = metadata.as_ref().map(|metadata| metadata.crate_name.as_str()).unwrap_or("sdf");
+
+    // Instantiate the module as many times as configured, sharing the compiled code (see `pool`)
+    #[cfg(target_arch = "wasm32")]
+    let wasi = &wasi.with_downloaded_assets().await?; // Once for all the instances
+    let engine = store.engine().clone();
+    let mut store = Some(store);
+    let mut instances = Vec::with_capacity(pool_size);
+    for index in 0..pool_size {
+        let store = store.take().unwrap_or_else(|| Store::new(engine.clone()));
+        // The output of the secondary instances is told apart by its source in the console
+        let name = if index == 0 { module_name.to_string() } else { format!("{}#{}", module_name, index) };
+        instances.push(instantiate(store, &module, &name, wasi).await?);
+    }
+    if pool_size > 1 {
+        tracing::info!("Created {} instances of the SDF module for parallel sampling", pool_size);
+    }
+
+    WasmSDF::new(instances, metadata, execution_limits)
+}
 
+/// Creates a new instance of the module in the given store, which is initialized by [`WasmSDF::new`].
+pub(crate) async fn instantiate(mut store: Store, module: &wasmer::Module, name: &str, wasi: &CliWasiEnv) -> anyhow::Result<WasmerInstance> {
     // The module shouldn't import anything, except maybe wasix (WASI) functions.
-    let import_object = if let Some(wasi_version) = get_wasi_version(&module, false) {
-        let wasi_env = WasiEnv::builder("program_name").build()?; // Customize env?
-        let function_env = FunctionEnv::new(&mut store, wasi_env);
-        generate_import_object_from_env(&mut store, &function_env, wasi_version)
+    let (import_object, wasi_env) = if let Some(wasi_version) = get_wasi_version(module, false) {
+        let (stdout, stderr) = console::capture_wasi_output(name);
+        let wasi_env = wasi.configure(WasiEnv::builder(name)
+            .stdout(stdout)
+            .stderr(stderr)).await?
+            .build()?;
+        let wasi_env = WasiFunctionEnv::new(&mut store, wasi_env);
+        (generate_import_object_from_env(&mut store, &wasi_env.env, wasi_version), Some(wasi_env))
     } else {
-        Imports::default()
+        (Imports::default(), None)
     };
 
-    let instance = Instance::new(&mut store, &module, &import_object)?;
-
-    // Call init() to initialize the module (optional).
-    if let Ok(init) = instance.exports.get_function("init") {
-        if let Err(err) = init.call(&mut store, &[]) {
-            tracing::error!("Calling init() failed: {:?}", err);
-        }
+    let instance = Instance::new(&mut store, module, &import_object)?;
+    if let Some(mut wasi_env) = wasi_env {
+        wasi_env.initialize(&mut store, instance.clone())?; // Gives the WASI functions access to the memory
     }
 
     // Cache the exports of the module.
     let memory = instance.exports.get_memory("memory")?.clone();
-    let f_bounding_box = instance.exports.get_function("bounding_box")?.clone();
-    let f_bounding_box_free = instance.exports.get_function("bounding_box_free").ok().cloned();
-    let f_sample = instance.exports.get_function("sample")?.clone();
-    let f_sample_free = instance.exports.get_function("sample_free").ok().cloned();
-    let f_children = instance.exports.get_function("children").ok().cloned();
-    let f_children_free = instance.exports.get_function("children_free").ok().cloned();
-    let f_name = instance.exports.get_function("name").ok().cloned();
-    let f_name_free = instance.exports.get_function("name_free").ok().cloned();
-    let f_parameters = instance.exports.get_function("parameters").ok().cloned();
-    let f_parameters_free = instance.exports.get_function("parameters_free").ok().cloned();
-    let f_set_parameter = instance.exports.get_function("set_parameter").ok().cloned();
-    let f_set_parameter_free = instance.exports.get_function("set_parameter_free").ok().cloned();
-    let f_changed = instance.exports.get_function("changed").ok().cloned();
-    let f_changed_free = instance.exports.get_function("changed_free").ok().cloned();
-    let f_normal = instance.exports.get_function("normal").ok().cloned();
-    let f_normal_free = instance.exports.get_function("normal_free").ok().cloned();
+    let functions = EXPORTS.iter()
+        .filter_map(|name| Some((*name, instance.exports.get_function(name).ok()?.clone())))
+        .collect();
+    Ok(WasmerInstance { store, instance, memory, functions })
+}
 
-    Ok(Box::new(WasmerSDF {
-        sdf_id: 0, // This must always be the ID of the root SDF (as specified by the docs)
-        store: Arc::new(Mutex::new(store)),
-        memory,
-        f_bounding_box,
-        f_bounding_box_free,
-        f_sample,
-        f_sample_free,
-        f_children,
-        f_children_free,
-        f_name,
-        f_name_free,
-        f_parameters,
-        f_parameters_free,
-        f_set_parameter,
-        f_set_parameter_free,
-        f_changed,
-        f_changed_free,
-        f_normal,
-        f_normal_free,
-    }))
+/// A key that identifies how modules are compiled for the given limits, for the cache of compiled modules.
+#[cfg(all(feature = "module_cache", not(target_arch = "wasm32")))]
+fn compilation_key(limits: &ExecutionLimits) -> String {
+    format!("metered={} max_memory_bytes={:?}", limits.fuel_per_work.is_some(), limits.max_memory_bytes)
 }
 
 // HACK: Is there a better alternative to implement both return types than macros for code duplication?
 // load_sdf_wasm_code!(load_sdf_wasm_send_sync, anyhow::Result<Box<dyn SDFSurface + Send + Sync>>);
 // load_sdf_wasm_code!(load_sdf_wasm, anyhow::Result<Box<dyn SDFSurface>>);
 
-#[derive(Debug, Clone)] // Note: cloning is "cheap" (implementation details of wasmer)
-pub struct WasmerSDF {
-    sdf_id: u32,
-    store: Arc<Mutex<Store>>,
+/// An instance of the module, with its own store (and memory), that runs one call at a time.
+#[derive(Debug)]
+pub(crate) struct WasmerInstance {
+    store: Store,
+    instance: Instance,
     memory: Memory,
-    f_bounding_box: Function,
-    f_bounding_box_free: Option<Function>,
-    f_sample: Function,
-    f_sample_free: Option<Function>,
-    f_children: Option<Function>,
-    f_children_free: Option<Function>,
-    f_name: Option<Function>,
-    f_name_free: Option<Function>,
-    f_parameters: Option<Function>,
-    f_parameters_free: Option<Function>,
-    f_set_parameter: Option<Function>,
-    f_set_parameter_free: Option<Function>,
-    f_changed: Option<Function>,
-    f_changed_free: Option<Function>,
-    f_normal: Option<Function>,
-    f_normal_free: Option<Function>,
+    /// The exports used by the host (see [`EXPORTS`]) that are available.
+    functions: HashMap<&'static str, Function>,
 }
 
-impl WasmerSDF {
-    fn read_memory(&self, mem_pointer: u32, length: usize, store: &impl AsStoreRef) -> Vec<u8> {
-        let mut res = vec![0u8; length];
-        let mem_view = self.memory.view(store);
-        for (i, v) in res.iter_mut().enumerate() {
-            *v = mem_view.read_u8(mem_pointer as u64 + i as u64).unwrap_or_else(|err| {
-                debug_assert!(false, "Out of bounds memory access at index {mem_pointer}, length {length}");
-                tracing::error!("Out of bounds memory access at index {}, length {}: {:?}", mem_pointer, length, err);
-                0
-            })
-        }
-        res
+impl GuestMemory for WasmerInstance {
+    fn pointer_size(&self) -> usize {
+        self.memory.view(&self.store).pointer_size()
     }
 
-    fn write_memory(&self, mem_pointer: u32, to_write: &[u8], store: &impl AsStoreRef) {
-        // HACK: How to reserve free memory for this instead of randomly overwriting it?
-        // TODO: Maybe try malloc/free, which is published by tinygo binaries.
-        #[allow(unused_unsafe)] // This is not unsafe on wasm32
-        unsafe { // SAFETY: No data races.
-            self.memory.view(store).write(mem_pointer as u64, to_write).unwrap_or_else(|err| {
-                tracing::error!("Out of bounds memory access at index {}, length {}: {:?}", mem_pointer, to_write.len(), err);
-            });
-        }
-    }
-
-    fn read_pointer_length_memory(&self, mem_bytes: Vec<u8>, store: &impl AsStoreRef) -> Vec<u8> {
-        let pointer = u32::from_le_bytes(mem_bytes[0..size_of::<u32>()].try_into().unwrap());
-        let length_bytes = u32::from_le_bytes(mem_bytes[size_of::<u32>()..2 * size_of::<u32>()].try_into().unwrap());
-        // if length_bytes == 8 {
-        //     // DEBUG: "Should" be very stable over time if wasm is properly freeing the memory
-        //     println!("Pointer to 8 bytes at {}", pointer);
-        // }
-        self.read_memory(pointer, length_bytes as usize, store)
+    fn read_bytes(&self, pointer: u64, length: usize) -> Vec<u8> {
+        self.memory.view(&self.store).read_bytes(pointer, length)
     }
 }
 
-impl SDFSurface for WasmerSDF {
-    fn bounding_box(&self) -> [Vector3<f32>; 2] {
-        let mut _store = self.store.lock().unwrap();
-        let result = self.f_bounding_box.call(&mut _store.as_store_mut(), &[
-            Value::I32(reinterpret_u32_as_i32(self.sdf_id)),
-        ]).unwrap_or_else(|err| {
-            tracing::error!("Failed to get bounding box of wasm SDF with ID {}: {}", self.sdf_id, err);
-            Box::new([])
-        });
-        let mut res = [Vector3::<f32>::zero(), Vector3::<f32>::new(1.0, 1.0, 1.0)];
-        let mem_pointer = match return_value_to_mem_pointer(&result) {
-            Some(mem_pointer) => mem_pointer,
-            None => return res, // Errors already logged
-        };
-        let mem_bytes = self.read_memory(mem_pointer, size_of::<[Vector3<f32>; 2]>(), &_store.as_store_ref());
-        self.f_bounding_box_free.as_ref().map(|f| f.call(&mut _store.as_store_mut(), &result)); // Free the memory, now that we copied it
-        res[0].x = f32::from_le_bytes(mem_bytes[0..size_of::<f32>()].try_into().unwrap());
-        res[0].y = f32::from_le_bytes(mem_bytes[size_of::<f32>()..2 * size_of::<f32>()].try_into().unwrap());
-        res[0].z = f32::from_le_bytes(mem_bytes[2 * size_of::<f32>()..3 * size_of::<f32>()].try_into().unwrap());
-        res[1].x = f32::from_le_bytes(mem_bytes[3 * size_of::<f32>()..4 * size_of::<f32>()].try_into().unwrap());
-        res[1].y = f32::from_le_bytes(mem_bytes[4 * size_of::<f32>()..5 * size_of::<f32>()].try_into().unwrap());
-        res[1].z = f32::from_le_bytes(mem_bytes[5 * size_of::<f32>()..6 * size_of::<f32>()].try_into().unwrap());
-        res
+impl ModuleInstance for WasmerInstance {
+    fn signature(&self, export: &str) -> Option<(Vec<WasmType>, Vec<WasmType>)> {
+        let ty = self.functions.get(export)?.ty(&self.store);
+        Some((ty.params().iter().map(wasm_type).collect(), ty.results().iter().map(wasm_type).collect()))
     }
 
-    fn sample(&self, p: Vector3<f32>, distance_only: bool) -> SDFSample {
-        let mut _store = self.store.lock().unwrap();
-        let result = self.f_sample.call(&mut _store.as_store_mut(), &[
-            Value::I32(reinterpret_u32_as_i32(self.sdf_id)),
-            Value::F32(p.x),
-            Value::F32(p.y),
-            Value::F32(p.z),
-            Value::I32(i32::from(distance_only)),
-        ]).unwrap_or_else(|err| {
-            tracing::error!("Failed to get sample of wasm SDF with ID {}: {}", self.sdf_id, err);
-            Box::new([])
-        });
-        let mem_pointer = match return_value_to_mem_pointer(&result) {
-            Some(mem_pointer) => mem_pointer,
-            None => return SDFSample::new(1.0, Vector3::zero()), // Errors already logged
-        };
-        let mem_bytes = self.read_memory(mem_pointer, size_of::<SDFSample>(), &_store.as_store_ref());
-        self.f_sample_free.as_ref().map(|f| f.clone().call(&mut _store.as_store_mut(), &result)); // Free the memory, now that we copied it
-        SDFSample {
-            distance: f32::from_le_bytes(mem_bytes[0..size_of::<f32>()].try_into().unwrap()),
-            color: Vector3::new(
-                f32::from_le_bytes(mem_bytes[size_of::<f32>()..2 * size_of::<f32>()].try_into().unwrap()),
-                f32::from_le_bytes(mem_bytes[2 * size_of::<f32>()..3 * size_of::<f32>()].try_into().unwrap()),
-                f32::from_le_bytes(mem_bytes[3 * size_of::<f32>()..4 * size_of::<f32>()].try_into().unwrap()),
-            ),
-            metallic: f32::from_le_bytes(mem_bytes[4 * size_of::<f32>()..5 * size_of::<f32>()].try_into().unwrap()),
-            roughness: f32::from_le_bytes(mem_bytes[5 * size_of::<f32>()..6 * size_of::<f32>()].try_into().unwrap()),
-            occlusion: f32::from_le_bytes(mem_bytes[6 * size_of::<f32>()..7 * size_of::<f32>()].try_into().unwrap()),
+    fn call(&mut self, export: &str, args: &[WasmValue], fuel: Option<u64>) -> Result<Vec<WasmValue>, Trap> {
+        let function = self.functions.get(export)
+            .ok_or_else(|| SDFError::new(format!("the SDF module does not export `{export}`")).with_export(export))?;
+        #[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+        if let Some(fuel) = fuel {
+            limits::set_fuel(&mut self.store, &self.instance, fuel);
+        }
+        #[cfg(not(all(feature = "execution_limits", not(target_arch = "wasm32"))))]
+        let _ = fuel; // Instructions are only metered on native
+        let args = args.iter().map(|arg| match *arg {
+            WasmValue::I32(value) => Value::I32(value),
+            WasmValue::F32(value) => Value::F32(value),
+        }).collect::<Vec<_>>();
+        match function.call(&mut self.store, &args) {
+            Ok(results) => results.iter().map(|result| match result {
+                Value::I32(value) => Ok(WasmValue::I32(*value)),
+                Value::F32(value) => Ok(WasmValue::F32(*value)),
+                other => Err(SDFError::new(format!("unsupported result {other:?}")).with_export(export).into()),
+            }).collect(),
+            Err(err) => {
+                #[cfg(all(feature = "execution_limits", not(target_arch = "wasm32")))]
+                let out_of_fuel = fuel.is_some() && limits::fuel_exhausted(&mut self.store, &self.instance);
+                #[cfg(not(all(feature = "execution_limits", not(target_arch = "wasm32"))))]
+                let out_of_fuel = false;
+                Err(Trap { error: trap_error(export, &err), out_of_fuel })
+            }
         }
     }
 
-    fn children(&self) -> Vec<Box<dyn SDFSurface>> {
-        let mut _store = self.store.lock().unwrap();
-        let f_children = match &self.f_children {
-            Some(f_children) => f_children,
-            None => return children_default_impl(self),
-        };
-        let result = f_children.clone().call(&mut _store.as_store_mut(), &[
-            Value::I32(reinterpret_u32_as_i32(self.sdf_id)),
-        ]).unwrap_or_else(|err| {
-            tracing::error!("Failed to get children of wasm SDF with ID {}: {}", self.sdf_id, err);
-            Box::new([])
-        });
-        let mem_pointer = match return_value_to_mem_pointer(&result) {
-            Some(mem_pointer) => mem_pointer,
-            None => return children_default_impl(self), // Errors already logged
-        };
-        let pointer_length = self.read_memory(mem_pointer, 2 * size_of::<u32>(), &_store.as_store_ref());
-        let mem_bytes = self.read_pointer_length_memory(pointer_length, &_store.as_store_ref());
-        self.f_children_free.as_ref().map(|f| f.call(&mut _store.as_store_mut(), &result)); // Free the memory, now that we copied it
-        mem_bytes.chunks_exact(size_of::<u32>())
-            .map(|ch| u32::from_le_bytes(ch.try_into().unwrap()))
-            .filter_map(|child_sdf_id| {
-                if child_sdf_id == self.sdf_id {
-                    tracing::error!("Children of wasm SDF with ID {} include itself! Skipping, but this should be fixed.", self.sdf_id);
-                    return None;
-                }
-                Some(Box::new(Self {
-                    sdf_id: child_sdf_id,
-                    ..self.clone() // Cloning is cheap and shares the memory of children parameters
-                }) as Box<dyn SDFSurface>)
-            }).collect()
+    fn write_memory(&mut self, mem_pointer: u32, to_write: &[u8]) {
+        #[allow(unused_unsafe)] // This is not unsafe on wasm32
+        unsafe { // SAFETY: No data races.
+            self.memory.view(&self.store).write(mem_pointer as u64, to_write).unwrap_or_else(|err| {
+                tracing::error!("Out of bounds memory access at index {}, length {}: {:?}", mem_pointer, to_write.len(), err);
+            });
+        }
     }
+}
 
-    fn id(&self) -> u32 {
-        self.sdf_id // Already known!
+/// The type of a value in the shared host, for checking the signatures of the exports.
+fn wasm_type(ty: &Type) -> WasmType {
+    match ty {
+        Type::I32 => WasmType::I32,
+        Type::F32 => WasmType::F32,
+        Type::I64 => WasmType::I64,
+        Type::F64 => WasmType::F64,
+        _ => WasmType::Other,
     }
+}
 
-    fn name(&self) -> String {
-        let mut _store = self.store.lock().unwrap();
-        let f_name = match &self.f_name {
-            Some(f_name) => f_name,
-            None => return name_default_impl(self),
-        };
-        let result = f_name.call(&mut _store.as_store_mut(), &[
-            Value::I32(reinterpret_u32_as_i32(self.sdf_id)),
-        ]).unwrap_or_else(|err| {
-            tracing::error!("Failed to get name of wasm SDF with ID {}: {}", self.sdf_id, err);
-            Box::new([])
-        });
-        let mem_pointer = match return_value_to_mem_pointer(&result) {
-            Some(mem_pointer) => mem_pointer,
-            None => return name_default_impl(self), // Errors already logged
-        };
-        let pointer_length = self.read_memory(mem_pointer, 2 * size_of::<u32>(), &_store.as_store_ref());
-        let mem_bytes = self.read_pointer_length_memory(pointer_length, &_store.as_store_ref());
-        self.f_name_free.as_ref().map(|f| f.call(&mut _store.as_store_mut(), &result)); // Free the memory, now that we copied it
-        String::from_utf8_lossy(mem_bytes.as_slice()).to_string()
+impl GuestMemory for MemoryView<'_> {
+    fn pointer_size(&self) -> usize {
+        size_of::<u32>() // wasm32
     }
 
-    fn parameters(&self) -> Vec<SDFParam> {
-        let mut _store = self.store.lock().unwrap();
-        let f_parameters = match &self.f_parameters {
-            Some(f_parameters) => f_parameters,
-            None => return parameters_default_impl(self),
-        };
-        let result = f_parameters.call(&mut _store.as_store_mut(), &[
-            Value::I32(reinterpret_u32_as_i32(self.sdf_id)),
-        ]).unwrap_or_else(|err| {
-            tracing::error!("Failed to get parameters of wasm SDF with ID {}: {}", self.sdf_id, err);
-            Box::new([])
-        });
-        let mem_pointer = match return_value_to_mem_pointer(&result) {
-            Some(mem_pointer) => mem_pointer,
-            None => return parameters_default_impl(self), // Errors already logged
-        };
-        let pointer_length = self.read_memory(mem_pointer, 2 * size_of::<u32>(), &_store.as_store_ref());
-        let mem_bytes = self.read_pointer_length_memory(pointer_length, &_store.as_store_ref());
-        let res = mem_bytes.chunks_exact(
-            size_of::<u32>() /* param ID */ +
-                2 * size_of::<u32>() /* name pointer */ +
-                size_of::<u32>() + 3 * size_of::<f32>() /* SDFParamKindC */ +
-                size_of::<u32>() + 2 * size_of::<u32>() /* SDFParamValueC */ +
-                2 * size_of::<u32>() /* description pointer */)
-            .filter_map(|sdf_param_mem| {
-                let mut cur_offset = 0;
-                /* param ID */
-                let param_id = u32::from_le_bytes(sdf_param_mem[cur_offset..cur_offset + size_of::<u32>()].try_into().unwrap());
-                cur_offset += size_of::<u32>();
-                /* name pointer */
-                let name_pointer_length = sdf_param_mem[cur_offset..cur_offset + 2 * size_of::<u32>()].into();
-                let name_mem_bytes = self.read_pointer_length_memory(name_pointer_length, &_store.as_store_ref());
-                // println!("sdf_param_mem: {:?} (name: {:?})", sdf_param_mem, String::from_utf8_lossy(&name_mem_bytes));
-                cur_offset += 2 * size_of::<u32>();
-                /* SDFParamKindC */
-                let sdf_param_kind_enum_type = /* enum index = u32 */
-                    u32::from_le_bytes(sdf_param_mem[cur_offset..cur_offset + size_of::<u32>()].try_into().unwrap());
-                cur_offset += size_of::<u32>();
-                let sdf_param_kind = match sdf_param_kind_enum_type {
-                    0 => SDFParamKind::Boolean,
-                    1 => SDFParamKind::Int {
-                        range: RangeInclusive::new(
-                            i32::from_le_bytes(sdf_param_mem[cur_offset..cur_offset + size_of::<i32>()].try_into().unwrap()),
-                            i32::from_le_bytes(sdf_param_mem[cur_offset + size_of::<i32>()..cur_offset + 2 * size_of::<i32>()].try_into().unwrap()),
-                        ),
-                        step: i32::from_le_bytes(sdf_param_mem[cur_offset + 2 * size_of::<i32>()..cur_offset + 3 * size_of::<i32>()].try_into().unwrap()),
-                    },
-                    2 => SDFParamKind::Float {
-                        range: RangeInclusive::new(
-                            f32::from_le_bytes(sdf_param_mem[cur_offset..cur_offset + size_of::<f32>()].try_into().unwrap()),
-                            f32::from_le_bytes(sdf_param_mem[cur_offset + size_of::<f32>()..cur_offset + 2 * size_of::<f32>()].try_into().unwrap()),
-                        ),
-                        step: f32::from_le_bytes(sdf_param_mem[cur_offset + 2 * size_of::<f32>()..cur_offset + 3 * size_of::<f32>()].try_into().unwrap()),
-                    },
-                    3 => {
-                        let choices_pointer_length = sdf_param_mem[cur_offset..cur_offset + 2 * size_of::<u32>()].into();
-                        let choices_mem_bytes = self.read_pointer_length_memory(choices_pointer_length, &_store.as_store_ref());
-                        let choices = choices_mem_bytes.chunks_exact(2 * size_of::<u32>())
-                            .map(|choice_mem_bytes| {
-                                let choice_mem_bytes = self.read_pointer_length_memory(choice_mem_bytes.to_vec(), &_store.as_store_ref());
-                                String::from_utf8_lossy(choice_mem_bytes.as_slice()).to_string()
-                            })
-                            .collect();
-                        SDFParamKind::String { choices }
-                    }
-                    _ => {
-                        debug_assert!(false, "Unknown SDF param kind enum type {sdf_param_kind_enum_type}");
-                        tracing::error!("Unknown SDF param kind enum type {}", sdf_param_kind_enum_type); // TODO: less logging in case of multiple errors
-                        return None;
-                    }
-                };
-                cur_offset += size_of::<f32>() * 3; // Maximum size of SDFParamKindC
-                /* SDFParamValueC */
-                let sdf_param_value_enum_type = /* enum index = u32 */
-                    u32::from_le_bytes(sdf_param_mem[cur_offset..cur_offset + size_of::<u32>()].try_into().unwrap());
-                debug_assert_eq!(sdf_param_kind_enum_type, sdf_param_value_enum_type, "SDF param kind enum type {sdf_param_kind_enum_type} != SDF param value enum type {sdf_param_value_enum_type}");
-                cur_offset += size_of::<u32>();
-                let sdf_param_value = match sdf_param_value_enum_type {
-                    0 => SDFParamValue::Boolean(sdf_param_mem[cur_offset] != 0) /* bool = u8 */,
-                    1 => SDFParamValue::Int(i32::from_le_bytes(sdf_param_mem[cur_offset..cur_offset + size_of::<i32>()].try_into().unwrap())),
-                    2 => SDFParamValue::Float(f32::from_le_bytes(sdf_param_mem[cur_offset..cur_offset + size_of::<f32>()].try_into().unwrap())),
-                    3 => {
-                        let value_pointer_length = sdf_param_mem[cur_offset..cur_offset + 2 * size_of::<u32>()].into();
-                        let value_mem_bytes = self.read_pointer_length_memory(value_pointer_length, &_store.as_store_ref());
-                        SDFParamValue::String(String::from_utf8_lossy(value_mem_bytes.as_slice()).to_string())
-                    }
-                    _ => {
-                        debug_assert!(false, "Unknown SDF param value enum type {sdf_param_value_enum_type}");
-                        tracing::error!("Unknown SDF param value enum type {}", sdf_param_value_enum_type); // TODO: less logging in case of multiple errors
-                        return None;
-                    }
-                };
-                cur_offset += 2 * size_of::<u32>(); // Maximum size of SDFParamValueC
-                /* description */
-                let desc_pointer_length = sdf_param_mem[cur_offset..cur_offset + 2 * size_of::<u32>()].into();
-                let desc_mem_bytes = self.read_pointer_length_memory(desc_pointer_length, &_store.as_store_ref());
-
-                Some(SDFParam {
-                    id: param_id,
-                    name: String::from_utf8_lossy(name_mem_bytes.as_slice()).to_string(),
-                    kind: sdf_param_kind,
-                    value: sdf_param_value,
-                    description: String::from_utf8_lossy(desc_mem_bytes.as_slice()).to_string(),
-                })
+    fn read_bytes(&self, pointer: u64, length: usize) -> Vec<u8> {
+        let mut res = vec![0u8; length];
+        for (i, v) in res.iter_mut().enumerate() {
+            *v = self.read_u8(pointer + i as u64).unwrap_or_else(|err| {
+                debug_assert!(false, "Out of bounds memory access at index {pointer}, length {length}");
+                tracing::error!("Out of bounds memory access at index {}, length {}: {:?}", pointer, length, err);
+                0
             })
-            .collect();
-        self.f_parameters_free.as_ref().map(|f| f.call(&mut _store.as_store_mut(), &result)); // Free the memory, now that we copied it
-        res
-    }
-
-    fn set_parameter(&self, param_id: u32, param_value: &SDFParamValue) -> Result<(), String> {
-        let mut _store = self.store.lock().unwrap();
-        let f_set_parameter = match &self.f_set_parameter {
-            Some(f_set_parameter) => f_set_parameter,
-            None => return set_parameter_default_impl(self, param_id, param_value),
-        };
-        let tmp_store_mut = &mut _store.as_store_mut();
-        let result = f_set_parameter.call(tmp_store_mut, &[
-            Value::I32(reinterpret_u32_as_i32(self.sdf_id)),
-            Value::I32(reinterpret_u32_as_i32(param_id)),
-            Value::I32(match param_value {
-                SDFParamValue::Boolean(_value) => 0,
-                SDFParamValue::Int(_value) => 1,
-                SDFParamValue::Float(_value) => 2,
-                SDFParamValue::String(_value) => 3,
-            }),
-            Value::I32(match param_value {
-                SDFParamValue::Boolean(value) => i32::from(*value),
-                SDFParamValue::Int(value) => *value,
-                SDFParamValue::Float(value) => unsafe { *(value as *const f32 as *const i32) }, // f32 bits to i32
-                SDFParamValue::String(value) => {
-                    let write_string_address = 0x12345;
-                    self.write_memory(write_string_address, value.as_bytes(), &tmp_store_mut);
-                    reinterpret_u32_as_i32(write_string_address)
-                }
-            }),
-            Value::I32(match param_value {
-                SDFParamValue::String(value) => reinterpret_u32_as_i32(value.len() as u32),
-                _ => 0, // Unused
-            }),
-        ]).unwrap_or_else(|err| {
-            tracing::error!("Failed to get parameters of wasm SDF with ID {}: {}", self.sdf_id, err);
-            Box::new([])
-        });
-        let mem_pointer = match return_value_to_mem_pointer(&result) {
-            Some(mem_pointer) => mem_pointer,
-            None => return set_parameter_default_impl(self, param_id, param_value), // Errors already logged
-        };
-        let mem_bytes = self.read_memory(mem_pointer, 3 * size_of::<u32>(), &_store.as_store_ref());
-        let mut cur_offset = 0;
-        let enum_result_kind = u32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<u32>()].try_into().unwrap());
-        cur_offset += size_of::<u32>();
-        let res = match enum_result_kind {
-            0 => Ok(()),
-            1 => {
-                let error_string_ptr = u32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<u32>()].try_into().unwrap());
-                cur_offset += size_of::<u32>();
-                let error_string_length = u32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<u32>()].try_into().unwrap());
-                // cur_offset += size_of::<u32>();
-                let error_string_bytes = self.read_memory(error_string_ptr, error_string_length as usize, &_store.as_store_ref());
-                Err(String::from_utf8_lossy(&error_string_bytes[..]).to_string())
-            }
-            _ => {
-                debug_assert!(false, "Unknown SDF set parameter result kind enum type {enum_result_kind}");
-                tracing::error!("Unknown SDF set parameter result kind enum type {}", enum_result_kind); // TODO: less logging in case of multiple errors
-                Err(String::from("Unknown SDF set parameter result kind enum type"))
-            }
-        };
-        self.f_set_parameter_free.as_ref().map(|f| f.call(&mut _store.as_store_mut(), &result)); // Free the memory, now that we copied it
-        res
-    }
-
-    fn changed(&self) -> Option<[Vector3<f32>; 2]> {
-        let mut _store = self.store.lock().unwrap();
-        let f_changed = match &self.f_changed {
-            Some(f_changed) => f_changed,
-            None => return None,
-        };
-        let result = f_changed.clone().call(&mut _store.as_store_mut(), &[
-            Value::I32(reinterpret_u32_as_i32(self.sdf_id)),
-        ]).unwrap_or_else(|err| {
-            tracing::error!("Failed to get changed of wasm SDF with ID {}: {}", self.sdf_id, err);
-            Box::new([])
-        });
-        let mem_pointer = return_value_to_mem_pointer(&result)?;
-        let mem_bytes = self.read_memory(mem_pointer, (1 + 6) * size_of::<f32>(), &_store.as_store_ref());
-        let mut cur_offset = 0;
-        let enum_result_kind = u32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<u32>()].try_into().unwrap());
-        cur_offset += size_of::<u32>();
-        let res = match enum_result_kind {
-            0 => None,
-            1 => {
-                let x = f32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<f32>()].try_into().unwrap());
-                cur_offset += size_of::<f32>();
-                let y = f32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<f32>()].try_into().unwrap());
-                cur_offset += size_of::<f32>();
-                let z = f32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<f32>()].try_into().unwrap());
-                cur_offset += size_of::<f32>();
-                let x2 = f32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<f32>()].try_into().unwrap());
-                cur_offset += size_of::<f32>();
-                let y2 = f32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<f32>()].try_into().unwrap());
-                cur_offset += size_of::<f32>();
-                let z2 = f32::from_le_bytes(mem_bytes[cur_offset..cur_offset + size_of::<f32>()].try_into().unwrap());
-                // cur_offset += size_of::<f32>();
-                Some([Vector3::new(x, y, z), Vector3::new(x2, y2, z2)])
-            }
-            _ => {
-                debug_assert!(false, "Unknown SDF changed result kind enum type {enum_result_kind}");
-                tracing::error!("Unknown SDF changed result kind enum type {}", enum_result_kind); // TODO: less logging in case of multiple errors
-                None
-            }
-        };
-        self.f_changed_free.as_ref().map(|f| f.call(&mut _store.as_store_mut(), &result)); // Free the memory, now that we copied it
+        }
         res
     }
-
-    fn normal(&self, p: Vector3<f32>, eps: Option<f32>) -> Vector3<f32> {
-        let mut _store = self.store.lock().unwrap();
-        let f_normal = match &self.f_normal {
-            Some(f_normal) => f_normal,
-            None => return Vector3::new(0.0, 0.0, 0.0),
-        };
-        let tmp_store_mut = &mut _store.as_store_mut();
-        let result = f_normal.call(tmp_store_mut, &[
-            Value::I32(reinterpret_u32_as_i32(self.sdf_id)),
-            Value::F32(p.x),
-            Value::F32(p.y),
-            Value::F32(p.z),
-            Value::F32(eps.unwrap_or(-1.0)),
-        ]).unwrap_or_else(|err| {
-            tracing::error!("Failed to get normal of wasm SDF with ID {}: {}", self.sdf_id, err);
-            Box::new([])
-        });
-        let mem_pointer = match return_value_to_mem_pointer(&result) {
-            Some(mem_pointer) => mem_pointer,
-            None => return Vector3::new(0.0, 0.0, 0.0), // Errors already logged
-        };
-        let mem_bytes = self.read_memory(mem_pointer, 3 * size_of::<f32>(), &_store.as_store_ref());
-        let x = f32::from_le_bytes(mem_bytes[0..size_of::<f32>()].try_into().unwrap());
-        let y = f32::from_le_bytes(mem_bytes[size_of::<f32>()..2 * size_of::<f32>()].try_into().unwrap());
-        let z = f32::from_le_bytes(mem_bytes[2 * size_of::<f32>()..3 * size_of::<f32>()].try_into().unwrap());
-        self.f_normal_free.as_ref().map(|f| f.call(&mut _store.as_store_mut(), &result)); // Free the memory, now that we copied it
-        Vector3::new(x, y, z)
-    }
 }
 
-fn return_value_to_mem_pointer(result: &[Value]) -> Option<u32> {
-    if result.len() != 1 {
-        tracing::error!("Expected 1 output, got {}", result.len());
-        return None;
-    }
-    let mem_pointer = match result[0].i32() {
-        Some(pointer) => reinterpret_i32_as_u32(pointer),
-        None => {
-            tracing::error!("Expected i32 output for bounding_box(), got {:?}", result[0]);
-            return None;
-        }
-    };
-    Some(mem_pointer)
+/// Converts a trap of a call into the given export to an error that can be reported to the user.
+fn trap_error(export: &str, err: &RuntimeError) -> SDFError {
+    let backtrace = err.trace().iter()
+        .map(|frame| format!("{}!{} at offset {:#x}",
+                             frame.module_name(),
+                             frame.function_name().map(|name| name.to_string()).unwrap_or_else(|| format!("<function {}>", frame.func_index())),
+                             frame.module_offset()))
+        .collect();
+    SDFError::new(err.message()).with_export(export).with_backtrace(backtrace)
 }
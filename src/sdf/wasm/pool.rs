@@ -0,0 +1,129 @@
+//! A pool of independent instances of the same WebAssembly module, so that SDFs can be sampled
+//! from many threads at once (like while meshing or loading voxels), as each instance can only run
+//! one call at a time.
+//!
+//! All the instances are created at load time and kept in sync by applying every parameter change
+//! to each of them. Queries that depend on the state of the module (like
+//! [`changed`](crate::sdf::SDFSurface::changed)) are only answered by the primary instance.
+//!
+//! There is only one instance on web, as the browser runs the module on the main thread.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// The configured number of instances of each module, or 0 to choose one per core (see `CliInstancePool::apply`).
+#[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+static POOL_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// The minimum number of points sampled by each instance when splitting a batch between them, as
+/// smaller batches are faster to sample by a single instance.
+#[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+pub const MIN_POINTS_PER_INSTANCE: usize = 1024;
+
+/// The number of instances of each module loaded from now on.
+pub fn pool_size() -> usize {
+    #[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+    {
+        match POOL_SIZE.load(Ordering::Relaxed) {
+            0 => std::thread::available_parallelism().map(|cores| cores.get()).unwrap_or(1),
+            size => size,
+        }
+    }
+    #[cfg(not(all(feature = "instance_pool", not(target_arch = "wasm32"))))]
+    {
+        1
+    }
+}
+
+/// Options for the parallel sampling of WebAssembly SDFs, shared by all commands.
+#[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+#[derive(clap::Parser, Debug, Clone, PartialEq, Eq)]
+pub struct CliInstancePool {
+    /// The number of instances of each WebAssembly SDF, which sample in parallel. Each one uses its
+    /// own memory and is created and initialized at load time (also on every reload), so lower it
+    /// if loading is too slow. 0 uses one per core.
+    #[clap(long, global = true, default_value = "0")]
+    pub wasm_instances: usize,
+}
+
+#[cfg(all(feature = "instance_pool", not(target_arch = "wasm32")))]
+impl CliInstancePool {
+    /// Configures the number of instances for all the following loads.
+    pub fn apply(&self) {
+        POOL_SIZE.store(self.wasm_instances, Ordering::Relaxed);
+    }
+}
+
+/// The instances of a module, the first one being the primary instance.
+#[derive(Debug)]
+pub struct InstancePool<T> {
+    instances: Vec<Mutex<T>>,
+    /// The instance to wait for when all of them are busy, rotated to spread the load.
+    next: AtomicUsize,
+}
+
+impl<T> InstancePool<T> {
+    /// Creates a pool with the given instances, which must not be empty.
+    pub fn new(instances: Vec<T>) -> Self {
+        assert!(!instances.is_empty(), "an instance pool needs at least one instance");
+        Self { instances: instances.into_iter().map(Mutex::new).collect(), next: AtomicUsize::new(0) }
+    }
+
+    /// The number of instances.
+    pub fn size(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// Locks the primary instance, which answers all the queries that are not sampling.
+    pub fn primary(&self) -> MutexGuard<'_, T> {
+        Self::lock(&self.instances[0])
+    }
+
+    /// Locks any instance, preferring the ones that are not busy (and then the secondary ones, to
+    /// leave the primary instance for other queries).
+    pub fn acquire(&self) -> MutexGuard<'_, T> {
+        let len = self.instances.len();
+        for i in 1..=len {
+            if let Ok(guard) = self.instances[i % len].try_lock() {
+                return guard;
+            }
+        }
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % len;
+        Self::lock(&self.instances[i])
+    }
+
+    /// Locks every secondary instance in turn, like for replaying a change of the primary one.
+    pub fn for_each_secondary(&self, mut f: impl FnMut(usize, &mut T)) {
+        for (i, instance) in self.instances.iter().enumerate().skip(1) {
+            f(i, &mut Self::lock(instance));
+        }
+    }
+
+    /// Locks an instance, even if a previous call panicked while using it (its state is managed by
+    /// the module, which reports its own errors).
+    fn lock(instance: &Mutex<T>) -> MutexGuard<'_, T> {
+        instance.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sdf::wasm::pool::InstancePool;
+
+    #[test]
+    fn test_acquire_prefers_free_instances() {
+        let pool = InstancePool::new(vec![0, 1, 2]);
+        let first = pool.acquire();
+        let second = pool.acquire();
+        assert_eq!((*first, *second), (1, 2));
+        assert_eq!(*pool.acquire(), 0); // Only the primary instance is free
+        let primary = pool.primary();
+        drop(first);
+        assert_eq!(*pool.acquire(), 1);
+        drop((second, primary));
+
+        let mut replayed = vec![];
+        pool.for_each_secondary(|i, instance| replayed.push((i, *instance)));
+        assert_eq!(replayed, vec![(1, 1), (2, 2)]);
+    }
+}
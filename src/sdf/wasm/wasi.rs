@@ -0,0 +1,240 @@
+//! The environment of the SDF modules that import WASI functions: their arguments, environment
+//! variables and a read-only directory of assets, so that they can load data (like lookup tables,
+//! fonts or heightmaps) at `init()`.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+#[cfg(feature = "wasmer_backend")]
+use wasmer_wasix::WasiEnvBuilder;
+
+/// Where the assets are mounted in the filesystem of the module (read-only).
+pub const WASI_ASSETS_DIR: &str = "/assets";
+
+/// Options for the environment of SDF modules that use WASI (ignored by other modules).
+#[derive(clap::Parser, Debug, Clone, PartialEq, Eq, Default)]
+pub struct CliWasiEnv {
+    /// An argument for the SDF module, after the name of the module. Repeat it to pass more arguments.
+    #[clap(long = "wasi-arg", allow_hyphen_values = true)]
+    pub wasi_args: Vec<String>,
+    /// An environment variable for the SDF module, as KEY=VALUE. Repeat it to set more variables.
+    #[clap(long = "wasi-env", parse(try_from_str = parse_env_var))]
+    pub wasi_envs: Vec<(String, String)>,
+    /// A local directory that the SDF module can read (but not modify) at /assets.
+    #[cfg(not(target_arch = "wasm32"))]
+    #[clap(long, parse(from_os_str), value_hint = clap::ValueHint::DirPath)]
+    pub wasi_assets_dir: Option<PathBuf>,
+    /// The URL of a file that is downloaded to an in-memory directory that the SDF module can read
+    /// (but not modify) at /assets, keeping its file name. Repeat it to add more files.
+    #[cfg(target_arch = "wasm32")]
+    #[clap(long = "wasi-asset")]
+    pub wasi_assets: Vec<String>,
+    /// The assets of the bundle that contained the module (see [`super::input`]), by their paths
+    /// relative to /assets. They replace any other assets.
+    #[clap(skip)]
+    pub wasi_bundled_assets: Vec<(String, Vec<u8>)>,
+}
+
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, got {s:?}"))?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+impl CliWasiEnv {
+    /// Configures the environment of a module with these options. On web, the assets must have been
+    /// downloaded with [`CliWasiEnv::with_downloaded_assets`] first.
+    #[cfg(feature = "wasmer_backend")]
+    pub async fn configure(&self, builder: WasiEnvBuilder) -> anyhow::Result<WasiEnvBuilder> {
+        let mut builder = builder.args(&self.wasi_args).envs(self.wasi_envs.iter().cloned());
+        let files = self.wasi_bundled_assets.clone();
+        #[cfg(target_arch = "wasm32")]
+        if files.is_empty() && !self.wasi_assets.is_empty() {
+            tracing::warn!("Ignoring the WASI assets that were not downloaded: {:?}", self.wasi_assets);
+        }
+        if !files.is_empty() {
+            #[cfg(not(target_arch = "wasm32"))]
+            if self.wasi_assets_dir.is_some() {
+                tracing::warn!("Ignoring the WASI assets directory, as the SDF bundle provides its own assets");
+            }
+            builder = builder.fs(Box::new(in_memory_assets(files).await?))
+                .preopen_build(|p| p.directory(WASI_ASSETS_DIR).alias(WASI_ASSETS_DIR).read(true).write(false).create(false))?;
+        } else {
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(dir) = &self.wasi_assets_dir {
+                builder = builder.preopen_build(|p| p.directory(dir).alias(WASI_ASSETS_DIR).read(true).write(false).create(false))?;
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Downloads the assets (there is no filesystem on web), returning options that provide them like
+    /// the assets of a bundle. Call it once per load, so that all the instances of a module share them.
+    #[cfg(all(feature = "wasmer_backend", target_arch = "wasm32"))]
+    pub async fn with_downloaded_assets(&self) -> anyhow::Result<Self> {
+        let mut wasi = self.clone();
+        if wasi.wasi_bundled_assets.is_empty() {
+            wasi.wasi_bundled_assets = self.download_assets().await?;
+        }
+        Ok(wasi)
+    }
+
+    /// Downloads all the assets, keeping their file names.
+    #[cfg(all(feature = "wasmer_backend", target_arch = "wasm32"))]
+    async fn download_assets(&self) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let mut files = vec![];
+        for url in &self.wasi_assets {
+            let resp = ehttp::fetch_async(ehttp::Request::get(url)).await.map_err(|err| anyhow::anyhow!(err))?;
+            if !resp.ok {
+                anyhow::bail!("Failed to download WASI asset {:?}: status {} {}", url, resp.status, resp.status_text);
+            }
+            let name = url.split(['?', '#']).next().unwrap_or_default().rsplit('/').next().unwrap_or_default();
+            if name.is_empty() {
+                anyhow::bail!("The URL of WASI asset {:?} has no file name", url);
+            }
+            tracing::info!("Downloaded WASI asset {:?} to {:?} ({} bytes)", url, name, resp.bytes.len());
+            files.push((name.to_string(), resp.bytes));
+        }
+        Ok(files)
+    }
+}
+
+/// Creates an in-memory filesystem with the given files (and their parent directories) in the assets directory.
+#[cfg(feature = "wasmer_backend")]
+async fn in_memory_assets(files: Vec<(String, Vec<u8>)>) -> anyhow::Result<wasmer_wasix::virtual_fs::mem_fs::FileSystem> {
+    use std::path::Path;
+
+    use tokio::io::AsyncWriteExt;
+    use wasmer_wasix::virtual_fs::FileSystem;
+
+    let fs = wasmer_wasix::virtual_fs::mem_fs::FileSystem::default();
+    fs.create_dir(Path::new(WASI_ASSETS_DIR))?;
+    for (name, bytes) in files {
+        let path = Path::new(WASI_ASSETS_DIR).join(&name);
+        let mut dir = Path::new(WASI_ASSETS_DIR).to_path_buf();
+        for component in Path::new(&name).parent().into_iter().flat_map(|parent| parent.components()) {
+            dir.push(component);
+            if fs.metadata(&dir).is_err() {
+                fs.create_dir(&dir)?;
+            }
+        }
+        let mut file = fs.new_open_options().write(true).create(true).truncate(true).open(&path)?;
+        file.write_all(&bytes).await?;
+    }
+    Ok(fs)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::sdf::wasm::backend::WasmBackend;
+    use crate::sdf::wasm::fixtures::{load, wasm};
+    use crate::sdf::wasm::wasi::CliWasiEnv;
+
+    /// A module that reports its arguments as its name and its environment variables as its GLSL code.
+    /// Its color contains the errors of creating and overwriting files in the assets directory, and the
+    /// first byte of `/assets/hello.txt` (or -1 if it can't be read).
+    const WASI_WAT: &str = r#"(module
+            (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "environ_sizes_get" (func $environ_sizes_get (param i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "environ_get" (func $environ_get (param i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_prestat_get" (func $fd_prestat_get (param i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_prestat_dir_name" (func $fd_prestat_dir_name (param i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (global $assets (mut i32) (i32.const -1))
+            (global $create_errno (mut i32) (i32.const 0))
+            (global $overwrite_errno (mut i32) (i32.const 0))
+            (global $first_byte (mut i32) (i32.const -1))
+            (data (i32.const 2048) "assets")
+            (data (i32.const 2064) "new.txt")
+            (data (i32.const 2080) "hello.txt")
+            (func $find_assets (result i32) (local $fd i32) (local $len i32)
+                (local.set $fd (i32.const 3))
+                (block $not_found (loop $next
+                    (br_if $not_found (i32.ge_u (local.get $fd) (i32.const 32)))
+                    (if (i32.eqz (call $fd_prestat_get (local.get $fd) (i32.const 256))) (then
+                        (local.set $len (i32.load (i32.const 260)))
+                        (if (i32.and (i32.ge_u (local.get $len) (i32.const 6)) (i32.le_u (local.get $len) (i32.const 64))) (then
+                            (drop (call $fd_prestat_dir_name (local.get $fd) (i32.const 272) (local.get $len)))
+                            (if (i32.and
+                                    (i32.eq (i32.load (i32.add (i32.const 266) (local.get $len))) (i32.load (i32.const 2048)))
+                                    (i32.eq (i32.load16_u (i32.add (i32.const 270) (local.get $len))) (i32.load16_u (i32.const 2052))))
+                                (then (return (local.get $fd))))))))
+                    (local.set $fd (i32.add (local.get $fd) (i32.const 1)))
+                    (br $next)))
+                (i32.const -1))
+            (func $try_write (param $path i32) (param $len i32) (param $oflags i32) (result i32) (local $errno i32)
+                (local.set $errno (call $path_open (global.get $assets) (i32.const 0) (local.get $path) (local.get $len)
+                    (local.get $oflags) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 400)))
+                (if (local.get $errno) (then (return (local.get $errno))))
+                (i32.store (i32.const 408) (i32.const 2064))
+                (i32.store (i32.const 412) (i32.const 1))
+                (call $fd_write (i32.load (i32.const 400)) (i32.const 408) (i32.const 1) (i32.const 404)))
+            (func $read_first_byte (result i32)
+                (if (call $path_open (global.get $assets) (i32.const 0) (i32.const 2080) (i32.const 9)
+                        (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 400))
+                    (then (return (i32.const -1))))
+                (i32.store (i32.const 408) (i32.const 420))
+                (i32.store (i32.const 412) (i32.const 1))
+                (if (call $fd_read (i32.load (i32.const 400)) (i32.const 408) (i32.const 1) (i32.const 404))
+                    (then (return (i32.const -1))))
+                (i32.load8_u (i32.const 420)))
+            (func (export "init")
+                (drop (call $args_sizes_get (i32.const 120) (i32.const 124)))
+                (drop (call $args_get (i32.const 4096) (i32.const 8192)))
+                (i32.store (i32.const 128) (i32.const 8192))
+                (i32.store (i32.const 132) (i32.load (i32.const 124)))
+                (drop (call $environ_sizes_get (i32.const 120) (i32.const 124)))
+                (drop (call $environ_get (i32.const 12288) (i32.const 16384)))
+                (i32.store (i32.const 136) (i32.const 16384))
+                (i32.store (i32.const 140) (i32.load (i32.const 124)))
+                (global.set $assets (call $find_assets))
+                (global.set $first_byte (call $read_first_byte))
+                (global.set $create_errno (call $try_write (i32.const 2064) (i32.const 7) (i32.const 1)))
+                (global.set $overwrite_errno (call $try_write (i32.const 2080) (i32.const 9) (i32.const 8))))
+            (func (export "bounding_box") (param i32) (result i32) (i32.const 0))
+            (func (export "sample") (param i32 f32 f32 f32 i32) (result i32)
+                (f32.store (i32.const 64) (local.get 1))
+                (f32.store (i32.const 68) (f32.convert_i32_s (global.get $create_errno)))
+                (f32.store (i32.const 72) (f32.convert_i32_s (global.get $overwrite_errno)))
+                (f32.store (i32.const 76) (f32.convert_i32_s (global.get $first_byte)))
+                (i32.const 64))
+            (func (export "name") (param i32) (result i32) (i32.const 128))
+            (func (export "glsl") (param i32) (result i32) (i32.const 136)))"#;
+
+    #[test]
+    fn test_wasi_args_envs_and_read_only_assets() {
+        let wasm_bytes = wasm(WASI_WAT);
+        let assets_dir = std::env::temp_dir().join(format!("sdf-viewer-wasi-test-{}", std::process::id()));
+        std::fs::create_dir_all(&assets_dir).unwrap();
+        std::fs::write(assets_dir.join("hello.txt"), "hello").unwrap();
+        let wasi = CliWasiEnv {
+            wasi_args: vec!["--flag".to_string(), "value with spaces".to_string()],
+            wasi_envs: vec![("KEY".to_string(), "value".to_string()), ("EMPTY".to_string(), String::new())],
+            ..CliWasiEnv::default()
+        };
+        let wasi_with_dir = CliWasiEnv { wasi_assets_dir: Some(assets_dir.clone()), ..wasi.clone() };
+        let wasi_with_bundle = CliWasiEnv { wasi_bundled_assets: vec![("hello.txt".to_string(), b"hello".to_vec())], ..wasi };
+        for backend in WasmBackend::ALL {
+            for wasi in [&wasi_with_dir, &wasi_with_bundle] {
+                let sdf = load(*backend, &wasm_bytes, wasi).unwrap();
+                assert_eq!(sdf.name(), "sdf\0--flag\0value with spaces\0", "{:?}", backend);
+                assert_eq!(sdf.glsl(), "KEY=value\0EMPTY=\0", "{:?}", backend);
+                let errors = sdf.sample(Vector3::new(0.0, 0.0, 0.0), false).color;
+                assert_ne!(errors.x, 0.0, "{:?} allowed creating a file in the assets", backend);
+                assert_ne!(errors.y, 0.0, "{:?} allowed overwriting a file in the assets", backend);
+                #[cfg(feature = "wasmer_backend")]
+                if *backend == WasmBackend::Wasmer {
+                    assert_eq!(errors.z, f32::from(b'h'), "{:?} did not provide the assets", backend);
+                }
+            }
+        }
+        assert_eq!(std::fs::read_to_string(assets_dir.join("hello.txt")).unwrap(), "hello");
+        assert!(!assets_dir.join("new.txt").exists());
+        std::fs::remove_dir_all(&assets_dir).unwrap();
+    }
+}
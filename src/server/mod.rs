@@ -22,6 +22,9 @@ use tokio::sync::Mutex;
 
 use crate::metadata::short_version_info;
 
+#[cfg(feature = "wasminterpreters")]
+pub mod remote;
+
 #[derive(clap::Parser, Debug, Clone, PartialEq, Eq)]
 pub struct CliServer {
     /// The path of the files that will be served. Usually just one *.wasm file. Only exact path
@@ -46,6 +49,12 @@ pub struct CliServer {
     /// The port to listen on.
     #[clap(short, long, default_value = "8080")]
     pub port: u16,
+    /// A SDF (WebAssembly file or native library, from a path or URL) to evaluate in this server and
+    /// expose under /sdf/ with the remote SDF protocol, so that any viewer can render it with the
+    /// `remote` SDF provider. WebAssembly URLs are watched for changes like in the app.
+    #[cfg(feature = "wasminterpreters")]
+    #[clap(short, long)]
+    pub remote_sdf: Option<String>,
 }
 
 fn parse_duration_ns(arg: &str) -> Result<Duration, std::num::ParseIntError> {
@@ -314,13 +323,21 @@ impl CliServer {
             }
         });
 
-        // Create the main router pointing to the main handler
-        let router = Router::new().filter(AnyFilter {}).get(FileServerHandler {
+        // Create the main router pointing to the main handler (after the remote SDF, if configured)
+        #[allow(unused_mut)]
+        let mut router = Router::new();
+        #[cfg(feature = "wasminterpreters")]
+        if let Some(url) = &self.remote_sdf {
+            let handler = remote::RemoteSDFHandler::default();
+            handler.load(url.clone());
+            router = router.push(handler.into_router());
+        }
+        let router = router.push(Router::new().filter(AnyFilter {}).get(FileServerHandler {
             cfg: self.clone(),
             sender: modified_sender,
             remote_events: Mutex::new(LruCache::new(NonZeroUsize::new(64).unwrap())), // Up to N clients (without races that may skip events)
             last_build_event: Mutex::new(0),
-        });
+        }));
 
         // Await forever serving requests
         // TODO: Graceful shutdown
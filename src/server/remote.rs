@@ -0,0 +1,198 @@
+//! Serves a SDF with the remote SDF protocol (see [`crate::sdf::remote`]).
+
+use std::sync::{Arc, RwLock};
+
+use salvo::http::{HeaderMap, HeaderValue, Method};
+use salvo::http::response::ResBody;
+use salvo::prelude::*;
+use salvo::routing::{Filter, PathState};
+use tokio::sync::mpsc;
+
+use crate::metadata::short_version_info;
+use crate::sdf::remote::handle_request;
+use crate::sdf::SDFSurface;
+use crate::sdf::wasm::load;
+use crate::sdf::wasm::wasi::CliWasiEnv;
+
+/// The path under which the protocol is served.
+pub const REMOTE_SDF_PATH: &str = "/sdf/";
+
+/// The maximum size of a request body, which is enough for batches of more than a million points.
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Handles the requests of the protocol for the latest loaded SDF.
+#[derive(Clone, Default)]
+pub struct RemoteSDFHandler {
+    sdf: Arc<RwLock<Option<Arc<dyn SDFSurface + Send + Sync>>>>,
+}
+
+impl RemoteSDFHandler {
+    /// Replaces the SDF that is served (for new requests).
+    pub fn set_sdf(&self, sdf: Box<dyn SDFSurface + Send + Sync>) {
+        *self.sdf.write().unwrap() = Some(Arc::from(sdf));
+    }
+
+    /// Loads the SDF from the given path or URL (see [`load::load_sdf_from_path_or_url`]) in the
+    /// background, replacing the served SDF whenever it is updated.
+    pub fn load(&self, url: String) {
+        let (sender_of_updates, mut receiver_of_updates) = mpsc::channel(1);
+        load::load_sdf_from_path_or_url(sender_of_updates, url.clone(), CliWasiEnv::default());
+        let slf = self.clone();
+        tokio::spawn(async move {
+            while let Some(mut receiver_single_update) = receiver_of_updates.recv().await {
+                match receiver_single_update.recv().await {
+                    Some(Ok(sdf)) => {
+                        tracing::info!(url=url, "Serving updated remote SDF");
+                        slf.set_sdf(sdf);
+                    }
+                    Some(Err(err)) => tracing::error!(url=url, "Failed to load remote SDF, still serving the previous one: {}", err),
+                    None => {}
+                }
+            }
+        });
+    }
+
+    /// Creates the router that serves the protocol under [`REMOTE_SDF_PATH`].
+    pub fn into_router(self) -> Router {
+        #[derive(Debug)]
+        struct PrefixFilter;
+
+        #[async_trait]
+        impl Filter for PrefixFilter {
+            async fn filter(&self, req: &mut Request, path: &mut PathState) -> bool {
+                if !req.uri().path().starts_with(REMOTE_SDF_PATH) {
+                    return false;
+                }
+                *path = PathState::new(""); // HACK: Forces the filter to match (like the file server).
+                true
+            }
+        }
+
+        Router::new().filter(PrefixFilter {}).goal(self)
+    }
+}
+
+#[async_trait]
+impl Handler for RemoteSDFHandler {
+    async fn handle(&self, req: &mut Request, _depot: &mut Depot, res: &mut Response, _ctrl: &mut FlowCtrl) {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            salvo::http::header::CACHE_CONTROL,
+            HeaderValue::from_static("no-cache"),
+        );
+        headers.insert(
+            salvo::http::header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("*"),
+        );
+        headers.insert(
+            salvo::http::header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_static("*"),
+        );
+        headers.insert(
+            salvo::http::header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("GET, POST"),
+        );
+        headers.insert(
+            salvo::http::header::SERVER,
+            HeaderValue::from_str(&short_version_info()).unwrap(),
+        );
+        headers.insert(
+            salvo::http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+        res.set_headers(headers);
+        if req.method() == Method::OPTIONS { // CORS preflight
+            return;
+        }
+
+        let sdf = match self.sdf.read().unwrap().clone() {
+            Some(sdf) => sdf,
+            None => {
+                tracing::warn!("Received remote SDF request while the SDF is still loading");
+                StatusError::service_unavailable().render(res);
+                return;
+            }
+        };
+        let path = req.uri().path().strip_prefix(REMOTE_SDF_PATH).unwrap_or_default().to_string();
+        let query = req.uri().query().unwrap_or_default().to_string();
+        let body = match req.payload_with_max_size(MAX_BODY_SIZE).await {
+            Ok(body) => body.to_vec(),
+            Err(err) => {
+                tracing::error!(path=path, "Failed to read remote SDF request body: {}", err);
+                StatusError::bad_request().render(res);
+                return;
+            }
+        };
+
+        // Sampling may be slow, so don't block the async runtime
+        let path_clone = path.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            handle_request(sdf.as_ref(), &path_clone, &query, &body)
+        }).await;
+        match response {
+            Ok(response) => {
+                if response.status >= 400 {
+                    tracing::warn!(path=path, status=response.status, "Remote SDF request failed: {}", String::from_utf8_lossy(&response.body));
+                }
+                res.status_code(StatusCode::from_u16(response.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
+                res.body(ResBody::from(response.body));
+            }
+            Err(err) => {
+                tracing::error!(path=path, "Remote SDF request panicked: {}", err);
+                StatusError::internal_server_error().render(res);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ehttp"))]
+mod tests {
+    use cgmath::Vector3;
+    use salvo::prelude::*;
+
+    use crate::sdf::{SDFParamValue, SDFSurface};
+    use crate::sdf::ops::Translate;
+    use crate::sdf::primitives::{Primitive, Sphere};
+    use crate::sdf::remote::client::{check_version, RemoteSDF};
+    use crate::server::remote::{REMOTE_SDF_PATH, RemoteSDFHandler};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_remote_sdf_on_localhost() {
+        let root = Translate::new(Primitive::new(Sphere::new(1.0)), Vector3::new(2.0, 0.0, 0.0)).with_id(0);
+        let handler = RemoteSDFHandler::default();
+        handler.set_sdf(Box::new(root.clone()));
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let acceptor = TcpListener::new(("127.0.0.1", port)).bind().await;
+        tokio::spawn(Server::new(acceptor).serve(handler.into_router()));
+
+        let base_url = format!("http://127.0.0.1:{}{}", port, REMOTE_SDF_PATH);
+        tokio::task::spawn_blocking(move || {
+            let remote = RemoteSDF::new(base_url);
+            check_version(&ehttp::fetch_blocking(&ehttp::Request::get(remote.version_url())).unwrap()).unwrap();
+
+            assert_eq!(remote.bounding_box(), root.bounding_box());
+            let points = [Vector3::new(2.0, 0.0, 0.0), Vector3::new(4.0, 0.0, 0.0), Vector3::new(0.0, 3.0, 1.0)];
+            for (remote_sample, sample) in remote.sample_batch(&points, false).into_iter().zip(root.sample_batch(&points, false)) {
+                assert_eq!(remote_sample.distance, sample.distance);
+                assert_eq!(remote_sample.color, sample.color);
+            }
+            assert_eq!(remote.name(), root.name());
+
+            let remote_children = remote.children();
+            assert_eq!(remote_children.len(), 1);
+            let remote_sphere = &remote_children[0];
+            assert_eq!(remote_sphere.id(), root.children()[0].id());
+            assert_eq!(remote_sphere.name(), root.children()[0].name());
+            let params = remote_sphere.parameters();
+            assert_eq!(format!("{params:?}"), format!("{:?}", root.children()[0].parameters()));
+
+            assert_eq!(remote.changed(), None);
+            remote_sphere.set_parameter(params[0].id, &SDFParamValue::Float(2.0)).unwrap();
+            assert_eq!(root.sample(Vector3::new(2.0, 0.0, 0.0), true).distance, -2.0);
+            assert!(remote.changed().is_some());
+            assert_eq!(remote.changed(), None);
+            assert!(remote_sphere.set_parameter(params[0].id, &SDFParamValue::Boolean(true)).is_err());
+        }).await.unwrap();
+    }
+}
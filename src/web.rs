@@ -2,6 +2,7 @@
 
 use eframe::wasm_bindgen::{self, prelude::*};
 use console_error_panic_hook;
+use tracing_subscriber::layer::SubscriberExt;
 use tracing_wasm;
 use eframe;
 
@@ -18,7 +19,11 @@ impl WebHandle {
     #[allow(clippy::new_without_default)]
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
-        tracing_wasm::set_as_global_default();
+        // Setup logging (also to the console of the app)
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_wasm::WASMLayer::new(tracing_wasm::WASMLayerConfig::default()))
+            .with(crate::sdf::wasm::console::ConsoleLayer);
+        tracing::subscriber::set_global_default(subscriber).expect("Failed to set global default subscriber");
         std::panic::set_hook(Box::new(console_error_panic_hook::hook));
         Self {
             runner: eframe::WebRunner::new(),